@@ -0,0 +1,62 @@
+#![feature(test)]
+
+extern crate prost;
+#[macro_use]
+extern crate prost_derive;
+extern crate test;
+
+use prost::Message;
+use test::Bencher;
+
+/// A message tree node. Each node has a single child, so a tree of depth `d`
+/// has `d` nodes, and encoding it should take time linear in `d`.
+#[derive(Clone, Debug, PartialEq, Message)]
+pub struct Node {
+    #[prost(message, optional, tag="1")]
+    pub child: Option<Box<Node>>,
+    #[prost(string, tag="2")]
+    pub name: String,
+    #[prost(message, repeated, tag="3")]
+    pub leaves: Vec<Leaf>,
+}
+
+#[derive(Clone, Debug, PartialEq, Message)]
+pub struct Leaf {
+    #[prost(int64, tag="1")]
+    pub value: i64,
+}
+
+fn tree(depth: usize) -> Node {
+    let mut node = Node::default();
+    for i in 0..depth {
+        node = Node {
+            child: Some(Box::new(node)),
+            name: format!("node {}", i),
+            leaves: vec![Leaf { value: i as i64 }, Leaf { value: -(i as i64) }],
+        };
+    }
+    node
+}
+
+macro_rules! nested_bench {
+    ($name:ident, $depth:expr) => {
+        #[bench]
+        fn $name(b: &mut Bencher) {
+            let node = tree($depth);
+            let len = node.encoded_len();
+            let mut buf = Vec::with_capacity(len);
+            b.iter(|| {
+                buf.clear();
+                node.encode(&mut buf).unwrap();
+                test::black_box(&buf[..]);
+            });
+            b.bytes = len as u64;
+        }
+    }
+}
+
+// Throughput (bytes per second) should be roughly constant across depths.
+nested_bench!(encode_nested_depth_010, 10);
+nested_bench!(encode_nested_depth_050, 50);
+nested_bench!(encode_nested_depth_100, 100);
+nested_bench!(encode_nested_depth_500, 500);
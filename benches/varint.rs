@@ -1,84 +1,125 @@
 #![feature(test)]
 
-extern crate proto;
-extern crate test;
 extern crate bytes;
+extern crate prost;
+extern crate test;
 
 use test::Bencher;
 
-use bytes::{
-    BytesMut,
-    IntoBuf,
-};
+use bytes::IntoBuf;
 
-use proto::field::{
-    encode_varint,
+use prost::encoding::{
     decode_varint,
-    varint_width
+    decode_varint_slow,
+    encode_varint,
+    encode_varint_slow,
 };
 
 macro_rules! varint_bench {
-    ($encode_name:ident, $decode_name:ident, $expected_bytes:expr, $encode:expr) => {
+    ($encode_name:ident, $encode_slow_name:ident,
+     $decode_name:ident, $decode_slow_name:ident,
+     $expected_bytes:expr, $values:expr) => {
         #[bench]
         fn $encode_name(b: &mut Bencher) {
-            let mut buf = BytesMut::with_capacity(4096);
+            let values: Vec<u64> = $values;
+            let mut buf = Vec::<u8>::with_capacity(100 * 10);
             b.iter(|| {
                 buf.clear();
-                $encode(&mut buf);
+                for &value in &values {
+                    encode_varint(value, &mut buf);
+                }
                 test::black_box(&buf[..]);
             });
             assert_eq!(buf.len(), $expected_bytes);
             b.bytes = 100 * 8;
         }
+
+        #[bench]
+        fn $encode_slow_name(b: &mut Bencher) {
+            let values: Vec<u64> = $values;
+            let mut buf = Vec::<u8>::with_capacity(100 * 10);
+            b.iter(|| {
+                buf.clear();
+                for &value in &values {
+                    encode_varint_slow(value, &mut buf);
+                }
+                test::black_box(&buf[..]);
+            });
+            assert_eq!(buf.len(), $expected_bytes);
+            b.bytes = 100 * 8;
+        }
+
         #[bench]
         fn $decode_name(b: &mut Bencher) {
-            let mut buf = BytesMut::with_capacity(4096);
-            $encode(&mut buf);
-            let buf = buf.freeze();
+            let values: Vec<u64> = $values;
+            let mut buf = Vec::<u8>::with_capacity(100 * 10);
+            for &value in &values {
+                encode_varint(value, &mut buf);
+            }
+            let mut decoded = [0u64; 100];
+
+            b.iter(|| {
+                let mut buf = (&buf[..]).into_buf();
+                for value in decoded.iter_mut() {
+                    *value = decode_varint(&mut buf).unwrap();
+                }
+                test::black_box(&decoded[..]);
+            });
+            assert_eq!(&values[..], &decoded[..]);
+            b.bytes = 100 * 8;
+        }
 
-            let mut values = [0u64; 100];
+        #[bench]
+        fn $decode_slow_name(b: &mut Bencher) {
+            let values: Vec<u64> = $values;
+            let mut buf = Vec::<u8>::with_capacity(100 * 10);
+            for &value in &values {
+                encode_varint(value, &mut buf);
+            }
+            let mut decoded = [0u64; 100];
 
             b.iter(|| {
-                let mut buf = buf.clone().into_buf();
-                for i in 0..100 {
-                    values[i] = decode_varint(&mut buf).unwrap();
+                let mut buf = (&buf[..]).into_buf();
+                for value in decoded.iter_mut() {
+                    *value = decode_varint_slow(&mut buf).unwrap();
                 }
-                test::black_box(&values[..]);
+                test::black_box(&decoded[..]);
             });
+            assert_eq!(&values[..], &decoded[..]);
             b.bytes = 100 * 8;
         }
     }
 }
 
-/// Benchmark encoding and decoding 100 varints of mixed width (average 5.5 bytes).
-varint_bench!(encode_varint_mixed, decode_varint_mixed, 550, |ref mut buf| {
-    for width in 0..10 {
-        let exponent = width * 7;
-        for offset in 0..10 {
-            encode_varint(offset + (1 << exponent), buf);
-        }
-    }
-});
+// Benchmark encoding and decoding 100 varints of mixed width (average 5.5 bytes).
+varint_bench!(encode_varint_mixed, encode_varint_slow_mixed,
+              decode_varint_mixed, decode_varint_slow_mixed,
+              550,
+              (0..10).flat_map(|width| {
+                  let exponent = width * 7;
+                  (0..10).map(move |offset| offset + (1 << exponent))
+              }).collect());
 
-/// Benchmark encoding and decoding 100 small (1 byte) varints.
-varint_bench!(encode_varint_small, decode_varint_small, 100, |ref mut buf| {
-    for value in 0..100 {
-        encode_varint(value, buf);
-    }
-});
+// Benchmark encoding and decoding 100 small (1 byte) varints.
+varint_bench!(encode_varint_small, encode_varint_slow_small,
+              decode_varint_small, decode_varint_slow_small,
+              100,
+              (0..100).collect());
 
-/// Benchmark encoding and decoding 100 medium (5 byte) varints.
-varint_bench!(encode_varint_medium, decode_varint_medium, 500, |ref mut buf| {
-    let start = 1 << 28;
-    for value in start..start + 100 {
-        encode_varint(value, buf);
-    }
-});
+// Benchmark encoding and decoding 100 medium (5 byte) varints.
+varint_bench!(encode_varint_medium, encode_varint_slow_medium,
+              decode_varint_medium, decode_varint_slow_medium,
+              500,
+              {
+                  let start = 1 << 28;
+                  (start..start + 100).collect()
+              });
 
-/// Benchmark encoding and decoding 100 large (10 byte) varints.
-varint_bench!(encode_varint_large, decode_varint_large, 1000, |ref mut buf| {
-    let start = 1 << 63;
-    for value in start..start + 100 {
-        encode_varint(value, buf);
-    }
-});
+// Benchmark encoding and decoding 100 large (10 byte) varints.
+varint_bench!(encode_varint_large, encode_varint_slow_large,
+              decode_varint_large, decode_varint_slow_large,
+              1000,
+              {
+                  let start = 1 << 63;
+                  (start..start + 100).collect()
+              });
@@ -2,6 +2,7 @@ extern crate bytes;
 extern crate env_logger;
 extern crate test_all_types;
 extern crate prost;
+extern crate prost_reflect;
 #[macro_use]
 extern crate prost_derive;
 
@@ -20,11 +21,13 @@ use bytes::{
     LittleEndian,
 };
 use prost::Message;
+use prost_reflect::json::JsonMessage;
 
 use test_all_types::{
     RoundtripResult,
     test_all_types_proto3_roundtrip,
 };
+use test_all_types::protobuf_test_messages::proto3::TestAllTypes;
 
 fn main() {
     env_logger::init().unwrap();
@@ -65,24 +68,43 @@ fn main() {
 }
 
 fn handle_request(request: ConformanceRequest) -> conformance_response::Result {
-    match request.requested_output_format() {
-        Some(WireFormat::Json) => {
-            return conformance_response::Result::Skipped("JSON output is not supported".to_string());
-        },
-        None => {
+    let output_format = match request.requested_output_format() {
+        Some(WireFormat::Unspecified) | None => {
             return conformance_response::Result::ParseError("unrecognized requested output format".to_string());
         },
-        _ => (),
+        Some(output_format) => output_format,
     };
 
-    let buf = match request.payload {
+    let all_types = match request.payload {
         None => return conformance_response::Result::ParseError("no payload".to_string()),
-        Some(conformance_request::Payload::JsonPayload(_)) =>
-            return conformance_response::Result::Skipped("JSON input is not supported".to_string()),
-        Some(conformance_request::Payload::ProtobufPayload(buf)) => buf,
+        Some(conformance_request::Payload::JsonPayload(json)) => {
+            match TestAllTypes::from_json(&json) {
+                Ok(all_types) => all_types,
+                Err(error) => return conformance_response::Result::ParseError(error.to_string()),
+            }
+        },
+        Some(conformance_request::Payload::ProtobufPayload(buf)) => {
+            if output_format == WireFormat::Protobuf {
+                return protobuf_roundtrip(&buf);
+            }
+            match TestAllTypes::decode_from_slice(&buf) {
+                Ok(all_types) => all_types,
+                Err(error) => return conformance_response::Result::ParseError(error.to_string()),
+            }
+        },
     };
 
-    match test_all_types_proto3_roundtrip(&buf) {
+    match output_format {
+        WireFormat::Json => match all_types.to_json() {
+            Ok(json) => conformance_response::Result::JsonPayload(json),
+            Err(error) => conformance_response::Result::SerializeError(error.to_string()),
+        },
+        _ => conformance_response::Result::ProtobufPayload(all_types.encode_to_vec()),
+    }
+}
+
+fn protobuf_roundtrip(buf: &[u8]) -> conformance_response::Result {
+    match test_all_types_proto3_roundtrip(buf) {
         RoundtripResult::Ok(buf) => {
             conformance_response::Result::ProtobufPayload(buf)
         },
@@ -1,10 +1,34 @@
 #![no_main]
 
 #[macro_use] extern crate libfuzzer_sys;
+extern crate bytes;
+extern crate prost;
 extern crate test_all_types;
 
+use std::io::Cursor;
+
+use bytes::Buf;
+use prost::{
+    DecodeErrorKind,
+    Message,
+};
+use prost::encoding::DecodeContext;
+
+use test_all_types::protobuf_test_messages::proto3::TestAllTypes;
 use test_all_types::test_all_types_proto3_roundtrip;
 
 fuzz_target!(|data: &[u8]| {
     let _ = test_all_types_proto3_roundtrip(data).unwrap_error();
+
+    // `TestAllTypes` is recursive, so deeply nested input must fail cleanly when
+    // decoded with a small recursion limit, and otherwise decode the same as
+    // with the default limit.
+    let len = data.len();
+    let limited = TestAllTypes::decode_with_context(&mut Buf::take(Cursor::new(data), len),
+                                                    DecodeContext::with_recursion_limit(4));
+    let unlimited = TestAllTypes::decode(&mut Buf::take(Cursor::new(data), len));
+    match limited {
+        Err(ref error) if *error.kind() == DecodeErrorKind::RecursionLimitReached => (),
+        limited => assert_eq!(limited.is_ok(), unlimited.is_ok()),
+    }
 });
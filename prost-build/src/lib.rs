@@ -31,10 +31,22 @@ use zip::ZipArchive;
 use prost::Message;
 use prost_codegen::google::protobuf::FileDescriptorSet;
 
+pub use prost_codegen::Config;
+
 pub fn compile_protos<P>(protos: &[P],
                          includes: &[P],
                          service_generator: Option<&prost_codegen::ServiceGenerator>)
                          -> Result<()> where P: AsRef<Path> {
+    compile_protos_with_config(&Config::new(), protos, includes, service_generator)
+}
+
+/// Compiles `.proto` files into Rust files during a Cargo build, using the
+/// provided code generator configuration.
+pub fn compile_protos_with_config<P>(config: &Config,
+                                     protos: &[P],
+                                     includes: &[P],
+                                     service_generator: Option<&prost_codegen::ServiceGenerator>)
+                                     -> Result<()> where P: AsRef<Path> {
     let target = match env::var("OUT_DIR") {
         Ok(val) => PathBuf::from(val),
         Err(env::VarError::NotPresent) => return Err(Error::new(ErrorKind::Other,
@@ -92,7 +104,7 @@ pub fn compile_protos<P>(protos: &[P],
     let len = buf.len();
     let descriptor_set = FileDescriptorSet::decode(&mut <Cursor<Vec<u8>> as Buf>::take(Cursor::new(buf), len))?;
 
-    let modules = prost_codegen::generate(descriptor_set.file, service_generator);
+    let modules = prost_codegen::generate(descriptor_set.file, config, service_generator);
     for (module, content) in modules {
         let mut filename = match module.last() {
             Some(filename) => PathBuf::from(filename),
@@ -33,7 +33,18 @@ fn main() {
     let request = CodeGeneratorRequest::decode(&mut Buf::take(Cursor::new(&mut bytes), len)).unwrap();
     let mut response = CodeGeneratorResponse::default();
 
-    let modules = prost_codegen::generate(request.proto_file, None);
+    let config = match parse_options(request.parameter()) {
+        Ok(config) => config,
+        Err(error) => {
+            // protoc reports the error and exits, as it does for errors in
+            // the `.proto` files.
+            response.error = Some(error);
+            write_response(&response);
+            return;
+        },
+    };
+
+    let modules = prost_codegen::generate(request.proto_file, &config, None);
 
     // For each module, build up a list of its child modules.
     let mut children: HashMap<prost_codegen::Module, Vec<String>> = HashMap::new();
@@ -75,7 +86,57 @@ fn main() {
         });
     }
 
+    write_response(&response);
+}
+
+/// Parses the generator options, which are passed as a comma separated list,
+/// e.g. `--prost_out=retain_unknown_fields,btree_map=.my.package:<out-dir>`.
+fn parse_options(parameter: &str) -> Result<prost_codegen::Config, String> {
+    let mut config = prost_codegen::Config::new();
+    let mut btree_map = Vec::new();
+    let mut lazy = Vec::new();
+    for option in parameter.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        if option.starts_with("btree_map=") {
+            btree_map.push(&option["btree_map=".len()..]);
+            continue;
+        }
+        if option.starts_with("lazy=") {
+            lazy.push(&option["lazy=".len()..]);
+            continue;
+        }
+        match option {
+            "retain_unknown_fields" => { config.retain_unknown_fields(true); },
+            "bytes_type" => { config.bytes_type(true); },
+            "reflection" => { config.reflection(true); },
+            "json" => { config.json(true); },
+            "compile_well_known_types" => { config.compile_well_known_types(true); },
+            "btree_map" => btree_map.push("."),
+            "lazy" => lazy.push("."),
+            _ => return Err(format!("unknown protoc-gen-prost option: {}", option)),
+        }
+    }
+    config.btree_map(btree_map);
+    config.lazy(lazy);
+    Ok(config)
+}
+
+fn write_response(response: &CodeGeneratorResponse) {
     let mut out = Vec::new();
     response.encode(&mut out).unwrap();
     io::stdout().write_all(&out).unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_options() {
+        assert!(parse_options("").is_ok());
+        assert!(parse_options("retain_unknown_fields, btree_map=.foo,lazy=.foo.Bar.baz,json").is_ok());
+        assert_eq!("unknown protoc-gen-prost option: foo",
+                   parse_options("bytes_type,foo").err().unwrap());
+        assert_eq!("unknown protoc-gen-prost option: reflection=true",
+                   parse_options("reflection=true").err().unwrap());
+    }
+}
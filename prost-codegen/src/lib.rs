@@ -1,8 +1,6 @@
 #![doc(html_root_url = "https://docs.rs/prost-codegen/0.1.1")]
 #![recursion_limit = "128"]
 
-#[macro_use]
-extern crate prost_derive;
 #[macro_use]
 extern crate log;
 
@@ -12,6 +10,7 @@ extern crate itertools;
 extern crate multimap;
 extern crate petgraph;
 extern crate prost;
+extern crate prost_reflect;
 
 mod message_graph;
 
@@ -22,7 +21,9 @@ use itertools::{Either, Itertools};
 use multimap::MultiMap;
 
 mod ast;
-pub mod google;
+pub use prost_reflect::google;
+use prost::Message;
+
 use google::protobuf::{
     DescriptorProto,
     EnumDescriptorProto,
@@ -30,6 +31,7 @@ use google::protobuf::{
     field_descriptor_proto,
     FieldDescriptorProto,
     FileDescriptorProto,
+    FileDescriptorSet,
     OneofDescriptorProto,
     ServiceDescriptorProto,
     SourceCodeInfo,
@@ -57,20 +59,252 @@ pub trait ServiceGenerator {
     fn generate(&self, service: Service, buf: &mut String);
 }
 
+/// Configuration options for code generation.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    retain_unknown_fields: bool,
+    bytes_type: bool,
+    reflection: bool,
+    json: bool,
+    compile_well_known_types: bool,
+    btree_map: Vec<String>,
+    lazy: Vec<String>,
+}
+
+impl Config {
+
+    /// Creates a new code generator configuration with default options.
+    pub fn new() -> Config {
+        Config::default()
+    }
+
+    /// Configures whether generated messages retain unknown fields.
+    ///
+    /// When enabled, each generated message struct gets an `unknown_fields`
+    /// member which captures fields not recognized during decoding, so that
+    /// they are written back out when the message is re-encoded.
+    ///
+    /// Defaults to `false`.
+    pub fn retain_unknown_fields(&mut self, enabled: bool) -> &mut Config {
+        self.retain_unknown_fields = enabled;
+        self
+    }
+
+    /// Configures whether `bytes` fields are generated as `::bytes::Bytes`
+    /// instead of `Vec<u8>`, and `string` fields as `::prost::BytesString`
+    /// instead of `String`.
+    ///
+    /// `Bytes` and `BytesString` fields are sliced from the input buffer
+    /// instead of being copied when messages are decoded with
+    /// `Message::decode_bytes`. The crate containing the generated code must
+    /// depend on `bytes`.
+    ///
+    /// Defaults to `false`.
+    pub fn bytes_type(&mut self, enabled: bool) -> &mut Config {
+        self.bytes_type = enabled;
+        self
+    }
+
+    /// Configures whether generated code supports runtime reflection.
+    ///
+    /// When enabled, each generated module embeds the encoded
+    /// `FileDescriptorSet` of its `.proto` files and their dependencies in a
+    /// `FILE_DESCRIPTOR_SET` constant, and each generated message implements
+    /// `prost_reflect::reflect::ReflectMessage`. The crate containing the
+    /// generated code must depend on `prost-reflect`.
+    ///
+    /// Defaults to `false`.
+    pub fn reflection(&mut self, enabled: bool) -> &mut Config {
+        self.reflection = enabled;
+        self
+    }
+
+    /// Configures whether generated messages support the canonical proto3
+    /// JSON mapping.
+    ///
+    /// When enabled, each generated message implements
+    /// `prost_reflect::json::JsonMessage`, which provides `to_json` and
+    /// `from_json`. Implies `reflection`.
+    ///
+    /// Defaults to `false`.
+    pub fn json(&mut self, enabled: bool) -> &mut Config {
+        self.json = enabled;
+        self
+    }
+
+    /// Configures whether the well-known types in the `google.protobuf`
+    /// package (`Timestamp`, `Duration`, `Any`, etc.) are generated along with
+    /// the `.proto` files which import them.
+    ///
+    /// When disabled, references to the well-known types resolve to the types
+    /// in the `prost-types` crate, which the crate containing the generated
+    /// code must depend on. Enable this option when generating `prost-types`
+    /// itself, or to generate a local copy of the well-known types.
+    ///
+    /// Defaults to `false`.
+    pub fn compile_well_known_types(&mut self, enabled: bool) -> &mut Config {
+        self.compile_well_known_types = enabled;
+        self
+    }
+
+    /// Configures the map fields which are generated as
+    /// `::std::collections::BTreeMap` instead of `HashMap`.
+    ///
+    /// `BTreeMap` fields iterate, and are encoded, in key order. Each path is
+    /// a fully-qualified Protobuf name, and selects the map fields at or
+    /// beneath it: `.` selects every map field, `.my.package` the map fields
+    /// of a package, `.my.package.MyMessage` the map fields of a message and
+    /// its nested messages, and `.my.package.MyMessage.my_field` a single
+    /// field.
+    ///
+    /// Defaults to no paths, so map fields are generated as `HashMap`.
+    pub fn btree_map<I, S>(&mut self, paths: I) -> &mut Config where I: IntoIterator<Item=S>, S: AsRef<str> {
+        self.btree_map = paths.into_iter().map(|path| path.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Configures the message fields which are generated as
+    /// `::prost::Lazy<T>` instead of `T`.
+    ///
+    /// `Lazy` fields store their encoded bytes when decoded, and are decoded
+    /// on first access. Paths select fields in the same way as `btree_map`.
+    /// Fields of `oneof`s and groups are never lazy. With the `reflection`
+    /// option, a `Lazy` field is reflected as a `Value::EncodedMessage`
+    /// holding its stored bytes, so it is not decoded by reflection.
+    ///
+    /// Defaults to no paths, so message fields are decoded eagerly.
+    pub fn lazy<I, S>(&mut self, paths: I) -> &mut Config where I: IntoIterator<Item=S>, S: AsRef<str> {
+        self.lazy = paths.into_iter().map(|path| path.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Returns `true` if the map field with the fully-qualified name is
+    /// generated as a `BTreeMap`.
+    fn is_btree_map(&self, fq_field_name: &str) -> bool {
+        matches_path(&self.btree_map, fq_field_name)
+    }
+
+    /// Returns `true` if the message field with the fully-qualified name is
+    /// generated as a `Lazy`.
+    fn is_lazy(&self, fq_field_name: &str) -> bool {
+        matches_path(&self.lazy, fq_field_name)
+    }
+}
+
+/// Returns `true` if the fully-qualified name is at or beneath one of the
+/// paths.
+fn matches_path(paths: &[String], fq_name: &str) -> bool {
+    paths.iter().any(|path| {
+        path == "." || (fq_name.starts_with(path.as_str()) &&
+                        (fq_name.len() == path.len() ||
+                         fq_name[path.len()..].starts_with('.')))
+    })
+}
+
 pub fn generate(files: Vec<FileDescriptorProto>,
+                config: &Config,
                 service_generator: Option<&ServiceGenerator>) -> HashMap<Module, String> {
     let mut modules = HashMap::new();
 
     let message_graph = MessageGraph::new(&files);
 
+    if config.reflection || config.json {
+        for (module, set) in module_descriptor_sets(&files, config) {
+            let mut buf = modules.entry(module).or_insert(String::new());
+            append_descriptor_set(&set, &mut buf);
+        }
+    }
+
     for file in files {
+        if !config.compile_well_known_types && is_well_known_types_file(&file) {
+            continue;
+        }
         let module = module(&file);
         let mut buf = modules.entry(module).or_insert(String::new());
-        CodeGenerator::generate(&service_generator, file, &message_graph, &mut buf);
+        CodeGenerator::generate(config, &service_generator, file, &message_graph, &mut buf);
     }
     modules
 }
 
+/// The files defining the well-known types, which are provided by the
+/// `prost-types` crate.
+const WELL_KNOWN_TYPES_FILES: &'static [&'static str] = &[
+    "google/protobuf/any.proto",
+    "google/protobuf/duration.proto",
+    "google/protobuf/empty.proto",
+    "google/protobuf/field_mask.proto",
+    "google/protobuf/struct.proto",
+    "google/protobuf/timestamp.proto",
+    "google/protobuf/wrappers.proto",
+];
+
+/// The well-known types, which are provided by the `prost-types` crate.
+const WELL_KNOWN_TYPES: &'static [&'static str] = &[
+    "Any",
+    "BoolValue",
+    "BytesValue",
+    "DoubleValue",
+    "Duration",
+    "Empty",
+    "FieldMask",
+    "FloatValue",
+    "Int32Value",
+    "Int64Value",
+    "ListValue",
+    "NullValue",
+    "StringValue",
+    "Struct",
+    "Timestamp",
+    "UInt32Value",
+    "UInt64Value",
+    "Value",
+];
+
+fn is_well_known_types_file(file: &FileDescriptorProto) -> bool {
+    file.package() == "google.protobuf" && WELL_KNOWN_TYPES_FILES.contains(&file.name())
+}
+
+/// Returns the descriptor set of each generated module, containing the files
+/// in the module and their transitive dependencies, without source code info.
+fn module_descriptor_sets(files: &[FileDescriptorProto], config: &Config) -> HashMap<Module, FileDescriptorSet> {
+    let by_name = files.iter()
+                       .map(|file| (file.name(), file))
+                       .collect::<HashMap<_, _>>();
+
+    let mut sets = HashMap::new();
+    for file in files {
+        if !config.compile_well_known_types && is_well_known_types_file(file) {
+            continue;
+        }
+        let set: &mut FileDescriptorSet = sets.entry(module(file)).or_insert_with(Default::default);
+        let mut stack = vec![file];
+        while let Some(file) = stack.pop() {
+            if set.file.iter().any(|f| f.name == file.name) {
+                continue;
+            }
+            let mut file = file.clone();
+            file.source_code_info = None;
+            stack.extend(file.dependency.iter().filter_map(|name| by_name.get(&name[..]).cloned()));
+            set.file.push(file);
+        }
+    }
+    sets
+}
+
+fn append_descriptor_set(set: &FileDescriptorSet, buf: &mut String) {
+    buf.push_str("/// The encoded `FileDescriptorSet` of the `.proto` files in this module, and their\n");
+    buf.push_str("/// dependencies.\n");
+    buf.push_str("pub const FILE_DESCRIPTOR_SET: &'static [u8] = b\"");
+    for byte in set.encode_to_vec() {
+        if byte >= 0x20 && byte < 0x7f && byte != b'"' && byte != b'\\' {
+            buf.push(byte as char);
+        } else {
+            buf.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    buf.push_str("\";\n");
+}
+
 #[derive(PartialEq)]
 enum Syntax {
     Proto2,
@@ -78,6 +312,11 @@ enum Syntax {
 }
 
 struct CodeGenerator<'a> {
+    config: &'a Config,
+    /// The package of the file.
+    file_package: String,
+    /// The package of the file, followed by the names of the enclosing
+    /// messages of the current scope.
     package: String,
     source_info: SourceCodeInfo,
     syntax: Syntax,
@@ -88,7 +327,8 @@ struct CodeGenerator<'a> {
 }
 
 impl <'a> CodeGenerator<'a> {
-    fn generate(service_generator: &Option<&ServiceGenerator>,
+    fn generate(config: &Config,
+                service_generator: &Option<&ServiceGenerator>,
                 file: FileDescriptorProto,
                 message_graph: &MessageGraph,
                 buf: &mut String) {
@@ -107,6 +347,8 @@ impl <'a> CodeGenerator<'a> {
         };
 
         let mut code_gen = CodeGenerator {
+            config: config,
+            file_package: file.package.clone().unwrap(),
             package: file.package.unwrap(),
             source_info: source_info,
             syntax: syntax,
@@ -134,6 +376,14 @@ impl <'a> CodeGenerator<'a> {
         }
         code_gen.path.pop();
 
+        code_gen.path.push(7);
+        for (idx, extension) in file.extension.into_iter().enumerate() {
+            code_gen.path.push(idx as i32);
+            code_gen.append_extension(extension);
+            code_gen.path.pop();
+        }
+        code_gen.path.pop();
+
         if let &Some(ref service_generator) = service_generator {
             code_gen.path.push(6);
             for (idx, service) in file.service.into_iter().enumerate() {
@@ -193,11 +443,11 @@ impl <'a> CodeGenerator<'a> {
 
         self.depth += 1;
         self.path.push(2);
-        for (field, idx) in fields.into_iter() {
+        for &(ref field, idx) in &fields {
             self.path.push(idx as i32);
             match field.type_name.as_ref().and_then(|type_name| map_types.get(type_name)) {
-                Some(&(ref key, ref value)) => self.append_map_field(field, key, value),
-                None => self.append_field(&fq_message_name, field),
+                Some(&(ref key, ref value)) => self.append_map_field(&fq_message_name, field.clone(), key, value),
+                None => self.append_field(&fq_message_name, field.clone()),
             }
             self.path.pop();
         }
@@ -212,11 +462,43 @@ impl <'a> CodeGenerator<'a> {
         }
         self.path.pop();
 
+        if !message.extension_range.is_empty() {
+            let ranges = message.extension_range
+                                .iter()
+                                .map(|range| format!("{}..{}", range.start(), range.end()))
+                                .join(", ");
+            self.push_indent();
+            self.buf.push_str(&format!("#[prost(extension_set=\"{}\")]\n", ranges));
+            self.push_indent();
+            self.buf.push_str("pub extensions: ::prost::ExtensionSet,\n");
+        }
+
+        if self.config.retain_unknown_fields {
+            self.push_indent();
+            self.buf.push_str("#[prost(unknown_fields)]\n");
+            self.push_indent();
+            self.buf.push_str("pub unknown_fields: ::prost::UnknownFieldSet,\n");
+        }
+
         self.depth -= 1;
         self.push_indent();
         self.buf.push_str("}\n");
 
-        if !message.enum_type.is_empty() || !nested_types.is_empty() || !oneof_fields.is_empty() {
+        self.append_name(&message_name, &fq_message_name);
+
+        if self.config.reflection || self.config.json {
+            self.append_reflect_message(&message_name,
+                                        &fq_message_name,
+                                        &fields,
+                                        &map_types,
+                                        &message.oneof_decl,
+                                        &oneof_fields);
+        }
+
+        if !message.enum_type.is_empty() ||
+           !nested_types.is_empty() ||
+           !oneof_fields.is_empty() ||
+           !message.extension.is_empty() {
             self.push_mod(&message_name);
             self.path.push(3);
             for (nested_type, idx) in nested_types.into_iter() {
@@ -239,6 +521,14 @@ impl <'a> CodeGenerator<'a> {
                 self.append_oneof(oneof, idx, oneof_fields.remove(&idx).unwrap());
             }
 
+            self.path.push(6);
+            for (idx, extension) in message.extension.into_iter().enumerate() {
+                self.path.push(idx as i32);
+                self.append_extension(extension);
+                self.path.pop();
+            }
+            self.path.pop();
+
             self.pop_mod();
         }
     }
@@ -251,8 +541,10 @@ impl <'a> CodeGenerator<'a> {
         let ty = self.resolve_type(&field);
 
         let boxed = !repeated
-                 && field.type_().unwrap() == field_descriptor_proto::Type::TypeMessage
+                 && (field.type_().unwrap() == field_descriptor_proto::Type::TypeMessage ||
+                     field.type_().unwrap() == field_descriptor_proto::Type::TypeGroup)
                  && self.message_graph.is_nested(field.type_name(), msg_name);
+        let lazy = self.lazy(msg_name, &field);
 
         debug!("\t\tfield: {:?}, type: {:?}", field.name(), ty);
 
@@ -278,7 +570,9 @@ impl <'a> CodeGenerator<'a> {
         if boxed { self.buf.push_str(", boxed"); }
         self.buf.push_str(", tag=\"");
         self.buf.push_str(&field.number().to_string());
-        self.buf.push_str("\")]\n");
+        self.buf.push_str("\"");
+        self.buf.push_str(&name_attr(field.name(), &camel_to_snake(field.name())));
+        self.buf.push_str(")]\n");
         self.push_indent();
         self.buf.push_str("pub ");
         self.buf.push_str(&camel_to_snake(field.name()));
@@ -286,13 +580,189 @@ impl <'a> CodeGenerator<'a> {
         if repeated { self.buf.push_str("Vec<"); }
         else if optional { self.buf.push_str("Option<"); }
         if boxed { self.buf.push_str("Box<"); }
+        if lazy { self.buf.push_str("::prost::Lazy<"); }
         self.buf.push_str(&ty);
+        if lazy { self.buf.push_str(">"); }
         if boxed { self.buf.push_str(">"); }
         if repeated || optional { self.buf.push_str(">"); }
         self.buf.push_str(",\n");
     }
 
+    /// Appends an implementation of `prost::Name` for the message.
+    fn append_name(&mut self, message_name: &str, fq_message_name: &str) {
+        let name = &fq_message_name[self.file_package.len() + 2..];
+        self.push_indent();
+        self.buf.push_str(&format!("impl ::prost::Name for {} {{\n", message_name));
+        self.depth += 1;
+        self.push_indent();
+        self.buf.push_str(&format!("const NAME: &'static str = {:?};\n", name));
+        self.push_indent();
+        self.buf.push_str(&format!("const PACKAGE: &'static str = {:?};\n", self.file_package));
+        self.depth -= 1;
+        self.push_indent();
+        self.buf.push_str("}\n");
+    }
+
+    fn append_reflect_message(&mut self,
+                              message_name: &str,
+                              fq_message_name: &str,
+                              fields: &[(FieldDescriptorProto, usize)],
+                              map_types: &HashMap<String, (FieldDescriptorProto, FieldDescriptorProto)>,
+                              oneofs: &[OneofDescriptorProto],
+                              oneof_fields: &MultiMap<i32, (FieldDescriptorProto, usize)>) {
+        use field_descriptor_proto::Label::*;
+
+        let mut arms = Vec::new();
+        for &(ref field, _) in fields {
+            let ident = camel_to_snake(field.name());
+            let value = match field.type_name.as_ref().and_then(|type_name| map_types.get(type_name)) {
+                Some(&(ref key, ref value)) => {
+                    format!("Some(Value::Map(self.{}.iter().map(|(k, v)| ({}, {})).collect()))",
+                            ident, self.reflect_value(key, "k"), self.reflect_value(value, "v"))
+                },
+                None if field.label() == Some(LabelRepeated) => {
+                    format!("Some(Value::List(self.{}.iter().map(|v| {}).collect()))",
+                            ident, self.reflect_field_value(fq_message_name, field, "v"))
+                },
+                None => {
+                    let boxed = (field.type_() == Some(field_descriptor_proto::Type::TypeMessage) ||
+                                 field.type_() == Some(field_descriptor_proto::Type::TypeGroup))
+                             && self.message_graph.is_nested(field.type_name(), fq_message_name);
+                    if self.optional(field) {
+                        let v = if boxed { "&**v" } else { "v" };
+                        format!("self.{}.as_ref().map(|v| {})",
+                                ident, self.reflect_field_value(fq_message_name, field, v))
+                    } else {
+                        let v = format!("{}self.{}", if boxed { "&*" } else { "&" }, ident);
+                        format!("Some({})", self.reflect_field_value(fq_message_name, field, &v))
+                    }
+                },
+            };
+            arms.push((field.number(), value));
+        }
+        for (idx, oneof) in oneofs.iter().enumerate() {
+            for &(ref field, _) in oneof_fields.get_vec(&(idx as i32)).unwrap() {
+                let value = format!("match self.{} {{ Some({}::{}::{}(ref v)) => Some({}), _ => None }}",
+                                    camel_to_snake(oneof.name()),
+                                    camel_to_snake(message_name),
+                                    snake_to_upper_camel(oneof.name()),
+                                    snake_to_upper_camel(field.name()),
+                                    self.reflect_value(field, "v"));
+                arms.push((field.number(), value));
+            }
+        }
+        arms.sort_by_key(|&(number, _)| number);
+
+        let descriptor_set = format!("{}FILE_DESCRIPTOR_SET", "super::".repeat(self.depth as usize));
+        let full_name = fq_message_name.trim_left_matches('.');
+
+        self.push_indent();
+        self.buf.push_str(&format!("impl ::prost_reflect::reflect::ReflectMessage for {} {{\n", message_name));
+        self.depth += 1;
+        self.push_indent();
+        self.buf.push_str("fn descriptor(&self) -> &'static ::prost_reflect::google::protobuf::DescriptorProto {\n");
+        self.depth += 1;
+        self.push_indent();
+        self.buf.push_str(&format!("::prost_reflect::reflect::message_descriptor({}, {:?})\n",
+                                   descriptor_set, full_name));
+        self.depth -= 1;
+        self.push_indent();
+        self.buf.push_str("}\n");
+        self.push_indent();
+        self.buf.push_str(&format!("fn full_name(&self) -> &'static str {{ {:?} }}\n", full_name));
+        self.push_indent();
+        self.buf.push_str("fn field(&self, number: i32) -> Option<::prost_reflect::reflect::Value> {\n");
+        self.depth += 1;
+        self.push_indent();
+        self.buf.push_str("use ::prost_reflect::reflect::Value;\n");
+        self.push_indent();
+        self.buf.push_str("match number {\n");
+        self.depth += 1;
+        for (number, value) in arms {
+            self.push_indent();
+            self.buf.push_str(&format!("{} => {},\n", number, value));
+        }
+        self.push_indent();
+        self.buf.push_str("_ => None,\n");
+        self.depth -= 1;
+        self.push_indent();
+        self.buf.push_str("}\n");
+        self.depth -= 1;
+        self.push_indent();
+        self.buf.push_str("}\n");
+        self.depth -= 1;
+        self.push_indent();
+        self.buf.push_str("}\n");
+
+        if self.config.json {
+            self.push_indent();
+            self.buf.push_str(&format!("impl ::prost_reflect::json::JsonMessage for {} {{\n", message_name));
+            self.depth += 1;
+            self.push_indent();
+            self.buf.push_str("fn descriptor_pool() -> ::std::sync::Arc<::prost_reflect::dynamic::DescriptorPool> {\n");
+            self.depth += 1;
+            self.push_indent();
+            self.buf.push_str(&format!("::prost_reflect::reflect::descriptor_pool({})\n", descriptor_set));
+            self.depth -= 1;
+            self.push_indent();
+            self.buf.push_str("}\n");
+            self.depth -= 1;
+            self.push_indent();
+            self.buf.push_str("}\n");
+        }
+    }
+
+    /// Returns an expression which converts `value`, a reference to the Rust
+    /// value of a field of the message which is not a map or oneof field, to
+    /// a `reflect::Value`. `Lazy` fields are reflected in their encoded form,
+    /// since `ReflectMessage` borrows the message immutably and can not
+    /// decode them.
+    fn reflect_field_value(&self, fq_message_name: &str, field: &FieldDescriptorProto, value: &str) -> String {
+        if self.lazy(fq_message_name, field) {
+            return format!("Value::EncodedMessage(::prost::Message::encode_to_vec({}))", value);
+        }
+        self.reflect_value(field, value)
+    }
+
+    /// Returns an expression which converts `value`, a reference to the Rust
+    /// value of the field, to a `reflect::Value`.
+    fn reflect_value(&self, field: &FieldDescriptorProto, value: &str) -> String {
+        use field_descriptor_proto::Type::*;
+        let variant = match field.type_().expect("unknown field type") {
+            TypeFloat => "F32",
+            TypeDouble => "F64",
+            TypeInt32 | TypeSint32 | TypeSfixed32 => "I32",
+            TypeInt64 | TypeSint64 | TypeSfixed64 => "I64",
+            TypeUint32 | TypeFixed32 => "U32",
+            TypeUint64 | TypeFixed64 => "U64",
+            TypeBool => "Bool",
+            TypeEnum => "Enum",
+            TypeString => return format!("Value::String({})", value),
+            TypeBytes => return format!("Value::Bytes({})", value),
+            TypeMessage | TypeGroup if self.is_reflected(field.type_name()) => {
+                return format!("Value::Message({})", value);
+            },
+            TypeMessage | TypeGroup => {
+                return format!("Value::EncodedMessage(::prost::Message::encode_to_vec({}))", value);
+            },
+        };
+        format!("Value::{}(*{})", variant, value)
+    }
+
+    /// Returns `true` if the message type implements `ReflectMessage`: it is
+    /// generated along with this file, or is a well-known type from
+    /// `prost-types`. Other message types, for instance types generated
+    /// separately without the `reflection` option, may not.
+    fn is_reflected(&self, pb_ident: &str) -> bool {
+        if !self.config.compile_well_known_types && pb_ident.starts_with(".google.protobuf.") &&
+           WELL_KNOWN_TYPES.contains(&&pb_ident[".google.protobuf.".len()..]) {
+            return true;
+        }
+        self.message_graph.contains(pb_ident)
+    }
+
     fn append_map_field(&mut self,
+                        msg_name: &str,
                         field: FieldDescriptorProto,
                         key: &FieldDescriptorProto,
                         value: &FieldDescriptorProto) {
@@ -305,15 +775,23 @@ impl <'a> CodeGenerator<'a> {
         self.append_doc();
         self.push_indent();
 
+        let (annotation, map_ty) = if self.config.is_btree_map(&format!("{}.{}", msg_name, field.name())) {
+            ("btree_map", "BTreeMap")
+        } else {
+            ("map", "HashMap")
+        };
+
         let key_tag = self.field_type_tag(key);
         let value_tag = self.map_value_type_tag(value);
-        self.buf.push_str(&format!("#[prost(map=\"{}, {}\", tag=\"{}\")]\n",
+        self.buf.push_str(&format!("#[prost({}=\"{}, {}\", tag=\"{}\"{})]\n",
+                                   annotation,
                                    key_tag,
                                    value_tag,
-                                   field.number()));
+                                   field.number(),
+                                   name_attr(field.name(), &camel_to_snake(field.name()))));
         self.push_indent();
-        self.buf.push_str(&format!("pub {}: ::std::collections::HashMap<{}, {}>,\n",
-                                   camel_to_snake(field.name()), key_ty, value_ty));
+        self.buf.push_str(&format!("pub {}: ::std::collections::{}<{}, {}>,\n",
+                                   camel_to_snake(field.name()), map_ty, key_ty, value_ty));
     }
 
     fn append_oneof_field(&mut self,
@@ -358,11 +836,15 @@ impl <'a> CodeGenerator<'a> {
 
             self.push_indent();
             let ty_tag = self.field_type_tag(&field);
-            self.buf.push_str(&format!("#[prost({}, tag=\"{}\")]\n", ty_tag, field.number()));
+            let variant = snake_to_upper_camel(field.name());
+            self.buf.push_str(&format!("#[prost({}, tag=\"{}\"{})]\n",
+                                       ty_tag,
+                                       field.number(),
+                                       name_attr(field.name(), &camel_to_snake(&variant))));
 
             self.push_indent();
             let ty = self.resolve_type(&field);
-            self.buf.push_str(&format!("{}({}),\n", snake_to_upper_camel(field.name()), ty));
+            self.buf.push_str(&format!("{}({}),\n", variant, ty));
         }
         self.depth -= 1;
         self.path.pop();
@@ -371,6 +853,45 @@ impl <'a> CodeGenerator<'a> {
         self.buf.push_str("}\n");
     }
 
+    fn append_extension(&mut self, field: FieldDescriptorProto) {
+        use field_descriptor_proto::Label::*;
+        use field_descriptor_proto::Type::*;
+
+        debug!("\textension: {:?}, extendee: {:?}", field.name(), field.extendee());
+
+        let ty = match field.type_().expect("unknown field type") {
+            TypeGroup => format!("::prost::extension::Group<{}>", self.resolve_ident(field.type_name())),
+            TypeMessage => format!("::prost::extension::Message<{}>", self.resolve_ident(field.type_name())),
+            TypeEnum => "::prost::extension::Enumeration".to_string(),
+            _ => format!("::prost::extension::{}", snake_to_upper_camel(&self.field_type_tag(&field))),
+        };
+        let ty = if field.label() != Some(LabelRepeated) {
+            ty
+        } else if can_pack(&field) && field.options.as_ref().map_or(false, |options| options.packed()) {
+            format!("::prost::extension::Packed<{}>", ty)
+        } else {
+            format!("::prost::extension::Repeated<{}>", ty)
+        };
+        let name = format!("{}.{}", self.package, field.name());
+
+        self.append_doc();
+        self.push_indent();
+        self.buf.push_str(&format!("pub const {}: ::prost::Extension<{}, {}> = ::prost::Extension {{\n",
+                                   camel_to_snake(field.name()).to_uppercase(),
+                                   self.resolve_ident(field.extendee()),
+                                   ty));
+        self.depth += 1;
+        self.push_indent();
+        self.buf.push_str(&format!("name: {:?},\n", name.trim_left_matches('.')));
+        self.push_indent();
+        self.buf.push_str(&format!("tag: {},\n", field.number()));
+        self.push_indent();
+        self.buf.push_str("marker: ::std::marker::PhantomData,\n");
+        self.depth -= 1;
+        self.push_indent();
+        self.buf.push_str("};\n");
+    }
+
     fn location(&self) -> &Location {
         let idx = self.source_info
                       .location
@@ -540,7 +1061,9 @@ impl <'a> CodeGenerator<'a> {
             TypeInt32 | TypeSfixed32 | TypeSint32 => Cow::Borrowed("i32"),
             TypeInt64 | TypeSfixed64 | TypeSint64 => Cow::Borrowed("i64"),
             TypeBool => Cow::Borrowed("bool"),
+            TypeString if self.config.bytes_type => Cow::Borrowed("::prost::BytesString"),
             TypeString => Cow::Borrowed("String"),
+            TypeBytes if self.config.bytes_type => Cow::Borrowed("::bytes::Bytes"),
             TypeBytes => Cow::Borrowed("Vec<u8>"),
             TypeGroup | TypeMessage => Cow::Owned(self.resolve_ident(field.type_name())),
             TypeEnum => Cow::Borrowed("i32"),
@@ -551,6 +1074,13 @@ impl <'a> CodeGenerator<'a> {
         // protoc should always give fully qualified identifiers.
         assert_eq!(".", &pb_ident[..1]);
 
+        if !self.config.compile_well_known_types && pb_ident.starts_with(".google.protobuf.") {
+            let ident_type = &pb_ident[".google.protobuf.".len()..];
+            if WELL_KNOWN_TYPES.contains(&ident_type) {
+                return format!("::prost_types::{}", ident_type);
+            }
+        }
+
         let mut local_path = self.package.split('.').peekable();
 
         let mut ident_path = pb_ident[1..].split('.');
@@ -602,6 +1132,12 @@ impl <'a> CodeGenerator<'a> {
         }
     }
 
+    /// Returns `true` if the field of the message is generated as a `Lazy`.
+    fn lazy(&self, fq_message_name: &str, field: &FieldDescriptorProto) -> bool {
+        field.type_().expect("unknown field type") == field_descriptor_proto::Type::TypeMessage
+            && self.config.is_lazy(&format!("{}.{}", fq_message_name, field.name()))
+    }
+
     fn optional(&self, field: &FieldDescriptorProto) -> bool {
         if field.label().expect("unknown label") != field_descriptor_proto::Label::LabelOptional {
             return false;
@@ -609,7 +1145,7 @@ impl <'a> CodeGenerator<'a> {
 
         use field_descriptor_proto::Type::*;
         match field.type_().expect("unknown field type") {
-            TypeMessage => true,
+            TypeMessage | TypeGroup => true,
             _ => self.syntax == Syntax::Proto2,
         }
     }
@@ -667,6 +1203,17 @@ fn camel_to_snake(camel: &str) -> String {
     ident
 }
 
+/// Returns the `name` attribute for a field, which is only needed if the
+/// derived name of the field, based on its Rust identifier, differs from its
+/// Protobuf name.
+fn name_attr(name: &str, derived_name: &str) -> String {
+    if name == derived_name {
+        String::new()
+    } else {
+        format!(", name={:?}", name)
+    }
+}
+
 fn snake_to_upper_camel(snake: &str) -> String {
     let mut s = String::with_capacity(snake.len());
 
@@ -700,6 +1247,7 @@ fn to_lowercase(c: u8) -> u8 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use google::protobuf::descriptor_proto;
 
     #[test]
     fn test_camel_to_snake() {
@@ -752,4 +1300,151 @@ mod tests {
         assert_eq!("_FooBar_", &snake_to_upper_camel("_FOO_BAR_"));
         assert_eq!("Fuzzbuster", &snake_to_upper_camel("fuzzBuster"));
     }
+    #[test]
+    fn test_is_btree_map() {
+        let mut config = Config::new();
+        assert!(!config.is_btree_map(".foo.Bar.baz"));
+
+        config.btree_map(&[".foo.Bar", ".qux.Quux.corge"]);
+        assert!(config.is_btree_map(".foo.Bar.baz"));
+        assert!(config.is_btree_map(".foo.Bar.Nested.baz"));
+        assert!(!config.is_btree_map(".foo.Barn.baz"));
+        assert!(config.is_btree_map(".qux.Quux.corge"));
+        assert!(!config.is_btree_map(".qux.Quux.corge_grault"));
+
+        config.btree_map(&["."]);
+        assert!(config.is_btree_map(".foo.Barn.baz"));
+    }
+
+    /// Returns a file in package `test` containing the message, with a source
+    /// location for the message and each of its fields.
+    fn test_file(syntax: &str, message: DescriptorProto) -> FileDescriptorProto {
+        let mut source_info = SourceCodeInfo::default();
+        let mut location = Location::default();
+        location.path = vec![4, 0];
+        source_info.location.push(location);
+        for idx in 0..message.field.len() {
+            let mut location = Location::default();
+            location.path = vec![4, 0, 2, idx as i32];
+            source_info.location.push(location);
+        }
+
+        let mut file = FileDescriptorProto::default();
+        file.name = Some("test.proto".to_string());
+        file.package = Some("test".to_string());
+        file.syntax = Some(syntax.to_string());
+        file.message_type.push(message);
+        file.source_code_info = Some(source_info);
+        file
+    }
+
+    fn generate_test_file(file: FileDescriptorProto, config: &Config) -> String {
+        generate(vec![file], config, None).remove(&vec!["test".to_string()]).unwrap()
+    }
+
+    /// Returns a proto3 file with a message which has a `string` and a `bytes`
+    /// field.
+    fn blob_file() -> FileDescriptorProto {
+        let field = |name: &str, number: i32, ty: field_descriptor_proto::Type| {
+            let mut field = FieldDescriptorProto::default();
+            field.name = Some(name.to_string());
+            field.number = Some(number);
+            field.set_label(field_descriptor_proto::Label::LabelOptional);
+            field.set_type_(ty);
+            field
+        };
+        let mut message = DescriptorProto::default();
+        message.name = Some("Blob".to_string());
+        message.field.push(field("name", 1, field_descriptor_proto::Type::TypeString));
+        message.field.push(field("data", 2, field_descriptor_proto::Type::TypeBytes));
+        test_file("proto3", message)
+    }
+
+    #[test]
+    fn test_bytes_type() {
+        let generated = generate_test_file(blob_file(), &Config::new());
+        assert!(generated.contains("pub name: String,"));
+        assert!(generated.contains("pub data: Vec<u8>,"));
+
+        let mut config = Config::new();
+        config.bytes_type(true);
+        let generated = generate_test_file(blob_file(), &config);
+        assert!(generated.contains("pub name: ::prost::BytesString,"));
+        assert!(generated.contains("pub data: ::bytes::Bytes,"));
+    }
+
+    #[test]
+    fn test_extension_set() {
+        // The `*Options` messages in the bootstrapped `google::protobuf`
+        // module rely on this member being generated.
+        let mut message = DescriptorProto::default();
+        message.name = Some("Options".to_string());
+        for &(start, end) in &[(100, 200), (1000, 536870912)] {
+            let mut range = descriptor_proto::ExtensionRange::default();
+            range.start = Some(start);
+            range.end = Some(end);
+            message.extension_range.push(range);
+        }
+        let generated = generate_test_file(test_file("proto2", message), &Config::new());
+        assert!(generated.contains("#[prost(extension_set=\"100..200, 1000..536870912\")]\n    \
+                                    pub extensions: ::prost::ExtensionSet,\n"));
+    }
+
+    #[test]
+    fn test_reflect_message_fields() {
+        let field = |name: &str, number: i32, type_name: &str| {
+            let mut field = FieldDescriptorProto::default();
+            field.name = Some(name.to_string());
+            field.number = Some(number);
+            field.set_label(field_descriptor_proto::Label::LabelOptional);
+            field.set_type_(field_descriptor_proto::Type::TypeMessage);
+            field.type_name = Some(type_name.to_string());
+            field
+        };
+        let mut message = DescriptorProto::default();
+        message.name = Some("Node".to_string());
+        message.field.push(field("child", 1, ".test.Node"));
+        message.field.push(field("timestamp", 2, ".google.protobuf.Timestamp"));
+        message.field.push(field("other", 3, ".other.Other"));
+
+        let mut config = Config::new();
+        config.reflection(true);
+        let generated = generate_test_file(test_file("proto3", message), &config);
+        assert!(generated.contains("impl ::prost_reflect::reflect::ReflectMessage for Node {"));
+        assert!(generated.contains("1 => self.child.as_ref().map(|v| Value::Message(&**v)),"));
+        assert!(generated.contains("2 => self.timestamp.as_ref().map(|v| Value::Message(v)),"));
+        // `.other.Other` is not generated along with `Node`, so it may not
+        // implement `ReflectMessage`.
+        assert!(generated.contains("3 => self.other.as_ref().map(|v| \
+                                    Value::EncodedMessage(::prost::Message::encode_to_vec(v))),"));
+    }
+
+    #[test]
+    fn test_is_lazy() {
+        let mut config = Config::new();
+        config.lazy(&[".foo.Bar.baz"]);
+        assert!(config.is_lazy(".foo.Bar.baz"));
+        assert!(!config.is_lazy(".foo.Bar.bazz"));
+    }
+
+    #[test]
+    fn test_lazy_reflection() {
+        let mut field = FieldDescriptorProto::default();
+        field.name = Some("child".to_string());
+        field.number = Some(1);
+        field.set_label(field_descriptor_proto::Label::LabelOptional);
+        field.set_type_(field_descriptor_proto::Type::TypeMessage);
+        field.type_name = Some(".test.Node".to_string());
+        let mut message = DescriptorProto::default();
+        message.name = Some("Node".to_string());
+        message.field.push(field);
+
+        let mut config = Config::new();
+        config.lazy(&[".test.Node.child"]).reflection(true);
+        let generated = generate_test_file(test_file("proto3", message), &config);
+        assert!(generated.contains("pub child: Option<Box<::prost::Lazy<Node>>>,"));
+        // Lazy fields are reflected without being decoded.
+        assert!(generated.contains("1 => self.child.as_ref().map(|v| \
+                                    Value::EncodedMessage(::prost::Message::encode_to_vec(&**v))),"));
+    }
 }
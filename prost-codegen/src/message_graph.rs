@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use petgraph::algo::has_path_connecting;
 use petgraph::graph::NodeIndex;
@@ -16,6 +16,8 @@ use google::protobuf::{
 pub struct MessageGraph {
     index: HashMap<String, NodeIndex>,
     graph: Graph<String, ()>,
+    /// The fully-qualified names of the message types defined by the files.
+    messages: HashSet<String>,
 }
 
 impl MessageGraph {
@@ -23,6 +25,7 @@ impl MessageGraph {
         let mut msg_graph = MessageGraph {
             index: HashMap::new(),
             graph: Graph::new(),
+            messages: HashSet::new(),
         };
 
         for file in files {
@@ -36,7 +39,7 @@ impl MessageGraph {
     }
 
     fn get_or_insert_index(&mut self, msg_name: String) -> NodeIndex {
-        let MessageGraph { ref mut index, ref mut graph } = *self;
+        let MessageGraph { ref mut index, ref mut graph, .. } = *self;
         assert_eq!(b'.', msg_name.as_bytes()[0]);
         index.entry(msg_name.clone()).or_insert_with(|| {
             graph.add_node(msg_name)
@@ -46,9 +49,12 @@ impl MessageGraph {
     fn add_message(&mut self, package: &str, msg: &DescriptorProto) {
         let msg_name = format!("{}.{}", package, msg.name.as_ref().unwrap());
         let msg_index = self.get_or_insert_index(msg_name.clone());
+        self.messages.insert(msg_name.clone());
 
         for field in &msg.field {
-            if field.type_().unwrap() == field_descriptor_proto::Type::TypeMessage {
+            let ty = field.type_().unwrap();
+            if ty == field_descriptor_proto::Type::TypeMessage ||
+               ty == field_descriptor_proto::Type::TypeGroup {
                 let field_index = self.get_or_insert_index(field.type_name.clone().unwrap());
                 self.graph.add_edge(msg_index, field_index, ());
             }
@@ -59,6 +65,11 @@ impl MessageGraph {
         }
     }
 
+    /// Returns true if the message type is defined by one of the files.
+    pub fn contains(&self, message: &str) -> bool {
+        self.messages.contains(message)
+    }
+
     /// Returns true if message type `inner` is nested in message type `outer`.
     pub fn is_nested(&self, outer: &str, inner: &str) -> bool {
         let outer = match self.index.get(outer) {
@@ -0,0 +1,185 @@
+use syn::{
+    Ident,
+    MetaItem,
+};
+use quote::Tokens;
+
+use error::*;
+use field::{
+    word_attr,
+    tag_attr,
+    set_option,
+    set_bool,
+    Label,
+};
+
+/// A proto2 group field.
+pub struct Field {
+    pub label: Label,
+    pub tag: u32,
+    /// Whether the field belongs to a `BorrowedMessage`.
+    pub borrowed: bool,
+}
+
+impl Field {
+    pub fn new(attrs: &[MetaItem]) -> Result<Option<Field>> {
+        let mut group = false;
+        let mut label = None;
+        let mut tag = None;
+        let mut boxed = false;
+
+        let mut unknown_attrs = Vec::new();
+
+        for attr in attrs {
+            if word_attr("group", attr) {
+                set_bool(&mut group, "duplicate group attribute")?;
+            } else if word_attr("boxed", attr) {
+                set_bool(&mut boxed, "duplicate boxed attribute")?;
+            } else if let Some(t) = tag_attr(attr)? {
+                set_option(&mut tag, t, "duplicate tag attributes")?;
+            } else if let Some(l) = Label::from_attr(attr) {
+                set_option(&mut label, l, "duplicate label attributes")?;
+            } else {
+                unknown_attrs.push(attr);
+            }
+        }
+
+        if !group {
+            return Ok(None);
+        }
+
+        match unknown_attrs.len() {
+            0 => (),
+            1 => bail!("unknown attribute for group field: {:?}", unknown_attrs[0]),
+            _ => bail!("unknown attributes for group field: {:?}", unknown_attrs),
+        }
+
+        let tag = match tag {
+            Some(tag) => tag,
+            None => bail!("group field is missing a tag attribute"),
+        };
+
+        Ok(Some(Field {
+            label: label.unwrap_or(Label::Optional),
+            tag: tag,
+            borrowed: false,
+        }))
+    }
+
+    pub fn new_oneof(attrs: &[MetaItem]) -> Result<Option<Field>> {
+        if let Some(mut field) = Field::new(attrs)? {
+            if let Some(attr) = attrs.iter().find(|attr| Label::from_attr(attr).is_some()) {
+                bail!("invalid atribute for oneof field: {}", attr.name());
+            }
+            field.label = Label::Required;
+            Ok(Some(field))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn encode(&self, ident: &Ident) -> Tokens {
+        let module = self.module();
+        let tag = self.tag;
+        match self.label {
+            Label::Optional => quote! {
+                if let Some(ref msg) = #ident {
+                    #module::encode(#tag, msg, buf);
+                }
+            },
+            Label::Required => quote! {
+                #module::encode(#tag, &#ident, buf);
+            },
+            Label::Repeated => quote! {
+                #module::encode_repeated(#tag, &#ident, buf);
+            },
+        }
+    }
+
+    pub fn merge(&self, ident: &Ident) -> Tokens {
+        let module = self.module();
+        let tag = self.tag;
+        match self.label {
+            Label::Optional => quote! {
+                {
+                    if #ident.is_none() {
+                        #ident = Some(Default::default());
+                    }
+                    match #ident {
+                        Some(ref mut msg) => #module::merge(#tag, wire_type, msg, buf, ctx),
+                        _ => unreachable!(),
+                    }
+                }
+            },
+            Label::Required => quote! {
+                #module::merge(#tag, wire_type, &mut #ident, buf, ctx)
+            },
+            Label::Repeated => quote! {
+                #module::merge_repeated(#tag, wire_type, &mut #ident, buf, ctx)
+            },
+        }
+    }
+
+    pub fn encoded_len(&self, ident: &Ident) -> Tokens {
+        let module = self.module();
+        let tag = self.tag;
+        match self.label {
+            Label::Optional => quote! {
+                #ident.as_ref().map_or(0, |msg| #module::encoded_len(#tag, msg))
+            },
+            Label::Required => quote! {
+                #module::encoded_len(#tag, &#ident)
+            },
+            Label::Repeated => quote! {
+                #module::encoded_len_repeated(#tag, &#ident)
+            },
+        }
+    }
+
+    /// Returns a statement which encodes the field, reading the lengths of
+    /// nested messages from the size cache.
+    pub fn encode_cached(&self, ident: &Ident) -> Tokens {
+        let module = self.module();
+        let tag = self.tag;
+        match self.label {
+            Label::Optional => quote! {
+                if let Some(ref msg) = #ident {
+                    #module::encode_cached(#tag, msg, buf, cache);
+                }
+            },
+            Label::Required => quote! {
+                #module::encode_cached(#tag, &#ident, buf, cache);
+            },
+            Label::Repeated => quote! {
+                #module::encode_repeated_cached(#tag, &#ident, buf, cache);
+            },
+        }
+    }
+
+    /// Returns an expression which evaluates to the encoded length of the
+    /// field, recording the lengths of nested messages in the size cache.
+    pub fn encoded_len_cached(&self, ident: &Ident) -> Tokens {
+        let module = self.module();
+        let tag = self.tag;
+        match self.label {
+            Label::Optional => quote! {
+                #ident.as_ref().map_or(0, |msg| #module::encoded_len_cached(#tag, msg, cache))
+            },
+            Label::Required => quote! {
+                #module::encoded_len_cached(#tag, &#ident, cache)
+            },
+            Label::Repeated => quote! {
+                #module::encoded_len_repeated_cached(#tag, &#ident, cache)
+            },
+        }
+    }
+
+    /// Returns the path of the module containing the field's encoding functions.
+    fn module(&self) -> Ident {
+        if self.borrowed {
+            Ident::new("_prost::encoding::borrowed::group")
+        } else {
+            Ident::new("_prost::encoding::group")
+        }
+    }
+}
@@ -14,6 +14,7 @@ use field::{
 };
 
 pub struct Field {
+    pub map_ty: MapTy,
     pub key_ty: scalar::Ty,
     pub value_ty: ValueTy,
     pub tag: u32,
@@ -28,7 +29,7 @@ impl Field {
         for attr in attrs {
             if let Some(t) = tag_attr(attr)? {
                 set_option(&mut tag, t, "duplicate tag attributes")?;
-            } else if attr.name() == "map" {
+            } else if let Some(map_ty) = MapTy::from_str(attr.name()) {
                 let (k, v) = match *attr {
                     MetaItem::NameValue(_, Lit::Str(ref ident, _)) => {
                         let mut items = ident.split(',');
@@ -59,7 +60,7 @@ impl Field {
                     },
                     _ => return Ok(None),
                 };
-                set_option(&mut types, (key_ty_from_str(k)?, ValueTy::from_str(v)?),
+                set_option(&mut types, (map_ty, key_ty_from_str(k)?, ValueTy::from_str(v)?),
                            "duplicate map type attribute")?;
             } else {
                 return Ok(None);
@@ -67,8 +68,9 @@ impl Field {
         }
 
         Ok(match (types, tag) {
-            (Some((key_ty, val_ty)), Some(tag)) => {
+            (Some((map_ty, key_ty, val_ty)), Some(tag)) => {
                 Some(Field {
+                    map_ty: map_ty,
                     key_ty: key_ty,
                     value_ty: val_ty,
                     tag: tag
@@ -84,6 +86,7 @@ impl Field {
 
     /// Returns a statement which encodes the map field.
     pub fn encode(&self, ident: &Ident) -> Tokens {
+        let map = Ident::new(format!("_prost::encoding::{}", self.map_ty.module()));
         let tag = self.tag;
         let ke = Ident::new(format!("_prost::encoding::{}::encode", self.key_ty.encode_as()));
         let kl = Ident::new(format!("_prost::encoding::{}::encoded_len", self.key_ty.encode_as()));
@@ -91,27 +94,27 @@ impl Field {
             ValueTy::Scalar(scalar::Ty::Enumeration(ref ty)) => {
                 let default = Ident::new(format!("{}::default() as i32", ty));
                 quote! {
-                    _prost::encoding::map::encode_with_default(#ke, #kl,
-                                                               _prost::encoding::int32::encode,
-                                                               _prost::encoding::int32::encoded_len,
-                                                               &(#default),
-                                                               #tag, &#ident, buf);
+                    #map::encode_with_default(#ke, #kl,
+                                              _prost::encoding::int32::encode,
+                                              _prost::encoding::int32::encoded_len,
+                                              &(#default),
+                                              #tag, &#ident, buf);
                 }
             },
             ValueTy::Scalar(ref value_ty) => {
                 let ve = Ident::new(format!("_prost::encoding::{}::encode", value_ty.encode_as()));
                 let vl = Ident::new(format!("_prost::encoding::{}::encoded_len", value_ty.encode_as()));
                 quote! {
-                    _prost::encoding::map::encode(#ke, #kl, #ve, #vl,
-                                                  #tag, &#ident, buf);
+                    #map::encode(#ke, #kl, #ve, #vl,
+                                 #tag, &#ident, buf);
                 }
             },
             ValueTy::Message => {
                 quote! {
-                    _prost::encoding::map::encode(#ke, #kl,
-                                                  _prost::encoding::message::encode,
-                                                  _prost::encoding::message::encoded_len,
-                                                  #tag, &#ident, buf);
+                    #map::encode(#ke, #kl,
+                                 _prost::encoding::message::encode,
+                                 _prost::encoding::message::encoded_len,
+                                 #tag, &#ident, buf);
                 }
             },
         }
@@ -120,46 +123,120 @@ impl Field {
     /// Returns an expression which evaluates to the result of merging a decoded key value pair
     /// into the map.
     pub fn merge(&self, ident: &Ident) -> Tokens {
+        let map = Ident::new(format!("_prost::encoding::{}", self.map_ty.module()));
         let km = Ident::new(format!("_prost::encoding::{}::merge", self.key_ty.encode_as()));
         match self.value_ty {
             ValueTy::Scalar(scalar::Ty::Enumeration(ref ty)) => {
                 let default = Ident::new(format!("{}::default() as i32", ty));
                 quote! {
-                    _prost::encoding::map::merge_with_default(#km, _prost::encoding::int32::merge,
-                                                              #default, &mut #ident, buf)
+                    #map::merge_with_default(#km, _prost::encoding::int32::merge,
+                                             #default, &mut #ident, buf, ctx)
                 }
             },
             ValueTy::Scalar(ref value_ty) => {
                 let vm = Ident::new(format!("_prost::encoding::{}::merge", value_ty.encode_as()));
-                quote!(_prost::encoding::map::merge(#km, #vm, &mut #ident, buf))
+                quote!(#map::merge(#km, #vm, &mut #ident, buf, ctx))
             },
             ValueTy::Message => {
-                quote!(_prost::encoding::map::merge(#km, _prost::encoding::message::merge,
-                                                    &mut #ident, buf))
+                quote!(#map::merge(#km, _prost::encoding::message::merge,
+                                   &mut #ident, buf, ctx))
             },
         }
     }
 
     /// Returns an expression which evaluates to the encoded length of the map.
     pub fn encoded_len(&self, ident: &Ident) -> Tokens {
+        let map = Ident::new(format!("_prost::encoding::{}", self.map_ty.module()));
         let tag = self.tag;
         let kl = Ident::new(format!("_prost::encoding::{}::encoded_len", self.key_ty.encode_as()));
         match self.value_ty {
             ValueTy::Scalar(scalar::Ty::Enumeration(ref ty)) => {
                 let default = Ident::new(format!("{}::default() as i32", ty));
                 quote! {
-                    _prost::encoding::map::encoded_len_with_default(
+                    #map::encoded_len_with_default(
                         #kl, _prost::encoding::int32::encoded_len,
                         &(#default), #tag, &#ident)
                 }
             },
             ValueTy::Scalar(ref value_ty) => {
                 let vl = Ident::new(format!("_prost::encoding::{}::encoded_len", value_ty.encode_as()));
-                quote!(_prost::encoding::map::encoded_len(#kl, #vl, #tag, &#ident))
+                quote!(#map::encoded_len(#kl, #vl, #tag, &#ident))
             },
             ValueTy::Message => {
-                quote!(_prost::encoding::map::encoded_len(#kl, _prost::encoding::message::encoded_len,
-                                                          #tag, &#ident))
+                quote!(#map::encoded_len(#kl, _prost::encoding::message::encoded_len,
+                                         #tag, &#ident))
+            },
+        }
+    }
+
+    /// Returns a statement which encodes the map field, reading the lengths of
+    /// the entries from the size cache.
+    pub fn encode_cached(&self, ident: &Ident) -> Tokens {
+        let map = Ident::new(format!("_prost::encoding::{}", self.map_ty.module()));
+        let tag = self.tag;
+        let ke = Ident::new(format!("_prost::encoding::{}::encode", self.key_ty.encode_as()));
+        match self.value_ty {
+            ValueTy::Scalar(scalar::Ty::Enumeration(ref ty)) => {
+                let default = Ident::new(format!("{}::default() as i32", ty));
+                quote! {
+                    #map::encode_with_default_cached(#ke, _prost::encoding::int32::encode,
+                                                     &(#default), #tag, &#ident, buf, cache);
+                }
+            },
+            ValueTy::Scalar(ref value_ty) => {
+                let ve = Ident::new(format!("_prost::encoding::{}::encode", value_ty.encode_as()));
+                quote! {
+                    #map::encode_cached(#ke, #ve, #tag, &#ident, buf, cache);
+                }
+            },
+            ValueTy::Message => {
+                quote! {
+                    #map::encode_message_cached(#ke, #tag, &#ident, buf, cache);
+                }
+            },
+        }
+    }
+
+    /// Returns an expression which evaluates to the encoded length of the map,
+    /// recording the lengths of the entries in the size cache.
+    pub fn encoded_len_cached(&self, ident: &Ident) -> Tokens {
+        let map = Ident::new(format!("_prost::encoding::{}", self.map_ty.module()));
+        let tag = self.tag;
+        let kl = Ident::new(format!("_prost::encoding::{}::encoded_len", self.key_ty.encode_as()));
+        match self.value_ty {
+            ValueTy::Scalar(scalar::Ty::Enumeration(ref ty)) => {
+                let default = Ident::new(format!("{}::default() as i32", ty));
+                quote! {
+                    #map::encoded_len_with_default_cached(
+                        #kl, _prost::encoding::int32::encoded_len,
+                        &(#default), #tag, &#ident, cache)
+                }
+            },
+            ValueTy::Scalar(ref value_ty) => {
+                let vl = Ident::new(format!("_prost::encoding::{}::encoded_len", value_ty.encode_as()));
+                quote!(#map::encoded_len_cached(#kl, #vl, #tag, &#ident, cache))
+            },
+            ValueTy::Message => {
+                quote!(#map::encoded_len_message_cached(#kl, #tag, &#ident, cache))
+            },
+        }
+    }
+
+    /// Returns a statement which merges the map from the message `source`
+    /// into `self`, replacing the entries with the same keys.
+    pub fn merge_from_source(&self, ident: &Ident) -> Tokens {
+        match self.value_ty {
+            ValueTy::Scalar(..) => quote! {
+                self.#ident.extend(source.#ident.iter().map(|(key, value)| {
+                    (_core::clone::Clone::clone(key), _core::clone::Clone::clone(value))
+                }));
+            },
+            ValueTy::Message => quote! {
+                for (key, source) in &source.#ident {
+                    let mut value = _core::default::Default::default();
+                    _prost::field_mask::merge_message(&mut value, source)?;
+                    self.#ident.insert(_core::clone::Clone::clone(key), value);
+                }
             },
         }
     }
@@ -175,10 +252,10 @@ impl Field {
             let take_ref = Ident::new(if self.key_ty.is_numeric() { "&" } else { "" });
 
             Some(quote! {
-                pub fn #get(&self, key: #key_ref_ty) -> ::std::option::Option<#ty> {
+                pub fn #get(&self, key: #key_ref_ty) -> _core::option::Option<#ty> {
                     self.#ident.get(#take_ref key).cloned().and_then(#ty::from_i32)
                 }
-                pub fn #insert(&mut self, key: #key_ty, value: #ty) -> ::std::option::Option<#ty> {
+                pub fn #insert(&mut self, key: #key_ty, value: #ty) -> _core::option::Option<#ty> {
                     self.#ident.insert(key, value as i32).and_then(#ty::from_i32)
                 }
             })
@@ -199,6 +276,31 @@ fn key_ty_from_str(s: &str) -> Result<scalar::Ty> {
     }
 }
 
+/// A map type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapTy {
+    HashMap,
+    BTreeMap,
+}
+
+impl MapTy {
+    fn from_str(s: &str) -> Option<MapTy> {
+        match s {
+            "map" | "hash_map" => Some(MapTy::HashMap),
+            "btree_map" => Some(MapTy::BTreeMap),
+            _ => None,
+        }
+    }
+
+    /// Returns the module of `prost::encoding` which encodes the map type.
+    fn module(&self) -> &'static str {
+        match *self {
+            MapTy::HashMap => "map",
+            MapTy::BTreeMap => "btree_map",
+        }
+    }
+}
+
 /// A map value type.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ValueTy {
@@ -16,6 +16,8 @@ use field::{
 pub struct Field {
     pub label: Label,
     pub tag: u32,
+    /// Whether the field belongs to a `BorrowedMessage`.
+    pub borrowed: bool,
 }
 
 impl Field {
@@ -59,6 +61,7 @@ impl Field {
         Ok(Some(Field {
             label: label.unwrap_or(Label::Optional),
             tag: tag,
+            borrowed: false,
         }))
     }
 
@@ -75,25 +78,27 @@ impl Field {
     }
 
     pub fn encode(&self, ident: &Ident) -> Tokens {
+        let module = self.module();
         let tag = self.tag;
         match self.label {
             Label::Optional => quote! {
                 if let Some(ref msg) = #ident {
-                    _prost::encoding::message::encode(#tag, msg, buf);
+                    #module::encode(#tag, msg, buf);
                 }
             },
             Label::Required => quote! {
-                _prost::encoding::message::encode(#tag, &#ident, buf);
+                #module::encode(#tag, &#ident, buf);
             },
             Label::Repeated => quote! {
                 for msg in &#ident {
-                    _prost::encoding::message::encode(#tag, msg, buf);
+                    #module::encode(#tag, msg, buf);
                 }
             },
         }
     }
 
     pub fn merge(&self, ident: &Ident) -> Tokens {
+        let module = self.module();
         match self.label {
             // TODO(rustlang/rust#39288): Use Option::get_or_insert_with when available:
             // _prost::encoding::message::merge(#ident.get_or_insert_with(Default::default), buf)
@@ -103,32 +108,80 @@ impl Field {
                         #ident = Some(Default::default());
                     }
                     match #ident {
-                        Some(ref mut msg) => _prost::encoding::message::merge(wire_type, msg, buf),
+                        Some(ref mut msg) => #module::merge(wire_type, msg, buf, ctx),
                         _ => unreachable!(),
                     }
                 }
             },
             Label::Required => quote! {
-                _prost::encoding::message::merge(wire_type, &mut #ident, buf)
+                #module::merge(wire_type, &mut #ident, buf, ctx)
             },
             Label::Repeated => quote! {
-                _prost::encoding::message::merge_repeated(wire_type, &mut #ident, buf)
+                #module::merge_repeated(wire_type, &mut #ident, buf, ctx)
             },
         }
     }
 
     pub fn encoded_len(&self, ident: &Ident) -> Tokens {
+        let module = self.module();
         let tag = self.tag;
         match self.label {
             Label::Optional => quote! {
-                #ident.as_ref().map_or(0, |msg| _prost::encoding::message::encoded_len(#tag, msg))
+                #ident.as_ref().map_or(0, |msg| #module::encoded_len(#tag, msg))
             },
             Label::Required => quote! {
-                _prost::encoding::message::encoded_len(#tag, &#ident)
+                #module::encoded_len(#tag, &#ident)
             },
             Label::Repeated => quote! {
-                _prost::encoding::message::encoded_len_repeated(#tag, &#ident)
+                #module::encoded_len_repeated(#tag, &#ident)
             },
         }
     }
+
+    /// Returns a statement which encodes the field, reading the lengths of
+    /// nested messages from the size cache.
+    pub fn encode_cached(&self, ident: &Ident) -> Tokens {
+        let module = self.module();
+        let tag = self.tag;
+        match self.label {
+            Label::Optional => quote! {
+                if let Some(ref msg) = #ident {
+                    #module::encode_cached(#tag, msg, buf, cache);
+                }
+            },
+            Label::Required => quote! {
+                #module::encode_cached(#tag, &#ident, buf, cache);
+            },
+            Label::Repeated => quote! {
+                #module::encode_repeated_cached(#tag, &#ident, buf, cache);
+            },
+        }
+    }
+
+    /// Returns an expression which evaluates to the encoded length of the
+    /// field, recording the lengths of nested messages in the size cache.
+    pub fn encoded_len_cached(&self, ident: &Ident) -> Tokens {
+        let module = self.module();
+        let tag = self.tag;
+        match self.label {
+            Label::Optional => quote! {
+                #ident.as_ref().map_or(0, |msg| #module::encoded_len_cached(#tag, msg, cache))
+            },
+            Label::Required => quote! {
+                #module::encoded_len_cached(#tag, &#ident, cache)
+            },
+            Label::Repeated => quote! {
+                #module::encoded_len_repeated_cached(#tag, &#ident, cache)
+            },
+        }
+    }
+
+    /// Returns the path of the module containing the field's encoding functions.
+    fn module(&self) -> Ident {
+        if self.borrowed {
+            Ident::new("_prost::encoding::borrowed::message")
+        } else {
+            Ident::new("_prost::encoding::message")
+        }
+    }
 }
@@ -1,3 +1,4 @@
+mod group;
 mod map;
 mod message;
 mod oneof;
@@ -22,6 +23,8 @@ pub enum Field {
     Scalar(scalar::Field),
     /// A message field.
     Message(message::Field),
+    /// A group field.
+    Group(group::Field),
     /// A map field.
     Map(map::Field),
     /// A oneof field.
@@ -35,7 +38,7 @@ impl Field {
     /// If the meta items are invalid, an error will be returned.
     /// If the field should be ignored, `None` is returned.
     pub fn new(attrs: Vec<Attribute>) -> Result<Option<Field>> {
-        let attrs = prost_attrs(attrs)?;
+        let attrs = without_name_attr(prost_attrs(attrs)?);
 
         // TODO: check for ignore attribute.
 
@@ -43,6 +46,8 @@ impl Field {
             Field::Scalar(field)
         } else if let Some(field) = message::Field::new(&attrs)? {
             Field::Message(field)
+        } else if let Some(field) = group::Field::new(&attrs)? {
+            Field::Group(field)
         } else if let Some(field) = map::Field::new(&attrs)? {
             Field::Map(field)
         } else if let Some(field) = oneof::Field::new(&attrs)? {
@@ -59,7 +64,7 @@ impl Field {
     /// If the meta items are invalid, an error will be returned.
     /// If the field should be ignored, `None` is returned.
     pub fn new_oneof(attrs: Vec<Attribute>) -> Result<Option<Field>> {
-        let attrs = prost_attrs(attrs)?;
+        let attrs = without_name_attr(prost_attrs(attrs)?);
 
         // TODO: check for ignore attribute.
 
@@ -67,6 +72,8 @@ impl Field {
             Field::Scalar(field)
         } else if let Some(field) = message::Field::new_oneof(&attrs)? {
             Field::Message(field)
+        } else if let Some(field) = group::Field::new_oneof(&attrs)? {
+            Field::Group(field)
         } else if let Some(field) = map::Field::new_oneof(&attrs)? {
             Field::Map(field)
         } else {
@@ -76,10 +83,33 @@ impl Field {
         Ok(Some(field))
     }
 
+    /// Converts the field to a field of a `BorrowedMessage`.
+    ///
+    /// Map and oneof fields are not supported in borrowed messages.
+    pub fn into_borrowed(self) -> Result<Field> {
+        Ok(match self {
+            Field::Scalar(mut scalar) => {
+                scalar.borrowed = true;
+                Field::Scalar(scalar)
+            },
+            Field::Message(mut message) => {
+                message.borrowed = true;
+                Field::Message(message)
+            },
+            Field::Group(mut group) => {
+                group.borrowed = true;
+                Field::Group(group)
+            },
+            Field::Map(..) => bail!("map fields are not supported in borrowed messages"),
+            Field::Oneof(..) => bail!("oneof fields are not supported in borrowed messages"),
+        })
+    }
+
     pub fn tags(&self) -> Vec<u32> {
         match *self {
             Field::Scalar(ref scalar) => vec![scalar.tag],
             Field::Message(ref message) => vec![message.tag],
+            Field::Group(ref group) => vec![group.tag],
             Field::Map(ref map) => vec![map.tag],
             Field::Oneof(ref oneof) => oneof.tags.clone(),
         }
@@ -90,6 +120,7 @@ impl Field {
         match *self {
             Field::Scalar(ref scalar) => scalar.encode(ident),
             Field::Message(ref message) => message.encode(ident),
+            Field::Group(ref group) => group.encode(ident),
             Field::Map(ref map) => map.encode(ident),
             Field::Oneof(ref oneof) => oneof.encode(ident),
         }
@@ -101,6 +132,7 @@ impl Field {
         match *self {
             Field::Scalar(ref scalar) => scalar.merge(ident),
             Field::Message(ref message) => message.merge(ident),
+            Field::Group(ref group) => group.merge(ident),
             Field::Map(ref map) => map.merge(ident),
             Field::Oneof(ref oneof) => oneof.merge(ident),
         }
@@ -112,17 +144,69 @@ impl Field {
             Field::Scalar(ref scalar) => scalar.encoded_len(ident),
             Field::Map(ref map) => map.encoded_len(ident),
             Field::Message(ref msg) => msg.encoded_len(ident),
+            Field::Group(ref group) => group.encoded_len(ident),
             Field::Oneof(ref oneof) => oneof.encoded_len(ident),
         }
     }
 
+    /// Returns a statement which encodes the field, reading the lengths of
+    /// nested messages from the size cache.
+    pub fn encode_cached(&self, ident: &Ident) -> Tokens {
+        match *self {
+            Field::Message(ref message) => message.encode_cached(ident),
+            Field::Group(ref group) => group.encode_cached(ident),
+            Field::Map(ref map) => map.encode_cached(ident),
+            Field::Oneof(ref oneof) => oneof.encode_cached(ident),
+            _ => self.encode(ident),
+        }
+    }
+
+    /// Returns an expression which evaluates to the encoded length of the
+    /// field, recording the lengths of nested messages in the size cache.
+    ///
+    /// Must record lengths in the same order as `encode_cached` reads them.
+    pub fn encoded_len_cached(&self, ident: &Ident) -> Tokens {
+        match *self {
+            Field::Message(ref message) => message.encoded_len_cached(ident),
+            Field::Group(ref group) => group.encoded_len_cached(ident),
+            Field::Map(ref map) => map.encoded_len_cached(ident),
+            Field::Oneof(ref oneof) => oneof.encoded_len_cached(ident),
+            _ => self.encoded_len(ident),
+        }
+    }
+
     pub fn default(&self) -> Tokens {
         match *self {
             Field::Scalar(ref scalar) => scalar.default(),
-            _ => quote!(::std::default::Default::default()),
+            _ => quote!(_core::default::Default::default()),
+        }
+    }
+
+    /// Returns statements which merge the field from the message `source`
+    /// into `self`, for a field selected by a leaf of a field mask. Singular
+    /// scalar fields are copied, repeated and map fields are extended, and
+    /// message fields are merged. Oneof fields are merged by the oneof.
+    pub fn merge_from_source(&self, ident: &Ident) -> Tokens {
+        match *self {
+            Field::Scalar(ref scalar) => scalar.merge_from_source(ident),
+            Field::Message(ref message) => merge_message_from_source(message.label, ident),
+            Field::Group(ref group) => merge_message_from_source(group.label, ident),
+            Field::Map(ref map) => map.merge_from_source(ident),
+            Field::Oneof(..) => unreachable!("oneof fields are merged by the oneof"),
         }
     }
 
+    /// Returns the label of the field if it is a singular message or group
+    /// field, which field mask paths may descend into.
+    pub fn message_label(&self) -> Option<Label> {
+        let label = match *self {
+            Field::Message(ref message) => message.label,
+            Field::Group(ref group) => group.label,
+            _ => return None,
+        };
+        if label == Label::Repeated { None } else { Some(label) }
+    }
+
     pub fn methods(&self, ident: &Ident) -> Option<Tokens> {
         match *self {
             Field::Scalar(ref scalar) => scalar.methods(ident),
@@ -132,6 +216,91 @@ impl Field {
     }
 }
 
+/// Returns statements which merge a message or group field from the message
+/// `source` into `self`. Repeated messages are appended, and messages which
+/// are not yet set are merged into the default message, so that message types
+/// need not implement `Clone`.
+fn merge_message_from_source(label: Label, ident: &Ident) -> Tokens {
+    match label {
+        Label::Optional => quote! {
+            if let _core::option::Option::Some(ref source) = source.#ident {
+                if self.#ident.is_none() {
+                    self.#ident = _core::option::Option::Some(_core::default::Default::default());
+                }
+                if let _core::option::Option::Some(ref mut field) = self.#ident {
+                    _prost::field_mask::merge_message(field, source)?;
+                }
+            }
+        },
+        Label::Required => quote! {
+            _prost::field_mask::merge_message(&mut self.#ident, &source.#ident)?;
+        },
+        Label::Repeated => quote! {
+            for source in &source.#ident {
+                let mut value = _core::default::Default::default();
+                _prost::field_mask::merge_message(&mut value, source)?;
+                self.#ident.push(value);
+            }
+        },
+    }
+}
+
+/// Returns `true` if the field attributes mark the field as the message's
+/// unknown field set (e.g. `#[prost(unknown_fields)]`).
+pub fn unknown_fields_attr(attrs: &[Attribute]) -> Result<bool> {
+    let attrs = prost_attrs(attrs.to_vec())?;
+    if !attrs.iter().any(|attr| word_attr("unknown_fields", attr)) {
+        return Ok(false);
+    }
+    if attrs.len() > 1 {
+        bail!("unknown_fields attribute may not be combined with other attributes");
+    }
+    Ok(true)
+}
+
+/// Returns the extension ranges if the field attributes mark the field as the
+/// message's extension set (e.g. `#[prost(extension_set="100..200, 1000..2000")]`).
+///
+/// Ranges are half-open, as in `DescriptorProto.ExtensionRange`.
+pub fn extension_set_attr(attrs: &[Attribute]) -> Result<Option<Vec<(u32, u32)>>> {
+    let attrs = prost_attrs(attrs.to_vec())?;
+    let ranges = match attrs.iter().find(|attr| attr.name() == "extension_set") {
+        Some(&MetaItem::NameValue(_, Lit::Str(ref ranges, _))) => ranges.clone(),
+        Some(attr) => bail!("invalid extension_set attribute: {:?}", attr),
+        None => return Ok(None),
+    };
+    if attrs.len() > 1 {
+        bail!("extension_set attribute may not be combined with other attributes");
+    }
+
+    let ranges = ranges.split(',').map(|range| {
+        let range = range.trim();
+        let mut bounds = range.splitn(2, "..");
+        match (bounds.next().map(str::parse::<u32>), bounds.next().map(str::parse::<u32>)) {
+            (Some(Ok(start)), Some(Ok(end))) if start < end => Ok((start, end)),
+            _ => bail!("invalid extension range: {}", range),
+        }
+    }).collect::<Result<Vec<_>>>()?;
+    Ok(Some(ranges))
+}
+
+/// Returns the Protobuf name of the field if it is set by the field attributes
+/// (e.g. `#[prost(int32, tag="1", name="FieldName")]`). Field masks refer to
+/// fields by their Protobuf name.
+pub fn name_attr(attrs: &[Attribute]) -> Result<Option<String>> {
+    let attrs = prost_attrs(attrs.to_vec())?;
+    match attrs.iter().find(|attr| attr.name() == "name") {
+        Some(&MetaItem::NameValue(_, Lit::Str(ref name, _))) => Ok(Some(name.clone())),
+        Some(attr) => bail!("invalid name attribute: {:?}", attr),
+        None => Ok(None),
+    }
+}
+
+/// Removes the name attribute, which applies to every type of field.
+fn without_name_attr(attrs: Vec<MetaItem>) -> Vec<MetaItem> {
+    attrs.into_iter().filter(|attr| attr.name() != "name").collect()
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum Label {
     /// An optional field.
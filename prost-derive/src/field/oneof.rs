@@ -82,7 +82,25 @@ impl Field {
     pub fn merge(&self, ident: &Ident) -> Tokens {
         let ty = &self.ty;
         quote! {
-            #ty::merge(&mut #ident, tag, wire_type, buf)
+            #ty::merge(&mut #ident, tag, wire_type, buf, ctx)
+        }
+    }
+
+    /// Returns a statement which encodes the oneof field, reading the lengths
+    /// of nested messages from the size cache.
+    pub fn encode_cached(&self, ident: &Ident) -> Tokens {
+        quote! {
+            if let Some(ref oneof) = #ident {
+                oneof.encode_cached(buf, cache)
+            }
+        }
+    }
+
+    /// Returns an expression which evaluates to the encoded length of the
+    /// oneof field, recording the lengths of nested messages in the size cache.
+    pub fn encoded_len_cached(&self, ident: &Ident) -> Tokens {
+        quote! {
+            #ident.as_ref().map_or(0, |oneof| oneof.encoded_len_cached(cache))
         }
     }
 
@@ -25,6 +25,8 @@ pub struct Field {
     pub ty: Ty,
     pub kind: Kind,
     pub tag: u32,
+    /// Whether the field belongs to a `BorrowedMessage`.
+    pub borrowed: bool,
 }
 
 impl Field {
@@ -97,6 +99,7 @@ impl Field {
             ty: ty,
             kind: kind,
             tag: tag,
+            borrowed: false,
         }))
     }
 
@@ -133,7 +136,7 @@ impl Field {
                 }
             },
             Kind::Optional(..) => quote! {
-                if let ::std::option::Option::Some(ref value) = #ident {
+                if let _core::option::Option::Some(ref value) = #ident {
                     #encode_fn(#tag, value, buf);
                 }
             },
@@ -150,12 +153,18 @@ impl Field {
             Kind::Plain(..) | Kind::Optional(..) | Kind::Required(..) => "",
             Kind::Repeated | Kind::Packed => "_repeated",
         };
-        let merge_fn = Ident::new(format!("_prost::encoding::{}::merge{}",
-                                          self.ty.encode_as(), kind));
+        // Borrowed messages decode string and bytes values as slices of the buffer.
+        let module = if self.borrowed && !self.ty.is_numeric() {
+            "_prost::encoding::borrowed"
+        } else {
+            "_prost::encoding"
+        };
+        let merge_fn = Ident::new(format!("{}::{}::merge{}",
+                                          module, self.ty.encode_as(), kind));
 
         match self.kind {
             Kind::Plain(..) | Kind::Required(..) | Kind::Repeated | Kind::Packed => quote! {
-                #merge_fn(wire_type, &mut #ident, buf)
+                #merge_fn(wire_type, &mut #ident, buf, ctx)
             },
             Kind::Optional(..) => quote! {
                 {
@@ -163,7 +172,7 @@ impl Field {
                         #ident = Some(Default::default());
                     }
                     match #ident {
-                        Some(ref mut value) => #merge_fn(wire_type, value, buf),
+                        Some(ref mut value) => #merge_fn(wire_type, value, buf, ctx),
                         _ => unreachable!(),
                     }
                 }
@@ -203,8 +212,27 @@ impl Field {
     pub fn default(&self) -> Tokens {
         match self.kind {
             Kind::Plain(ref value) | Kind::Required(ref value) => value.owned(),
-            Kind::Optional(_) => quote!(::std::option::Option::None),
-            Kind::Repeated | Kind::Packed => quote!(::std::vec::Vec::new()),
+            Kind::Optional(_) => quote!(_core::option::Option::None),
+            Kind::Repeated | Kind::Packed => quote!(_prost::alloc::vec::Vec::new()),
+        }
+    }
+
+    /// Returns a statement which merges the field from the message `source`
+    /// into `self`. Singular values are copied even if they are the default
+    /// value, and repeated values are appended.
+    pub fn merge_from_source(&self, ident: &Ident) -> Tokens {
+        match self.kind {
+            Kind::Plain(..) | Kind::Required(..) => quote! {
+                self.#ident = _core::clone::Clone::clone(&source.#ident);
+            },
+            Kind::Optional(..) => quote! {
+                if source.#ident.is_some() {
+                    self.#ident = _core::clone::Clone::clone(&source.#ident);
+                }
+            },
+            Kind::Repeated | Kind::Packed => quote! {
+                self.#ident.extend(source.#ident.iter().cloned());
+            },
         }
     }
 
@@ -216,7 +244,7 @@ impl Field {
             Some(match self.kind {
                 Kind::Plain(..) | Kind::Required(..) => {
                     quote! {
-                        pub fn #ident(&self) -> ::std::option::Option<#ty> {
+                        pub fn #ident(&self) -> _core::option::Option<#ty> {
                             #ty::from_i32(self.#ident)
                         }
 
@@ -227,18 +255,18 @@ impl Field {
                 },
                 Kind::Optional(..) => {
                     quote! {
-                        pub fn #ident(&self) -> ::std::option::Option<#ty> {
+                        pub fn #ident(&self) -> _core::option::Option<#ty> {
                             self.#ident.and_then(#ty::from_i32)
                         }
 
                         pub fn #set(&mut self, value: #ty) {
-                            self.#ident = ::std::option::Option::Some(value as i32);
+                            self.#ident = _core::option::Option::Some(value as i32);
                         }
                     }
                 },
                 Kind::Repeated | Kind::Packed => {
                     quote! {
-                        pub fn #ident(&self) -> ::std::iter::FilterMap<::std::iter::Cloned<::std::slice::Iter<i32>>,
+                        pub fn #ident(&self) -> _core::iter::FilterMap<_core::iter::Cloned<_core::slice::Iter<i32>>,
                                                                        fn(i32) -> Option<#ty>> {
                             self.#ident.iter().cloned().filter_map(#ty::from_i32)
                         }
@@ -252,16 +280,16 @@ impl Field {
             let ty = Ident::new(self.ty.rust_ref_type());
 
             let match_some = if self.ty.is_numeric() {
-                quote!(::std::option::Option::Some(val) => val,)
+                quote!(_core::option::Option::Some(val) => val,)
             } else {
-                quote!(::std::option::Option::Some(ref val) => &val[..],)
+                quote!(_core::option::Option::Some(ref val) => &val[..],)
             };
 
             Some(quote! {
                 pub fn #ident(&self) -> #ty {
                     match self.#ident {
                         #match_some
-                        ::std::option::Option::None => #default,
+                        _core::option::Option::None => #default,
                     }
                 }
             })
@@ -391,8 +419,8 @@ impl Ty {
 
     pub fn rust_type(&self) -> &'static str {
         match *self {
-            Ty::String => "::std::string::String",
-            Ty::Bytes => "::std::vec::Vec<u8>",
+            Ty::String => "_prost::alloc::string::String",
+            Ty::Bytes => "_prost::alloc::vec::Vec<u8>",
             _ => self.rust_ref_type(),
         }
     }
@@ -545,12 +573,15 @@ impl DefaultValue {
         DefaultValue::Lit(lit)
     }
 
+    /// Returns an expression which evaluates to an owned instance of the
+    /// default value. String and bytes values are converted with `From`, so
+    /// that fields may be `String` or `BytesString`, and `Vec<u8>` or `Bytes`.
     pub fn owned(&self) -> Tokens {
         match *self {
-            DefaultValue::Lit(Lit::Str(ref value, ..)) if value.is_empty() => quote!(::std::string::String::new()),
-            DefaultValue::Lit(ref lit@Lit::Str(..)) => quote!(#lit.to_owned()),
-            DefaultValue::Lit(Lit::ByteStr(ref value, ..)) if value.is_empty() => quote!(::std::vec::Vec::new()),
-            DefaultValue::Lit(ref lit@Lit::ByteStr(..)) => quote!(#lit.to_owned()),
+            DefaultValue::Lit(Lit::Str(ref value, ..)) if value.is_empty() => quote!(_core::default::Default::default()),
+            DefaultValue::Lit(ref lit@Lit::Str(..)) => quote!(_core::convert::From::from(#lit)),
+            DefaultValue::Lit(Lit::ByteStr(ref value, ..)) if value.is_empty() => quote!(_core::default::Default::default()),
+            DefaultValue::Lit(ref lit@Lit::ByteStr(..)) => quote!(_core::convert::From::from(&#lit[..])),
             DefaultValue::Lit(ref lit) => quote!(#lit),
             DefaultValue::Ident(ref ident) => quote!(#ident),
         }
@@ -560,6 +591,8 @@ impl DefaultValue {
 impl quote::ToTokens for DefaultValue {
     fn to_tokens(&self, tokens: &mut Tokens) {
         match *self {
+            // Byte string literals are arrays, which `Bytes` can not be compared to.
+            DefaultValue::Lit(ref lit@Lit::ByteStr(..)) => quote!(&#lit[..]).to_tokens(tokens),
             DefaultValue::Lit(ref lit) => lit.to_tokens(tokens),
             DefaultValue::Ident(ref ident) => ident.to_tokens(tokens),
         }
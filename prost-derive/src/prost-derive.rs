@@ -11,10 +11,12 @@ extern crate error_chain;
 #[macro_use]
 extern crate quote;
 
+use std::collections::HashMap;
 use std::str;
 
 use itertools::Itertools;
 use proc_macro::TokenStream;
+use quote::Tokens;
 use syn::Ident;
 
 // Proc-macro crates can't export anything, so error chain definitions go in a private module.
@@ -24,17 +26,24 @@ mod error {
 use error::*;
 
 mod field;
-use field::Field;
+use field::{
+    Field,
+    Label,
+};
 
 fn try_message(input: TokenStream) -> Result<TokenStream> {
     let syn::DeriveInput { ident, generics, body, .. } = syn::parse_derive_input(&input.to_string())?;
 
-    if !generics.lifetimes.is_empty() ||
+    if generics.lifetimes.len() > 1 ||
        !generics.ty_params.is_empty() ||
        !generics.where_clause.predicates.is_empty() {
         bail!("Message may not be derived for generic type");
     }
 
+    // A message with a lifetime parameter is a `BorrowedMessage`, which
+    // borrows string and bytes field values from the decoded buffer.
+    let lifetime = generics.lifetimes.into_iter().next().map(|def| def.lifetime);
+
     let fields = match body {
         syn::Body::Struct(syn::VariantData::Struct(fields)) => fields,
         syn::Body::Struct(syn::VariantData::Tuple(fields)) => fields,
@@ -42,11 +51,58 @@ fn try_message(input: TokenStream) -> Result<TokenStream> {
         syn::Body::Enum(..) => bail!("Message can not be derived for an enum"),
     };
 
+    // The unknown field set, if present, is not a tagged field, so it is tracked separately.
+    let mut unknown_fields: Option<Ident> = None;
+    // Likewise the extension set, along with the extension ranges.
+    let mut extension_set: Option<(Ident, Vec<(u32, u32)>)> = None;
+    // The Protobuf names of the fields, by field identifier.
+    let mut names: HashMap<String, String> = HashMap::new();
+
     let mut fields = fields.into_iter()
                            .enumerate()
                            .flat_map(|(idx, field)| {
                                let field_ident = field.ident
                                                        .unwrap_or_else(|| Ident::new(idx.to_string()));
+                               match field::unknown_fields_attr(&field.attrs) {
+                                   Ok(true) if unknown_fields.is_some() => {
+                                       return Some(Err(format!("message {} has duplicate unknown_fields members",
+                                                               ident).into()));
+                                   },
+                                   Ok(true) => {
+                                       unknown_fields = Some(field_ident);
+                                       return None;
+                                   },
+                                   Ok(false) => (),
+                                   Err(err) => return Some(Err(err).chain_err(|| {
+                                       format!("invalid message field {}.{}",
+                                               ident, field_ident)
+                                   })),
+                               }
+                               match field::extension_set_attr(&field.attrs) {
+                                   Ok(Some(..)) if extension_set.is_some() => {
+                                       return Some(Err(format!("message {} has duplicate extension_set members",
+                                                               ident).into()));
+                                   },
+                                   Ok(Some(ranges)) => {
+                                       extension_set = Some((field_ident, ranges));
+                                       return None;
+                                   },
+                                   Ok(None) => (),
+                                   Err(err) => return Some(Err(err).chain_err(|| {
+                                       format!("invalid message field {}.{}",
+                                               ident, field_ident)
+                                   })),
+                               }
+                               match field::name_attr(&field.attrs) {
+                                   Ok(name) => {
+                                       let name = name.unwrap_or_else(|| field_ident.to_string());
+                                       names.insert(field_ident.to_string(), name);
+                                   },
+                                   Err(err) => return Some(Err(err).chain_err(|| {
+                                       format!("invalid message field {}.{}",
+                                               ident, field_ident)
+                                   })),
+                               }
                                match Field::new(field.attrs) {
                                    Ok(Some(field)) => Some(Ok((field_ident, field))),
                                    Ok(None) => None,
@@ -58,6 +114,17 @@ fn try_message(input: TokenStream) -> Result<TokenStream> {
                            })
                            .collect::<Result<Vec<(Ident, Field)>>>()?;
 
+    if lifetime.is_some() {
+        fields = fields.into_iter()
+                       .map(|(field_ident, field)| {
+                           match field.into_borrowed() {
+                               Ok(field) => Ok((field_ident, field)),
+                               Err(err) => bail!("invalid message field {}.{}: {}", ident, field_ident, err),
+                           }
+                       })
+                       .collect::<Result<Vec<(Ident, Field)>>>()?;
+    }
+
     // Sort the fields by tag number so that fields will be encoded in tag order.
     // TODO: This encodes oneof fields in the position of their lowest tag,
     // regardless of the currently occupied variant, is that consequential?
@@ -73,6 +140,15 @@ fn try_message(input: TokenStream) -> Result<TokenStream> {
         bail!("message {} has fields with duplicate tags", ident);
     }
 
+    if let Some((_, ref ranges)) = extension_set {
+        if lifetime.is_some() {
+            bail!("borrowed message {} may not have an extension_set member", ident);
+        }
+        if tags.iter().any(|&tag| ranges.iter().any(|&(start, end)| tag >= start && tag < end)) {
+            bail!("message {} has fields with tags in its extension ranges", ident);
+        }
+    }
+
     let dummy_const = Ident::new(format!("_IMPL_MESSAGE_FOR_{}", ident));
 
     let encoded_len = fields.iter()
@@ -85,10 +161,25 @@ fn try_message(input: TokenStream) -> Result<TokenStream> {
                            field.encode(&Ident::new(format!("self.{}", field_ident)))
                        });
 
+    let encoded_len_cached = fields.iter()
+                                   .map(|&(ref field_ident, ref field)| {
+                                       field.encoded_len_cached(&Ident::new(format!("self.{}", field_ident)))
+                                   });
+
+    let encode_cached = fields.iter()
+                              .map(|&(ref field_ident, ref field)| {
+                                  field.encode_cached(&Ident::new(format!("self.{}", field_ident)))
+                              });
+
     let merge = fields.iter().map(|&(ref field_ident, ref field)| {
         let merge = field.merge(&Ident::new(format!("self.{}", field_ident)));
         let tags = field.tags().into_iter().map(|tag| quote!(#tag)).intersperse(quote!(|));
-        quote!(#(#tags)* => #merge.map_err(|error| map_err(stringify!(#field_ident), error))?,)
+        quote! {
+            #(#tags)* => #merge.map_err(|mut error| {
+                error.push(STRUCT_NAME, stringify!(#field_ident));
+                error
+            })?,
+        }
     });
 
     let default = fields.iter()
@@ -97,19 +188,126 @@ fn try_message(input: TokenStream) -> Result<TokenStream> {
                             quote!(#field_ident: #value,)
                         });
 
+    let (encode_unknown, merge_unknown, encoded_len_unknown, default_unknown) = match unknown_fields {
+        Some(ref field_ident) => {
+            let unknown = Ident::new(format!("self.{}", field_ident));
+            (quote!(#unknown.encode_raw(buf);),
+             quote!(#unknown.merge_field(tag, wire_type, buf, ctx)?),
+             quote!(+ #unknown.encoded_len()),
+             quote!(#field_ident: _core::default::Default::default(),))
+        },
+        None => (quote!(),
+                 quote!(_prost::encoding::skip_field(wire_type, tag, buf, ctx)?),
+                 quote!(),
+                 quote!()),
+    };
+
+    let (encode_extensions, merge_extensions, encoded_len_extensions, default_extensions, extendable) =
+    match extension_set {
+        Some((ref field_ident, ref ranges)) => {
+            let extensions = Ident::new(format!("self.{}", field_ident));
+            let ranges = ranges.iter()
+                               .map(|&(start, end)| quote!((tag >= #start && tag < #end)))
+                               .intersperse(quote!(||));
+            (quote!(#extensions.encode_raw(buf);),
+             quote! {
+                 tag if #(#ranges)* => #extensions.merge_field::<#ident, _>(tag, wire_type, buf, ctx)?,
+             },
+             quote!(+ #extensions.encoded_len()),
+             quote!(#field_ident: _core::default::Default::default(),),
+             quote! {
+                 #[automatically_derived]
+                 impl _prost::ExtendableMessage for #ident {
+                     fn extension_set(&self) -> &_prost::ExtensionSet {
+                         &#extensions
+                     }
+
+                     fn extension_set_mut(&mut self) -> &mut _prost::ExtensionSet {
+                         &mut #extensions
+                     }
+                 }
+             })
+        },
+        None => (quote!(), quote!(), quote!(), quote!(), quote!()),
+    };
+
+    let maskable = match lifetime {
+        Some(..) => quote!(),
+        None => maskable_message(&ident, &fields, &names, unknown_fields.as_ref(),
+                                 extension_set.as_ref().map(|&(ref field_ident, _)| field_ident)),
+    };
+
     let methods = fields.iter()
                         .flat_map(|&(ref field_ident, ref field)| field.methods(field_ident))
                         .collect::<Vec<_>>();
+    let (impl_generics, ty, message_trait, buf_bound) = match lifetime {
+        Some(ref lifetime) => (quote!(<#lifetime>),
+                               quote!(#ident<#lifetime>),
+                               quote!(_prost::BorrowedMessage<#lifetime>),
+                               quote!(_prost::encoding::borrowed::BorrowBuf<#lifetime>)),
+        None => (quote!(), quote!(#ident), quote!(_prost::Message), quote!(_bytes::Buf)),
+    };
+
     let methods = if methods.is_empty() {
         quote!()
     } else {
         quote! {
-            impl #ident {
+            impl #impl_generics #ty {
                 #(#methods)*
             }
         }
     };
 
+    // `BorrowedMessage` has no size cache, so the cached methods are only
+    // generated for owned messages.
+    let cached_methods = match lifetime {
+        Some(..) => quote!(),
+        None => quote! {
+            #[inline]
+            fn encode_raw_cached<B>(&self, buf: &mut B, cache: &mut _prost::encoding::SizeCache)
+            where B: _bytes::BufMut {
+                #(#encode_cached)*
+                #encode_extensions
+                #encode_unknown
+            }
+
+            #[inline]
+            fn encoded_len_cached(&self, cache: &mut _prost::encoding::SizeCache) -> usize {
+                0 #(+ #encoded_len_cached)* #encoded_len_extensions #encoded_len_unknown
+            }
+        },
+    };
+
+    // Allows borrowed messages to be embedded in other borrowed messages.
+    let message_adapter = match lifetime {
+        Some(ref lifetime) => quote! {
+            #[automatically_derived]
+            impl <#lifetime> _prost::encoding::borrowed::MessageAdapter<#lifetime> for #ty {
+                #[inline]
+                fn encode_raw<B>(&self, buf: &mut B) where B: _bytes::BufMut {
+                    _prost::BorrowedMessage::encode_raw(self, buf)
+                }
+
+                #[inline]
+                fn merge_field<B>(&mut self,
+                                  tag: u32,
+                                  wire_type: _prost::encoding::WireType,
+                                  buf: &mut _bytes::Take<B>,
+                                  ctx: _prost::encoding::DecodeContext)
+                                  -> _core::result::Result<(), _prost::DecodeError>
+                where B: #buf_bound {
+                    _prost::BorrowedMessage::merge_field(self, tag, wire_type, buf, ctx)
+                }
+
+                #[inline]
+                fn encoded_len(&self) -> usize {
+                    _prost::BorrowedMessage::encoded_len(self)
+                }
+            }
+        },
+        None => quote!(),
+    };
+
     let expanded = quote! {
         #[allow(
             non_upper_case_globals,
@@ -120,58 +318,189 @@ fn try_message(input: TokenStream) -> Result<TokenStream> {
         )]
         const #dummy_const: () = {
 
+            extern crate core as _core;
             extern crate prost as _prost;
             extern crate bytes as _bytes;
 
             #[automatically_derived]
-            impl _prost::Message for #ident {
+            impl #impl_generics #message_trait for #ty {
                 #[inline]
                 fn encode_raw<B>(&self, buf: &mut B) where B: _bytes::BufMut {
                     #(#encode)*
+                    #encode_extensions
+                    #encode_unknown
                 }
 
                 #[inline]
-                fn merge<B>(&mut self, buf: &mut _bytes::Take<B>) -> ::std::io::Result<()> where B: _bytes::Buf {
-                    fn map_err(field: &str, cause: ::std::io::Error) -> ::std::io::Error {
-                        ::std::io::Error::new(cause.kind(),
-                                              format!(concat!("failed to decode field ",
-                                                              stringify!(#ident),
-                                                              ".{}: {}"),
-                                                      field, cause))
-                    }
-
-                    while _bytes::Buf::has_remaining(buf) {
-                        let (tag, wire_type) = _prost::encoding::decode_key(buf)?;
-                        match tag {
-                            #(#merge)*
-                            _ => _prost::encoding::skip_field(wire_type, buf)?,
-                        }
+                fn merge_field<B>(&mut self,
+                                  tag: u32,
+                                  wire_type: _prost::encoding::WireType,
+                                  buf: &mut _bytes::Take<B>,
+                                  ctx: _prost::encoding::DecodeContext)
+                                  -> _core::result::Result<(), _prost::DecodeError>
+                where B: #buf_bound {
+                    const STRUCT_NAME: &'static str = stringify!(#ident);
+                    match tag {
+                        #(#merge)*
+                        #merge_extensions
+                        _ => #merge_unknown,
                     }
                     Ok(())
                 }
 
                 #[inline]
                 fn encoded_len(&self) -> usize {
-                    0 #(+ #encoded_len)*
+                    0 #(+ #encoded_len)* #encoded_len_extensions #encoded_len_unknown
                 }
+
+                #cached_methods
             }
 
+            #message_adapter
+
+            #extendable
+
+            #maskable
+
             #[automatically_derived]
-            impl Default for #ident {
-                fn default() -> #ident {
+            impl #impl_generics Default for #ty {
+                fn default() -> #ty {
                     #ident {
                         #(#default)*
+                        #default_extensions
+                        #default_unknown
                     }
                 }
             }
-        };
 
-        #methods
+            #methods
+        };
     };
 
     expanded.parse::<TokenStream>().map_err(|err| Error::from(format!("{:?}", err)))
 }
 
+/// Returns the implementation of `MaskableMessage` for an owned message.
+fn maskable_message(ident: &Ident,
+                    fields: &[(Ident, Field)],
+                    names: &HashMap<String, String>,
+                    unknown_fields: Option<&Ident>,
+                    extension_set: Option<&Ident>) -> Tokens {
+    let mut valid_paths = Vec::new();
+    let mut merge = Vec::new();
+    let mut prune = Vec::new();
+    let mut oneofs = Vec::new();
+
+    for &(ref field_ident, ref field) in fields {
+        if let Field::Oneof(ref oneof) = *field {
+            let ty = &oneof.ty;
+            oneofs.push(ty);
+            merge.push(quote!(#ty::merge_masked(&mut self.#field_ident, &source.#field_ident, mask)?;));
+            prune.push(quote!(#ty::prune_masked(&mut self.#field_ident, mask);));
+            continue;
+        }
+
+        let name = &names[field_ident.as_ref()];
+        let default = field.default();
+        let merge_leaf = field.merge_from_source(field_ident);
+
+        match field.message_label() {
+            Some(label) => {
+                let (field_value, merge_nested, prune_nested) = if label == Label::Optional {
+                    (quote!(message.#field_ident.as_ref()),
+                     quote! {
+                         if let _core::option::Option::Some(ref source) = source.#field_ident {
+                             if self.#field_ident.is_none() {
+                                 self.#field_ident = _core::option::Option::Some(_core::default::Default::default());
+                             }
+                             if let _core::option::Option::Some(ref mut field) = self.#field_ident {
+                                 _prost::MaskableMessage::merge_masked(field, source, mask)?;
+                             }
+                         }
+                     },
+                     quote! {
+                         if let _core::option::Option::Some(ref mut field) = self.#field_ident {
+                             _prost::MaskableMessage::prune_masked(field, mask);
+                         }
+                     })
+                } else {
+                    (quote!(_core::option::Option::Some(&message.#field_ident)),
+                     quote!(_prost::MaskableMessage::merge_masked(&mut self.#field_ident, &source.#field_ident, mask)?;),
+                     quote!(_prost::MaskableMessage::prune_masked(&mut self.#field_ident, mask);))
+                };
+                valid_paths.push(quote! {
+                    _core::option::Option::Some((&#name, rest)) => {
+                        rest.is_empty() ||
+                        _prost::field_mask::is_valid_message_path(|message: &#ident| #field_value, rest)
+                    },
+                });
+                merge.push(quote! {
+                    if let _core::option::Option::Some(mask) = mask.get(#name) {
+                        if mask.is_leaf() {
+                            #merge_leaf
+                        } else {
+                            #merge_nested
+                        }
+                    }
+                });
+                prune.push(quote! {
+                    match mask.get(#name) {
+                        _core::option::Option::Some(mask) => if !mask.is_leaf() {
+                            #prune_nested
+                        },
+                        _core::option::Option::None => self.#field_ident = #default,
+                    }
+                });
+            },
+            None => {
+                valid_paths.push(quote! {
+                    _core::option::Option::Some((&#name, rest)) => rest.is_empty(),
+                });
+                merge.push(quote! {
+                    if mask.get(#name).map_or(false, _prost::FieldMaskTree::is_leaf) {
+                        #merge_leaf
+                    }
+                });
+                prune.push(quote! {
+                    if !mask.get(#name).map_or(false, _prost::FieldMaskTree::is_leaf) {
+                        self.#field_ident = #default;
+                    }
+                });
+            },
+        }
+    }
+
+    if let Some(field_ident) = unknown_fields {
+        prune.push(quote!(self.#field_ident = _core::default::Default::default();));
+    }
+    if let Some(field_ident) = extension_set {
+        prune.push(quote!(self.#field_ident = _core::default::Default::default();));
+    }
+
+    quote! {
+        #[automatically_derived]
+        impl _prost::MaskableMessage for #ident {
+            fn is_valid_path(path: &[&str]) -> bool {
+                match path.split_first() {
+                    #(#valid_paths)*
+                    _core::option::Option::Some(..) => false #(|| #oneofs::is_valid_path(path))*,
+                    _core::option::Option::None => false,
+                }
+            }
+
+            fn merge_masked(&mut self, source: &#ident, mask: &_prost::FieldMaskTree)
+                            -> _core::result::Result<(), _prost::DecodeError> {
+                #(#merge)*
+                _core::result::Result::Ok(())
+            }
+
+            fn prune_masked(&mut self, mask: &_prost::FieldMaskTree) {
+                #(#prune)*
+            }
+        }
+    }
+}
+
 #[proc_macro_derive(Message, attributes(prost))]
 pub fn message(input: TokenStream) -> TokenStream {
     try_message(input).unwrap()
@@ -213,7 +542,7 @@ pub fn enumeration(input: TokenStream) -> TokenStream {
 
     let dummy_const = Ident::new(format!("_IMPL_ENUMERATION_FOR_{}", ident));
     let is_valid = variants.iter().map(|&(_, ref value)| quote!(#value => true));
-    let from = variants.iter().map(|&(ref variant, ref value)| quote!(#value => ::std::option::Option::Some(#ident::#variant)));
+    let from = variants.iter().map(|&(ref variant, ref value)| quote!(#value => _core::option::Option::Some(#ident::#variant)));
 
     let is_valid_doc = format!("Returns `true` if `value` is a variant of `{}`.", ident);
     let from_i32_doc = format!("Converts an `i32` to a `{}`, or `None` if `value` is not a valid variant.", ident);
@@ -222,6 +551,7 @@ pub fn enumeration(input: TokenStream) -> TokenStream {
         #[allow(non_upper_case_globals, unused_attributes, unused_qualifications)]
         const #dummy_const: () = {
             extern crate bytes as _bytes;
+            extern crate core as _core;
             extern crate prost as _prost;
 
             #[automatically_derived]
@@ -236,23 +566,23 @@ pub fn enumeration(input: TokenStream) -> TokenStream {
                 }
 
                 #[doc=#from_i32_doc]
-                pub fn from_i32(value: i32) -> ::std::option::Option<#ident> {
+                pub fn from_i32(value: i32) -> _core::option::Option<#ident> {
                     match value {
                         #(#from,)*
-                        _ => ::std::option::Option::None,
+                        _ => _core::option::Option::None,
                     }
                 }
             }
 
             #[automatically_derived]
-            impl ::std::default::Default for #ident {
+            impl _core::default::Default for #ident {
                 fn default() -> #ident {
                     #ident::#default
                 }
             }
 
             #[automatically_derived]
-            impl ::std::convert::From<#ident> for i32 {
+            impl _core::convert::From<#ident> for i32 {
                 fn from(value: #ident) -> i32 {
                     value as i32
                 }
@@ -277,6 +607,9 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream> {
         syn::Body::Struct(..) => panic!("Oneof can not be derived for a struct"),
     };
 
+    // The Protobuf names of the variants, by variant identifier.
+    let mut names: HashMap<String, String> = HashMap::new();
+
     // Map the variants into 'fields'.
     let fields = variants.into_iter().map(|variant| {
         let variant_ident = variant.ident;
@@ -286,6 +619,11 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream> {
                 bail!("invalid oneof variant {}::{}: oneof variants must have a single field",
                       ident, variant_ident);
             }
+            let name = match field::name_attr(&attrs) {
+                Ok(name) => name.unwrap_or_else(|| to_snake_case(variant_ident.as_ref())),
+                Err(err) => bail!("invalid oneof variant {}::{}: {}", ident, variant_ident, err),
+            };
+            names.insert(variant_ident.to_string(), name);
             match Field::new_oneof(attrs) {
                 Ok(Some(field)) => Ok((variant_ident, field)),
                 Ok(None) => bail!("invalid oneof variant {}::{}: oneof variants may not be ignored",
@@ -323,8 +661,8 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream> {
         let merge = field.merge(&Ident::new("value"));
         quote! {
             #tag => {
-                let mut value = ::std::default::Default::default();
-                #merge.map(|_| *field = ::std::option::Option::Some(#ident::#variant_ident(value)))
+                let mut value = _core::default::Default::default();
+                #merge.map(|_| *field = _core::option::Option::Some(#ident::#variant_ident(value)))
             }
         }
     });
@@ -334,6 +672,101 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream> {
         quote!(#ident::#variant_ident(ref value) => #encoded_len)
     });
 
+    let encode_cached = fields.iter().map(|&(ref variant_ident, ref field)| {
+        let encode = field.encode_cached(&Ident::new("*value"));
+        quote!(#ident::#variant_ident(ref value) => { #encode })
+    });
+
+    let encoded_len_cached = fields.iter().map(|&(ref variant_ident, ref field)| {
+        let encoded_len = field.encoded_len_cached(&Ident::new("*value"));
+        quote!(#ident::#variant_ident(ref value) => #encoded_len)
+    });
+
+    let valid_paths = fields.iter().map(|&(ref variant_ident, ref field)| {
+        let name = &names[variant_ident.as_ref()];
+        let nested = match field.message_label() {
+            Some(..) => quote! {
+                || _prost::field_mask::is_valid_message_path(|oneof: &#ident| match *oneof {
+                    #ident::#variant_ident(ref value) => _core::option::Option::Some(value),
+                    _ => _core::option::Option::None,
+                }, rest)
+            },
+            None => quote!(),
+        };
+        quote!(_core::option::Option::Some((&#name, rest)) => rest.is_empty() #nested,)
+    });
+
+    let merge_masked = fields.iter().map(|&(ref variant_ident, ref field)| {
+        let name = &names[variant_ident.as_ref()];
+        let merge_leaf = match field.message_label() {
+            Some(..) => quote! {
+                match *field {
+                    _core::option::Option::Some(#ident::#variant_ident(ref mut field)) => {
+                        _prost::field_mask::merge_message(field, value)?;
+                    },
+                    _ => {
+                        let mut merged = _core::default::Default::default();
+                        _prost::field_mask::merge_message(&mut merged, value)?;
+                        *field = _core::option::Option::Some(#ident::#variant_ident(merged));
+                    },
+                }
+            },
+            None => quote! {
+                *field = _core::option::Option::Some(#ident::#variant_ident(_core::clone::Clone::clone(value)));
+            },
+        };
+        let merge_nested = match field.message_label() {
+            Some(..) => quote! {
+                else {
+                    match *field {
+                        _core::option::Option::Some(#ident::#variant_ident(..)) => (),
+                        _ => {
+                            *field = _core::option::Option::Some(
+                                #ident::#variant_ident(_core::default::Default::default()));
+                        },
+                    }
+                    if let _core::option::Option::Some(#ident::#variant_ident(ref mut field)) = *field {
+                        _prost::MaskableMessage::merge_masked(field, value, mask)?;
+                    }
+                }
+            },
+            None => quote!(),
+        };
+        quote! {
+            _core::option::Option::Some(#ident::#variant_ident(ref value)) => {
+                if let _core::option::Option::Some(mask) = mask.get(#name) {
+                    if mask.is_leaf() {
+                        #merge_leaf
+                    } #merge_nested
+                }
+            },
+        }
+    });
+
+    let prune_masked = fields.iter().map(|&(ref variant_ident, ref field)| {
+        let name = &names[variant_ident.as_ref()];
+        match field.message_label() {
+            Some(..) => quote! {
+                _core::option::Option::Some(#ident::#variant_ident(ref mut value)) => {
+                    match mask.get(#name) {
+                        _core::option::Option::Some(mask) => {
+                            if !mask.is_leaf() {
+                                _prost::MaskableMessage::prune_masked(value, mask);
+                            }
+                            true
+                        },
+                        _core::option::Option::None => false,
+                    }
+                },
+            },
+            None => quote! {
+                _core::option::Option::Some(#ident::#variant_ident(..)) => {
+                    mask.get(#name).map_or(false, _prost::FieldMaskTree::is_leaf)
+                },
+            },
+        }
+    });
+
     let expanded = quote! {
         #[allow(
             non_upper_case_globals,
@@ -344,8 +777,10 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream> {
         )]
         const #dummy_const: () = {
             extern crate bytes as _bytes;
+            extern crate core as _core;
             extern crate prost as _prost;
 
+            #[allow(unreachable_patterns)]
             impl #ident {
                 pub fn encode<B>(&self, buf: &mut B) where B: _bytes::BufMut {
                     match *self {
@@ -353,11 +788,12 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream> {
                     }
                 }
 
-                pub fn merge<B>(field: &mut ::std::option::Option<#ident>,
+                pub fn merge<B>(field: &mut _core::option::Option<#ident>,
                                 tag: u32,
                                 wire_type: _prost::encoding::WireType,
-                                buf: &mut _bytes::Take<B>)
-                                -> ::std::io::Result<()>
+                                buf: &mut _bytes::Take<B>,
+                                ctx: _prost::encoding::DecodeContext)
+                                -> _core::result::Result<(), _prost::DecodeError>
                 where B: _bytes::Buf {
                     match tag {
                         #(#merge,)*
@@ -370,6 +806,53 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream> {
                         #(#encoded_len,)*
                     }
                 }
+
+                pub fn encode_cached<B>(&self, buf: &mut B, cache: &mut _prost::encoding::SizeCache)
+                where B: _bytes::BufMut {
+                    match *self {
+                        #(#encode_cached,)*
+                    }
+                }
+
+                pub fn encoded_len_cached(&self, cache: &mut _prost::encoding::SizeCache) -> usize {
+                    match *self {
+                        #(#encoded_len_cached,)*
+                    }
+                }
+
+                /// Returns `true` if the path, split into field names, names
+                /// a variant of the oneof.
+                pub fn is_valid_path(path: &[&str]) -> bool {
+                    match path.split_first() {
+                        #(#valid_paths)*
+                        _ => false,
+                    }
+                }
+
+                /// Merges the variant of `source` into `field` if it is
+                /// selected by the mask.
+                pub fn merge_masked(field: &mut _core::option::Option<#ident>,
+                                    source: &_core::option::Option<#ident>,
+                                    mask: &_prost::FieldMaskTree)
+                                    -> _core::result::Result<(), _prost::DecodeError> {
+                    match *source {
+                        #(#merge_masked)*
+                        _core::option::Option::None => (),
+                    }
+                    _core::result::Result::Ok(())
+                }
+
+                /// Clears `field` if its variant is not selected by the mask.
+                pub fn prune_masked(field: &mut _core::option::Option<#ident>,
+                                    mask: &_prost::FieldMaskTree) {
+                    let selected = match *field {
+                        #(#prune_masked)*
+                        _core::option::Option::None => true,
+                    };
+                    if !selected {
+                        *field = _core::option::Option::None;
+                    }
+                }
             }
         };
     };
@@ -381,3 +864,23 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream> {
 pub fn oneof(input: TokenStream) -> TokenStream {
     try_oneof(input).unwrap()
 }
+
+/// Converts an upper camel case variant identifier to the snake case field
+/// name it was generated from, e.g. `OneofUint32` to `oneof_uint32`.
+fn to_snake_case(ident: &str) -> String {
+    let mut snake = String::with_capacity(ident.len() + 4);
+    let mut break_on_cap = false;
+    for c in ident.chars() {
+        if c.is_ascii_uppercase() {
+            if break_on_cap {
+                snake.push('_');
+            }
+            snake.push(c.to_ascii_lowercase());
+            break_on_cap = false;
+        } else {
+            snake.push(c);
+            break_on_cap = c != '_';
+        }
+    }
+    snake
+}
@@ -0,0 +1,261 @@
+//! Field-by-field comparison of messages.
+//!
+//! `diff` walks a pair of messages through their descriptors, and returns the
+//! path and values of each field which differs, which is far easier to read
+//! than the `Debug` output of two large messages. Floating point values are
+//! compared bitwise, so identical `NaN`s compare equal, and map fields are
+//! compared by key, regardless of the iteration order of the maps.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use reflect::{
+    ReflectMessage,
+    Value,
+};
+
+/// A field whose value differs between two messages.
+#[derive(Clone, Debug)]
+pub struct Difference<'a> {
+    /// The path of the field, e.g. `a.b[2].c["key"]`, made up of field
+    /// names, list indices and map keys. The path is empty if the messages
+    /// are of different types.
+    pub path: String,
+    /// The value in the left message, or `None` if the field is unset, or the
+    /// list index or map key is not present.
+    pub left: Option<Value<'a>>,
+    /// The value in the right message, or `None` if the field is unset, or the
+    /// list index or map key is not present.
+    pub right: Option<Value<'a>>,
+}
+
+impl <'a> fmt::Display for Difference<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn write_value(f: &mut fmt::Formatter, value: &Option<Value>) -> fmt::Result {
+            match *value {
+                Some(Value::Bool(v)) => write!(f, "{}", v),
+                Some(Value::I32(v)) | Some(Value::Enum(v)) => write!(f, "{}", v),
+                Some(Value::I64(v)) => write!(f, "{}", v),
+                Some(Value::U32(v)) => write!(f, "{}", v),
+                Some(Value::U64(v)) => write!(f, "{}", v),
+                Some(Value::F32(v)) => write!(f, "{:?}", v),
+                Some(Value::F64(v)) => write!(f, "{:?}", v),
+                Some(Value::String(v)) => write!(f, "{:?}", v),
+                Some(Value::Bytes(v)) => write!(f, "{:?}", v),
+                Some(Value::Message(v)) => write!(f, "{:?}", v),
+                Some(Value::EncodedMessage(ref v)) => write!(f, "{:?}", v),
+                Some(Value::List(ref v)) => write!(f, "{:?}", v),
+                Some(Value::Map(ref v)) => write!(f, "{:?}", v),
+                None => f.write_str("<unset>"),
+            }
+        }
+
+        if !self.path.is_empty() {
+            write!(f, "{}: ", self.path)?;
+        }
+        write_value(f, &self.left)?;
+        f.write_str(" != ")?;
+        write_value(f, &self.right)
+    }
+}
+
+/// Returns the fields which differ between the two messages, in field order.
+pub fn diff<'a>(left: &'a ReflectMessage, right: &'a ReflectMessage) -> Vec<Difference<'a>> {
+    let mut differences = Vec::new();
+    diff_message(String::new(), left, right, &mut differences);
+    differences
+}
+
+fn diff_message<'a>(path: String,
+                    left: &'a ReflectMessage,
+                    right: &'a ReflectMessage,
+                    differences: &mut Vec<Difference<'a>>) {
+    if left.full_name() != right.full_name() {
+        differences.push(Difference {
+            path: path,
+            left: Some(Value::Message(left)),
+            right: Some(Value::Message(right)),
+        });
+        return;
+    }
+
+    for field in &left.descriptor().field {
+        let field_path = if path.is_empty() {
+            field.name().to_string()
+        } else {
+            format!("{}.{}", path, field.name())
+        };
+        diff_value(field_path, left.field(field.number()), right.field(field.number()), differences);
+    }
+}
+
+fn diff_value<'a>(path: String,
+                  left: Option<Value<'a>>,
+                  right: Option<Value<'a>>,
+                  differences: &mut Vec<Difference<'a>>) {
+    match (left, right) {
+        (None, None) => (),
+        (Some(Value::Message(left)), Some(Value::Message(right))) => {
+            diff_message(path, left, right, differences);
+        },
+        (Some(Value::List(left)), Some(Value::List(right))) => {
+            let len = left.len().max(right.len());
+            let mut left = left.into_iter();
+            let mut right = right.into_iter();
+            for idx in 0..len {
+                diff_value(format!("{}[{}]", path, idx), left.next(), right.next(), differences);
+            }
+        },
+        (Some(Value::Map(mut left)), Some(Value::Map(mut right))) => {
+            left.sort_by(|a, b| cmp_keys(&a.0, &b.0));
+            right.sort_by(|a, b| cmp_keys(&a.0, &b.0));
+            let mut left = left.into_iter().peekable();
+            let mut right = right.into_iter().peekable();
+            loop {
+                let ordering = match (left.peek(), right.peek()) {
+                    (Some(l), Some(r)) => cmp_keys(&l.0, &r.0),
+                    (Some(..), None) => Ordering::Less,
+                    (None, Some(..)) => Ordering::Greater,
+                    (None, None) => break,
+                };
+                let (key, l, r) = match ordering {
+                    Ordering::Less => {
+                        let (key, l) = left.next().unwrap();
+                        (key, Some(l), None)
+                    },
+                    Ordering::Greater => {
+                        let (key, r) = right.next().unwrap();
+                        (key, None, Some(r))
+                    },
+                    Ordering::Equal => {
+                        let (key, l) = left.next().unwrap();
+                        let (_, r) = right.next().unwrap();
+                        (key, Some(l), Some(r))
+                    },
+                };
+                diff_value(format!("{}[{}]", path, format_key(&key)), l, r, differences);
+            }
+        },
+        (Some(left), Some(right)) => {
+            if !scalar_eq(&left, &right) {
+                differences.push(Difference { path: path, left: Some(left), right: Some(right) });
+            }
+        },
+        (left, right) => differences.push(Difference { path: path, left: left, right: right }),
+    }
+}
+
+/// Compares scalar values. Floating point values are equal if they compare
+/// equal, or have the same bit pattern.
+fn scalar_eq(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (&Value::Bool(a), &Value::Bool(b)) => a == b,
+        (&Value::I32(a), &Value::I32(b)) => a == b,
+        (&Value::I64(a), &Value::I64(b)) => a == b,
+        (&Value::U32(a), &Value::U32(b)) => a == b,
+        (&Value::U64(a), &Value::U64(b)) => a == b,
+        (&Value::F32(a), &Value::F32(b)) => a == b || a.to_bits() == b.to_bits(),
+        (&Value::F64(a), &Value::F64(b)) => a == b || a.to_bits() == b.to_bits(),
+        (&Value::String(a), &Value::String(b)) => a == b,
+        (&Value::Bytes(a), &Value::Bytes(b)) => a == b,
+        (&Value::Enum(a), &Value::Enum(b)) => a == b,
+        (&Value::EncodedMessage(ref a), &Value::EncodedMessage(ref b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Orders map keys, which are always integers, booleans or strings.
+fn cmp_keys(left: &Value, right: &Value) -> Ordering {
+    match (left, right) {
+        (&Value::Bool(a), &Value::Bool(b)) => a.cmp(&b),
+        (&Value::I32(a), &Value::I32(b)) => a.cmp(&b),
+        (&Value::I64(a), &Value::I64(b)) => a.cmp(&b),
+        (&Value::U32(a), &Value::U32(b)) => a.cmp(&b),
+        (&Value::U64(a), &Value::U64(b)) => a.cmp(&b),
+        (&Value::String(a), &Value::String(b)) => a.cmp(b),
+        _ => Ordering::Equal,
+    }
+}
+
+fn format_key(key: &Value) -> String {
+    match *key {
+        Value::Bool(v) => v.to_string(),
+        Value::I32(v) => v.to_string(),
+        Value::I64(v) => v.to_string(),
+        Value::U32(v) => v.to_string(),
+        Value::U64(v) => v.to_string(),
+        Value::String(v) => format!("{:?}", v),
+        ref other => format!("{:?}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::f64;
+
+    use prost_types::{
+        Duration,
+        ListValue,
+        Struct,
+        Timestamp,
+    };
+    use prost_types::value::Kind;
+
+    use super::*;
+
+    fn value(kind: Kind) -> ::prost_types::Value {
+        ::prost_types::Value { kind: Some(kind) }
+    }
+
+    fn to_strings(differences: Vec<Difference>) -> Vec<String> {
+        differences.into_iter().map(|difference| difference.to_string()).collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn test_diff() {
+        let a = Duration { seconds: 1, nanos: 2 };
+        assert!(diff(&a, &a.clone()).is_empty());
+        assert_eq!(vec!["nanos: 2 != 3"], to_strings(diff(&a, &Duration { seconds: 1, nanos: 3 })));
+        assert_eq!(vec!["Duration { seconds: 1, nanos: 2 } != Timestamp { seconds: 1, nanos: 2 }"],
+                   to_strings(diff(&a, &Timestamp { seconds: 1, nanos: 2 })));
+
+        // Floats are compared bitwise.
+        let nan = value(Kind::NumberValue(f64::NAN));
+        assert!(diff(&nan, &nan.clone()).is_empty());
+        assert_eq!(vec!["number_value: NaN != 1.0"],
+                   to_strings(diff(&nan, &value(Kind::NumberValue(1.0)))));
+        assert_eq!(vec!["number_value: 1.0 != <unset>", "bool_value: <unset> != true"],
+                   to_strings(diff(&value(Kind::NumberValue(1.0)), &value(Kind::BoolValue(true)))));
+
+        let list = |values: Vec<::prost_types::Value>| value(Kind::ListValue(ListValue { values: values }));
+        assert_eq!(vec!["list_value.values[1].string_value: \"b\" != \"c\"",
+                        "list_value.values[2]: <unset> != Value { kind: Some(BoolValue(true)) }"],
+                   to_strings(diff(&list(vec![value(Kind::StringValue("a".to_string())),
+                                         value(Kind::StringValue("b".to_string()))]),
+                              &list(vec![value(Kind::StringValue("a".to_string())),
+                                         value(Kind::StringValue("c".to_string())),
+                                         value(Kind::BoolValue(true))]))));
+    }
+
+    #[test]
+    fn test_diff_map() {
+        let mut a = HashMap::new();
+        let mut b = HashMap::new();
+        for i in 0..32 {
+            a.insert(i.to_string(), value(Kind::NumberValue(i as f64)));
+            b.insert(i.to_string(), value(Kind::NumberValue(i as f64)));
+        }
+        a.insert("a".to_string(), value(Kind::NumberValue(1.0)));
+        b.insert("b".to_string(), value(Kind::NumberValue(2.0)));
+        b.insert("5".to_string(), value(Kind::NumberValue(-5.0)));
+
+        let a = Struct { fields: a };
+        let b = Struct { fields: b };
+        assert!(diff(&a, &a.clone()).is_empty());
+        assert_eq!(vec!["fields[\"5\"].number_value: 5.0 != -5.0",
+                        "fields[\"a\"]: Value { kind: Some(NumberValue(1.0)) } != <unset>",
+                        "fields[\"b\"]: <unset> != Value { kind: Some(NumberValue(2.0)) }"],
+                   to_strings(diff(&a, &b)));
+    }
+}
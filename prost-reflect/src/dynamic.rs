@@ -0,0 +1,1467 @@
+//! Messages whose type is only known at runtime, from a `FileDescriptorSet`.
+//!
+//! A `DescriptorPool` indexes the message types in a descriptor set, and a
+//! `DynamicMessage` holds the field values of a message of one of those types.
+//! Dynamic messages are encoded the same way as messages generated by
+//! `prost-codegen`: fields are written in field number order, proto3 scalar
+//! fields with default values are skipped, repeated numeric fields are packed
+//! only if declared with `[packed = true]`, and map entries omit default keys
+//! and values. A message encoded by generated code therefore re-encodes
+//! byte-for-byte identically after being decoded as a `DynamicMessage`.
+
+use std::collections::{
+    btree_map,
+    BTreeMap,
+    HashMap,
+};
+use std::error;
+use std::fmt;
+use std::io::Cursor;
+use std::sync::Arc;
+
+use bytes::{
+    Buf,
+    BufMut,
+    Take,
+};
+use prost::{
+    DecodeError,
+    DecodeErrorKind,
+    EncodeError,
+    UnknownFieldSet,
+};
+use prost::encoding::{
+    self,
+    check_wire_type,
+    decode_key,
+    decode_varint,
+    encode_key,
+    encode_varint,
+    encoded_len_varint,
+    key_len,
+    DecodeContext,
+    SizeCache,
+    WireType,
+};
+
+use google::protobuf::{
+    DescriptorProto,
+    EnumDescriptorProto,
+    FieldDescriptorProto,
+    FileDescriptorSet,
+};
+use google::protobuf::field_descriptor_proto::{
+    Label,
+    Type,
+};
+
+/// An index of the message types, enum types and extensions in a
+/// `FileDescriptorSet`.
+#[derive(Debug)]
+pub struct DescriptorPool {
+    messages: Vec<MessageInfo>,
+    enums: Vec<EnumInfo>,
+    names: HashMap<String, usize>,
+    enum_names: HashMap<String, usize>,
+}
+
+#[derive(Debug)]
+struct MessageInfo {
+    full_name: String,
+    descriptor: DescriptorProto,
+    fields: Vec<FieldInfo>,
+    by_number: HashMap<u32, usize>,
+    by_name: HashMap<String, usize>,
+    extensions_by_name: HashMap<String, usize>,
+}
+
+#[derive(Debug)]
+struct EnumInfo {
+    full_name: String,
+    descriptor: EnumDescriptorProto,
+}
+
+#[derive(Debug)]
+struct FieldInfo {
+    descriptor: FieldDescriptorProto,
+    /// The fully qualified name of an extension field.
+    extension: Option<String>,
+    number: u32,
+    ty: Type,
+    /// The index of the message type of a message, group or map field.
+    message: Option<usize>,
+    /// The index of the enum type of an enum field.
+    enumeration: Option<usize>,
+    /// The field is a map field, in which case `message` is the entry type.
+    map: bool,
+    repeated: bool,
+    packed: bool,
+    /// The field is a proto3 singular scalar field, which is not encoded when
+    /// it holds the default value.
+    skip_default: bool,
+    oneof_index: Option<i32>,
+}
+
+impl DescriptorPool {
+
+    /// Creates a pool containing the message types, enum types and
+    /// extensions in the descriptor set.
+    ///
+    /// An error is returned if a field or extension refers to a type which
+    /// is not in the descriptor set, for instance because the set was
+    /// generated without `protoc --include_imports`.
+    pub fn new(set: FileDescriptorSet) -> Result<DescriptorPool, DescriptorError> {
+        let mut messages = Vec::new();
+        let mut enums = Vec::new();
+        let mut extensions = Vec::new();
+        for file in set.file {
+            let proto3 = file.syntax() == "proto3";
+            let package = file.package().to_string();
+            add_types(&mut messages,
+                      &mut enums,
+                      &mut extensions,
+                      &package,
+                      file.message_type,
+                      file.enum_type,
+                      file.extension,
+                      proto3);
+        }
+
+        let names = messages.iter()
+                            .enumerate()
+                            .map(|(idx, &(ref info, _))| (info.full_name.clone(), idx))
+                            .collect::<HashMap<String, usize>>();
+        let enum_names = enums.iter()
+                              .enumerate()
+                              .map(|(idx, info)| (info.full_name.clone(), idx))
+                              .collect::<HashMap<String, usize>>();
+        let map_entries = messages.iter().map(|&(ref info, _)| {
+            info.descriptor.options.as_ref().and_then(|options| options.map_entry).unwrap_or(false)
+        }).collect::<Vec<bool>>();
+
+        let field_info = |field: &FieldDescriptorProto, proto3: bool, extension: Option<String>| {
+            let ty = match field.type_() {
+                Some(ty) => ty,
+                None => return Err(DescriptorError::UnknownFieldType(field.name().to_string())),
+            };
+            let type_name = field.type_name().trim_left_matches('.');
+            let message = match ty {
+                Type::TypeMessage | Type::TypeGroup => match names.get(type_name) {
+                    Some(&idx) => Some(idx),
+                    None => return Err(DescriptorError::TypeNotFound(type_name.to_string())),
+                },
+                _ => None,
+            };
+            let enumeration = match ty {
+                Type::TypeEnum => match enum_names.get(type_name) {
+                    Some(&idx) => Some(idx),
+                    None => return Err(DescriptorError::TypeNotFound(type_name.to_string())),
+                },
+                _ => None,
+            };
+            let repeated = field.label() == Some(Label::LabelRepeated);
+            Ok(FieldInfo {
+                descriptor: field.clone(),
+                number: field.number() as u32,
+                ty: ty,
+                message: message,
+                enumeration: enumeration,
+                map: repeated && message.map_or(false, |idx| map_entries[idx]),
+                repeated: repeated,
+                packed: repeated && field.options.as_ref().map_or(false, |options| options.packed()),
+                skip_default: proto3
+                           && extension.is_none()
+                           && !repeated
+                           && message.is_none()
+                           && field.oneof_index.is_none(),
+                oneof_index: field.oneof_index,
+                extension: extension,
+            })
+        };
+
+        let mut messages = messages.into_iter().map(|(mut info, proto3)| {
+            info.fields = info.descriptor
+                              .field
+                              .iter()
+                              .map(|field| field_info(field, proto3, None))
+                              .collect::<Result<_, _>>()?;
+            info.by_name = info.fields.iter().enumerate().map(|(idx, field)| {
+                (field.descriptor.name().to_string(), idx)
+            }).collect();
+            Ok(info)
+        }).collect::<Result<Vec<_>, DescriptorError>>()?;
+
+        for (full_name, field) in extensions {
+            let extendee = field.extendee().trim_left_matches('.');
+            let idx = match names.get(extendee) {
+                Some(&idx) => idx,
+                None => return Err(DescriptorError::TypeNotFound(extendee.to_string())),
+            };
+            let field = field_info(&field, false, Some(full_name.clone()))?;
+            let info = &mut messages[idx];
+            info.extensions_by_name.insert(full_name, info.fields.len());
+            info.fields.push(field);
+        }
+
+        for info in &mut messages {
+            info.by_number = info.fields.iter().enumerate().map(|(idx, field)| (field.number, idx)).collect();
+        }
+
+        Ok(DescriptorPool {
+            messages: messages,
+            enums: enums,
+            names: names,
+            enum_names: enum_names,
+        })
+    }
+}
+
+/// An error returned when building a `DescriptorPool` from an invalid
+/// descriptor set.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DescriptorError {
+    /// A field, or the extendee of an extension, refers to a message or enum
+    /// type which is not in the descriptor set.
+    TypeNotFound(String),
+    /// The field with the given name has a missing or unknown type.
+    UnknownFieldType(String),
+}
+
+impl fmt::Display for DescriptorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DescriptorError::TypeNotFound(ref name) => write!(f, "type {} not found", name),
+            DescriptorError::UnknownFieldType(ref name) => write!(f, "unknown type of field {}", name),
+        }
+    }
+}
+
+impl error::Error for DescriptorError {
+    fn description(&self) -> &str {
+        match *self {
+            DescriptorError::TypeNotFound(..) => "type not found",
+            DescriptorError::UnknownFieldType(..) => "unknown field type",
+        }
+    }
+}
+
+/// Adds the message types, enum types and extensions declared in a file or
+/// message scope to the pool being built.
+fn add_types(messages: &mut Vec<(MessageInfo, bool)>,
+             enums: &mut Vec<EnumInfo>,
+             extensions: &mut Vec<(String, FieldDescriptorProto)>,
+             scope: &str,
+             message_types: Vec<DescriptorProto>,
+             enum_types: Vec<EnumDescriptorProto>,
+             extension_fields: Vec<FieldDescriptorProto>,
+             proto3: bool) {
+    let qualify = |name: &str| if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", scope, name)
+    };
+
+    for enum_type in enum_types {
+        enums.push(EnumInfo {
+            full_name: qualify(enum_type.name()),
+            descriptor: enum_type,
+        });
+    }
+    for field in extension_fields {
+        extensions.push((qualify(field.name()), field));
+    }
+    for message in message_types {
+        let full_name = qualify(message.name());
+        add_types(messages,
+                  enums,
+                  extensions,
+                  &full_name,
+                  message.nested_type.clone(),
+                  message.enum_type.clone(),
+                  message.extension.clone(),
+                  proto3);
+        messages.push((MessageInfo {
+            full_name: full_name,
+            descriptor: message,
+            fields: Vec::new(),
+            by_number: HashMap::new(),
+            by_name: HashMap::new(),
+            extensions_by_name: HashMap::new(),
+        }, proto3));
+    }
+}
+
+/// A message type in a `DescriptorPool`.
+#[derive(Clone)]
+pub struct MessageDescriptor {
+    pool: Arc<DescriptorPool>,
+    index: usize,
+}
+
+impl MessageDescriptor {
+
+    /// Returns the message type in the pool with the given fully qualified
+    /// name, which may have a leading period, or `None` if the pool does not
+    /// contain it.
+    pub fn new(pool: Arc<DescriptorPool>, full_name: &str) -> Option<MessageDescriptor> {
+        let index = match pool.names.get(full_name.trim_left_matches('.')) {
+            Some(&index) => index,
+            None => return None,
+        };
+        Some(MessageDescriptor {
+            pool: pool,
+            index: index,
+        })
+    }
+
+    /// Returns the fully qualified name of the message type, without a
+    /// leading period.
+    pub fn full_name(&self) -> &str {
+        &self.info().full_name
+    }
+
+    /// Returns the descriptor of the message type.
+    pub fn descriptor(&self) -> &DescriptorProto {
+        &self.info().descriptor
+    }
+
+    /// Returns the pool containing the message type.
+    pub fn pool(&self) -> &Arc<DescriptorPool> {
+        &self.pool
+    }
+
+    /// Returns the descriptor of the field with the given name. Extension
+    /// fields are not included.
+    pub fn field_by_name(&self, name: &str) -> Option<&FieldDescriptorProto> {
+        self.info().by_name.get(name).map(|&idx| &self.info().fields[idx].descriptor)
+    }
+
+    /// Returns the descriptor of the field or extension with the given number.
+    pub fn field_by_number(&self, number: u32) -> Option<&FieldDescriptorProto> {
+        self.field(number).map(|field| &field.descriptor)
+    }
+
+    /// Returns the descriptor of the extension of this message type with the
+    /// given fully qualified name, which may have a leading period.
+    pub fn extension_by_name(&self, full_name: &str) -> Option<&FieldDescriptorProto> {
+        let info = self.info();
+        info.extensions_by_name
+            .get(full_name.trim_left_matches('.'))
+            .map(|&idx| &info.fields[idx].descriptor)
+    }
+
+    /// Returns the fully qualified name of the field with the given number if
+    /// it is an extension, or `None` if it is not.
+    pub fn extension_name(&self, number: u32) -> Option<&str> {
+        self.field(number).and_then(|field| field.extension.as_ref()).map(|name| &name[..])
+    }
+
+    /// Returns `true` if the field with the given number distinguishes
+    /// between being unset and being set to its default value. This is the
+    /// case for singular proto2 fields, message fields, oneof fields and
+    /// extensions, but not for proto3 scalar fields, or repeated fields.
+    pub fn field_has_presence(&self, number: u32) -> bool {
+        self.field(number).map_or(false, |field| !field.repeated && !field.skip_default)
+    }
+
+    /// Returns `true` if the field with the given number is a map field.
+    pub fn is_map_field(&self, number: u32) -> bool {
+        self.field(number).map_or(false, |field| field.map)
+    }
+
+    /// Returns the message type of the message or group field with the given
+    /// number, or the entry type if it is a map field.
+    pub fn field_message_type(&self, number: u32) -> Option<MessageDescriptor> {
+        self.field(number).and_then(|field| field.message).map(|index| self.message(index))
+    }
+
+    /// Returns the enum type of the enum field with the given number.
+    pub fn field_enum_type(&self, number: u32) -> Option<EnumDescriptor> {
+        self.field(number).and_then(|field| field.enumeration).map(|index| EnumDescriptor {
+            pool: self.pool.clone(),
+            index: index,
+        })
+    }
+
+    /// Returns the default value of the field with the given number. For a
+    /// repeated or map field, this is the default value of a single element.
+    pub fn field_default_value(&self, number: u32) -> Option<Value> {
+        self.field(number).map(|field| self.default_value(field))
+    }
+
+    fn info(&self) -> &MessageInfo {
+        &self.pool.messages[self.index]
+    }
+
+    /// Returns the default value of a singular field.
+    fn default_value(&self, field: &FieldInfo) -> Value {
+        match field.ty {
+            Type::TypeDouble => Value::F64(0.0),
+            Type::TypeFloat => Value::F32(0.0),
+            Type::TypeInt64 | Type::TypeSint64 | Type::TypeSfixed64 => Value::I64(0),
+            Type::TypeUint64 | Type::TypeFixed64 => Value::U64(0),
+            Type::TypeInt32 | Type::TypeSint32 | Type::TypeSfixed32 => Value::I32(0),
+            Type::TypeUint32 | Type::TypeFixed32 => Value::U32(0),
+            Type::TypeBool => Value::Bool(false),
+            Type::TypeString => Value::String(String::new()),
+            Type::TypeBytes => Value::Bytes(Vec::new()),
+            Type::TypeEnum => Value::Enum(0),
+            Type::TypeMessage | Type::TypeGroup => {
+                Value::Message(DynamicMessage::new(self.message(field.message.unwrap())))
+            },
+        }
+    }
+
+    fn field(&self, number: u32) -> Option<&FieldInfo> {
+        let info = self.info();
+        info.by_number.get(&number).map(|&idx| &info.fields[idx])
+    }
+
+    fn message(&self, index: usize) -> MessageDescriptor {
+        MessageDescriptor {
+            pool: self.pool.clone(),
+            index: index,
+        }
+    }
+}
+
+impl PartialEq for MessageDescriptor {
+    fn eq(&self, other: &MessageDescriptor) -> bool {
+        Arc::ptr_eq(&self.pool, &other.pool) && self.index == other.index
+    }
+}
+
+impl fmt::Debug for MessageDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("MessageDescriptor").field(&self.full_name()).finish()
+    }
+}
+
+/// An enum type in a `DescriptorPool`.
+#[derive(Clone)]
+pub struct EnumDescriptor {
+    pool: Arc<DescriptorPool>,
+    index: usize,
+}
+
+impl EnumDescriptor {
+
+    /// Returns the enum type in the pool with the given fully qualified name,
+    /// which may have a leading period, or `None` if the pool does not
+    /// contain it.
+    pub fn new(pool: Arc<DescriptorPool>, full_name: &str) -> Option<EnumDescriptor> {
+        let index = match pool.enum_names.get(full_name.trim_left_matches('.')) {
+            Some(&index) => index,
+            None => return None,
+        };
+        Some(EnumDescriptor {
+            pool: pool,
+            index: index,
+        })
+    }
+
+    /// Returns the fully qualified name of the enum type, without a leading
+    /// period.
+    pub fn full_name(&self) -> &str {
+        &self.pool.enums[self.index].full_name
+    }
+
+    /// Returns the descriptor of the enum type.
+    pub fn descriptor(&self) -> &EnumDescriptorProto {
+        &self.pool.enums[self.index].descriptor
+    }
+
+    /// Returns the name of the first enum value with the given number.
+    pub fn value_name(&self, number: i32) -> Option<&str> {
+        self.descriptor().value.iter().find(|value| value.number() == number).map(|value| value.name())
+    }
+
+    /// Returns the number of the enum value with the given name.
+    pub fn value_number(&self, name: &str) -> Option<i32> {
+        self.descriptor().value.iter().find(|value| value.name() == name).map(|value| value.number())
+    }
+}
+
+impl PartialEq for EnumDescriptor {
+    fn eq(&self, other: &EnumDescriptor) -> bool {
+        Arc::ptr_eq(&self.pool, &other.pool) && self.index == other.index
+    }
+}
+
+impl fmt::Debug for EnumDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("EnumDescriptor").field(&self.full_name()).finish()
+    }
+}
+
+/// The value of a field of a `DynamicMessage`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    I32(i32),
+    I64(i64),
+    U32(u32),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    /// An enum value, which may not be one of the values declared by the
+    /// enum type.
+    Enum(i32),
+    Message(DynamicMessage),
+    /// The values of a repeated field.
+    List(Vec<Value>),
+    /// The entries of a map field, in the order they were decoded or
+    /// inserted. Keys are unique.
+    Map(Vec<(Value, Value)>),
+}
+
+impl Value {
+
+    /// Returns `true` if the value is the default value of its type. Lists
+    /// and maps are default if they are empty, and messages are default if
+    /// their encoding is empty.
+    pub fn is_default(&self) -> bool {
+        match *self {
+            Value::Bool(value) => !value,
+            Value::I32(value) | Value::Enum(value) => value == 0,
+            Value::I64(value) => value == 0,
+            Value::U32(value) => value == 0,
+            Value::U64(value) => value == 0,
+            Value::F32(value) => value == 0.0,
+            Value::F64(value) => value == 0.0,
+            Value::String(ref value) => value.is_empty(),
+            Value::Bytes(ref value) => value.is_empty(),
+            Value::Message(ref value) => value.encoded_len() == 0,
+            Value::List(ref values) => values.is_empty(),
+            Value::Map(ref entries) => entries.is_empty(),
+        }
+    }
+}
+
+/// An error returned when setting a field of a `DynamicMessage`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SetFieldError {
+    /// The message type has no field with the given name.
+    NoSuchField(String),
+    /// The value does not match the type of the field.
+    InvalidValue(String),
+}
+
+impl fmt::Display for SetFieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SetFieldError::NoSuchField(ref name) => write!(f, "no such field: {}", name),
+            SetFieldError::InvalidValue(ref name) => write!(f, "invalid value for field {}", name),
+        }
+    }
+}
+
+impl error::Error for SetFieldError {
+    fn description(&self) -> &str {
+        match *self {
+            SetFieldError::NoSuchField(..) => "no such field",
+            SetFieldError::InvalidValue(..) => "invalid field value",
+        }
+    }
+}
+
+/// A message of a type described by a `MessageDescriptor`.
+///
+/// Fields are held as `Value`s, keyed by field number, and are only modified
+/// through `set`, which checks values against the type of the field. Fields
+/// which are not recognized while decoding are retained, and written back out
+/// when the message is re-encoded.
+#[derive(Clone, PartialEq)]
+pub struct DynamicMessage {
+    descriptor: MessageDescriptor,
+    fields: BTreeMap<u32, Value>,
+    unknown_fields: UnknownFieldSet,
+}
+
+impl DynamicMessage {
+
+    /// Creates an empty message of the given type.
+    pub fn new(descriptor: MessageDescriptor) -> DynamicMessage {
+        DynamicMessage {
+            descriptor: descriptor,
+            fields: BTreeMap::new(),
+            unknown_fields: UnknownFieldSet::new(),
+        }
+    }
+
+    /// Decodes a message of the given type from the buffer.
+    pub fn decode(descriptor: MessageDescriptor, buf: &[u8]) -> Result<DynamicMessage, DecodeError> {
+        let mut message = DynamicMessage::new(descriptor);
+        message.merge(buf)?;
+        Ok(message)
+    }
+
+    /// Decodes a message from the buffer, and merges it into `self`.
+    pub fn merge(&mut self, buf: &[u8]) -> Result<(), DecodeError> {
+        self.merge_with_context(&mut Buf::take(Cursor::new(buf), buf.len()), DecodeContext::default())
+    }
+
+    /// Decodes a message from the buffer with the given decode context, and
+    /// merges it into `self`.
+    pub fn merge_with_context<B>(&mut self, buf: &mut Take<B>, ctx: DecodeContext) -> Result<(), DecodeError>
+    where B: Buf {
+        let mut map_indexes = MapIndexes::new();
+        while buf.has_remaining() {
+            let (tag, wire_type) = decode_key(buf)?;
+            self.merge_field(tag, wire_type, buf, ctx, &mut map_indexes)?;
+        }
+        Ok(())
+    }
+
+    /// Encodes the message, and writes it to the buffer. An error will be
+    /// returned if the buffer does not have sufficient capacity.
+    pub fn encode<B>(&self, buf: &mut B) -> Result<(), EncodeError> where B: BufMut {
+        let mut cache = SizeCache::new();
+        let required = self.encoded_len_cached(&mut cache);
+        let remaining = buf.remaining_mut();
+        if required > remaining {
+            return Err(EncodeError::new(required, remaining));
+        }
+        self.encode_raw_cached(buf, &mut cache);
+        Ok(())
+    }
+
+    /// Encodes the message to a newly allocated buffer.
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        let mut cache = SizeCache::new();
+        let mut buf = Vec::with_capacity(self.encoded_len_cached(&mut cache));
+        self.encode_raw_cached(&mut buf, &mut cache);
+        buf
+    }
+
+    /// Returns the encoded length of the message.
+    pub fn encoded_len(&self) -> usize {
+        self.encoded_len_cached(&mut SizeCache::new())
+    }
+
+    /// Returns the type of the message.
+    pub fn descriptor(&self) -> &MessageDescriptor {
+        &self.descriptor
+    }
+
+    /// Returns the value of the field with the given name, or `None` if the
+    /// field is not set.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.descriptor
+            .field_by_name(name)
+            .and_then(|field| self.fields.get(&(field.number() as u32)))
+    }
+
+    /// Returns the value of the field with the given number, or `None` if
+    /// the field is not set.
+    pub fn get_by_number(&self, number: u32) -> Option<&Value> {
+        self.fields.get(&number)
+    }
+
+    /// Sets the value of the field with the given name, returning the
+    /// previous value. Setting a field of a oneof clears the other fields of
+    /// the oneof.
+    pub fn set(&mut self, name: &str, value: Value) -> Result<Option<Value>, SetFieldError> {
+        let number = match self.descriptor.field_by_name(name) {
+            Some(field) => field.number() as u32,
+            None => return Err(SetFieldError::NoSuchField(name.to_string())),
+        };
+        self.set_by_number(number, value)
+    }
+
+    /// Sets the value of the field or extension with the given number,
+    /// returning the previous value. Setting a field of a oneof clears the
+    /// other fields of the oneof.
+    pub fn set_by_number(&mut self, number: u32, value: Value) -> Result<Option<Value>, SetFieldError> {
+        {
+            let field = match self.descriptor.field(number) {
+                Some(field) => field,
+                None => return Err(SetFieldError::NoSuchField(number.to_string())),
+            };
+            if !self.check_field_value(field, &value) {
+                let name = field.extension.clone().unwrap_or_else(|| field.descriptor.name().to_string());
+                return Err(SetFieldError::InvalidValue(name));
+            }
+        }
+        self.clear_oneof(number);
+        Ok(self.fields.insert(number, value))
+    }
+
+    /// Clears the field with the given name, returning its value.
+    pub fn clear(&mut self, name: &str) -> Option<Value> {
+        let number = match self.descriptor.field_by_name(name) {
+            Some(field) => field.number() as u32,
+            None => return None,
+        };
+        self.fields.remove(&number)
+    }
+
+    /// Clears the field or extension with the given number, returning its
+    /// value.
+    pub fn clear_by_number(&mut self, number: u32) -> Option<Value> {
+        self.fields.remove(&number)
+    }
+
+    /// Returns `true` if the field with the given name is set.
+    pub fn has(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Returns an iterator over the descriptors and values of the fields
+    /// which are set, in field number order.
+    pub fn fields<'a>(&'a self) -> Box<Iterator<Item=(&'a FieldDescriptorProto, &'a Value)> + 'a> {
+        Box::new(self.fields.iter().map(move |(&number, value)| {
+            (self.descriptor.field_by_number(number).unwrap(), value)
+        }))
+    }
+
+    /// Returns the fields which were not recognized while decoding.
+    pub fn unknown_fields(&self) -> &UnknownFieldSet {
+        &self.unknown_fields
+    }
+
+    /// Clears the other fields of the oneof containing the field, if any.
+    fn clear_oneof(&mut self, number: u32) {
+        let info = self.descriptor.info();
+        let oneof_index = match self.descriptor.field(number).and_then(|field| field.oneof_index) {
+            Some(oneof_index) => oneof_index,
+            None => return,
+        };
+        for field in &info.fields {
+            if field.oneof_index == Some(oneof_index) && field.number != number {
+                self.fields.remove(&field.number);
+            }
+        }
+    }
+
+    fn check_field_value(&self, field: &FieldInfo, value: &Value) -> bool {
+        match *value {
+            Value::Map(ref entries) if field.map => {
+                let entry = self.descriptor.message(field.message.unwrap());
+                let key = entry.field(1).unwrap();
+                let val = entry.field(2).unwrap();
+                entries.iter().all(|&(ref k, ref v)| self.check_value(key, k) && self.check_value(val, v))
+            },
+            Value::List(ref values) if field.repeated && !field.map => {
+                values.iter().all(|value| self.check_value(field, value))
+            },
+            _ if field.repeated => false,
+            ref value => self.check_value(field, value),
+        }
+    }
+
+    /// Returns `true` if the value is a valid value of the field's type.
+    fn check_value(&self, field: &FieldInfo, value: &Value) -> bool {
+        match (field.ty, value) {
+            (Type::TypeDouble, &Value::F64(..)) |
+            (Type::TypeFloat, &Value::F32(..)) |
+            (Type::TypeInt64, &Value::I64(..)) |
+            (Type::TypeSint64, &Value::I64(..)) |
+            (Type::TypeSfixed64, &Value::I64(..)) |
+            (Type::TypeUint64, &Value::U64(..)) |
+            (Type::TypeFixed64, &Value::U64(..)) |
+            (Type::TypeInt32, &Value::I32(..)) |
+            (Type::TypeSint32, &Value::I32(..)) |
+            (Type::TypeSfixed32, &Value::I32(..)) |
+            (Type::TypeUint32, &Value::U32(..)) |
+            (Type::TypeFixed32, &Value::U32(..)) |
+            (Type::TypeBool, &Value::Bool(..)) |
+            (Type::TypeString, &Value::String(..)) |
+            (Type::TypeBytes, &Value::Bytes(..)) |
+            (Type::TypeEnum, &Value::Enum(..)) => true,
+            (Type::TypeMessage, &Value::Message(ref message)) |
+            (Type::TypeGroup, &Value::Message(ref message)) => {
+                message.descriptor == self.descriptor.message(field.message.unwrap())
+            },
+            _ => false,
+        }
+    }
+
+    /// Returns the default value of a singular field.
+    fn default_value(&self, field: &FieldInfo) -> Value {
+        self.descriptor.default_value(field)
+    }
+
+    /// Decodes a field, and merges it into the message. Map entries replace
+    /// the entries with the same key, which are found through the indexes of
+    /// the map fields merged so far.
+    fn merge_field<B>(&mut self,
+                      tag: u32,
+                      wire_type: WireType,
+                      buf: &mut Take<B>,
+                      ctx: DecodeContext,
+                      map_indexes: &mut MapIndexes)
+                      -> Result<(), DecodeError> where B: Buf {
+        let descriptor = self.descriptor.clone();
+        let field = match descriptor.field(tag) {
+            Some(field) => field,
+            None => return self.unknown_fields.merge_field(tag, wire_type, buf, ctx),
+        };
+
+        if field.map {
+            let mut entry = DynamicMessage::new(descriptor.message(field.message.unwrap()));
+            merge_message(wire_type, &mut entry, buf, ctx)?;
+            let key = entry.fields.remove(&1).unwrap_or_else(|| entry.default_value(entry.descriptor.field(1).unwrap()));
+            let val = entry.fields.remove(&2).unwrap_or_else(|| entry.default_value(entry.descriptor.field(2).unwrap()));
+            let entries = match *self.fields.entry(tag).or_insert_with(|| Value::Map(Vec::new())) {
+                Value::Map(ref mut entries) => entries,
+                _ => unreachable!(),
+            };
+            let index = map_indexes.entry(tag).or_insert_with(|| {
+                entries.iter()
+                       .enumerate()
+                       .filter_map(|(idx, &(ref k, _))| MapKey::new(k).map(|k| (k, idx)))
+                       .collect()
+            });
+            match MapKey::new(&key) {
+                Some(map_key) => match index.entry(map_key) {
+                    btree_map::Entry::Occupied(idx) => entries[*idx.get()].1 = val,
+                    btree_map::Entry::Vacant(idx) => {
+                        idx.insert(entries.len());
+                        entries.push((key, val));
+                    },
+                },
+                // Keys of other types are not valid map keys, so they are
+                // only found by searching the entries.
+                None => match entries.iter().position(|&(ref k, _)| *k == key) {
+                    Some(idx) => entries[idx].1 = val,
+                    None => entries.push((key, val)),
+                },
+            }
+        } else if field.repeated {
+            let value = match field.message {
+                Some(..) => {
+                    let mut value = self.default_value(field);
+                    self.merge_value(field, wire_type, &mut value, buf, ctx)?;
+                    Some(value)
+                },
+                None => None,
+            };
+            match *self.fields.entry(tag).or_insert_with(|| Value::List(Vec::new())) {
+                Value::List(ref mut values) => match value {
+                    Some(value) => values.push(value),
+                    None => merge_repeated_scalar(field.ty, wire_type, values, buf, ctx)?,
+                },
+                _ => unreachable!(),
+            }
+        } else {
+            self.clear_oneof(tag);
+            let mut value = match self.fields.remove(&tag) {
+                Some(value) => value,
+                None => self.default_value(field),
+            };
+            let result = self.merge_value(field, wire_type, &mut value, buf, ctx);
+            self.fields.insert(tag, value);
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Decodes a singular value of the field's type, and merges it into
+    /// `value`.
+    fn merge_value<B>(&self,
+                      field: &FieldInfo,
+                      wire_type: WireType,
+                      value: &mut Value,
+                      buf: &mut Take<B>,
+                      ctx: DecodeContext)
+                      -> Result<(), DecodeError> where B: Buf {
+        match (field.ty, value) {
+            (Type::TypeMessage, &mut Value::Message(ref mut message)) => {
+                merge_message(wire_type, message, buf, ctx)
+            },
+            (Type::TypeGroup, &mut Value::Message(ref mut message)) => {
+                merge_group(field.number, wire_type, message, buf, ctx)
+            },
+            (ty, value) => {
+                *value = merge_scalar(ty, wire_type, buf, ctx)?;
+                Ok(())
+            },
+        }
+    }
+
+    /// Returns the encoded length of the message, recording the lengths of
+    /// nested messages and map entries in the cache, in the order
+    /// `encode_raw_cached` reads them back.
+    fn encoded_len_cached(&self, cache: &mut SizeCache) -> usize {
+        self.fields.iter().map(|(&number, value)| {
+            let field = self.descriptor.field(number).unwrap();
+            self.field_encoded_len(field, value, cache)
+        }).sum::<usize>() + self.unknown_fields.encoded_len()
+    }
+
+    /// Encodes the message, with the lengths of nested messages and map
+    /// entries read from a cache filled by `encoded_len_cached`.
+    fn encode_raw_cached<B>(&self, buf: &mut B, cache: &mut SizeCache) where B: BufMut {
+        for (&number, value) in &self.fields {
+            let field = self.descriptor.field(number).unwrap();
+            match *value {
+                Value::Map(ref entries) => {
+                    let entry = self.descriptor.message(field.message.unwrap());
+                    let key_field = entry.field(1).unwrap();
+                    let val_field = entry.field(2).unwrap();
+                    for &(ref key, ref val) in entries {
+                        encode_key(number, WireType::LengthDelimited, buf);
+                        encode_varint(cache.next_len() as u64, buf);
+                        self.encode_map_entry_value(key_field, key, buf, cache);
+                        self.encode_map_entry_value(val_field, val, buf, cache);
+                    }
+                },
+                Value::List(ref values) if field.packed => encode_packed_scalar(field.ty, number, values, buf),
+                Value::List(ref values) => {
+                    for value in values {
+                        self.encode_value(field, value, buf, cache);
+                    }
+                },
+                ref value if field.skip_default && value.is_default() => (),
+                ref value => self.encode_value(field, value, buf, cache),
+            }
+        }
+        self.unknown_fields.encode_raw(buf);
+    }
+
+    /// Encodes the key or value of a map entry, unless it is the default
+    /// value.
+    fn encode_map_entry_value<B>(&self, field: &FieldInfo, value: &Value, buf: &mut B, cache: &mut SizeCache)
+    where B: BufMut {
+        match *value {
+            Value::Message(ref message) => {
+                let len = cache.next_len();
+                if len != 0 {
+                    encode_key(field.number, WireType::LengthDelimited, buf);
+                    encode_varint(len as u64, buf);
+                    message.encode_raw_cached(buf, cache);
+                }
+            },
+            ref value if value.is_default() => (),
+            ref value => self.encode_value(field, value, buf, cache),
+        }
+    }
+
+    fn encode_value<B>(&self, field: &FieldInfo, value: &Value, buf: &mut B, cache: &mut SizeCache)
+    where B: BufMut {
+        match (field.ty, value) {
+            (Type::TypeMessage, &Value::Message(ref message)) => {
+                encode_key(field.number, WireType::LengthDelimited, buf);
+                encode_varint(cache.next_len() as u64, buf);
+                message.encode_raw_cached(buf, cache);
+            },
+            (Type::TypeGroup, &Value::Message(ref message)) => {
+                encode_key(field.number, WireType::StartGroup, buf);
+                message.encode_raw_cached(buf, cache);
+                encode_key(field.number, WireType::EndGroup, buf);
+            },
+            (ty, value) => encode_scalar(ty, field.number, value, buf),
+        }
+    }
+
+    fn field_encoded_len(&self, field: &FieldInfo, value: &Value, cache: &mut SizeCache) -> usize {
+        match *value {
+            Value::Map(ref entries) => {
+                let entry = self.descriptor.message(field.message.unwrap());
+                let key_field = entry.field(1).unwrap();
+                let val_field = entry.field(2).unwrap();
+                entries.iter().map(|&(ref key, ref val)| {
+                    let slot = cache.reserve();
+                    let len = self.map_entry_value_encoded_len(key_field, key, cache) +
+                              self.map_entry_value_encoded_len(val_field, val, cache);
+                    cache.set(slot, len);
+                    key_len(field.number) + encoded_len_varint(len as u64) + len
+                }).sum()
+            },
+            Value::List(ref values) if field.packed => encoded_len_packed_scalar(field.ty, field.number, values),
+            Value::List(ref values) => {
+                values.iter().map(|value| self.value_encoded_len(field, value, cache)).sum()
+            },
+            ref value if field.skip_default && value.is_default() => 0,
+            ref value => self.value_encoded_len(field, value, cache),
+        }
+    }
+
+    /// Returns the encoded length of the key or value of a map entry, which is
+    /// zero if it is the default value. The length of a message value is
+    /// recorded in the cache even if it is empty, since `Value::is_default`
+    /// would measure it again.
+    fn map_entry_value_encoded_len(&self, field: &FieldInfo, value: &Value, cache: &mut SizeCache) -> usize {
+        match *value {
+            Value::Message(ref message) => {
+                let slot = cache.reserve();
+                let len = message.encoded_len_cached(cache);
+                cache.set(slot, len);
+                match len {
+                    0 => 0,
+                    len => key_len(field.number) + encoded_len_varint(len as u64) + len,
+                }
+            },
+            ref value if value.is_default() => 0,
+            ref value => self.value_encoded_len(field, value, cache),
+        }
+    }
+
+    fn value_encoded_len(&self, field: &FieldInfo, value: &Value, cache: &mut SizeCache) -> usize {
+        match (field.ty, value) {
+            (Type::TypeMessage, &Value::Message(ref message)) => {
+                let slot = cache.reserve();
+                let len = message.encoded_len_cached(cache);
+                cache.set(slot, len);
+                key_len(field.number) + encoded_len_varint(len as u64) + len
+            },
+            (Type::TypeGroup, &Value::Message(ref message)) => {
+                2 * key_len(field.number) + message.encoded_len_cached(cache)
+            },
+            (ty, value) => encoded_len_scalar(ty, field.number, value),
+        }
+    }
+}
+
+impl fmt::Debug for DynamicMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug = f.debug_struct(self.descriptor.full_name());
+        for (field, value) in self.fields() {
+            debug.field(field.name(), value);
+        }
+        debug.finish()
+    }
+}
+
+/// A map key, ordered so that the entry with a given key can be found without
+/// searching the entries of a map field.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum MapKey {
+    Bool(bool),
+    I32(i32),
+    I64(i64),
+    U32(u32),
+    U64(u64),
+    String(String),
+}
+
+impl MapKey {
+
+    /// Returns the key of the value, or `None` if it is not of a valid map
+    /// key type.
+    fn new(value: &Value) -> Option<MapKey> {
+        Some(match *value {
+            Value::Bool(value) => MapKey::Bool(value),
+            Value::I32(value) => MapKey::I32(value),
+            Value::I64(value) => MapKey::I64(value),
+            Value::U32(value) => MapKey::U32(value),
+            Value::U64(value) => MapKey::U64(value),
+            Value::String(ref value) => MapKey::String(value.clone()),
+            _ => return None,
+        })
+    }
+}
+
+/// The positions of the entries of each map field by key, built as map
+/// entries are decoded.
+type MapIndexes = BTreeMap<u32, BTreeMap<MapKey, usize>>;
+
+fn merge_message<B>(wire_type: WireType,
+                    message: &mut DynamicMessage,
+                    buf: &mut Take<B>,
+                    ctx: DecodeContext)
+                    -> Result<(), DecodeError> where B: Buf {
+    check_wire_type(WireType::LengthDelimited, wire_type)?;
+    let ctx = ctx.enter_recursion()?;
+    let len = decode_varint(buf)?;
+    if len > buf.remaining() as u64 {
+        return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
+    }
+
+    let len = len as usize;
+    let limit = buf.limit();
+    buf.set_limit(len);
+    message.merge_with_context(buf, ctx)?;
+    buf.set_limit(limit - len);
+    Ok(())
+}
+
+fn merge_group<B>(tag: u32,
+                  wire_type: WireType,
+                  message: &mut DynamicMessage,
+                  buf: &mut Take<B>,
+                  ctx: DecodeContext)
+                  -> Result<(), DecodeError> where B: Buf {
+    check_wire_type(WireType::StartGroup, wire_type)?;
+    let ctx = ctx.enter_recursion()?;
+    let mut map_indexes = MapIndexes::new();
+    loop {
+        if !buf.has_remaining() {
+            return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
+        }
+        let (field_tag, field_wire_type) = decode_key(buf)?;
+        if field_wire_type == WireType::EndGroup {
+            if field_tag != tag {
+                return Err(DecodeError::new(DecodeErrorKind::UnexpectedEndGroup));
+            }
+            return Ok(());
+        }
+        message.merge_field(field_tag, field_wire_type, buf, ctx, &mut map_indexes)?;
+    }
+}
+
+/// Defines the encoding functions for scalar values, dispatching on the field
+/// type to the corresponding module of `prost::encoding`.
+macro_rules! scalar_functions {
+    ($(($numeric_ty:ident, $numeric_module:ident, $numeric_variant:ident, $numeric_rust_ty:ty)),*;
+     $(($ty:ident, $module:ident, $variant:ident, $rust_ty:ty)),*) => (
+
+        fn encode_scalar<B>(ty: Type, tag: u32, value: &Value, buf: &mut B) where B: BufMut {
+            match (ty, value) {
+                $((Type::$numeric_ty, &Value::$numeric_variant(ref value)) => {
+                    encoding::$numeric_module::encode(tag, value, buf)
+                },)*
+                $((Type::$ty, &Value::$variant(ref value)) => encoding::$module::encode(tag, value, buf),)*
+                (ty, value) => panic!("invalid value for {:?} field: {:?}", ty, value),
+            }
+        }
+
+        fn encoded_len_scalar(ty: Type, tag: u32, value: &Value) -> usize {
+            match (ty, value) {
+                $((Type::$numeric_ty, &Value::$numeric_variant(ref value)) => {
+                    encoding::$numeric_module::encoded_len(tag, value)
+                },)*
+                $((Type::$ty, &Value::$variant(ref value)) => encoding::$module::encoded_len(tag, value),)*
+                (ty, value) => panic!("invalid value for {:?} field: {:?}", ty, value),
+            }
+        }
+
+        fn merge_scalar<B>(ty: Type,
+                           wire_type: WireType,
+                           buf: &mut Take<B>,
+                           ctx: DecodeContext)
+                           -> Result<Value, DecodeError> where B: Buf {
+            match ty {
+                $(Type::$numeric_ty => {
+                    let mut value = <$numeric_rust_ty>::default();
+                    encoding::$numeric_module::merge(wire_type, &mut value, buf, ctx)?;
+                    Ok(Value::$numeric_variant(value))
+                },)*
+                $(Type::$ty => {
+                    let mut value = <$rust_ty>::default();
+                    encoding::$module::merge(wire_type, &mut value, buf, ctx)?;
+                    Ok(Value::$variant(value))
+                },)*
+                Type::TypeMessage | Type::TypeGroup => unreachable!(),
+            }
+        }
+
+        fn merge_repeated_scalar<B>(ty: Type,
+                                    wire_type: WireType,
+                                    values: &mut Vec<Value>,
+                                    buf: &mut Take<B>,
+                                    ctx: DecodeContext)
+                                    -> Result<(), DecodeError> where B: Buf {
+            match ty {
+                $(Type::$numeric_ty => {
+                    let mut decoded = Vec::<$numeric_rust_ty>::new();
+                    encoding::$numeric_module::merge_repeated(wire_type, &mut decoded, buf, ctx)?;
+                    values.extend(decoded.into_iter().map(Value::$numeric_variant));
+                },)*
+                $(Type::$ty => {
+                    let mut decoded = Vec::<$rust_ty>::new();
+                    encoding::$module::merge_repeated(wire_type, &mut decoded, buf, ctx)?;
+                    values.extend(decoded.into_iter().map(Value::$variant));
+                },)*
+                Type::TypeMessage | Type::TypeGroup => unreachable!(),
+            }
+            Ok(())
+        }
+
+        fn encode_packed_scalar<B>(ty: Type, tag: u32, values: &[Value], buf: &mut B) where B: BufMut {
+            match ty {
+                $(Type::$numeric_ty => {
+                    let values = values.iter().map(|value| match *value {
+                        Value::$numeric_variant(value) => value,
+                        ref value => panic!("invalid value for {:?} field: {:?}", ty, value),
+                    }).collect::<Vec<$numeric_rust_ty>>();
+                    encoding::$numeric_module::encode_packed(tag, &values, buf)
+                },)*
+                _ => panic!("{:?} fields can not be packed", ty),
+            }
+        }
+
+        fn encoded_len_packed_scalar(ty: Type, tag: u32, values: &[Value]) -> usize {
+            match ty {
+                $(Type::$numeric_ty => {
+                    let values = values.iter().map(|value| match *value {
+                        Value::$numeric_variant(value) => value,
+                        ref value => panic!("invalid value for {:?} field: {:?}", ty, value),
+                    }).collect::<Vec<$numeric_rust_ty>>();
+                    encoding::$numeric_module::encoded_len_packed(tag, &values)
+                },)*
+                _ => panic!("{:?} fields can not be packed", ty),
+            }
+        }
+    );
+}
+
+scalar_functions!((TypeDouble, double, F64, f64),
+                  (TypeFloat, float, F32, f32),
+                  (TypeInt64, int64, I64, i64),
+                  (TypeUint64, uint64, U64, u64),
+                  (TypeInt32, int32, I32, i32),
+                  (TypeFixed64, fixed64, U64, u64),
+                  (TypeFixed32, fixed32, U32, u32),
+                  (TypeBool, bool, Bool, bool),
+                  (TypeUint32, uint32, U32, u32),
+                  (TypeEnum, int32, Enum, i32),
+                  (TypeSfixed32, sfixed32, I32, i32),
+                  (TypeSfixed64, sfixed64, I64, i64),
+                  (TypeSint32, sint32, I32, i32),
+                  (TypeSint64, sint64, I64, i64);
+                  (TypeString, string, String, String),
+                  (TypeBytes, bytes, Bytes, Vec<u8>));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use google::protobuf::{
+        FileDescriptorProto,
+        MessageOptions,
+        OneofDescriptorProto,
+    };
+    use google::protobuf::descriptor_proto::ExtensionRange;
+
+    fn field(name: &str, number: i32, label: Label, ty: Type, type_name: Option<&str>) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(number),
+            label: Some(label as i32),
+            type_: Some(ty as i32),
+            type_name: type_name.map(|type_name| type_name.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn message(name: &str, fields: Vec<FieldDescriptorProto>) -> DescriptorProto {
+        DescriptorProto {
+            name: Some(name.to_string()),
+            field: fields,
+            ..Default::default()
+        }
+    }
+
+    /// A proto3 file declaring a message with a map field and a oneof, and a
+    /// proto2 file declaring a message with a group and an extension.
+    fn files() -> Vec<FileDescriptorProto> {
+        let mut bars_entry = message("BarsEntry", vec![
+            field("key", 1, Label::LabelOptional, Type::TypeString, None),
+            field("value", 2, Label::LabelOptional, Type::TypeMessage, Some(".test.Bar")),
+        ]);
+        bars_entry.options = Some(MessageOptions { map_entry: Some(true), ..Default::default() });
+        let mut foo = message("Foo", vec![
+            field("id", 1, Label::LabelOptional, Type::TypeInt32, None),
+            field("bars", 2, Label::LabelRepeated, Type::TypeMessage, Some(".test.Foo.BarsEntry")),
+            FieldDescriptorProto {
+                oneof_index: Some(0),
+                ..field("name", 3, Label::LabelOptional, Type::TypeString, None)
+            },
+            FieldDescriptorProto {
+                oneof_index: Some(0),
+                ..field("bar", 4, Label::LabelOptional, Type::TypeMessage, Some(".test.Bar"))
+            },
+        ]);
+        foo.nested_type.push(bars_entry);
+        foo.oneof_decl.push(OneofDescriptorProto { name: Some("choice".to_string()), ..Default::default() });
+
+        let mut baz = message("Baz", vec![
+            field("item", 1, Label::LabelOptional, Type::TypeGroup, Some(".test2.Baz.Item")),
+        ]);
+        baz.nested_type.push(message("Item", vec![field("x", 2, Label::LabelOptional, Type::TypeInt32, None)]));
+        baz.extension_range.push(ExtensionRange { start: Some(100), end: Some(200) });
+
+        vec![
+            FileDescriptorProto {
+                name: Some("test.proto".to_string()),
+                package: Some("test".to_string()),
+                syntax: Some("proto3".to_string()),
+                message_type: vec![
+                    foo,
+                    message("Bar", vec![field("value", 1, Label::LabelOptional, Type::TypeInt32, None)]),
+                ],
+                ..Default::default()
+            },
+            FileDescriptorProto {
+                name: Some("test2.proto".to_string()),
+                package: Some("test2".to_string()),
+                message_type: vec![baz],
+                extension: vec![FieldDescriptorProto {
+                    extendee: Some(".test2.Baz".to_string()),
+                    ..field("nums", 100, Label::LabelRepeated, Type::TypeSint32, None)
+                }],
+                ..Default::default()
+            },
+        ]
+    }
+
+    fn pool() -> Arc<DescriptorPool> {
+        Arc::new(DescriptorPool::new(FileDescriptorSet { file: files() }).unwrap())
+    }
+
+    #[test]
+    fn test_missing_types() {
+        let mut files = files();
+        files.remove(0);
+        files[0].message_type[0].field.push(field("bar", 2, Label::LabelOptional, Type::TypeMessage, Some(".test.Bar")));
+        assert_eq!(DescriptorError::TypeNotFound("test.Bar".to_string()),
+                   DescriptorPool::new(FileDescriptorSet { file: files.clone() }).unwrap_err());
+
+        files[0].message_type[0].field.pop();
+        files[0].extension[0].extendee = Some(".test.Foo".to_string());
+        assert_eq!(DescriptorError::TypeNotFound("test.Foo".to_string()),
+                   DescriptorPool::new(FileDescriptorSet { file: files.clone() }).unwrap_err());
+
+        files[0].extension.clear();
+        files[0].message_type[0].field[0].type_ = None;
+        assert_eq!(DescriptorError::UnknownFieldType("item".to_string()),
+                   DescriptorPool::new(FileDescriptorSet { file: files }).unwrap_err());
+    }
+
+    #[test]
+    fn test_map_and_oneof() {
+        let foo = MessageDescriptor::new(pool(), "test.Foo").unwrap();
+        assert!(foo.is_map_field(2));
+        assert!(!foo.field_has_presence(1));
+        assert!(foo.field_has_presence(3));
+
+        let mut bar = DynamicMessage::new(foo.field_message_type(4).unwrap());
+        bar.set("value", Value::I32(1)).unwrap();
+
+        let mut message = DynamicMessage::new(foo.clone());
+        assert_eq!(Ok(None), message.set("id", Value::I32(5)));
+        message.set("bars", Value::Map(vec![(Value::String("a".to_string()), Value::Message(bar.clone()))])).unwrap();
+        message.set("name", Value::String("n".to_string())).unwrap();
+        message.set("bar", Value::Message(bar.clone())).unwrap();
+        assert!(!message.has("name"));
+
+        let encoded = [0x08, 0x05,
+                       0x12, 0x07, 0x0a, 0x01, b'a', 0x12, 0x02, 0x08, 0x01,
+                       0x22, 0x02, 0x08, 0x01];
+        assert_eq!(&encoded[..], &message.encode_to_vec()[..]);
+        assert_eq!(encoded.len(), message.encoded_len());
+        assert_eq!(message, DynamicMessage::decode(foo.clone(), &encoded).unwrap());
+
+        // The last oneof field decoded wins, and later map entries replace
+        // earlier entries with the same key.
+        let decoded = DynamicMessage::decode(foo.clone(), &[0x22, 0x00, 0x1a, 0x01, b'x',
+                                                            0x12, 0x03, 0x0a, 0x01, b'a',
+                                                            0x12, 0x00,
+                                                            0x12, 0x05, 0x0a, 0x01, b'a', 0x12, 0x00]).unwrap();
+        assert!(!decoded.has("bar"));
+        assert_eq!(Some(&Value::String("x".to_string())), decoded.get("name"));
+        let empty = DynamicMessage::new(foo.field_message_type(4).unwrap());
+        assert_eq!(Some(&Value::Map(vec![(Value::String("a".to_string()), Value::Message(empty.clone())),
+                                         (Value::String(String::new()), Value::Message(empty.clone()))])),
+                   decoded.get("bars"));
+
+        // Entries merged into a map which was set replace its entries too.
+        let mut merged = message.clone();
+        merged.merge(&[0x12, 0x05, 0x0a, 0x01, b'a', 0x12, 0x00]).unwrap();
+        assert_eq!(Some(&Value::Map(vec![(Value::String("a".to_string()), Value::Message(empty))])),
+                   merged.get("bars"));
+    }
+
+    #[test]
+    fn test_large_map() {
+        let foo = MessageDescriptor::new(pool(), "test.Foo").unwrap();
+
+        // Each entry is decoded twice, so that every key is replaced once.
+        let mut encoded = Vec::new();
+        for _ in 0..2 {
+            for key in 0..100000 {
+                let key = key.to_string();
+                encoded.extend(&[0x12, key.len() as u8 + 2, 0x0a, key.len() as u8]);
+                encoded.extend(key.as_bytes());
+            }
+        }
+        let decoded = DynamicMessage::decode(foo, &encoded).unwrap();
+        match decoded.get("bars") {
+            Some(&Value::Map(ref entries)) => {
+                assert_eq!(100000, entries.len());
+                assert_eq!(Value::String("99999".to_string()), entries[99999].0);
+            },
+            value => panic!("unexpected value: {:?}", value),
+        }
+        assert_eq!(encoded.len() / 2, decoded.encoded_len());
+    }
+
+    #[test]
+    fn test_nested_map_encoding() {
+        use json::JsonMessage;
+        let pool = <::prost_types::Struct as JsonMessage>::descriptor_pool();
+        let struct_type = MessageDescriptor::new(pool.clone(), "google.protobuf.Struct").unwrap();
+        let value_type = MessageDescriptor::new(pool, "google.protobuf.Value").unwrap();
+
+        // Each level is a map entry holding a message, which must only be
+        // measured once per level for encoding to finish.
+        let mut message = DynamicMessage::new(struct_type.clone());
+        for _ in 0..40 {
+            let mut value = DynamicMessage::new(value_type.clone());
+            value.set("struct_value", Value::Message(message)).unwrap();
+            message = DynamicMessage::new(struct_type.clone());
+            message.set("fields", Value::Map(vec![(Value::String("a".to_string()), Value::Message(value))])).unwrap();
+        }
+        assert_eq!(message.encoded_len(), message.encode_to_vec().len());
+    }
+
+    #[test]
+    fn test_group_and_extension() {
+        let baz = MessageDescriptor::new(pool(), ".test2.Baz").unwrap();
+        assert_eq!(Some("test2.nums"), baz.extension_name(100));
+        assert_eq!(Some(100), baz.extension_by_name(".test2.nums").map(FieldDescriptorProto::number));
+        assert!(baz.field_by_name("nums").is_none());
+
+        let mut item = DynamicMessage::new(baz.field_message_type(1).unwrap());
+        item.set("x", Value::I32(3)).unwrap();
+        let mut message = DynamicMessage::new(baz.clone());
+        message.set("item", Value::Message(item)).unwrap();
+        message.set_by_number(100, Value::List(vec![Value::I32(-1), Value::I32(2)])).unwrap();
+
+        let encoded = [0x0b, 0x10, 0x03, 0x0c, 0xa0, 0x06, 0x01, 0xa0, 0x06, 0x04];
+        assert_eq!(&encoded[..], &message.encode_to_vec()[..]);
+        assert_eq!(message, DynamicMessage::decode(baz.clone(), &encoded).unwrap());
+
+        // A group must be closed by an end group tag with its own number.
+        assert!(DynamicMessage::decode(baz.clone(), &[0x0b, 0x10, 0x03]).is_err());
+        assert!(DynamicMessage::decode(baz, &[0x0b, 0x10, 0x03, 0x14]).is_err());
+    }
+
+    #[test]
+    fn test_set_and_clear() {
+        let foo = MessageDescriptor::new(pool(), "test.Foo").unwrap();
+        let baz = MessageDescriptor::new(foo.pool().clone(), "test2.Baz").unwrap();
+        let mut message = DynamicMessage::new(foo.clone());
+
+        assert_eq!(Err(SetFieldError::NoSuchField("missing".to_string())),
+                   message.set("missing", Value::I32(1)));
+        assert_eq!(Err(SetFieldError::NoSuchField("5".to_string())),
+                   message.set_by_number(5, Value::I32(1)));
+        assert_eq!(Err(SetFieldError::InvalidValue("id".to_string())),
+                   message.set("id", Value::Bool(true)));
+        assert_eq!(Err(SetFieldError::InvalidValue("id".to_string())),
+                   message.set("id", Value::List(vec![Value::I32(1)])));
+        assert_eq!(Err(SetFieldError::InvalidValue("bars".to_string())),
+                   message.set("bars", Value::Map(vec![(Value::I32(1), Value::I32(1))])));
+        assert_eq!(Err(SetFieldError::InvalidValue("bar".to_string())),
+                   message.set("bar", Value::Message(DynamicMessage::new(baz))));
+        assert_eq!(0, message.fields().count());
+
+        // Proto3 scalar fields with default values are not encoded.
+        message.set("id", Value::I32(0)).unwrap();
+        assert!(message.has("id"));
+        assert_eq!(0, message.encoded_len());
+
+        assert_eq!(Ok(Some(Value::I32(0))), message.set("id", Value::I32(7)));
+        message.set("name", Value::String("n".to_string())).unwrap();
+        assert_eq!(vec!["id", "name"], message.fields().map(|(field, _)| field.name()).collect::<Vec<_>>());
+        assert_eq!(Some(Value::I32(7)), message.clear("id"));
+        assert_eq!(None, message.clear("id"));
+        assert_eq!(Some(Value::String("n".to_string())), message.clear_by_number(3));
+        assert_eq!(DynamicMessage::new(foo), message);
+    }
+}
@@ -361,6 +361,8 @@ pub struct FileOptions {
     /// The parser stores options it doesn't recognize here. See above.
     #[prost(message, repeated, tag="999")]
     pub uninterpreted_option: Vec<UninterpretedOption>,
+    #[prost(extension_set="1000..536870912")]
+    pub extensions: ::prost::ExtensionSet,
 }
 pub mod file_options {
     /// Generated classes can be optimized for speed or code size.
@@ -434,6 +436,8 @@ pub struct MessageOptions {
     /// The parser stores options it doesn't recognize here. See above.
     #[prost(message, repeated, tag="999")]
     pub uninterpreted_option: Vec<UninterpretedOption>,
+    #[prost(extension_set="1000..536870912")]
+    pub extensions: ::prost::ExtensionSet,
 }
 #[derive(Clone, Debug, PartialEq, Message)]
 pub struct FieldOptions {
@@ -503,6 +507,8 @@ pub struct FieldOptions {
     /// The parser stores options it doesn't recognize here. See above.
     #[prost(message, repeated, tag="999")]
     pub uninterpreted_option: Vec<UninterpretedOption>,
+    #[prost(extension_set="1000..536870912")]
+    pub extensions: ::prost::ExtensionSet,
 }
 pub mod field_options {
     #[derive(Clone, Copy, Debug, PartialEq, Eq, Enumeration)]
@@ -527,6 +533,8 @@ pub struct OneofOptions {
     /// The parser stores options it doesn't recognize here. See above.
     #[prost(message, repeated, tag="999")]
     pub uninterpreted_option: Vec<UninterpretedOption>,
+    #[prost(extension_set="1000..536870912")]
+    pub extensions: ::prost::ExtensionSet,
 }
 #[derive(Clone, Debug, PartialEq, Message)]
 pub struct EnumOptions {
@@ -543,6 +551,8 @@ pub struct EnumOptions {
     /// The parser stores options it doesn't recognize here. See above.
     #[prost(message, repeated, tag="999")]
     pub uninterpreted_option: Vec<UninterpretedOption>,
+    #[prost(extension_set="1000..536870912")]
+    pub extensions: ::prost::ExtensionSet,
 }
 #[derive(Clone, Debug, PartialEq, Message)]
 pub struct EnumValueOptions {
@@ -555,6 +565,8 @@ pub struct EnumValueOptions {
     /// The parser stores options it doesn't recognize here. See above.
     #[prost(message, repeated, tag="999")]
     pub uninterpreted_option: Vec<UninterpretedOption>,
+    #[prost(extension_set="1000..536870912")]
+    pub extensions: ::prost::ExtensionSet,
 }
 #[derive(Clone, Debug, PartialEq, Message)]
 pub struct ServiceOptions {
@@ -572,6 +584,8 @@ pub struct ServiceOptions {
     /// The parser stores options it doesn't recognize here. See above.
     #[prost(message, repeated, tag="999")]
     pub uninterpreted_option: Vec<UninterpretedOption>,
+    #[prost(extension_set="1000..536870912")]
+    pub extensions: ::prost::ExtensionSet,
 }
 #[derive(Clone, Debug, PartialEq, Message)]
 pub struct MethodOptions {
@@ -591,6 +605,8 @@ pub struct MethodOptions {
     /// The parser stores options it doesn't recognize here. See above.
     #[prost(message, repeated, tag="999")]
     pub uninterpreted_option: Vec<UninterpretedOption>,
+    #[prost(extension_set="1000..536870912")]
+    pub extensions: ::prost::ExtensionSet,
 }
 pub mod method_options {
     /// Is this method side-effect-free (or safe in HTTP parlance), or idempotent,
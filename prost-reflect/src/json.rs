@@ -0,0 +1,1473 @@
+//! The canonical proto3 JSON mapping.
+//!
+//! Messages are printed and parsed through their descriptors, as
+//! `DynamicMessage`s, following the mapping described at
+//! <https://developers.google.com/protocol-buffers/docs/proto3#json>:
+//!
+//! * fields are named by their `json_name` (lowerCamelCase); the original
+//!   field names are also accepted when parsing,
+//! * 64-bit integers are written as strings, and `bytes` as base64,
+//! * enum values are written by name,
+//! * proto3 scalar fields with default values are omitted, and
+//! * the well-known types `Any`, `Timestamp`, `Duration`, `FieldMask`,
+//!   `Struct`, `Value`, `ListValue` and the wrapper types use their special
+//!   representations.
+//!
+//! Messages generated with the `json` option implement `JsonMessage`, and can
+//! be converted directly.
+
+use std::char;
+use std::error;
+use std::fmt::{
+    self,
+    Write,
+};
+use std::sync::Arc;
+
+use prost::Message;
+use prost::encoding::RECURSION_LIMIT;
+
+use dynamic::{
+    DescriptorPool,
+    DynamicMessage,
+    MessageDescriptor,
+    Value,
+};
+use google::protobuf::FieldDescriptorProto;
+use google::protobuf::field_descriptor_proto::{
+    Label,
+    Type,
+};
+use reflect::ReflectMessage;
+
+/// The range of valid `Timestamp` seconds, from 0001-01-01T00:00:00Z to
+/// 9999-12-31T23:59:59Z.
+const MIN_TIMESTAMP_SECONDS: i64 = -62135596800;
+const MAX_TIMESTAMP_SECONDS: i64 = 253402300799;
+
+/// The range of valid `Duration` seconds, approximately +-10,000 years.
+const MAX_DURATION_SECONDS: i64 = 315576000000;
+
+const WRAPPERS: &'static [&'static str] = &[
+    "google.protobuf.DoubleValue",
+    "google.protobuf.FloatValue",
+    "google.protobuf.Int64Value",
+    "google.protobuf.UInt64Value",
+    "google.protobuf.Int32Value",
+    "google.protobuf.UInt32Value",
+    "google.protobuf.BoolValue",
+    "google.protobuf.StringValue",
+    "google.protobuf.BytesValue",
+];
+
+/// A message which can be converted to and from the canonical proto3 JSON
+/// mapping.
+///
+/// `JsonMessage` is implemented by code generated with the `json` option.
+pub trait JsonMessage: Message + ReflectMessage + Default {
+
+    /// Returns a descriptor pool containing the message type.
+    fn descriptor_pool() -> Arc<DescriptorPool>;
+
+    /// Converts the message to JSON.
+    fn to_json(&self) -> Result<String, JsonError> {
+        print_message(self, &Self::descriptor_pool())
+    }
+
+    /// Parses a message from JSON.
+    fn from_json(json: &str) -> Result<Self, JsonError> {
+        parse_message(&Self::descriptor_pool(), json)
+    }
+}
+
+/// Converts the message to JSON.
+///
+/// An error is returned if the message contains a well-known type value
+/// which has no JSON representation, for instance a `Timestamp` outside of
+/// the supported range, or an `Any` whose type is not in the pool.
+pub fn print(message: &DynamicMessage) -> Result<String, JsonError> {
+    let mut buf = String::new();
+    print_message_value(&mut buf, message)?;
+    Ok(buf)
+}
+
+/// Converts a generated message to JSON, using its descriptor in the pool.
+///
+/// The message is converted by decoding it as a `DynamicMessage`, which
+/// fails if it is nested more deeply than `prost::encoding::RECURSION_LIMIT`.
+///
+/// Panics if the pool does not contain the message type.
+pub fn print_message<M>(message: &M, pool: &Arc<DescriptorPool>) -> Result<String, JsonError>
+where M: Message + ReflectMessage {
+    let descriptor = MessageDescriptor::new(pool.clone(), message.full_name()).unwrap_or_else(|| {
+        panic!("message type {} not found in descriptor pool", message.full_name())
+    });
+    let message = DynamicMessage::decode(descriptor, &message.encode_to_vec()).map_err(|error| {
+        JsonError::new(format!("failed to decode message: {}", error))
+    })?;
+    print(&message)
+}
+
+/// Parses a message of the given type from JSON.
+///
+/// Messages, including map entries, may be nested at most
+/// `prost::encoding::RECURSION_LIMIT` levels deep, the same limit which
+/// applies when decoding.
+pub fn parse(descriptor: MessageDescriptor, json: &str) -> Result<DynamicMessage, JsonError> {
+    let json = Reader::new(json).read_document()?;
+    let message = parse_message_value(descriptor, &json)?;
+    if nesting_depth(&message) > RECURSION_LIMIT {
+        return Err(JsonError::new(format!("messages are nested more than {} levels deep", RECURSION_LIMIT)));
+    }
+    Ok(message)
+}
+
+/// Parses a generated message from JSON, using its descriptor in the pool.
+///
+/// Panics if the pool does not contain the message type.
+pub fn parse_message<M>(pool: &Arc<DescriptorPool>, json: &str) -> Result<M, JsonError>
+where M: Message + ReflectMessage + Default {
+    let full_name = M::default().full_name();
+    let descriptor = MessageDescriptor::new(pool.clone(), full_name).unwrap_or_else(|| {
+        panic!("message type {} not found in descriptor pool", full_name)
+    });
+    let message = parse(descriptor, json)?;
+    M::decode_from_slice(&message.encode_to_vec()).map_err(|error| {
+        JsonError::new(format!("failed to decode parsed message: {}", error))
+    })
+}
+
+/// An error returned when a message can not be converted to or from JSON.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonError {
+    message: String,
+}
+
+impl JsonError {
+    fn new<S>(message: S) -> JsonError where S: Into<String> {
+        JsonError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl error::Error for JsonError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+/// Returns the JSON name of a field: its `json_name` if the descriptor
+/// includes one, or its name converted to lowerCamelCase.
+pub fn json_name(field: &FieldDescriptorProto) -> String {
+    if let Some(ref json_name) = field.json_name {
+        return json_name.clone();
+    }
+    let mut json_name = String::new();
+    let mut capitalize = false;
+    for c in field.name().chars() {
+        if c == '_' {
+            capitalize = true;
+        } else if capitalize {
+            json_name.extend(c.to_uppercase());
+            capitalize = false;
+        } else {
+            json_name.push(c);
+        }
+    }
+    json_name
+}
+
+// Printing.
+
+fn print_message_value(buf: &mut String, message: &DynamicMessage) -> Result<(), JsonError> {
+    let full_name = message.descriptor().full_name();
+    match full_name {
+        "google.protobuf.Any" => print_any(buf, message),
+        "google.protobuf.Timestamp" => print_timestamp(buf, message),
+        "google.protobuf.Duration" => print_duration(buf, message),
+        "google.protobuf.FieldMask" => print_field_mask(buf, message),
+        "google.protobuf.Struct" => print_struct(buf, message),
+        "google.protobuf.Value" => print_value_message(buf, message),
+        "google.protobuf.ListValue" => print_list_value(buf, message),
+        _ if WRAPPERS.contains(&full_name) => {
+            let value = field_or_default(message, 1);
+            print_value(buf, message.descriptor(), 1, &value)
+        },
+        _ => {
+            buf.push('{');
+            print_fields(buf, message, true)?;
+            buf.push('}');
+            Ok(())
+        },
+    }
+}
+
+/// Prints the fields of a message as comma separated object members.
+fn print_fields(buf: &mut String, message: &DynamicMessage, mut first: bool) -> Result<(), JsonError> {
+    let descriptor = message.descriptor();
+    for (field, value) in message.fields() {
+        let number = field.number() as u32;
+        if !descriptor.field_has_presence(number) && value.is_default() {
+            continue;
+        }
+
+        if !first {
+            buf.push(',');
+        }
+        first = false;
+        match descriptor.extension_name(number) {
+            Some(name) => print_string(buf, &format!("[{}]", name)),
+            None => print_string(buf, &json_name(field)),
+        }
+        buf.push(':');
+
+        match *value {
+            Value::List(ref values) => {
+                buf.push('[');
+                for (idx, value) in values.iter().enumerate() {
+                    if idx > 0 {
+                        buf.push(',');
+                    }
+                    print_value(buf, descriptor, number, value)?;
+                }
+                buf.push(']');
+            },
+            Value::Map(ref entries) => {
+                let entry_type = descriptor.field_message_type(number).unwrap();
+                buf.push('{');
+                for (idx, &(ref key, ref value)) in entries.iter().enumerate() {
+                    if idx > 0 {
+                        buf.push(',');
+                    }
+                    let key = match *key {
+                        Value::String(ref key) => key.clone(),
+                        Value::Bool(key) => key.to_string(),
+                        Value::I32(key) => key.to_string(),
+                        Value::I64(key) => key.to_string(),
+                        Value::U32(key) => key.to_string(),
+                        Value::U64(key) => key.to_string(),
+                        ref key => panic!("invalid map key: {:?}", key),
+                    };
+                    print_string(buf, &key);
+                    buf.push(':');
+                    print_value(buf, &entry_type, 2, value)?;
+                }
+                buf.push('}');
+            },
+            ref value => print_value(buf, descriptor, number, value)?,
+        }
+    }
+    Ok(())
+}
+
+/// Prints a singular value of the field with the given number.
+fn print_value(buf: &mut String,
+               descriptor: &MessageDescriptor,
+               number: u32,
+               value: &Value)
+               -> Result<(), JsonError> {
+    match *value {
+        Value::Bool(value) => write!(buf, "{}", value).unwrap(),
+        Value::I32(value) => write!(buf, "{}", value).unwrap(),
+        Value::U32(value) => write!(buf, "{}", value).unwrap(),
+        Value::I64(value) => write!(buf, "\"{}\"", value).unwrap(),
+        Value::U64(value) => write!(buf, "\"{}\"", value).unwrap(),
+        Value::F32(value) => print_float(buf, value as f64, &format!("{:?}", value)),
+        Value::F64(value) => print_float(buf, value, &format!("{:?}", value)),
+        Value::String(ref value) => print_string(buf, value),
+        Value::Bytes(ref value) => {
+            buf.push('"');
+            base64_encode(buf, value);
+            buf.push('"');
+        },
+        Value::Enum(value) => {
+            let enum_type = descriptor.field_enum_type(number).unwrap();
+            if enum_type.full_name() == "google.protobuf.NullValue" {
+                buf.push_str("null");
+            } else {
+                match enum_type.value_name(value) {
+                    Some(name) => print_string(buf, name),
+                    None => write!(buf, "{}", value).unwrap(),
+                }
+            }
+        },
+        Value::Message(ref message) => print_message_value(buf, message)?,
+        Value::List(..) | Value::Map(..) => unreachable!(),
+    }
+    Ok(())
+}
+
+fn print_float(buf: &mut String, value: f64, formatted: &str) {
+    if value.is_nan() {
+        buf.push_str("\"NaN\"");
+    } else if value.is_infinite() {
+        buf.push_str(if value > 0.0 { "\"Infinity\"" } else { "\"-Infinity\"" });
+    } else {
+        buf.push_str(formatted);
+    }
+}
+
+fn print_string(buf: &mut String, value: &str) {
+    buf.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            '\x08' => buf.push_str("\\b"),
+            '\x0c' => buf.push_str("\\f"),
+            c if (c as u32) < 0x20 => write!(buf, "\\u{:04x}", c as u32).unwrap(),
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+}
+
+/// Returns the value of the field, or its default value if it is not set.
+fn field_or_default(message: &DynamicMessage, number: u32) -> Value {
+    match message.get_by_number(number) {
+        Some(value) => value.clone(),
+        None => message.descriptor().field_default_value(number).unwrap(),
+    }
+}
+
+fn print_any(buf: &mut String, any: &DynamicMessage) -> Result<(), JsonError> {
+    let type_url = match field_or_default(any, 1) {
+        Value::String(type_url) => type_url,
+        _ => unreachable!(),
+    };
+    let value = match field_or_default(any, 2) {
+        Value::Bytes(value) => value,
+        _ => unreachable!(),
+    };
+    if type_url.is_empty() && value.is_empty() {
+        buf.push_str("{}");
+        return Ok(());
+    }
+
+    let descriptor = any_type(any.descriptor(), &type_url)?;
+    let message = match DynamicMessage::decode(descriptor, &value) {
+        Ok(message) => message,
+        Err(error) => return Err(JsonError::new(format!("failed to decode Any value: {}", error))),
+    };
+    buf.push_str("{\"@type\":");
+    print_string(buf, &type_url);
+    if has_special_mapping(message.descriptor().full_name()) {
+        buf.push_str(",\"value\":");
+        print_message_value(buf, &message)?;
+    } else {
+        print_fields(buf, &message, false)?;
+    }
+    buf.push('}');
+    Ok(())
+}
+
+/// Returns the type of the contents of an `Any` with the given type URL.
+fn any_type(any: &MessageDescriptor, type_url: &str) -> Result<MessageDescriptor, JsonError> {
+    let type_name = match type_url.rfind('/') {
+        Some(idx) => &type_url[idx + 1..],
+        None => return Err(JsonError::new(format!("invalid Any type URL: {}", type_url))),
+    };
+    MessageDescriptor::new(any.pool().clone(), type_name).ok_or_else(|| {
+        JsonError::new(format!("message type {} not found", type_name))
+    })
+}
+
+/// Returns `true` if the message type is a well-known type whose JSON
+/// representation is not an object of its fields.
+fn has_special_mapping(full_name: &str) -> bool {
+    match full_name {
+        "google.protobuf.Any" |
+        "google.protobuf.Timestamp" |
+        "google.protobuf.Duration" |
+        "google.protobuf.FieldMask" |
+        "google.protobuf.Struct" |
+        "google.protobuf.Value" |
+        "google.protobuf.ListValue" => true,
+        full_name => WRAPPERS.contains(&full_name),
+    }
+}
+
+/// Returns the `seconds` and `nanos` fields of a `Timestamp` or `Duration`.
+fn seconds_and_nanos(message: &DynamicMessage) -> (i64, i32) {
+    match (field_or_default(message, 1), field_or_default(message, 2)) {
+        (Value::I64(seconds), Value::I32(nanos)) => (seconds, nanos),
+        _ => unreachable!(),
+    }
+}
+
+/// Prints the fractional seconds of a `Timestamp` or `Duration` with 0, 3, 6
+/// or 9 digits.
+fn print_nanos(buf: &mut String, nanos: u32) {
+    if nanos == 0 {
+        return;
+    } else if nanos % 1000000 == 0 {
+        write!(buf, ".{:03}", nanos / 1000000).unwrap();
+    } else if nanos % 1000 == 0 {
+        write!(buf, ".{:06}", nanos / 1000).unwrap();
+    } else {
+        write!(buf, ".{:09}", nanos).unwrap();
+    }
+}
+
+fn print_timestamp(buf: &mut String, timestamp: &DynamicMessage) -> Result<(), JsonError> {
+    let (seconds, nanos) = seconds_and_nanos(timestamp);
+    if seconds < MIN_TIMESTAMP_SECONDS || seconds > MAX_TIMESTAMP_SECONDS || nanos < 0 || nanos > 999999999 {
+        return Err(JsonError::new("Timestamp out of range"));
+    }
+
+    let days = seconds.div_euclid_compat(86400);
+    let seconds_of_day = seconds - days * 86400;
+    let (year, month, day) = civil_from_days(days);
+    write!(buf, "\"{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+           year, month, day, seconds_of_day / 3600, seconds_of_day / 60 % 60, seconds_of_day % 60).unwrap();
+    print_nanos(buf, nanos as u32);
+    buf.push_str("Z\"");
+    Ok(())
+}
+
+fn print_duration(buf: &mut String, duration: &DynamicMessage) -> Result<(), JsonError> {
+    let (seconds, nanos) = seconds_and_nanos(duration);
+    if seconds.abs() > MAX_DURATION_SECONDS ||
+       nanos.abs() > 999999999 ||
+       (seconds > 0 && nanos < 0) ||
+       (seconds < 0 && nanos > 0) {
+        return Err(JsonError::new("Duration out of range"));
+    }
+
+    buf.push('"');
+    if seconds < 0 || nanos < 0 {
+        buf.push('-');
+    }
+    write!(buf, "{}", seconds.abs()).unwrap();
+    print_nanos(buf, nanos.abs() as u32);
+    buf.push_str("s\"");
+    Ok(())
+}
+
+fn print_field_mask(buf: &mut String, field_mask: &DynamicMessage) -> Result<(), JsonError> {
+    let paths = match field_mask.get_by_number(1) {
+        Some(&Value::List(ref paths)) => &paths[..],
+        _ => &[],
+    };
+    let mut mask = String::new();
+    for (idx, path) in paths.iter().enumerate() {
+        let path = match *path {
+            Value::String(ref path) => path,
+            _ => unreachable!(),
+        };
+        if idx > 0 {
+            mask.push(',');
+        }
+        // Paths must survive the round trip through lowerCamelCase.
+        let mut capitalize = false;
+        for c in path.chars() {
+            if c.is_uppercase() || (capitalize && !c.is_lowercase()) {
+                return Err(JsonError::new(format!("FieldMask path {} can not be converted to JSON", path)));
+            }
+            if c == '_' {
+                capitalize = true;
+            } else if capitalize {
+                mask.extend(c.to_uppercase());
+                capitalize = false;
+            } else {
+                mask.push(c);
+            }
+        }
+        if capitalize {
+            return Err(JsonError::new(format!("FieldMask path {} can not be converted to JSON", path)));
+        }
+    }
+    print_string(buf, &mask);
+    Ok(())
+}
+
+fn print_struct(buf: &mut String, message: &DynamicMessage) -> Result<(), JsonError> {
+    buf.push('{');
+    if let Some(&Value::Map(ref entries)) = message.get_by_number(1) {
+        for (idx, &(ref key, ref value)) in entries.iter().enumerate() {
+            if idx > 0 {
+                buf.push(',');
+            }
+            match (key, value) {
+                (&Value::String(ref key), &Value::Message(ref value)) => {
+                    print_string(buf, key);
+                    buf.push(':');
+                    print_value_message(buf, value)?;
+                },
+                _ => unreachable!(),
+            }
+        }
+    }
+    buf.push('}');
+    Ok(())
+}
+
+fn print_value_message(buf: &mut String, message: &DynamicMessage) -> Result<(), JsonError> {
+    match message.fields().next() {
+        Some((field, value)) => match (field.number(), value) {
+            (1, _) => buf.push_str("null"),
+            (2, &Value::F64(value)) => {
+                if !value.is_finite() {
+                    return Err(JsonError::new("Value number must be finite"));
+                }
+                write!(buf, "{:?}", value).unwrap();
+            },
+            (3, &Value::String(ref value)) => print_string(buf, value),
+            (4, &Value::Bool(value)) => write!(buf, "{}", value).unwrap(),
+            (5, &Value::Message(ref value)) => print_struct(buf, value)?,
+            (6, &Value::Message(ref value)) => print_list_value(buf, value)?,
+            _ => unreachable!(),
+        },
+        None => return Err(JsonError::new("Value has no kind set")),
+    }
+    Ok(())
+}
+
+fn print_list_value(buf: &mut String, message: &DynamicMessage) -> Result<(), JsonError> {
+    buf.push('[');
+    if let Some(&Value::List(ref values)) = message.get_by_number(1) {
+        for (idx, value) in values.iter().enumerate() {
+            if idx > 0 {
+                buf.push(',');
+            }
+            match *value {
+                Value::Message(ref value) => print_value_message(buf, value)?,
+                _ => unreachable!(),
+            }
+        }
+    }
+    buf.push(']');
+    Ok(())
+}
+
+// Parsing.
+
+fn parse_message_value(descriptor: MessageDescriptor, json: &Json) -> Result<DynamicMessage, JsonError> {
+    let full_name = descriptor.full_name().to_string();
+    match &full_name[..] {
+        "google.protobuf.Any" => parse_any(descriptor, json),
+        "google.protobuf.Timestamp" => parse_timestamp(descriptor, json),
+        "google.protobuf.Duration" => parse_duration(descriptor, json),
+        "google.protobuf.FieldMask" => parse_field_mask(descriptor, json),
+        "google.protobuf.Struct" => parse_struct(descriptor, json),
+        "google.protobuf.Value" => parse_value_message(descriptor, json),
+        "google.protobuf.ListValue" => parse_list_value(descriptor, json),
+        full_name if WRAPPERS.contains(&full_name) => {
+            let value = parse_value(&descriptor, 1, json)?;
+            let mut message = DynamicMessage::new(descriptor);
+            message.set_by_number(1, value).unwrap();
+            Ok(message)
+        },
+        _ => match *json {
+            Json::Object(ref members) => {
+                let mut message = DynamicMessage::new(descriptor);
+                parse_fields(&mut message, members)?;
+                Ok(message)
+            },
+            _ => Err(JsonError::new(format!("expected object for message type {}", full_name))),
+        },
+    }
+}
+
+/// Returns the number of levels of messages nested in the message, counting
+/// map entries as a level, as decoding does.
+fn nesting_depth(message: &DynamicMessage) -> u32 {
+    fn value_depth(value: &Value) -> u32 {
+        match *value {
+            Value::Message(ref message) => 1 + nesting_depth(message),
+            Value::List(ref values) => values.iter().map(value_depth).max().unwrap_or(0),
+            Value::Map(ref entries) => entries.iter().map(|&(_, ref value)| 1 + value_depth(value)).max().unwrap_or(0),
+            _ => 0,
+        }
+    }
+    message.fields().map(|(_, value)| value_depth(value)).max().unwrap_or(0)
+}
+
+/// Parses the members of a JSON object into fields of the message.
+fn parse_fields(message: &mut DynamicMessage, members: &[(String, Json)]) -> Result<(), JsonError> {
+    let descriptor = message.descriptor().clone();
+    let mut seen = Vec::new();
+    for &(ref name, ref json) in members {
+        let field = if name.starts_with('[') && name.ends_with(']') {
+            descriptor.extension_by_name(&name[1..name.len() - 1]).cloned()
+        } else {
+            descriptor.descriptor()
+                      .field
+                      .iter()
+                      .find(|field| json_name(field) == *name || field.name() == name)
+                      .cloned()
+        };
+        let field = match field {
+            Some(field) => field,
+            None => return Err(JsonError::new(format!("message type {} has no field named {}",
+                                                      descriptor.full_name(), name))),
+        };
+        let number = field.number() as u32;
+
+        if seen.contains(&number) {
+            return Err(JsonError::new(format!("field {} is specified multiple times", field.name())));
+        }
+        seen.push(number);
+        if let Some(oneof_index) = field.oneof_index {
+            let conflict = seen.iter().any(|&other| {
+                other != number &&
+                descriptor.field_by_number(other).and_then(|field| field.oneof_index) == Some(oneof_index)
+            });
+            if conflict {
+                return Err(JsonError::new(format!("field {} is specified along with another field of the same oneof",
+                                                  field.name())));
+            }
+        }
+
+        let is_null_value = field.type_name() == ".google.protobuf.Value" ||
+                            field.type_name() == ".google.protobuf.NullValue";
+        if *json == Json::Null && !(is_null_value && field.label() != Some(Label::LabelRepeated)) {
+            // Null is equivalent to the field being absent.
+            continue;
+        }
+
+        let value = if descriptor.is_map_field(number) {
+            let entry_type = descriptor.field_message_type(number).unwrap();
+            let members = match *json {
+                Json::Object(ref members) => members,
+                _ => return Err(JsonError::new(format!("expected object for map field {}", field.name()))),
+            };
+            let mut entries: Vec<(Value, Value)> = Vec::new();
+            for &(ref key, ref value) in members {
+                let key = parse_map_key(&entry_type, key)?;
+                if entries.iter().any(|&(ref k, _)| *k == key) {
+                    return Err(JsonError::new(format!("duplicate key in map field {}", field.name())));
+                }
+                let value = parse_value(&entry_type, 2, value)?;
+                entries.push((key, value));
+            }
+            Value::Map(entries)
+        } else if field.label() == Some(Label::LabelRepeated) {
+            match *json {
+                Json::Array(ref values) => {
+                    let values = values.iter()
+                                       .map(|value| parse_value(&descriptor, number, value))
+                                       .collect::<Result<Vec<_>, _>>()?;
+                    Value::List(values)
+                },
+                _ => return Err(JsonError::new(format!("expected array for repeated field {}", field.name()))),
+            }
+        } else {
+            parse_value(&descriptor, number, json)?
+        };
+        message.set_by_number(number, value).unwrap();
+    }
+    Ok(())
+}
+
+/// Parses a singular value of the field with the given number.
+fn parse_value(descriptor: &MessageDescriptor, number: u32, json: &Json) -> Result<Value, JsonError> {
+    let field = descriptor.field_by_number(number).unwrap();
+    let ty = match field.type_() {
+        Some(ty) => ty,
+        None => return Err(JsonError::new(format!("field {} has an unknown type", field.name()))),
+    };
+    let invalid = || JsonError::new(format!("invalid value for field {}", field.name()));
+
+    match ty {
+        Type::TypeMessage | Type::TypeGroup => {
+            let message_type = descriptor.field_message_type(number).unwrap();
+            parse_message_value(message_type, json).map(Value::Message)
+        },
+        Type::TypeEnum => {
+            let enum_type = descriptor.field_enum_type(number).unwrap();
+            match *json {
+                Json::Null if enum_type.full_name() == "google.protobuf.NullValue" => Ok(Value::Enum(0)),
+                Json::String(ref name) => match enum_type.value_number(name) {
+                    Some(value) => Ok(Value::Enum(value)),
+                    None => Err(JsonError::new(format!("enum type {} has no value named {}",
+                                                       enum_type.full_name(), name))),
+                },
+                Json::Number(..) => {
+                    parse_signed(json, i32::min_value() as i64, i32::max_value() as i64)
+                        .map(|value| Value::Enum(value as i32))
+                        .ok_or_else(invalid)
+                },
+                _ => Err(invalid()),
+            }
+        },
+        Type::TypeBool => match *json {
+            Json::Bool(value) => Ok(Value::Bool(value)),
+            _ => Err(invalid()),
+        },
+        Type::TypeString => match *json {
+            Json::String(ref value) => Ok(Value::String(value.clone())),
+            _ => Err(invalid()),
+        },
+        Type::TypeBytes => match *json {
+            Json::String(ref value) => base64_decode(value).map(Value::Bytes).ok_or_else(invalid),
+            _ => Err(invalid()),
+        },
+        Type::TypeFloat => {
+            let value = parse_float(json).ok_or_else(&invalid)?;
+            if value.is_finite() && value.abs() > ::std::f32::MAX as f64 {
+                return Err(JsonError::new(format!("value for field {} is out of range", field.name())));
+            }
+            Ok(Value::F32(value as f32))
+        },
+        Type::TypeDouble => parse_float(json).map(Value::F64).ok_or_else(invalid),
+        Type::TypeInt32 | Type::TypeSint32 | Type::TypeSfixed32 => {
+            parse_signed(json, i32::min_value() as i64, i32::max_value() as i64)
+                .map(|value| Value::I32(value as i32))
+                .ok_or_else(invalid)
+        },
+        Type::TypeInt64 | Type::TypeSint64 | Type::TypeSfixed64 => {
+            parse_signed(json, i64::min_value(), i64::max_value()).map(Value::I64).ok_or_else(invalid)
+        },
+        Type::TypeUint32 | Type::TypeFixed32 => {
+            parse_unsigned(json, u32::max_value() as u64).map(|value| Value::U32(value as u32)).ok_or_else(invalid)
+        },
+        Type::TypeUint64 | Type::TypeFixed64 => {
+            parse_unsigned(json, u64::max_value()).map(Value::U64).ok_or_else(invalid)
+        },
+    }
+}
+
+fn parse_map_key(entry_type: &MessageDescriptor, key: &str) -> Result<Value, JsonError> {
+    let key_json = match entry_type.field_by_number(1).and_then(|field| field.type_()) {
+        Some(Type::TypeString) => Json::String(key.to_string()),
+        Some(Type::TypeBool) if key == "true" => Json::Bool(true),
+        Some(Type::TypeBool) if key == "false" => Json::Bool(false),
+        Some(Type::TypeBool) => return Err(JsonError::new(format!("invalid map key: {}", key))),
+        // Integer keys are parsed in the same way as quoted integers.
+        _ => Json::String(key.to_string()),
+    };
+    parse_value(entry_type, 1, &key_json)
+}
+
+/// Returns the text of a number, which may be quoted.
+fn number_text(json: &Json) -> Option<&str> {
+    match *json {
+        Json::Number(ref number) => Some(number),
+        Json::String(ref number) if !number.is_empty() && number.trim() == number => Some(number),
+        _ => None,
+    }
+}
+
+/// Parses an integer, which may be written in exponential or decimal form as
+/// long as it has no fractional part.
+fn parse_integer(json: &Json) -> Option<(bool, u64)> {
+    let text = match number_text(json) {
+        Some(text) => text,
+        None => return None,
+    };
+    let (negative, magnitude) = if text.starts_with('-') {
+        (true, &text[1..])
+    } else {
+        (false, text)
+    };
+    if magnitude.starts_with('+') || magnitude.starts_with('-') {
+        return None;
+    }
+    if let Ok(value) = magnitude.parse::<u64>() {
+        return Some((negative, value));
+    }
+    match magnitude.parse::<f64>() {
+        Ok(value) if value.is_finite() && value.fract() == 0.0 && value < 18446744073709551616.0 => {
+            Some((negative, value as u64))
+        },
+        _ => None,
+    }
+}
+
+fn parse_signed(json: &Json, min: i64, max: i64) -> Option<i64> {
+    let (negative, magnitude) = match parse_integer(json) {
+        Some(integer) => integer,
+        None => return None,
+    };
+    let in_range = if negative {
+        magnitude <= (min as u64).wrapping_neg()
+    } else {
+        magnitude <= max as u64
+    };
+    if !in_range {
+        return None;
+    }
+    Some(if negative { (magnitude as i64).wrapping_neg() } else { magnitude as i64 })
+}
+
+fn parse_unsigned(json: &Json, max: u64) -> Option<u64> {
+    match parse_integer(json) {
+        Some((negative, magnitude)) if (!negative || magnitude == 0) && magnitude <= max => Some(magnitude),
+        _ => None,
+    }
+}
+
+fn parse_float(json: &Json) -> Option<f64> {
+    match *json {
+        Json::String(ref value) if value == "NaN" => return Some(::std::f64::NAN),
+        Json::String(ref value) if value == "Infinity" => return Some(::std::f64::INFINITY),
+        Json::String(ref value) if value == "-Infinity" => return Some(::std::f64::NEG_INFINITY),
+        _ => (),
+    }
+    let text = match number_text(json) {
+        Some(text) => text,
+        None => return None,
+    };
+    match text.parse::<f64>() {
+        Ok(value) if value.is_finite() => Some(value),
+        _ => None,
+    }
+}
+
+fn parse_any(descriptor: MessageDescriptor, json: &Json) -> Result<DynamicMessage, JsonError> {
+    let members = match *json {
+        Json::Object(ref members) => members,
+        _ => return Err(JsonError::new("expected object for message type google.protobuf.Any")),
+    };
+    let mut any = DynamicMessage::new(descriptor);
+    if members.is_empty() {
+        return Ok(any);
+    }
+
+    let type_url = match members.iter().find(|&&(ref name, _)| name == "@type") {
+        Some(&(_, Json::String(ref type_url))) => type_url.clone(),
+        Some(..) => return Err(JsonError::new("Any @type must be a string")),
+        None => return Err(JsonError::new("Any is missing @type")),
+    };
+    let contents_type = any_type(any.descriptor(), &type_url)?;
+    let members = members.iter().filter(|&&(ref name, _)| name != "@type").cloned().collect::<Vec<_>>();
+
+    let contents = if has_special_mapping(contents_type.full_name()) {
+        match members.iter().find(|&&(ref name, _)| name == "value") {
+            Some(&(_, ref value)) if members.len() == 1 => parse_message_value(contents_type, value)?,
+            _ => return Err(JsonError::new(format!("Any containing {} must have only a value member",
+                                                   contents_type.full_name()))),
+        }
+    } else {
+        let mut contents = DynamicMessage::new(contents_type);
+        parse_fields(&mut contents, &members)?;
+        contents
+    };
+
+    any.set_by_number(1, Value::String(type_url)).unwrap();
+    any.set_by_number(2, Value::Bytes(contents.encode_to_vec())).unwrap();
+    Ok(any)
+}
+
+/// Parses fractional seconds with 1 to 9 digits, returning the number of
+/// nanoseconds.
+fn parse_nanos(fraction: &str) -> Option<i32> {
+    if fraction.is_empty() || fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut nanos = fraction.parse::<i32>().unwrap();
+    for _ in fraction.len()..9 {
+        nanos *= 10;
+    }
+    Some(nanos)
+}
+
+fn seconds_and_nanos_message(descriptor: MessageDescriptor, seconds: i64, nanos: i32) -> DynamicMessage {
+    let mut message = DynamicMessage::new(descriptor);
+    message.set_by_number(1, Value::I64(seconds)).unwrap();
+    message.set_by_number(2, Value::I32(nanos)).unwrap();
+    message
+}
+
+fn parse_timestamp(descriptor: MessageDescriptor, json: &Json) -> Result<DynamicMessage, JsonError> {
+    let invalid = || JsonError::new("invalid Timestamp");
+    let text = match *json {
+        Json::String(ref text) => text,
+        _ => return Err(invalid()),
+    };
+
+    // YYYY-MM-DDTHH:MM:SS[.fffffffff](Z|+HH:MM|-HH:MM)
+    let bytes = text.as_bytes();
+    if bytes.len() < 20 ||
+       bytes[4] != b'-' || bytes[7] != b'-' || bytes[10] != b'T' || bytes[13] != b':' || bytes[16] != b':' {
+        return Err(invalid());
+    }
+    let digits = |range: ::std::ops::Range<usize>| -> Option<i64> {
+        let digits = &text[range];
+        if digits.bytes().all(|b| b.is_ascii_digit()) { digits.parse().ok() } else { None }
+    };
+    let (year, month, day, hour, minute, second) =
+        match (digits(0..4), digits(5..7), digits(8..10), digits(11..13), digits(14..16), digits(17..19)) {
+            (Some(year), Some(month), Some(day), Some(hour), Some(minute), Some(second)) => {
+                (year, month, day, hour, minute, second)
+            },
+            _ => return Err(invalid()),
+        };
+    if month < 1 || month > 12 || day < 1 || day > days_in_month(year, month) ||
+       hour > 23 || minute > 59 || second > 59 {
+        return Err(invalid());
+    }
+
+    let mut rest = &text[19..];
+    let mut nanos = 0;
+    if rest.starts_with('.') {
+        let end = rest.find(|c: char| !c.is_digit(10) && c != '.').unwrap_or(rest.len());
+        nanos = parse_nanos(&rest[1..end]).ok_or_else(&invalid)?;
+        rest = &rest[end..];
+    }
+    let offset = if rest == "Z" {
+        0
+    } else if rest.len() == 6 && (rest.starts_with('+') || rest.starts_with('-')) && &rest[3..4] == ":" {
+        let hours = rest[1..3].parse::<i64>().map_err(|_| invalid())?;
+        let minutes = rest[4..6].parse::<i64>().map_err(|_| invalid())?;
+        let offset = hours * 3600 + minutes * 60;
+        if rest.starts_with('-') { -offset } else { offset }
+    } else {
+        return Err(invalid());
+    };
+
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    if seconds < MIN_TIMESTAMP_SECONDS || seconds > MAX_TIMESTAMP_SECONDS {
+        return Err(JsonError::new("Timestamp out of range"));
+    }
+    Ok(seconds_and_nanos_message(descriptor, seconds, nanos))
+}
+
+fn parse_duration(descriptor: MessageDescriptor, json: &Json) -> Result<DynamicMessage, JsonError> {
+    let invalid = || JsonError::new("invalid Duration");
+    let text = match *json {
+        Json::String(ref text) if text.ends_with('s') => &text[..text.len() - 1],
+        _ => return Err(invalid()),
+    };
+    let (negative, text) = if text.starts_with('-') { (true, &text[1..]) } else { (false, text) };
+    let (seconds, fraction) = match text.find('.') {
+        Some(idx) => (&text[..idx], Some(&text[idx + 1..])),
+        None => (text, None),
+    };
+    if seconds.is_empty() || !seconds.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let seconds = seconds.parse::<i64>().map_err(|_| invalid())?;
+    let nanos = match fraction {
+        Some(fraction) => parse_nanos(fraction).ok_or_else(&invalid)?,
+        None => 0,
+    };
+    if seconds > MAX_DURATION_SECONDS {
+        return Err(JsonError::new("Duration out of range"));
+    }
+    let (seconds, nanos) = if negative { (-seconds, -nanos) } else { (seconds, nanos) };
+    Ok(seconds_and_nanos_message(descriptor, seconds, nanos))
+}
+
+fn parse_field_mask(descriptor: MessageDescriptor, json: &Json) -> Result<DynamicMessage, JsonError> {
+    let text = match *json {
+        Json::String(ref text) => text,
+        _ => return Err(JsonError::new("invalid FieldMask")),
+    };
+    let mut paths = Vec::new();
+    for path in text.split(',').filter(|path| !path.is_empty()) {
+        if path.contains('_') {
+            return Err(JsonError::new(format!("invalid FieldMask path: {}", path)));
+        }
+        let mut snake = String::new();
+        for c in path.chars() {
+            if c.is_uppercase() {
+                snake.push('_');
+                snake.extend(c.to_lowercase());
+            } else {
+                snake.push(c);
+            }
+        }
+        paths.push(Value::String(snake));
+    }
+    let mut message = DynamicMessage::new(descriptor);
+    if !paths.is_empty() {
+        message.set_by_number(1, Value::List(paths)).unwrap();
+    }
+    Ok(message)
+}
+
+fn parse_struct(descriptor: MessageDescriptor, json: &Json) -> Result<DynamicMessage, JsonError> {
+    let members = match *json {
+        Json::Object(ref members) => members,
+        _ => return Err(JsonError::new("expected object for message type google.protobuf.Struct")),
+    };
+    let value_type = descriptor.field_message_type(1).unwrap().field_message_type(2).unwrap();
+    let mut entries: Vec<(Value, Value)> = Vec::new();
+    for &(ref key, ref value) in members {
+        let key = Value::String(key.clone());
+        if entries.iter().any(|&(ref k, _)| *k == key) {
+            return Err(JsonError::new("duplicate key in Struct"));
+        }
+        let value = parse_value_message(value_type.clone(), value)?;
+        entries.push((key, Value::Message(value)));
+    }
+    let mut message = DynamicMessage::new(descriptor);
+    if !entries.is_empty() {
+        message.set_by_number(1, Value::Map(entries)).unwrap();
+    }
+    Ok(message)
+}
+
+fn parse_value_message(descriptor: MessageDescriptor, json: &Json) -> Result<DynamicMessage, JsonError> {
+    let (number, value) = match *json {
+        Json::Null => (1, Value::Enum(0)),
+        Json::Number(ref number) => match number.parse::<f64>() {
+            Ok(number) if number.is_finite() => (2, Value::F64(number)),
+            _ => return Err(JsonError::new(format!("number out of range: {}", number))),
+        },
+        Json::String(ref value) => (3, Value::String(value.clone())),
+        Json::Bool(value) => (4, Value::Bool(value)),
+        Json::Object(..) => {
+            let struct_type = descriptor.field_message_type(5).unwrap();
+            (5, Value::Message(parse_struct(struct_type, json)?))
+        },
+        Json::Array(..) => {
+            let list_type = descriptor.field_message_type(6).unwrap();
+            (6, Value::Message(parse_list_value(list_type, json)?))
+        },
+    };
+    let mut message = DynamicMessage::new(descriptor);
+    message.set_by_number(number, value).unwrap();
+    Ok(message)
+}
+
+fn parse_list_value(descriptor: MessageDescriptor, json: &Json) -> Result<DynamicMessage, JsonError> {
+    let values = match *json {
+        Json::Array(ref values) => values,
+        _ => return Err(JsonError::new("expected array for message type google.protobuf.ListValue")),
+    };
+    let value_type = descriptor.field_message_type(1).unwrap();
+    let values = values.iter()
+                       .map(|value| parse_value_message(value_type.clone(), value).map(Value::Message))
+                       .collect::<Result<Vec<_>, _>>()?;
+    let mut message = DynamicMessage::new(descriptor);
+    if !values.is_empty() {
+        message.set_by_number(1, Value::List(values)).unwrap();
+    }
+    Ok(message)
+}
+
+// Dates, using the proleptic Gregorian calendar. See
+// http://howardhinnant.github.io/date_algorithms.html.
+
+trait DivEuclid {
+    fn div_euclid_compat(self, rhs: Self) -> Self;
+}
+
+impl DivEuclid for i64 {
+    fn div_euclid_compat(self, rhs: i64) -> i64 {
+        let quotient = self / rhs;
+        if self % rhs < 0 { quotient - 1 } else { quotient }
+    }
+}
+
+/// Returns the year, month and day of the given number of days since the Unix
+/// epoch.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid_compat(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Returns the number of days since the Unix epoch of the given date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid_compat(400);
+    let year_of_era = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * mp + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Base64.
+
+const BASE64_ALPHABET: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes bytes as standard base64, with padding.
+fn base64_encode(buf: &mut String, bytes: &[u8]) {
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as usize) << 16 | (b[1] as usize) << 8 | b[2] as usize;
+        buf.push(BASE64_ALPHABET[n >> 18 & 63] as char);
+        buf.push(BASE64_ALPHABET[n >> 12 & 63] as char);
+        buf.push(if chunk.len() > 1 { BASE64_ALPHABET[n >> 6 & 63] as char } else { '=' });
+        buf.push(if chunk.len() > 2 { BASE64_ALPHABET[n & 63] as char } else { '=' });
+    }
+}
+
+/// Decodes standard or URL-safe base64, with or without padding.
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_right_matches('=');
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut n = 0u32;
+    let mut bits = 0;
+    for b in text.bytes() {
+        let value = match b {
+            b if b.is_ascii_uppercase() => b - b'A',
+            b if b.is_ascii_lowercase() => b - b'a' + 26,
+            b if b.is_ascii_digit() => b - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+        n = n << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((n >> bits) as u8);
+        }
+    }
+    // A single trailing character does not encode a whole byte.
+    if bits >= 6 {
+        return None;
+    }
+    Some(bytes)
+}
+
+// JSON syntax.
+
+/// A JSON value. Numbers are kept as text, so that 64-bit integers can be
+/// converted without loss of precision, and object members are kept in
+/// order, so that duplicate names can be detected.
+#[derive(Clone, Debug, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+struct Reader<'a> {
+    text: &'a str,
+    pos: usize,
+    depth: usize,
+}
+
+impl <'a> Reader<'a> {
+
+    fn new(text: &'a str) -> Reader<'a> {
+        Reader {
+            text: text,
+            pos: 0,
+            depth: 0,
+        }
+    }
+
+    fn error(&self, message: &str) -> JsonError {
+        JsonError::new(format!("{} at offset {}", message, self.pos))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).cloned()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn consume(&mut self, b: u8) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(b) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn read_document(&mut self) -> Result<Json, JsonError> {
+        let value = self.read_value()?;
+        self.skip_whitespace();
+        if self.pos < self.text.len() {
+            return Err(self.error("unexpected trailing characters"));
+        }
+        Ok(value)
+    }
+
+    fn read_value(&mut self) -> Result<Json, JsonError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'n') => self.read_literal("null", Json::Null),
+            Some(b't') => self.read_literal("true", Json::Bool(true)),
+            Some(b'f') => self.read_literal("false", Json::Bool(false)),
+            Some(b'"') => self.read_string().map(Json::String),
+            Some(b'[') => {
+                self.pos += 1;
+                self.enter()?;
+                let mut values = Vec::new();
+                if !self.consume(b']') {
+                    loop {
+                        values.push(self.read_value()?);
+                        if self.consume(b']') {
+                            break;
+                        }
+                        if !self.consume(b',') {
+                            return Err(self.error("expected ',' or ']'"));
+                        }
+                    }
+                }
+                self.depth -= 1;
+                Ok(Json::Array(values))
+            },
+            Some(b'{') => {
+                self.pos += 1;
+                self.enter()?;
+                let mut members = Vec::new();
+                if !self.consume(b'}') {
+                    loop {
+                        self.skip_whitespace();
+                        if self.peek() != Some(b'"') {
+                            return Err(self.error("expected string"));
+                        }
+                        let name = self.read_string()?;
+                        if !self.consume(b':') {
+                            return Err(self.error("expected ':'"));
+                        }
+                        members.push((name, self.read_value()?));
+                        if self.consume(b'}') {
+                            break;
+                        }
+                        if !self.consume(b',') {
+                            return Err(self.error("expected ',' or '}'"));
+                        }
+                    }
+                }
+                self.depth -= 1;
+                Ok(Json::Object(members))
+            },
+            Some(b) if b == b'-' || b.is_ascii_digit() => self.read_number().map(Json::Number),
+            Some(..) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), JsonError> {
+        self.depth += 1;
+        if self.depth > RECURSION_LIMIT as usize {
+            return Err(self.error("recursion limit reached"));
+        }
+        Ok(())
+    }
+
+    fn read_literal(&mut self, literal: &str, value: Json) -> Result<Json, JsonError> {
+        if self.text[self.pos..].starts_with(literal) {
+            self.pos += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("unexpected character"))
+        }
+    }
+
+    fn read_digits(&mut self) -> usize {
+        let start = self.pos;
+        while self.peek().map_or(false, |b| b.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    fn read_number(&mut self) -> Result<String, JsonError> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        if self.peek() == Some(b'0') {
+            self.pos += 1;
+        } else if self.read_digits() == 0 {
+            return Err(self.error("invalid number"));
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if self.read_digits() == 0 {
+                return Err(self.error("invalid number"));
+            }
+        }
+        if let Some(b'e') | Some(b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+') | Some(b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if self.read_digits() == 0 {
+                return Err(self.error("invalid number"));
+            }
+        }
+        Ok(self.text[start..self.pos].to_string())
+    }
+
+    fn read_hex4(&mut self) -> Result<u32, JsonError> {
+        let digits = match self.text.get(self.pos..self.pos + 4) {
+            Some(digits) if digits.bytes().all(|b| (b as char).is_digit(16)) => digits,
+            _ => return Err(self.error("invalid unicode escape")),
+        };
+        self.pos += 4;
+        Ok(u32::from_str_radix(digits, 16).unwrap())
+    }
+
+    fn read_string(&mut self) -> Result<String, JsonError> {
+        // Skip the opening quote.
+        self.pos += 1;
+        let mut string = String::new();
+        loop {
+            let c = match self.text[self.pos..].chars().next() {
+                Some(c) => c,
+                None => return Err(self.error("unterminated string")),
+            };
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Ok(string),
+                '\\' => {
+                    let escape = match self.peek() {
+                        Some(escape) => escape,
+                        None => return Err(self.error("unterminated string")),
+                    };
+                    self.pos += 1;
+                    match escape {
+                        b'"' => string.push('"'),
+                        b'\\' => string.push('\\'),
+                        b'/' => string.push('/'),
+                        b'b' => string.push('\x08'),
+                        b'f' => string.push('\x0c'),
+                        b'n' => string.push('\n'),
+                        b'r' => string.push('\r'),
+                        b't' => string.push('\t'),
+                        b'u' => {
+                            let mut code = self.read_hex4()?;
+                            if code >= 0xd800 && code < 0xdc00 {
+                                // A high surrogate, which must be followed by a low surrogate.
+                                if !self.text[self.pos..].starts_with("\\u") {
+                                    return Err(self.error("invalid surrogate pair"));
+                                }
+                                self.pos += 2;
+                                let low = self.read_hex4()?;
+                                if low < 0xdc00 || low >= 0xe000 {
+                                    return Err(self.error("invalid surrogate pair"));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            match char::from_u32(code) {
+                                Some(c) => string.push(c),
+                                None => return Err(self.error("invalid unicode escape")),
+                            }
+                        },
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                },
+                c if (c as u32) < 0x20 => return Err(self.error("control character in string")),
+                c => string.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_json() {
+        let json = Reader::new(r#" {"a": [1, -0.5e+3, "x\u00e9\ud83d\ude00\n"], "b": {}, "c": null, "d": true} "#)
+                              .read_document()
+                              .unwrap();
+        assert_eq!(Json::Object(vec![
+            ("a".to_string(), Json::Array(vec![Json::Number("1".to_string()),
+                                               Json::Number("-0.5e+3".to_string()),
+                                               Json::String("x\u{e9}\u{1f600}\n".to_string())])),
+            ("b".to_string(), Json::Object(vec![])),
+            ("c".to_string(), Json::Null),
+            ("d".to_string(), Json::Bool(true)),
+        ]), json);
+
+        for invalid in &["", "01", "1.", "+1", "[1,]", "{\"a\" 1}", "\"\\x\"", "\"\t\"", "nul", "1 2",
+                         "\"\\ud83d\"", "{\"a\":1,}"] {
+            assert!(Reader::new(invalid).read_document().is_err(), "{}", invalid);
+        }
+        let deep = "[".repeat(RECURSION_LIMIT as usize + 1) + &"]".repeat(RECURSION_LIMIT as usize + 1);
+        assert!(Reader::new(&deep).read_document().is_err());
+    }
+
+    /// Returns a `google.protobuf.Value` holding `depth` nested lists, in JSON
+    /// and as a message.
+    fn nested_lists(depth: usize) -> (String, ::prost_types::Value) {
+        use prost_types::value::Kind;
+        let mut value = ::prost_types::Value { kind: Some(Kind::ListValue(Default::default())) };
+        for _ in 1..depth {
+            value = ::prost_types::Value {
+                kind: Some(Kind::ListValue(::prost_types::ListValue { values: vec![value] })),
+            };
+        }
+        ("[".repeat(depth) + &"]".repeat(depth), value)
+    }
+
+    #[test]
+    fn test_recursion_limit() {
+        // Each nested list is a `ListValue` in a `Value`, so 50 lists are
+        // exactly at the recursion limit.
+        let (json, value) = nested_lists(50);
+        assert_eq!(json, value.to_json().unwrap());
+        assert_eq!(value, ::prost_types::Value::from_json(&json).unwrap());
+
+        let (json, value) = nested_lists(60);
+        assert_eq!("failed to decode message: failed to decode Protobuf message: recursion limit reached",
+                   value.to_json().unwrap_err().to_string());
+        assert_eq!("messages are nested more than 100 levels deep",
+                   ::prost_types::Value::from_json(&json).unwrap_err().to_string());
+
+        // Struct fields are map entries, which count as a level.
+        let json = "{\"a\": ".repeat(34) + "1" + &"}".repeat(34);
+        assert!(::prost_types::Value::from_json(&json).is_err());
+        let json = "{\"a\": ".repeat(33) + "1" + &"}".repeat(33);
+        assert!(::prost_types::Value::from_json(&json).is_ok());
+    }
+
+    #[test]
+    fn test_base64() {
+        for &(bytes, encoded) in &[(&b""[..], ""),
+                                   (&b"f"[..], "Zg=="),
+                                   (&b"fo"[..], "Zm8="),
+                                   (&b"foo"[..], "Zm9v"),
+                                   (&[0xfb, 0xff][..], "+/8=")] {
+            let mut buf = String::new();
+            base64_encode(&mut buf, bytes);
+            assert_eq!(encoded, buf);
+            assert_eq!(Some(bytes.to_vec()), base64_decode(encoded));
+        }
+        assert_eq!(Some(vec![0xfb, 0xff]), base64_decode("-_8"));
+        assert_eq!(None, base64_decode("Z"));
+        assert_eq!(None, base64_decode("Zm9v!"));
+    }
+
+    #[test]
+    fn test_civil_days() {
+        for &(days, date) in &[(0, (1970, 1, 1)),
+                               (-1, (1969, 12, 31)),
+                               (11016, (2000, 2, 29)),
+                               (MIN_TIMESTAMP_SECONDS / 86400, (1, 1, 1)),
+                               (MAX_TIMESTAMP_SECONDS / 86400, (9999, 12, 31))] {
+            assert_eq!(date, civil_from_days(days));
+            assert_eq!(days, days_from_civil(date.0, date.1, date.2));
+        }
+    }
+}
@@ -0,0 +1,23 @@
+#![doc(html_root_url = "https://docs.rs/prost-reflect/0.1.1")]
+
+//! Runtime reflection, dynamic messages, and the JSON and text formats for
+//! Protocol Buffers messages.
+//!
+//! Code generated by `prost-codegen` with the `reflection` or `json` option
+//! refers to this crate, which the crate containing the generated code must
+//! depend on. `prost-codegen` itself is only needed at build time.
+
+#[macro_use]
+extern crate prost_derive;
+
+extern crate bytes;
+extern crate prost;
+extern crate prost_types;
+
+pub mod diff;
+pub mod dynamic;
+pub mod google;
+pub mod json;
+pub mod reflect;
+pub mod text_format;
+mod well_known_types;
@@ -0,0 +1,219 @@
+//! Runtime reflection for generated messages.
+//!
+//! When code is generated with the `reflection` option (see
+//! `prost_codegen::Config::reflection`), each generated module embeds the
+//! encoded `FileDescriptorSet` of its `.proto` files and their dependencies,
+//! and each generated message implements `ReflectMessage`. Generic code can
+//! then walk any generated message through its descriptor, without per-type
+//! code.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{
+    Arc,
+    Mutex,
+    Once,
+};
+
+use prost::Message;
+
+use dynamic::DescriptorPool;
+use google::protobuf::{
+    DescriptorProto,
+    FieldDescriptorProto,
+    FileDescriptorSet,
+};
+
+/// The value of a message field, borrowed from the message.
+#[derive(Clone, Debug)]
+pub enum Value<'a> {
+    Bool(bool),
+    I32(i32),
+    I64(i64),
+    U32(u32),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    String(&'a str),
+    Bytes(&'a [u8]),
+    /// An enum value, which may not be one of the values declared by the
+    /// enum type.
+    Enum(i32),
+    Message(&'a ReflectMessage),
+    /// An embedded message whose type does not implement `ReflectMessage`,
+    /// for instance because it was generated without the `reflection`
+    /// option, in its encoded form.
+    EncodedMessage(Vec<u8>),
+    /// The values of a repeated field.
+    List(Vec<Value<'a>>),
+    /// The entries of a map field, in unspecified order.
+    Map(Vec<(Value<'a>, Value<'a>)>),
+}
+
+/// A message which provides its descriptor, and access to its fields by
+/// number or by name.
+///
+/// `ReflectMessage` is implemented by code generated with the `reflection`
+/// option. Unlike `Message`, it may be used as a trait object.
+pub trait ReflectMessage: fmt::Debug {
+
+    /// Returns the descriptor of the message type.
+    fn descriptor(&self) -> &'static DescriptorProto;
+
+    /// Returns the fully qualified name of the message type, without a
+    /// leading period, e.g. `google.protobuf.Timestamp`.
+    fn full_name(&self) -> &'static str;
+
+    /// Returns the value of the field with the given number, or `None` if the
+    /// message has no such field, or the field is an optional or oneof field
+    /// which is not set.
+    fn field(&self, number: i32) -> Option<Value>;
+
+    /// Returns the value of the field with the given name, or `None` if the
+    /// message has no such field, or the field is an optional or oneof field
+    /// which is not set.
+    fn field_by_name(&self, name: &str) -> Option<Value> {
+        self.descriptor()
+            .field
+            .iter()
+            .find(|field| field.name() == name)
+            .and_then(|field| self.field(field.number()))
+    }
+
+    /// Returns the descriptor and value of each field which is not an unset
+    /// optional or oneof field, in declaration order.
+    fn fields(&self) -> Vec<(&'static FieldDescriptorProto, Value)> {
+        self.descriptor()
+            .field
+            .iter()
+            .filter_map(|field| self.field(field.number()).map(|value| (field, value)))
+            .collect()
+    }
+}
+
+/// Returns the decoded form of an embedded `FileDescriptorSet`.
+///
+/// The descriptor set is decoded on first use, and cached for the lifetime of
+/// the program.
+pub fn file_descriptor_set(encoded: &'static [u8]) -> &'static FileDescriptorSet {
+    let mut cache = cache().lock().unwrap();
+    let set = cache.entry(encoded.as_ptr() as usize).or_insert_with(|| {
+        let set = FileDescriptorSet::decode_from_slice(encoded).expect("invalid embedded descriptor set");
+        unsafe { &*Box::into_raw(Box::new(set)) }
+    });
+    *set
+}
+
+/// Returns a `DescriptorPool` containing the types in an embedded
+/// `FileDescriptorSet`.
+///
+/// The pool is built on first use, and cached for the lifetime of the program.
+pub fn descriptor_pool(encoded: &'static [u8]) -> Arc<DescriptorPool> {
+    let mut pools = pools().lock().unwrap();
+    pools.entry(encoded.as_ptr() as usize).or_insert_with(|| {
+        Arc::new(DescriptorPool::new(file_descriptor_set(encoded).clone()).expect("invalid embedded descriptor set"))
+    }).clone()
+}
+
+/// Returns the descriptor of the message type with the given fully qualified
+/// name from an embedded `FileDescriptorSet`.
+///
+/// Panics if the descriptor set does not contain the message type.
+pub fn message_descriptor(encoded: &'static [u8], full_name: &str) -> &'static DescriptorProto {
+    find_message(file_descriptor_set(encoded), full_name).unwrap_or_else(|| {
+        panic!("message type {} not found in embedded descriptor set", full_name)
+    })
+}
+
+/// Returns the descriptor of the message type with the given fully qualified
+/// name, which may have a leading period, from a `FileDescriptorSet`.
+pub fn find_message<'a>(set: &'a FileDescriptorSet, full_name: &str) -> Option<&'a DescriptorProto> {
+    let full_name = full_name.trim_left_matches('.');
+    set.file.iter().filter_map(|file| {
+        let package = file.package();
+        let name = if package.is_empty() {
+            full_name
+        } else if full_name.starts_with(package) && full_name[package.len()..].starts_with('.') {
+            &full_name[package.len() + 1..]
+        } else {
+            return None;
+        };
+
+        let mut path = name.split('.');
+        let mut message = path.next().and_then(|name| {
+            file.message_type.iter().find(|message| message.name() == name)
+        });
+        for name in path {
+            message = message.and_then(|message| {
+                message.nested_type.iter().find(|nested| nested.name() == name)
+            });
+        }
+        message
+    }).next()
+}
+
+fn cache() -> &'static Mutex<HashMap<usize, &'static FileDescriptorSet>> {
+    static INIT: Once = Once::new();
+    static mut CACHE: *const Mutex<HashMap<usize, &'static FileDescriptorSet>> =
+        0 as *const Mutex<HashMap<usize, &'static FileDescriptorSet>>;
+    unsafe {
+        INIT.call_once(|| CACHE = Box::into_raw(Box::new(Mutex::new(HashMap::new()))));
+        &*CACHE
+    }
+}
+
+fn pools() -> &'static Mutex<HashMap<usize, Arc<DescriptorPool>>> {
+    static INIT: Once = Once::new();
+    static mut POOLS: *const Mutex<HashMap<usize, Arc<DescriptorPool>>> =
+        0 as *const Mutex<HashMap<usize, Arc<DescriptorPool>>>;
+    unsafe {
+        INIT.call_once(|| POOLS = Box::into_raw(Box::new(Mutex::new(HashMap::new()))));
+        &*POOLS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use google::protobuf::FileDescriptorProto;
+
+    fn message(name: &str, nested: Vec<DescriptorProto>) -> DescriptorProto {
+        DescriptorProto {
+            name: Some(name.to_string()),
+            nested_type: nested,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_find_message() {
+        let set = FileDescriptorSet {
+            file: vec![
+                FileDescriptorProto {
+                    name: Some("foo.proto".to_string()),
+                    package: Some("foo.bar".to_string()),
+                    message_type: vec![message("Outer", vec![message("Inner", vec![])])],
+                    ..Default::default()
+                },
+                FileDescriptorProto {
+                    name: Some("root.proto".to_string()),
+                    message_type: vec![message("Root", vec![])],
+                    ..Default::default()
+                },
+            ],
+        };
+
+        assert_eq!(Some("Outer"), find_message(&set, "foo.bar.Outer").map(DescriptorProto::name));
+        assert_eq!(Some("Inner"), find_message(&set, ".foo.bar.Outer.Inner").map(DescriptorProto::name));
+        assert_eq!(Some("Root"), find_message(&set, "Root").map(DescriptorProto::name));
+        assert!(find_message(&set, "foo.barOuter").is_none());
+        assert!(find_message(&set, "foo.bar.Inner").is_none());
+
+        let encoded: &'static [u8] = Box::leak(set.encode_to_vec().into_boxed_slice());
+        let inner = message_descriptor(encoded, "foo.bar.Outer.Inner");
+        assert_eq!("Inner", inner.name());
+        assert!(::std::ptr::eq(file_descriptor_set(encoded), file_descriptor_set(encoded)));
+        assert!(Arc::ptr_eq(&descriptor_pool(encoded), &descriptor_pool(encoded)));
+    }
+}
@@ -0,0 +1,1104 @@
+//! The protobuf text format.
+//!
+//! Text format is the human readable representation of protobuf messages used
+//! by `protoc` and the official protobuf libraries, e.g.
+//! `field: 1 nested { name: "foo" }`. It is commonly used to write test
+//! fixtures and to inspect messages while debugging.
+//!
+//! Messages are printed and parsed through their descriptors, as
+//! `DynamicMessage`s. Messages generated with the `reflection` option can be
+//! printed and parsed directly with `print_message` and `parse_message`.
+//!
+//! Extension fields are written as `[package.extension_name]`, and an
+//! `google.protobuf.Any` whose type is in the descriptor pool is expanded as
+//! `[type.googleapis.com/package.MessageType] { ... }`. Unknown fields are not
+//! printed.
+
+use std::collections::BTreeMap;
+use std::char;
+use std::error;
+use std::fmt::{
+    self,
+    Write,
+};
+use std::sync::Arc;
+
+use prost::{
+    DecodeError,
+    Message,
+};
+use prost::encoding::RECURSION_LIMIT;
+
+use dynamic::{
+    DescriptorPool,
+    DynamicMessage,
+    EnumDescriptor,
+    MessageDescriptor,
+    Value,
+};
+use google::protobuf::FieldDescriptorProto;
+use google::protobuf::field_descriptor_proto::{
+    Label,
+    Type,
+};
+use reflect::ReflectMessage;
+
+const ANY: &'static str = "google.protobuf.Any";
+
+/// Prints the message in text format, with one field per line.
+pub fn print(message: &DynamicMessage) -> String {
+    let mut printer = Printer {
+        buf: String::new(),
+        depth: 0,
+    };
+    printer.print_message(message);
+    printer.buf
+}
+
+/// Prints a generated message in text format, using its descriptor in the
+/// pool.
+///
+/// The message is printed by decoding it as a `DynamicMessage`, which fails
+/// if it is nested more deeply than `prost::encoding::RECURSION_LIMIT`.
+///
+/// Panics if the pool does not contain the message type.
+pub fn print_message<M>(message: &M, pool: &Arc<DescriptorPool>) -> Result<String, DecodeError>
+where M: Message + ReflectMessage {
+    let descriptor = MessageDescriptor::new(pool.clone(), message.full_name()).unwrap_or_else(|| {
+        panic!("message type {} not found in descriptor pool", message.full_name())
+    });
+    let message = DynamicMessage::decode(descriptor, &message.encode_to_vec())?;
+    Ok(print(&message))
+}
+
+/// Parses a message of the given type from text format.
+///
+/// Messages may be nested at most `prost::encoding::RECURSION_LIMIT` levels
+/// deep, the same limit which applies when decoding.
+pub fn parse(descriptor: MessageDescriptor, text: &str) -> Result<DynamicMessage, ParseError> {
+    Parser::new(text).parse_message(descriptor, None)
+}
+
+/// Parses a generated message from text format, using its descriptor in the
+/// pool.
+///
+/// Panics if the pool does not contain the message type.
+pub fn parse_message<M>(pool: &Arc<DescriptorPool>, text: &str) -> Result<M, ParseError>
+where M: Message + ReflectMessage + Default {
+    let full_name = M::default().full_name();
+    let descriptor = MessageDescriptor::new(pool.clone(), full_name).unwrap_or_else(|| {
+        panic!("message type {} not found in descriptor pool", full_name)
+    });
+    let mut parser = Parser::new(text);
+    let message = parser.parse_message(descriptor, None)?;
+    M::decode_from_slice(&message.encode_to_vec()).map_err(|error| {
+        parser.error(format!("failed to decode parsed message: {}", error))
+    })
+}
+
+/// An error returned when parsing text format fails.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    line: usize,
+    column: usize,
+    message: String,
+}
+
+impl ParseError {
+
+    /// Returns the line of the input at which the error occurred, starting at 1.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Returns the column of the input at which the error occurred, starting
+    /// at 1.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl error::Error for ParseError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+struct Printer {
+    buf: String,
+    depth: usize,
+}
+
+impl Printer {
+
+    fn print_message(&mut self, message: &DynamicMessage) {
+        if message.descriptor().full_name() == ANY && self.print_any(message) {
+            return;
+        }
+
+        let descriptor = message.descriptor();
+        for (field, value) in message.fields() {
+            let number = field.number() as u32;
+            let name = match descriptor.extension_name(number) {
+                Some(name) => format!("[{}]", name),
+                // Groups are named by their message type.
+                None if field.type_() == Some(Type::TypeGroup) => {
+                    descriptor.field_message_type(number).unwrap().descriptor().name().to_string()
+                },
+                None => field.name().to_string(),
+            };
+            let enum_type = descriptor.field_enum_type(number);
+
+            match *value {
+                Value::List(ref values) => {
+                    for value in values {
+                        self.print_field(&name, value, enum_type.as_ref());
+                    }
+                },
+                Value::Map(ref entries) => {
+                    let value_enum_type = descriptor.field_message_type(number).unwrap().field_enum_type(2);
+                    for &(ref key, ref value) in entries {
+                        self.start_message(&name);
+                        self.print_field("key", key, None);
+                        self.print_field("value", value, value_enum_type.as_ref());
+                        self.end_message();
+                    }
+                },
+                ref value => self.print_field(&name, value, enum_type.as_ref()),
+            }
+        }
+    }
+
+    /// Prints an `Any` message with its contents expanded, returning `false`
+    /// if the contained message type is not in the pool, or the contained
+    /// message can not be decoded.
+    fn print_any(&mut self, message: &DynamicMessage) -> bool {
+        let (type_url, value) = match (message.get_by_number(1), message.get_by_number(2)) {
+            (Some(&Value::String(ref type_url)), Some(&Value::Bytes(ref value))) => (type_url, &value[..]),
+            (Some(&Value::String(ref type_url)), None) => (type_url, &[][..]),
+            _ => return false,
+        };
+        let type_name = match type_url.rfind('/') {
+            Some(idx) => &type_url[idx + 1..],
+            None => return false,
+        };
+        let descriptor = match MessageDescriptor::new(message.descriptor().pool().clone(), type_name) {
+            Some(descriptor) => descriptor,
+            None => return false,
+        };
+        let contents = match DynamicMessage::decode(descriptor, value) {
+            Ok(contents) => contents,
+            Err(..) => return false,
+        };
+        self.start_message(&format!("[{}]", type_url));
+        self.print_message(&contents);
+        self.end_message();
+        true
+    }
+
+    fn print_field(&mut self, name: &str, value: &Value, enum_type: Option<&EnumDescriptor>) {
+        if let Value::Message(ref message) = *value {
+            self.start_message(name);
+            self.print_message(message);
+            self.end_message();
+            return;
+        }
+
+        self.indent();
+        self.buf.push_str(name);
+        self.buf.push_str(": ");
+        match *value {
+            Value::Bool(value) => write!(self.buf, "{}", value).unwrap(),
+            Value::I32(value) => write!(self.buf, "{}", value).unwrap(),
+            Value::I64(value) => write!(self.buf, "{}", value).unwrap(),
+            Value::U32(value) => write!(self.buf, "{}", value).unwrap(),
+            Value::U64(value) => write!(self.buf, "{}", value).unwrap(),
+            Value::F32(value) => print_float(&mut self.buf, value as f64, &format!("{:?}", value)),
+            Value::F64(value) => print_float(&mut self.buf, value, &format!("{:?}", value)),
+            Value::String(ref value) => print_string(&mut self.buf, value),
+            Value::Bytes(ref value) => print_bytes(&mut self.buf, value),
+            Value::Enum(value) => match enum_type.and_then(|enum_type| enum_type.value_name(value)) {
+                Some(name) => self.buf.push_str(name),
+                None => write!(self.buf, "{}", value).unwrap(),
+            },
+            Value::Message(..) | Value::List(..) | Value::Map(..) => unreachable!(),
+        }
+        self.buf.push('\n');
+    }
+
+    fn start_message(&mut self, name: &str) {
+        self.indent();
+        self.buf.push_str(name);
+        self.buf.push_str(" {\n");
+        self.depth += 1;
+    }
+
+    fn end_message(&mut self) {
+        self.depth -= 1;
+        self.indent();
+        self.buf.push_str("}\n");
+    }
+
+    fn indent(&mut self) {
+        for _ in 0..self.depth {
+            self.buf.push_str("  ");
+        }
+    }
+}
+
+/// Prints a floating point value. `formatted` is the shortest representation
+/// of the value which parses back to the same value.
+fn print_float(buf: &mut String, value: f64, formatted: &str) {
+    if value.is_nan() {
+        buf.push_str("nan");
+    } else if value.is_infinite() {
+        buf.push_str(if value > 0.0 { "inf" } else { "-inf" });
+    } else {
+        buf.push_str(formatted);
+    }
+}
+
+/// Prints a quoted string. Non-ASCII characters are printed as-is.
+fn print_string(buf: &mut String, value: &str) {
+    buf.push('"');
+    for c in value.chars() {
+        if (c as u32) < 0x80 {
+            escape_byte(buf, c as u8);
+        } else {
+            buf.push(c);
+        }
+    }
+    buf.push('"');
+}
+
+/// Prints a quoted byte string. Non-ASCII bytes are printed as octal escapes.
+fn print_bytes(buf: &mut String, value: &[u8]) {
+    buf.push('"');
+    for &byte in value {
+        escape_byte(buf, byte);
+    }
+    buf.push('"');
+}
+
+fn escape_byte(buf: &mut String, byte: u8) {
+    match byte {
+        b'\n' => buf.push_str("\\n"),
+        b'\r' => buf.push_str("\\r"),
+        b'\t' => buf.push_str("\\t"),
+        b'"' => buf.push_str("\\\""),
+        b'\'' => buf.push_str("\\'"),
+        b'\\' => buf.push_str("\\\\"),
+        byte if byte >= 0x20 && byte < 0x7f => buf.push(byte as char),
+        byte => write!(buf, "\\{:03o}", byte).unwrap(),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    /// An unsigned integer or floating point literal.
+    Number(String),
+    /// A string literal, with escapes processed.
+    String(Vec<u8>),
+    Symbol(char),
+}
+
+struct Tokenizer<'a> {
+    text: &'a str,
+    pos: usize,
+    line: usize,
+    column: usize,
+    /// The position of the most recently returned or peeked token.
+    token_line: usize,
+    token_column: usize,
+    peeked: Option<Option<Token>>,
+}
+
+impl <'a> Tokenizer<'a> {
+
+    fn new(text: &'a str) -> Tokenizer<'a> {
+        Tokenizer {
+            text: text,
+            pos: 0,
+            line: 1,
+            column: 1,
+            token_line: 1,
+            token_column: 1,
+            peeked: None,
+        }
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            line: self.token_line,
+            column: self.token_column,
+            message: message,
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<Token>, ParseError> {
+        if self.peeked.is_none() {
+            let token = self.read_token()?;
+            self.peeked = Some(token);
+        }
+        Ok(self.peeked.clone().unwrap())
+    }
+
+    fn next(&mut self) -> Result<Option<Token>, ParseError> {
+        match self.peeked.take() {
+            Some(token) => Ok(token),
+            None => self.read_token(),
+        }
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = match self.peek_char() {
+            Some(c) => c,
+            None => return None,
+        };
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        loop {
+            match self.peek_char() {
+                Some(c) if c.is_whitespace() => {
+                    self.bump();
+                },
+                Some('#') => {
+                    while let Some(c) = self.bump() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                },
+                _ => return,
+            }
+        }
+    }
+
+    fn read_token(&mut self) -> Result<Option<Token>, ParseError> {
+        self.skip_whitespace();
+        self.token_line = self.line;
+        self.token_column = self.column;
+
+        let c = match self.peek_char() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        let is_number = c.is_digit(10) ||
+                        (c == '.' && self.text[self.pos + 1..].chars().next().map_or(false, |c| c.is_digit(10)));
+        if c.is_alphabetic() || c == '_' {
+            let mut ident = String::new();
+            while let Some(c) = self.peek_char() {
+                if !c.is_alphanumeric() && c != '_' {
+                    break;
+                }
+                ident.push(c);
+                self.bump();
+            }
+            Ok(Some(Token::Ident(ident)))
+        } else if is_number {
+            let mut number = String::new();
+            while let Some(c) = self.peek_char() {
+                let exponent_sign = (c == '+' || c == '-') &&
+                                    (number.ends_with('e') || number.ends_with('E')) &&
+                                    !number.starts_with("0x") && !number.starts_with("0X");
+                if !c.is_alphanumeric() && c != '.' && c != '_' && !exponent_sign {
+                    break;
+                }
+                number.push(c);
+                self.bump();
+            }
+            Ok(Some(Token::Number(number)))
+        } else if c == '"' || c == '\'' {
+            self.bump();
+            self.read_string(c).map(|string| Some(Token::String(string)))
+        } else {
+            self.bump();
+            Ok(Some(Token::Symbol(c)))
+        }
+    }
+
+    /// Reads the remainder of a string literal, after the opening quote.
+    fn read_string(&mut self, quote: char) -> Result<Vec<u8>, ParseError> {
+        let mut string = Vec::new();
+        loop {
+            let c = match self.bump() {
+                Some('\n') | None => return Err(self.error("unterminated string literal".to_string())),
+                Some(c) => c,
+            };
+            if c == quote {
+                return Ok(string);
+            }
+            if c != '\\' {
+                let mut utf8 = [0; 4];
+                string.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                continue;
+            }
+
+            let c = match self.bump() {
+                Some(c) => c,
+                None => return Err(self.error("unterminated string literal".to_string())),
+            };
+            match c {
+                'a' => string.push(0x07),
+                'b' => string.push(0x08),
+                'f' => string.push(0x0c),
+                'n' => string.push(b'\n'),
+                'r' => string.push(b'\r'),
+                't' => string.push(b'\t'),
+                'v' => string.push(0x0b),
+                '\\' | '\'' | '"' | '?' => string.push(c as u8),
+                c if c.is_digit(8) => {
+                    let mut value = c.to_digit(8).unwrap();
+                    for _ in 0..2 {
+                        match self.peek_char().and_then(|c| c.to_digit(8)) {
+                            Some(digit) => {
+                                value = value * 8 + digit;
+                                self.bump();
+                            },
+                            None => break,
+                        }
+                    }
+                    if value > 0xff {
+                        return Err(self.error("octal escape sequence out of range".to_string()));
+                    }
+                    string.push(value as u8);
+                },
+                'x' | 'X' => {
+                    let value = self.read_hex_digits(1, 2)?;
+                    string.push(value as u8);
+                },
+                'u' | 'U' => {
+                    let digits = if c == 'u' { 4 } else { 8 };
+                    let value = self.read_hex_digits(digits, digits)?;
+                    let c = match char::from_u32(value) {
+                        Some(c) => c,
+                        None => return Err(self.error("invalid unicode escape sequence".to_string())),
+                    };
+                    let mut utf8 = [0; 4];
+                    string.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                },
+                c => return Err(self.error(format!("invalid escape sequence: \\{}", c))),
+            }
+        }
+    }
+
+    fn read_hex_digits(&mut self, min: usize, max: usize) -> Result<u32, ParseError> {
+        let mut value = 0;
+        for i in 0..max {
+            match self.peek_char().and_then(|c| c.to_digit(16)) {
+                Some(digit) => {
+                    value = value * 16 + digit;
+                    self.bump();
+                },
+                None if i >= min => break,
+                None => return Err(self.error("invalid hex escape sequence".to_string())),
+            }
+        }
+        Ok(value)
+    }
+}
+
+enum FloatLiteral {
+    Infinity,
+    Nan,
+    Number(String),
+}
+
+struct Parser<'a> {
+    tokenizer: Tokenizer<'a>,
+    /// The number of enclosing messages of the message being parsed.
+    depth: u32,
+}
+
+impl <'a> Parser<'a> {
+
+    fn new(text: &'a str) -> Parser<'a> {
+        Parser {
+            tokenizer: Tokenizer::new(text),
+            depth: 0,
+        }
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        self.tokenizer.error(message)
+    }
+
+    fn error_at(&self, (line, column): (usize, usize), message: String) -> ParseError {
+        ParseError {
+            line: line,
+            column: column,
+            message: message,
+        }
+    }
+
+    /// Consumes the next token if it is the given symbol.
+    fn try_consume(&mut self, symbol: char) -> Result<bool, ParseError> {
+        if self.tokenizer.peek()? == Some(Token::Symbol(symbol)) {
+            self.tokenizer.next()?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), ParseError> {
+        if self.try_consume(symbol)? {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected '{}'", symbol)))
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String, ParseError> {
+        match self.tokenizer.peek()? {
+            Some(Token::Ident(ident)) => {
+                self.tokenizer.next()?;
+                Ok(ident)
+            },
+            _ => Err(self.error("expected identifier".to_string())),
+        }
+    }
+
+    /// Parses the fields of a message, up to the terminator, or the end of
+    /// the input if there is no terminator.
+    fn parse_message(&mut self,
+                     descriptor: MessageDescriptor,
+                     terminator: Option<char>)
+                     -> Result<DynamicMessage, ParseError> {
+        let mut fields = BTreeMap::new();
+        loop {
+            match (self.tokenizer.peek()?, terminator) {
+                (None, None) => break,
+                (None, Some(terminator)) => return Err(self.error(format!("expected '{}'", terminator))),
+                (Some(Token::Symbol(c)), Some(terminator)) if c == terminator => {
+                    self.tokenizer.next()?;
+                    break;
+                },
+                _ => (),
+            }
+            self.parse_field(&descriptor, &mut fields)?;
+            if !self.try_consume(';')? {
+                self.try_consume(',')?;
+            }
+        }
+
+        let mut message = DynamicMessage::new(descriptor);
+        for (number, value) in fields {
+            message.set_by_number(number, value).map_err(|error| self.error(error.to_string()))?;
+        }
+        Ok(message)
+    }
+
+    fn parse_field(&mut self,
+                   descriptor: &MessageDescriptor,
+                   fields: &mut BTreeMap<u32, Value>)
+                   -> Result<(), ParseError> {
+        // Errors concerning the field as a whole are reported at its name.
+        self.tokenizer.peek()?;
+        let position = (self.tokenizer.token_line, self.tokenizer.token_column);
+
+        let field = if self.try_consume('[')? {
+            let name = self.parse_extension_name()?;
+            if name.contains('/') {
+                return self.parse_any(descriptor, name, fields, position);
+            }
+            match descriptor.extension_by_name(&name) {
+                Some(field) => field.clone(),
+                None => return Err(self.error_at(position, format!("extension {} of message type {} not found",
+                                                      name, descriptor.full_name()))),
+            }
+        } else {
+            let name = self.expect_ident()?;
+            match descriptor.field_by_name(&name).cloned().or_else(|| group_field(descriptor, &name)) {
+                Some(field) => field,
+                None => return Err(self.error_at(position, format!("message type {} has no field named {}",
+                                                      descriptor.full_name(), name))),
+            }
+        };
+        let number = field.number() as u32;
+        let repeated = field.label() == Some(Label::LabelRepeated);
+
+        match field.type_() {
+            Some(Type::TypeMessage) | Some(Type::TypeGroup) => {
+                self.try_consume(':')?;
+            },
+            _ => self.expect(':')?,
+        }
+
+        if !repeated {
+            if fields.contains_key(&number) {
+                return Err(self.error_at(position, format!("non-repeated field {} is specified multiple times",
+                                                           field.name())));
+            }
+            if let Some(oneof_index) = field.oneof_index {
+                let conflict = fields.keys().any(|&number| {
+                    descriptor.field_by_number(number).and_then(|field| field.oneof_index) == Some(oneof_index)
+                });
+                if conflict {
+                    return Err(self.error_at(position,
+                                             format!("field {} is specified along with another field of the same oneof",
+                                                     field.name())));
+                }
+            }
+        }
+
+        let mut values = Vec::new();
+        if repeated && self.try_consume('[')? {
+            if !self.try_consume(']')? {
+                loop {
+                    values.push(self.parse_value(descriptor, &field)?);
+                    if self.try_consume(']')? {
+                        break;
+                    }
+                    self.expect(',')?;
+                }
+            }
+        } else {
+            values.push(self.parse_value(descriptor, &field)?);
+        }
+
+        if descriptor.is_map_field(number) {
+            let entry_type = descriptor.field_message_type(number).unwrap();
+            let entries = match *fields.entry(number).or_insert_with(|| Value::Map(Vec::new())) {
+                Value::Map(ref mut entries) => entries,
+                _ => unreachable!(),
+            };
+            for value in values {
+                let entry = match value {
+                    Value::Message(entry) => entry,
+                    _ => unreachable!(),
+                };
+                let key = entry.get_by_number(1).cloned().unwrap_or_else(|| entry_type.field_default_value(1).unwrap());
+                let value = entry.get_by_number(2).cloned().unwrap_or_else(|| entry_type.field_default_value(2).unwrap());
+                match entries.iter().position(|&(ref k, _)| *k == key) {
+                    Some(idx) => entries[idx].1 = value,
+                    None => entries.push((key, value)),
+                }
+            }
+        } else if repeated {
+            match *fields.entry(number).or_insert_with(|| Value::List(Vec::new())) {
+                Value::List(ref mut list) => list.extend(values),
+                _ => unreachable!(),
+            }
+        } else {
+            fields.insert(number, values.pop().unwrap());
+        }
+        Ok(())
+    }
+
+    /// Parses the name of an extension, or the type URL of an expanded `Any`,
+    /// after the opening bracket.
+    fn parse_extension_name(&mut self) -> Result<String, ParseError> {
+        let mut name = String::new();
+        loop {
+            match self.tokenizer.next()? {
+                Some(Token::Ident(ident)) => name.push_str(&ident),
+                Some(Token::Symbol(']')) if !name.is_empty() => return Ok(name),
+                Some(Token::Symbol(c)) if c == '.' || c == '/' => name.push(c),
+                _ => return Err(self.error("invalid extension name".to_string())),
+            }
+        }
+    }
+
+    /// Parses the contents of an expanded `Any`, after the type URL.
+    fn parse_any(&mut self,
+                 descriptor: &MessageDescriptor,
+                 type_url: String,
+                 fields: &mut BTreeMap<u32, Value>,
+                 position: (usize, usize))
+                 -> Result<(), ParseError> {
+        if descriptor.full_name() != ANY {
+            return Err(self.error_at(position, format!("type URL {} is not allowed in message type {}",
+                                          type_url, descriptor.full_name())));
+        }
+        if !fields.is_empty() {
+            return Err(self.error_at(position, "expanded Any may not be combined with other fields".to_string()));
+        }
+        let type_name = type_url[type_url.rfind('/').unwrap() + 1..].to_string();
+        let contents_type = match MessageDescriptor::new(descriptor.pool().clone(), &type_name) {
+            Some(contents_type) => contents_type,
+            None => return Err(self.error_at(position, format!("message type {} not found", type_name))),
+        };
+
+        self.try_consume(':')?;
+        let contents = self.parse_nested_message(contents_type)?;
+        fields.insert(1, Value::String(type_url));
+        fields.insert(2, Value::Bytes(contents.encode_to_vec()));
+        Ok(())
+    }
+
+    /// Parses a delimited message value, checking that it is not nested too
+    /// deeply.
+    fn parse_nested_message(&mut self, descriptor: MessageDescriptor) -> Result<DynamicMessage, ParseError> {
+        let terminator = if self.try_consume('{')? {
+            '}'
+        } else if self.try_consume('<')? {
+            '>'
+        } else {
+            return Err(self.error("expected '{' or '<'".to_string()));
+        };
+        if self.depth >= RECURSION_LIMIT {
+            return Err(self.error(format!("messages are nested more than {} levels deep", RECURSION_LIMIT)));
+        }
+        self.depth += 1;
+        let message = self.parse_message(descriptor, Some(terminator));
+        self.depth -= 1;
+        message
+    }
+
+    fn parse_value(&mut self,
+                   descriptor: &MessageDescriptor,
+                   field: &FieldDescriptorProto)
+                   -> Result<Value, ParseError> {
+        let number = field.number() as u32;
+        let ty = match field.type_() {
+            Some(ty) => ty,
+            None => return Err(self.error(format!("field {} has an unknown type", field.name()))),
+        };
+        match ty {
+            Type::TypeMessage | Type::TypeGroup => {
+                let message_type = descriptor.field_message_type(number).unwrap();
+                self.parse_nested_message(message_type).map(Value::Message)
+            },
+            Type::TypeEnum => {
+                if let Some(Token::Ident(name)) = self.tokenizer.peek()? {
+                    self.tokenizer.next()?;
+                    let enum_type = descriptor.field_enum_type(number).unwrap();
+                    return match enum_type.value_number(&name) {
+                        Some(value) => Ok(Value::Enum(value)),
+                        None => Err(self.error(format!("enum type {} has no value named {}",
+                                                       enum_type.full_name(), name))),
+                    };
+                }
+                self.parse_signed(i32::min_value() as i64, i32::max_value() as i64)
+                    .map(|value| Value::Enum(value as i32))
+            },
+            Type::TypeBool => {
+                let value = match self.tokenizer.next()? {
+                    Some(Token::Ident(ref ident)) if ident == "true" || ident == "True" || ident == "t" => true,
+                    Some(Token::Ident(ref ident)) if ident == "false" || ident == "False" || ident == "f" => false,
+                    Some(Token::Number(ref number)) if number == "1" => true,
+                    Some(Token::Number(ref number)) if number == "0" => false,
+                    _ => return Err(self.error("expected boolean".to_string())),
+                };
+                Ok(Value::Bool(value))
+            },
+            Type::TypeString => {
+                let value = self.parse_string()?;
+                match String::from_utf8(value) {
+                    Ok(value) => Ok(Value::String(value)),
+                    Err(..) => Err(self.error("invalid UTF-8 in string".to_string())),
+                }
+            },
+            Type::TypeBytes => self.parse_string().map(Value::Bytes),
+            Type::TypeFloat => {
+                let (negative, literal) = self.parse_float()?;
+                let value = match literal {
+                    FloatLiteral::Infinity => ::std::f32::INFINITY,
+                    FloatLiteral::Nan => ::std::f32::NAN,
+                    FloatLiteral::Number(number) => match number.parse::<f32>() {
+                        Ok(value) => value,
+                        Err(..) => return Err(self.error(format!("invalid float: {}", number))),
+                    },
+                };
+                Ok(Value::F32(if negative { -value } else { value }))
+            },
+            Type::TypeDouble => {
+                let (negative, literal) = self.parse_float()?;
+                let value = match literal {
+                    FloatLiteral::Infinity => ::std::f64::INFINITY,
+                    FloatLiteral::Nan => ::std::f64::NAN,
+                    FloatLiteral::Number(number) => match number.parse::<f64>() {
+                        Ok(value) => value,
+                        Err(..) => return Err(self.error(format!("invalid double: {}", number))),
+                    },
+                };
+                Ok(Value::F64(if negative { -value } else { value }))
+            },
+            Type::TypeInt32 | Type::TypeSint32 | Type::TypeSfixed32 => {
+                self.parse_signed(i32::min_value() as i64, i32::max_value() as i64)
+                    .map(|value| Value::I32(value as i32))
+            },
+            Type::TypeInt64 | Type::TypeSint64 | Type::TypeSfixed64 => {
+                self.parse_signed(i64::min_value(), i64::max_value()).map(Value::I64)
+            },
+            Type::TypeUint32 | Type::TypeFixed32 => {
+                self.parse_unsigned(u32::max_value() as u64).map(|value| Value::U32(value as u32))
+            },
+            Type::TypeUint64 | Type::TypeFixed64 => {
+                self.parse_unsigned(u64::max_value()).map(Value::U64)
+            },
+        }
+    }
+
+    /// Parses one or more adjacent string literals, and concatenates them.
+    fn parse_string(&mut self) -> Result<Vec<u8>, ParseError> {
+        let mut value = match self.tokenizer.next()? {
+            Some(Token::String(value)) => value,
+            _ => return Err(self.error("expected string".to_string())),
+        };
+        while let Some(Token::String(next)) = self.tokenizer.peek()? {
+            self.tokenizer.next()?;
+            value.extend(next);
+        }
+        Ok(value)
+    }
+
+    /// Parses an integer literal, and returns its sign and magnitude.
+    fn parse_integer(&mut self) -> Result<(bool, u64), ParseError> {
+        let negative = self.try_consume('-')?;
+        let number = match self.tokenizer.next()? {
+            Some(Token::Number(number)) => number,
+            _ => return Err(self.error("expected integer".to_string())),
+        };
+        let value = if number.starts_with("0x") || number.starts_with("0X") {
+            u64::from_str_radix(&number[2..], 16)
+        } else if number.len() > 1 && number.starts_with('0') {
+            u64::from_str_radix(&number[1..], 8)
+        } else {
+            number.parse::<u64>()
+        };
+        match value {
+            Ok(value) => Ok((negative, value)),
+            Err(..) => Err(self.error(format!("invalid integer: {}", number))),
+        }
+    }
+
+    fn parse_signed(&mut self, min: i64, max: i64) -> Result<i64, ParseError> {
+        let (negative, magnitude) = self.parse_integer()?;
+        let in_range = if negative {
+            magnitude <= (min as u64).wrapping_neg()
+        } else {
+            magnitude <= max as u64
+        };
+        if !in_range {
+            return Err(self.error("integer out of range".to_string()));
+        }
+        Ok(if negative { (magnitude as i64).wrapping_neg() } else { magnitude as i64 })
+    }
+
+    fn parse_unsigned(&mut self, max: u64) -> Result<u64, ParseError> {
+        let (negative, magnitude) = self.parse_integer()?;
+        if (negative && magnitude != 0) || magnitude > max {
+            return Err(self.error("integer out of range".to_string()));
+        }
+        Ok(magnitude)
+    }
+
+    /// Parses a floating point literal, and returns its sign and value.
+    fn parse_float(&mut self) -> Result<(bool, FloatLiteral), ParseError> {
+        let negative = self.try_consume('-')?;
+        match self.tokenizer.next()? {
+            Some(Token::Ident(ref ident)) if ident.eq_ignore_ascii_case("inf") ||
+                                             ident.eq_ignore_ascii_case("infinity") => {
+                Ok((negative, FloatLiteral::Infinity))
+            },
+            Some(Token::Ident(ref ident)) if ident.eq_ignore_ascii_case("nan") => {
+                Ok((negative, FloatLiteral::Nan))
+            },
+            Some(Token::Number(number)) => {
+                let number = if number.ends_with('f') || number.ends_with('F') {
+                    number[..number.len() - 1].to_string()
+                } else {
+                    number
+                };
+                Ok((negative, FloatLiteral::Number(number)))
+            },
+            _ => Err(self.error("expected number".to_string())),
+        }
+    }
+}
+
+/// Returns the group field of the message type whose group type has the given
+/// name. Groups are written with the name of their type in text format.
+fn group_field(descriptor: &MessageDescriptor, name: &str) -> Option<FieldDescriptorProto> {
+    descriptor.descriptor().field.iter().find(|field| {
+        field.type_() == Some(Type::TypeGroup) &&
+        descriptor.field_message_type(field.number() as u32)
+                  .map_or(false, |group_type| group_type.descriptor().name() == name)
+    }).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use google::protobuf::{
+        DescriptorProto,
+        EnumDescriptorProto,
+        EnumValueDescriptorProto,
+        FileDescriptorProto,
+        FileDescriptorSet,
+    };
+    use google::protobuf::descriptor_proto::ExtensionRange;
+
+    fn field(name: &str, number: i32, label: Label, ty: Type, type_name: Option<&str>) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(number),
+            label: Some(label as i32),
+            type_: Some(ty as i32),
+            type_name: type_name.map(|type_name| type_name.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// A proto2 file declaring a message with an enum field, a group, an
+    /// extension range and an extension.
+    fn pool() -> Arc<DescriptorPool> {
+        let file = FileDescriptorProto {
+            name: Some("test.proto".to_string()),
+            package: Some("test".to_string()),
+            message_type: vec![
+                DescriptorProto {
+                    name: Some("Foo".to_string()),
+                    field: vec![
+                        field("name", 1, Label::LabelOptional, Type::TypeString, None),
+                        field("color", 2, Label::LabelRepeated, Type::TypeEnum, Some(".test.Color")),
+                        field("data", 3, Label::LabelOptional, Type::TypeBytes, None),
+                        field("child", 4, Label::LabelOptional, Type::TypeGroup, Some(".test.Foo.Child")),
+                    ],
+                    nested_type: vec![
+                        DescriptorProto {
+                            name: Some("Child".to_string()),
+                            field: vec![field("value", 5, Label::LabelOptional, Type::TypeSint64, None)],
+                            ..Default::default()
+                        },
+                    ],
+                    extension_range: vec![ExtensionRange { start: Some(100), end: Some(200) }],
+                    ..Default::default()
+                },
+            ],
+            enum_type: vec![
+                EnumDescriptorProto {
+                    name: Some("Color".to_string()),
+                    value: vec![
+                        EnumValueDescriptorProto {
+                            name: Some("RED".to_string()),
+                            number: Some(0),
+                            ..Default::default()
+                        },
+                        EnumValueDescriptorProto {
+                            name: Some("BLUE".to_string()),
+                            number: Some(1),
+                            ..Default::default()
+                        },
+                    ],
+                    ..Default::default()
+                },
+            ],
+            extension: vec![FieldDescriptorProto {
+                extendee: Some(".test.Foo".to_string()),
+                ..field("weight", 100, Label::LabelOptional, Type::TypeDouble, None)
+            }],
+            ..Default::default()
+        };
+        Arc::new(DescriptorPool::new(FileDescriptorSet { file: vec![file] }).unwrap())
+    }
+
+    #[test]
+    fn test_text_format() {
+        let descriptor = MessageDescriptor::new(pool(), "test.Foo").unwrap();
+        let text = "# A comment.\n\
+                    name: 'caf\\303\\251' \"\\x21\\u00e9\"\n\
+                    color: [BLUE, 0]; color: 7\n\
+                    data: \"\\000\\n\\377\"\n\
+                    Child < value: -0x10 >\n\
+                    [test.weight]: -inf\n";
+        let message = parse(descriptor.clone(), text).unwrap();
+
+        assert_eq!(Some(&Value::String("café!é".to_string())), message.get("name"));
+        assert_eq!(Some(&Value::List(vec![Value::Enum(1), Value::Enum(0), Value::Enum(7)])), message.get("color"));
+        assert_eq!(Some(&Value::Bytes(vec![0, b'\n', 0xff])), message.get("data"));
+        assert_eq!(Some(&Value::F64(::std::f64::NEG_INFINITY)), message.get_by_number(100));
+        match message.get("child") {
+            Some(&Value::Message(ref child)) => assert_eq!(Some(&Value::I64(-16)), child.get("value")),
+            value => panic!("unexpected value: {:?}", value),
+        }
+
+        let printed = print(&message);
+        assert_eq!("name: \"café!é\"\n\
+                    color: BLUE\n\
+                    color: RED\n\
+                    color: 7\n\
+                    data: \"\\000\\n\\377\"\n\
+                    Child {\n  value: -16\n}\n\
+                    [test.weight]: -inf\n", printed);
+        assert_eq!(message, parse(descriptor, &printed).unwrap());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let descriptor = MessageDescriptor::new(pool(), "test.Foo").unwrap();
+        let error = |text: &str| parse(descriptor.clone(), text).unwrap_err();
+
+        assert_eq!("2:1: message type test.Foo has no field named missing",
+                   error("name: \"a\"\nmissing: 1").to_string());
+        assert_eq!("1:11: non-repeated field name is specified multiple times",
+                   error("name: 'a' name: 'b'").to_string());
+        assert_eq!("1:8: enum type test.Color has no value named GREEN", error("color: GREEN").to_string());
+        assert_eq!("1:6: expected ':'", error("name 'a'").to_string());
+        assert_eq!("1:7: unterminated string literal", error("name: 'a").to_string());
+        assert_eq!("1:16: integer out of range",
+                   error("Child { value: 9223372036854775808 }").to_string());
+        assert_eq!("1:1: extension test.height of message type test.Foo not found",
+                   error("[test.height]: 1").to_string());
+        assert_eq!("1:17: expected '}'", error("Child { value: 1").to_string());
+    }
+
+    /// Returns a `google.protobuf.Value` holding `depth` nested lists, in text
+    /// format and as a message.
+    fn nested_lists(depth: usize) -> (String, ::prost_types::Value) {
+        use prost_types::value::Kind;
+        let mut value = ::prost_types::Value { kind: Some(Kind::ListValue(Default::default())) };
+        for _ in 1..depth {
+            value = ::prost_types::Value {
+                kind: Some(Kind::ListValue(::prost_types::ListValue { values: vec![value] })),
+            };
+        }
+        let text = "list_value { values { ".repeat(depth - 1) + "list_value { " + &"} } ".repeat(depth - 1) + "}";
+        (text, value)
+    }
+
+    #[test]
+    fn test_recursion_limit() {
+        use json::JsonMessage;
+        let pool = <::prost_types::Value as JsonMessage>::descriptor_pool();
+
+        // Each level of nesting is a `ListValue` in a `Value`, so 50 levels
+        // are exactly at the recursion limit.
+        let (text, value) = nested_lists(50);
+        assert_eq!(text, print_message(&value, &pool).unwrap().split_whitespace().collect::<Vec<_>>().join(" "));
+        assert_eq!(value, parse_message::<::prost_types::Value>(&pool, &text).unwrap());
+
+        let (text, value) = nested_lists(60);
+        assert!(print_message(&value, &pool).is_err());
+        assert_eq!("1:1112: messages are nested more than 100 levels deep",
+                   parse_message::<::prost_types::Value>(&pool, &text).unwrap_err().to_string());
+
+        // Deeply nested input fails without exhausting the stack.
+        let text = "list_value { values { ".repeat(200000);
+        assert!(parse_message::<::prost_types::Value>(&pool, &text).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+//! `ReflectMessage` and `JsonMessage` implementations for the well-known types
+//! in `prost-types`, which generated code refers to unless the
+//! `compile_well_known_types` option is enabled.
+
+use std::sync::Arc;
+
+use prost_types::value::Kind;
+
+use dynamic::DescriptorPool;
+use google::protobuf::DescriptorProto;
+use json::JsonMessage;
+use reflect::{
+    descriptor_pool,
+    message_descriptor,
+    ReflectMessage,
+    Value,
+};
+
+/// The encoded `FileDescriptorSet` of the `.proto` files defining the
+/// well-known types. See the `prost-reflect` README for how to regenerate it.
+const FILE_DESCRIPTOR_SET: &'static [u8] = include_bytes!("well_known_types.bin");
+
+/// Implements `ReflectMessage` and `JsonMessage` for a well-known type. The
+/// `field` expression returns the value of field `$number` of `$message`.
+macro_rules! well_known_type {
+    ($ty:ident, |$message:ident, $number:ident| $field:expr) => {
+        impl ReflectMessage for ::prost_types::$ty {
+            fn descriptor(&self) -> &'static DescriptorProto {
+                message_descriptor(FILE_DESCRIPTOR_SET, concat!("google.protobuf.", stringify!($ty)))
+            }
+            fn full_name(&self) -> &'static str {
+                concat!("google.protobuf.", stringify!($ty))
+            }
+            fn field(&self, number: i32) -> Option<Value> {
+                let $message = self;
+                let $number = number;
+                $field
+            }
+        }
+
+        impl JsonMessage for ::prost_types::$ty {
+            fn descriptor_pool() -> Arc<DescriptorPool> {
+                descriptor_pool(FILE_DESCRIPTOR_SET)
+            }
+        }
+    }
+}
+
+/// Implements `ReflectMessage` and `JsonMessage` for a wrapper type, whose
+/// only field is `value`.
+macro_rules! wrapper {
+    ($ty:ident, |$value:ident| $field:expr) => {
+        well_known_type!($ty, |message, number| match number {
+            1 => {
+                let $value = &message.value;
+                Some($field)
+            },
+            _ => None,
+        });
+    }
+}
+
+well_known_type!(Any, |message, number| match number {
+    1 => Some(Value::String(&message.type_url)),
+    2 => Some(Value::Bytes(&message.value)),
+    _ => None,
+});
+
+well_known_type!(Duration, |message, number| match number {
+    1 => Some(Value::I64(message.seconds)),
+    2 => Some(Value::I32(message.nanos)),
+    _ => None,
+});
+
+well_known_type!(Empty, |_message, _number| None);
+
+well_known_type!(FieldMask, |message, number| match number {
+    1 => Some(Value::List(message.paths.iter().map(|path| Value::String(path)).collect())),
+    _ => None,
+});
+
+well_known_type!(ListValue, |message, number| match number {
+    1 => Some(Value::List(message.values.iter().map(|value| Value::Message(value)).collect())),
+    _ => None,
+});
+
+well_known_type!(Struct, |message, number| match number {
+    1 => Some(Value::Map(message.fields
+                                .iter()
+                                .map(|(key, value)| (Value::String(key), Value::Message(value)))
+                                .collect())),
+    _ => None,
+});
+
+well_known_type!(Timestamp, |message, number| match number {
+    1 => Some(Value::I64(message.seconds)),
+    2 => Some(Value::I32(message.nanos)),
+    _ => None,
+});
+
+well_known_type!(Value, |message, number| match (number, message.kind.as_ref()) {
+    (1, Some(&Kind::NullValue(v))) => Some(Value::Enum(v)),
+    (2, Some(&Kind::NumberValue(v))) => Some(Value::F64(v)),
+    (3, Some(&Kind::StringValue(ref v))) => Some(Value::String(v)),
+    (4, Some(&Kind::BoolValue(v))) => Some(Value::Bool(v)),
+    (5, Some(&Kind::StructValue(ref v))) => Some(Value::Message(v)),
+    (6, Some(&Kind::ListValue(ref v))) => Some(Value::Message(v)),
+    _ => None,
+});
+
+wrapper!(DoubleValue, |value| Value::F64(*value));
+wrapper!(FloatValue, |value| Value::F32(*value));
+wrapper!(Int64Value, |value| Value::I64(*value));
+wrapper!(UInt64Value, |value| Value::U64(*value));
+wrapper!(Int32Value, |value| Value::I32(*value));
+wrapper!(UInt32Value, |value| Value::U32(*value));
+wrapper!(BoolValue, |value| Value::Bool(*value));
+wrapper!(StringValue, |value| Value::String(value));
+wrapper!(BytesValue, |value| Value::Bytes(value));
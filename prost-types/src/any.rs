@@ -0,0 +1,271 @@
+use std::any;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+use prost::{
+    DecodeError,
+    Message,
+    Name,
+};
+
+use Any;
+
+impl Any {
+
+    /// Packs the message into an `Any`, identified by the type URL of its
+    /// message type.
+    pub fn pack<M>(message: &M) -> Any where M: Name {
+        Any {
+            type_url: M::type_url(),
+            value: message.encode_to_vec(),
+        }
+    }
+
+    /// Unpacks the message contained in the `Any`.
+    ///
+    /// Returns `UnpackError::TypeMismatch` if the `Any` contains a message of
+    /// a type other than `M`.
+    pub fn unpack<M>(&self) -> Result<M, UnpackError> where M: Name {
+        let expected = M::full_name();
+        if self.type_name() != expected {
+            return Err(UnpackError::TypeMismatch {
+                expected: expected,
+                actual: self.type_name().to_string(),
+            });
+        }
+        M::decode_from_slice(&self.value).map_err(UnpackError::Decode)
+    }
+
+    /// Returns `true` if the `Any` contains a message of type `M`.
+    pub fn is<M>(&self) -> bool where M: Name {
+        self.type_name() == M::full_name()
+    }
+
+    /// Returns the fully qualified name of the type of the contained message,
+    /// which is the part of the type URL following the last `/`.
+    pub fn type_name(&self) -> &str {
+        match self.type_url.rfind('/') {
+            Some(idx) => &self.type_url[idx + 1..],
+            None => &self.type_url,
+        }
+    }
+}
+
+/// An error returned when the message contained in an `Any` can not be
+/// unpacked.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UnpackError {
+    /// The `Any` contains a message of a different type than the requested
+    /// type.
+    TypeMismatch {
+        /// The fully qualified name of the requested type.
+        expected: String,
+        /// The fully qualified name of the type of the contained message.
+        actual: String,
+    },
+    /// The type of the contained message is not registered in the
+    /// `TypeRegistry`.
+    UnknownType(String),
+    /// The contained message could not be decoded.
+    Decode(DecodeError),
+}
+
+impl fmt::Display for UnpackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UnpackError::TypeMismatch { ref expected, ref actual } => {
+                write!(f, "type mismatch: expected {}, found {}", expected, actual)
+            },
+            UnpackError::UnknownType(ref type_name) => write!(f, "unknown message type: {}", type_name),
+            UnpackError::Decode(ref error) => write!(f, "failed to decode Any value: {}", error),
+        }
+    }
+}
+
+impl error::Error for UnpackError {
+    fn description(&self) -> &str {
+        match *self {
+            UnpackError::TypeMismatch { .. } => "type mismatch",
+            UnpackError::UnknownType(..) => "unknown message type",
+            UnpackError::Decode(..) => "failed to decode Any value",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            UnpackError::Decode(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecodeError> for UnpackError {
+    fn from(error: DecodeError) -> UnpackError {
+        UnpackError::Decode(error)
+    }
+}
+
+/// A message unpacked from an `Any` by a `TypeRegistry`, whose type is only
+/// known at runtime.
+///
+/// `AnyMessage` is implemented for every message type which implements
+/// `Name`. Use `downcast_ref` to get the message as its concrete type.
+pub trait AnyMessage: fmt::Debug + Send + Sync {
+
+    /// Returns the fully qualified name of the message type.
+    fn full_name(&self) -> String;
+
+    /// Encodes the message to a newly allocated `Vec<u8>`.
+    fn encode_to_vec(&self) -> Vec<u8>;
+
+    /// Returns the message as a `std::any::Any`, which can be downcast to its
+    /// concrete type.
+    fn as_any(&self) -> &any::Any;
+}
+
+impl <M> AnyMessage for M where M: Name + 'static {
+    fn full_name(&self) -> String {
+        M::full_name()
+    }
+    fn encode_to_vec(&self) -> Vec<u8> {
+        Message::encode_to_vec(self)
+    }
+    fn as_any(&self) -> &any::Any {
+        self
+    }
+}
+
+impl AnyMessage {
+
+    /// Returns the message as type `M`, or `None` if it is of another type.
+    pub fn downcast_ref<M>(&self) -> Option<&M> where M: Name + 'static {
+        self.as_any().downcast_ref()
+    }
+
+    /// Packs the message into an `Any`.
+    pub fn pack(&self) -> Any {
+        Any {
+            type_url: format!("type.googleapis.com/{}", self.full_name()),
+            value: self.encode_to_vec(),
+        }
+    }
+}
+
+type Decoder = fn(&[u8]) -> Result<Box<AnyMessage>, DecodeError>;
+
+fn decode<M>(buf: &[u8]) -> Result<Box<AnyMessage>, DecodeError> where M: Name + 'static {
+    let message = M::decode_from_slice(buf)?;
+    Ok(Box::new(message))
+}
+
+/// A registry of message types, which unpacks `Any` values whose type is only
+/// known at runtime.
+#[derive(Clone, Default)]
+pub struct TypeRegistry {
+    decoders: HashMap<String, Decoder>,
+}
+
+impl TypeRegistry {
+
+    /// Creates an empty type registry.
+    pub fn new() -> TypeRegistry {
+        TypeRegistry::default()
+    }
+
+    /// Adds the message type `M` to the registry.
+    pub fn register<M>(&mut self) where M: Name + 'static {
+        self.decoders.insert(M::full_name(), decode::<M>);
+    }
+
+    /// Returns `true` if the registry contains the message type with the
+    /// given fully qualified name.
+    pub fn contains(&self, full_name: &str) -> bool {
+        self.decoders.contains_key(full_name)
+    }
+
+    /// Unpacks the message contained in the `Any`.
+    ///
+    /// Returns `UnpackError::UnknownType` if the type of the contained message
+    /// is not in the registry.
+    pub fn unpack(&self, any: &Any) -> Result<Box<AnyMessage>, UnpackError> {
+        match self.decoders.get(any.type_name()) {
+            Some(decode) => decode(&any.value).map_err(UnpackError::Decode),
+            None => Err(UnpackError::UnknownType(any.type_name().to_string())),
+        }
+    }
+
+    /// Returns the number of message types in the registry.
+    pub fn len(&self) -> usize {
+        self.decoders.len()
+    }
+
+    /// Returns `true` if the registry contains no message types.
+    pub fn is_empty(&self) -> bool {
+        self.decoders.is_empty()
+    }
+}
+
+impl fmt::Debug for TypeRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+         .entries(self.decoders.keys())
+         .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use {
+        Duration,
+        Empty,
+        Timestamp,
+    };
+
+    #[test]
+    fn test_pack_unpack() {
+        let timestamp = Timestamp { seconds: 10, nanos: 5 };
+        let any = Any::pack(&timestamp);
+        assert_eq!("type.googleapis.com/google.protobuf.Timestamp", any.type_url);
+        assert_eq!("google.protobuf.Timestamp", any.type_name());
+        assert!(any.is::<Timestamp>());
+        assert!(!any.is::<Duration>());
+        assert_eq!(Ok(timestamp), any.unpack::<Timestamp>());
+        assert_eq!(Err(UnpackError::TypeMismatch {
+                       expected: "google.protobuf.Duration".to_string(),
+                       actual: "google.protobuf.Timestamp".to_string(),
+                   }),
+                   any.unpack::<Duration>());
+
+        let invalid = Any {
+            type_url: "example.com/types/google.protobuf.Duration".to_string(),
+            value: vec![0x08],
+        };
+        assert!(invalid.is::<Duration>());
+        match invalid.unpack::<Duration>() {
+            Err(UnpackError::Decode(..)) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_type_registry() {
+        let mut registry = TypeRegistry::new();
+        registry.register::<Timestamp>();
+        registry.register::<Duration>();
+        assert_eq!(2, registry.len());
+        assert!(registry.contains("google.protobuf.Duration"));
+
+        let duration = Duration { seconds: -1, nanos: -2 };
+        let message = registry.unpack(&Any::pack(&duration)).unwrap();
+        assert_eq!("google.protobuf.Duration", message.full_name());
+        assert_eq!(Some(&duration), message.downcast_ref::<Duration>());
+        assert_eq!(None, message.downcast_ref::<Timestamp>());
+        assert_eq!(Any::pack(&duration), message.pack());
+
+        assert_eq!(Err(UnpackError::UnknownType("google.protobuf.Empty".to_string())),
+                   registry.unpack(&Any::pack(&Empty {})).map(|_| ()));
+    }
+}
@@ -0,0 +1,162 @@
+use std::error;
+use std::fmt;
+
+use prost::{
+    DecodeError,
+    FieldMaskTree,
+    MaskableMessage,
+};
+
+use FieldMask;
+
+impl FieldMask {
+
+    /// Creates a field mask from paths, for instance `a.b.c`.
+    pub fn new<I, S>(paths: I) -> FieldMask where I: IntoIterator<Item=S>, S: Into<String> {
+        FieldMask {
+            paths: paths.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Returns an error naming the first path of the mask which does not name
+    /// a field of message type `M`.
+    ///
+    /// Every segment of a path must name a field, and every segment but the
+    /// last must name a singular message field.
+    pub fn validate<M>(&self) -> Result<(), FieldMaskError> where M: MaskableMessage {
+        match self.paths.iter().find(|path| !M::is_valid_path(&path.split('.').collect::<Vec<_>>())) {
+            Some(path) => Err(FieldMaskError { path: path.clone() }),
+            None => Ok(()),
+        }
+    }
+
+    /// Merges the fields selected by the mask from `source` into
+    /// `destination`, with Protobuf merge semantics.
+    ///
+    /// Set scalar fields are copied, repeated and map fields are appended, and
+    /// message fields are merged. Paths which do not name a field are ignored.
+    /// An error is returned if a selected `Lazy` field can not be decoded.
+    pub fn merge<M>(&self, source: &M, destination: &mut M) -> Result<(), DecodeError>
+    where M: MaskableMessage {
+        destination.merge_masked(source, &self.to_tree())
+    }
+
+    /// Clears the fields of the message which are not selected by the mask.
+    pub fn prune<M>(&self, message: &mut M) where M: MaskableMessage {
+        message.prune_masked(&self.to_tree());
+    }
+
+    /// Returns a copy of the message containing only the fields selected by
+    /// the mask, or an error if a selected `Lazy` field can not be decoded.
+    pub fn apply<M>(&self, message: &M) -> Result<M, DecodeError> where M: MaskableMessage {
+        let mut masked = M::default();
+        self.merge(message, &mut masked)?;
+        Ok(masked)
+    }
+
+    /// Converts the mask to canonical form, in which the paths are sorted,
+    /// and paths covered by another path of the mask are removed.
+    pub fn normalize(&mut self) {
+        self.paths = self.to_tree().paths();
+    }
+
+    /// Returns the paths of the mask as a tree.
+    pub fn to_tree(&self) -> FieldMaskTree {
+        FieldMaskTree::new(&self.paths)
+    }
+}
+
+/// An error returned when a field mask path does not name a field of a
+/// message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldMaskError {
+    path: String,
+}
+
+impl FieldMaskError {
+
+    /// Returns the invalid path.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl fmt::Display for FieldMaskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid field mask path: {}", self.path)
+    }
+}
+
+impl error::Error for FieldMaskError {
+    fn description(&self) -> &str {
+        "invalid field mask path"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use {
+        Any,
+        Duration,
+        Struct,
+        Value,
+    };
+    use value::Kind;
+
+    #[test]
+    fn test_field_mask() {
+        let mask = FieldMask::new(vec!["seconds"]);
+        assert_eq!(Ok(()), mask.validate::<Duration>());
+        assert_eq!(Err(FieldMaskError { path: "nanos.seconds".to_string() }),
+                   FieldMask::new(vec!["seconds", "nanos.seconds"]).validate::<Duration>());
+        assert_eq!(Err(FieldMaskError { path: "type_url".to_string() }),
+                   FieldMask::new(vec!["type_url"]).validate::<Duration>());
+
+        let duration = Duration { seconds: 5, nanos: 10 };
+        assert_eq!(Duration { seconds: 5, nanos: 0 }, mask.apply(&duration).unwrap());
+
+        let mut destination = Duration { seconds: 1, nanos: 2 };
+        mask.merge(&duration, &mut destination).unwrap();
+        assert_eq!(Duration { seconds: 5, nanos: 2 }, destination);
+
+        mask.prune(&mut destination);
+        assert_eq!(Duration { seconds: 5, nanos: 0 }, destination);
+
+        // Scalar fields holding the default value are merged.
+        mask.merge(&Duration::default(), &mut destination).unwrap();
+        assert_eq!(Duration { seconds: 0, nanos: 0 }, destination);
+
+        let mut any = Any { type_url: "a".to_string(), value: vec![1] };
+        FieldMask::new(Vec::<String>::new()).prune(&mut any);
+        assert_eq!(Any::default(), any);
+
+        let mut mask = FieldMask::new(vec!["b", "a.c", "a"]);
+        mask.normalize();
+        assert_eq!(vec!["a".to_string(), "b".to_string()], mask.paths);
+    }
+
+    #[test]
+    fn test_field_mask_oneof() {
+        let value = Value { kind: Some(Kind::StringValue("foo".to_string())) };
+        assert!(FieldMask::new(vec!["string_value", "struct_value.fields"]).validate::<Value>().is_ok());
+        assert!(FieldMask::new(vec!["string_value.foo"]).validate::<Value>().is_err());
+        assert!(FieldMask::new(vec!["kind"]).validate::<Value>().is_err());
+
+        assert_eq!(value, FieldMask::new(vec!["string_value"]).apply(&value).unwrap());
+        assert_eq!(Value::default(), FieldMask::new(vec!["bool_value"]).apply(&value).unwrap());
+
+        let mut fields = ::std::collections::HashMap::new();
+        fields.insert("foo".to_string(), value.clone());
+        let value = Value { kind: Some(Kind::StructValue(Struct { fields: fields })) };
+        let mut destination = Value { kind: Some(Kind::NumberValue(1.0)) };
+        FieldMask::new(vec!["struct_value.fields"]).merge(&value, &mut destination).unwrap();
+        assert_eq!(value, destination);
+
+        FieldMask::new(vec!["struct_value.fields"]).prune(&mut destination);
+        assert_eq!(value, destination);
+        FieldMask::new(vec!["number_value"]).prune(&mut destination);
+        assert_eq!(Value::default(), destination);
+    }
+}
@@ -0,0 +1,350 @@
+#![doc(html_root_url = "https://docs.rs/prost-types/0.1.1")]
+
+//! Protocol Buffers well-known types.
+//!
+//! Code generated by `prost-codegen` refers to the types in this crate when a
+//! `.proto` file imports one of the well-known types, for instance
+//! `google/protobuf/timestamp.proto`, unless the
+//! `compile_well_known_types` option is enabled.
+//!
+//! See the [Protobuf reference][1] for more information about the well-known
+//! types.
+//!
+//! [1]: https://developers.google.com/protocol-buffers/docs/reference/google.protobuf
+
+extern crate bytes;
+extern crate prost;
+#[macro_use]
+extern crate prost_derive;
+
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::i64;
+use std::time;
+
+include!("protobuf.rs");
+
+mod any;
+mod field_mask;
+
+pub use any::{
+    AnyMessage,
+    TypeRegistry,
+    UnpackError,
+};
+pub use field_mask::FieldMaskError;
+
+const NANOS_PER_SECOND: i32 = 1_000_000_000;
+
+/// The maximum number of seconds of a valid `Duration`, approximately 10,000
+/// years.
+const MAX_DURATION_SECONDS: i64 = 315_576_000_000;
+
+/// The number of seconds of a valid `Timestamp` from 0001-01-01T00:00:00Z
+/// to 9999-12-31T23:59:59Z, inclusive.
+const MIN_TIMESTAMP_SECONDS: i64 = -62_135_596_800;
+const MAX_TIMESTAMP_SECONDS: i64 = 253_402_300_799;
+
+impl Duration {
+
+    /// Normalizes the duration to the canonical representation, in which
+    /// `nanos` is within `(-1s, 1s)`, and has the same sign as `seconds`.
+    ///
+    /// If `seconds` overflows, the duration saturates to the largest or
+    /// smallest representable duration.
+    pub fn normalize(&mut self) {
+        // Carry whole seconds out of nanos.
+        if self.nanos <= -NANOS_PER_SECOND || self.nanos >= NANOS_PER_SECOND {
+            match self.seconds.checked_add((self.nanos / NANOS_PER_SECOND) as i64) {
+                Some(seconds) => {
+                    self.seconds = seconds;
+                    self.nanos %= NANOS_PER_SECOND;
+                },
+                None if self.nanos < 0 => {
+                    self.seconds = i64::MIN;
+                    self.nanos = -NANOS_PER_SECOND + 1;
+                },
+                None => {
+                    self.seconds = i64::MAX;
+                    self.nanos = NANOS_PER_SECOND - 1;
+                },
+            }
+        }
+
+        // Make the signs of seconds and nanos agree. Neither adjustment can
+        // overflow, since seconds moves towards zero.
+        if self.seconds < 0 && self.nanos > 0 {
+            self.seconds += 1;
+            self.nanos -= NANOS_PER_SECOND;
+        } else if self.seconds > 0 && self.nanos < 0 {
+            self.seconds -= 1;
+            self.nanos += NANOS_PER_SECOND;
+        }
+    }
+
+    /// Returns `true` if the duration is normalized, and within the range of
+    /// durations supported by Protobuf, approximately +-10,000 years.
+    pub fn is_valid(&self) -> bool {
+        self.seconds >= -MAX_DURATION_SECONDS && self.seconds <= MAX_DURATION_SECONDS &&
+        self.nanos > -NANOS_PER_SECOND && self.nanos < NANOS_PER_SECOND &&
+        !(self.seconds < 0 && self.nanos > 0) && !(self.seconds > 0 && self.nanos < 0)
+    }
+}
+
+/// Converts a `std::time::Duration` to a `Duration`, failing if it is longer
+/// than the maximum valid `Duration`.
+impl TryFrom<time::Duration> for Duration {
+    type Error = DurationError;
+
+    fn try_from(duration: time::Duration) -> Result<Duration, DurationError> {
+        if duration.as_secs() > MAX_DURATION_SECONDS as u64 {
+            return Err(DurationError::OutOfRange);
+        }
+        Ok(Duration {
+            seconds: duration.as_secs() as i64,
+            nanos: duration.subsec_nanos() as i32,
+        })
+    }
+}
+
+/// Converts a `Duration` to a `std::time::Duration`, failing if it is
+/// negative or invalid once normalized.
+impl TryFrom<Duration> for time::Duration {
+    type Error = DurationError;
+
+    fn try_from(mut duration: Duration) -> Result<time::Duration, DurationError> {
+        duration.normalize();
+        if !duration.is_valid() {
+            return Err(DurationError::OutOfRange);
+        }
+        if duration.seconds < 0 || duration.nanos < 0 {
+            return Err(DurationError::Negative);
+        }
+        Ok(time::Duration::new(duration.seconds as u64, duration.nanos as u32))
+    }
+}
+
+impl Timestamp {
+
+    /// Normalizes the timestamp to the canonical representation, in which
+    /// `nanos` is within `[0, 1s)`.
+    ///
+    /// If `seconds` overflows, the timestamp saturates to the latest or
+    /// earliest representable timestamp.
+    pub fn normalize(&mut self) {
+        // Carry whole seconds out of nanos.
+        if self.nanos <= -NANOS_PER_SECOND || self.nanos >= NANOS_PER_SECOND {
+            match self.seconds.checked_add((self.nanos / NANOS_PER_SECOND) as i64) {
+                Some(seconds) => {
+                    self.seconds = seconds;
+                    self.nanos %= NANOS_PER_SECOND;
+                },
+                None if self.nanos < 0 => {
+                    self.seconds = i64::MIN;
+                    self.nanos = 0;
+                },
+                None => {
+                    self.seconds = i64::MAX;
+                    self.nanos = NANOS_PER_SECOND - 1;
+                },
+            }
+        }
+
+        // Borrow a second to make nanos positive.
+        if self.nanos < 0 {
+            match self.seconds.checked_sub(1) {
+                Some(seconds) => {
+                    self.seconds = seconds;
+                    self.nanos += NANOS_PER_SECOND;
+                },
+                None => self.nanos = 0,
+            }
+        }
+    }
+
+    /// Returns `true` if the timestamp is normalized, and within the range of
+    /// timestamps supported by Protobuf, from 0001-01-01T00:00:00Z to
+    /// 9999-12-31T23:59:59.999999999Z.
+    pub fn is_valid(&self) -> bool {
+        self.seconds >= MIN_TIMESTAMP_SECONDS && self.seconds <= MAX_TIMESTAMP_SECONDS &&
+        self.nanos >= 0 && self.nanos < NANOS_PER_SECOND
+    }
+}
+
+/// Converts a `SystemTime` to a `Timestamp`, failing if it is outside of the
+/// range of valid timestamps.
+impl TryFrom<time::SystemTime> for Timestamp {
+    type Error = TimestampError;
+
+    fn try_from(system_time: time::SystemTime) -> Result<Timestamp, TimestampError> {
+        let (negative, duration) = match system_time.duration_since(time::UNIX_EPOCH) {
+            Ok(duration) => (false, duration),
+            Err(error) => (true, error.duration()),
+        };
+        if duration.as_secs() > i64::MAX as u64 {
+            return Err(TimestampError::OutOfRange);
+        }
+        let mut timestamp = if negative {
+            Timestamp {
+                seconds: -(duration.as_secs() as i64),
+                nanos: -(duration.subsec_nanos() as i32),
+            }
+        } else {
+            Timestamp {
+                seconds: duration.as_secs() as i64,
+                nanos: duration.subsec_nanos() as i32,
+            }
+        };
+        timestamp.normalize();
+        if !timestamp.is_valid() {
+            return Err(TimestampError::OutOfRange);
+        }
+        Ok(timestamp)
+    }
+}
+
+/// Converts a `Timestamp` to a `SystemTime`, failing if it is invalid once
+/// normalized, or can not be represented by `SystemTime` on this platform.
+impl TryFrom<Timestamp> for time::SystemTime {
+    type Error = TimestampError;
+
+    fn try_from(mut timestamp: Timestamp) -> Result<time::SystemTime, TimestampError> {
+        timestamp.normalize();
+        if !timestamp.is_valid() {
+            return Err(TimestampError::OutOfRange);
+        }
+        let system_time = if timestamp.seconds >= 0 {
+            time::UNIX_EPOCH.checked_add(time::Duration::new(timestamp.seconds as u64, timestamp.nanos as u32))
+        } else {
+            time::UNIX_EPOCH.checked_sub(time::Duration::new(timestamp.seconds.wrapping_neg() as u64, 0))
+                            .and_then(|system_time| {
+                                system_time.checked_add(time::Duration::new(0, timestamp.nanos as u32))
+                            })
+        };
+        system_time.ok_or(TimestampError::OutOfSystemRange)
+    }
+}
+
+/// An error returned when converting to or from a `Duration` fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DurationError {
+    /// The duration is outside of the range of valid durations.
+    OutOfRange,
+    /// The duration is negative, and can not be converted to a
+    /// `std::time::Duration`.
+    Negative,
+}
+
+impl fmt::Display for DurationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(error::Error::description(self))
+    }
+}
+
+impl error::Error for DurationError {
+    fn description(&self) -> &str {
+        match *self {
+            DurationError::OutOfRange => "duration out of range",
+            DurationError::Negative => "negative duration",
+        }
+    }
+}
+
+/// An error returned when converting to or from a `Timestamp` fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TimestampError {
+    /// The timestamp is outside of the range of valid timestamps.
+    OutOfRange,
+    /// The timestamp can not be represented by a `SystemTime` on this
+    /// platform.
+    OutOfSystemRange,
+}
+
+impl fmt::Display for TimestampError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(error::Error::description(self))
+    }
+}
+
+impl error::Error for TimestampError {
+    fn description(&self) -> &str {
+        match *self {
+            TimestampError::OutOfRange => "timestamp out of range",
+            TimestampError::OutOfSystemRange => "timestamp out of the range of SystemTime",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duration_normalize() {
+        let cases = [
+            ((1, 0), (1, 0)),
+            ((1, 1_500_000_000), (2, 500_000_000)),
+            ((-1, -1_500_000_000), (-2, -500_000_000)),
+            ((1, -1), (0, 999_999_999)),
+            ((-1, 1), (0, -999_999_999)),
+            ((0, -2_000_000_001), (-2, -1)),
+            ((i64::MAX, 1_000_000_000), (i64::MAX, 999_999_999)),
+            ((i64::MIN, -1_000_000_000), (i64::MIN, -999_999_999)),
+        ];
+        for &((seconds, nanos), (normalized_seconds, normalized_nanos)) in &cases {
+            let mut duration = Duration { seconds: seconds, nanos: nanos };
+            duration.normalize();
+            assert_eq!(Duration { seconds: normalized_seconds, nanos: normalized_nanos }, duration,
+                       "normalizing ({}, {})", seconds, nanos);
+        }
+    }
+
+    #[test]
+    fn test_timestamp_normalize() {
+        let cases = [
+            ((1, 0), (1, 0)),
+            ((1, 1_500_000_000), (2, 500_000_000)),
+            ((1, -1), (0, 999_999_999)),
+            ((0, -1_500_000_000), (-2, 500_000_000)),
+            ((i64::MAX, 1_000_000_000), (i64::MAX, 999_999_999)),
+            ((i64::MIN, -1), (i64::MIN, 0)),
+        ];
+        for &((seconds, nanos), (normalized_seconds, normalized_nanos)) in &cases {
+            let mut timestamp = Timestamp { seconds: seconds, nanos: nanos };
+            timestamp.normalize();
+            assert_eq!(Timestamp { seconds: normalized_seconds, nanos: normalized_nanos }, timestamp,
+                       "normalizing ({}, {})", seconds, nanos);
+        }
+    }
+
+    #[test]
+    fn test_duration_conversions() {
+        let duration = time::Duration::new(5, 10);
+        assert_eq!(Ok(Duration { seconds: 5, nanos: 10 }), Duration::try_from(duration));
+        assert_eq!(Ok(duration), time::Duration::try_from(Duration { seconds: 6, nanos: -999_999_990 }));
+
+        assert_eq!(Err(DurationError::OutOfRange),
+                   Duration::try_from(time::Duration::new(MAX_DURATION_SECONDS as u64 + 1, 0)));
+        assert_eq!(Err(DurationError::Negative), time::Duration::try_from(Duration { seconds: 0, nanos: -1 }));
+        assert_eq!(Err(DurationError::OutOfRange),
+                   time::Duration::try_from(Duration { seconds: MAX_DURATION_SECONDS + 1, nanos: 0 }));
+    }
+
+    #[test]
+    fn test_timestamp_conversions() {
+        let cases = [(0, 0), (1, 1), (-1, 999_999_999), (1_500_000_000, 123_456_789), (-1_500_000_000, 0)];
+        for &(seconds, nanos) in &cases {
+            let timestamp = Timestamp { seconds: seconds, nanos: nanos };
+            let system_time = time::SystemTime::try_from(timestamp.clone()).unwrap();
+            assert_eq!(Ok(timestamp), Timestamp::try_from(system_time));
+        }
+
+        let system_time = time::UNIX_EPOCH - time::Duration::new(1, 1);
+        assert_eq!(Ok(Timestamp { seconds: -2, nanos: 999_999_999 }), Timestamp::try_from(system_time));
+        assert_eq!(Err(TimestampError::OutOfRange),
+                   time::SystemTime::try_from(Timestamp { seconds: MAX_TIMESTAMP_SECONDS + 1, nanos: 0 }));
+        assert_eq!(Err(TimestampError::OutOfRange),
+                   time::SystemTime::try_from(Timestamp { seconds: MIN_TIMESTAMP_SECONDS - 1, nanos: 0 }));
+    }
+}
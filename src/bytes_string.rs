@@ -0,0 +1,140 @@
+use alloc::string::String;
+use core::borrow::Borrow;
+use core::fmt;
+use core::hash::{
+    Hash,
+    Hasher,
+};
+use core::ops::Deref;
+use core::str::{
+    self,
+    Utf8Error,
+};
+
+use bytes::Bytes;
+
+/// An immutable UTF-8 string backed by `Bytes`.
+///
+/// `BytesString` may be used in place of `String` as the type of a Protobuf
+/// `string` field. When a message is decoded from a `Bytes` buffer (for
+/// instance with `Message::decode_bytes`), the field value is sliced from the
+/// buffer instead of being copied.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BytesString {
+    bytes: Bytes,
+}
+
+impl BytesString {
+
+    /// Creates a new empty `BytesString`.
+    pub fn new() -> BytesString {
+        BytesString::default()
+    }
+
+    /// Converts `Bytes` to a `BytesString`, or returns an error if the bytes
+    /// are not valid UTF-8.
+    pub fn from_utf8(bytes: Bytes) -> Result<BytesString, Utf8Error> {
+        str::from_utf8(&bytes)?;
+        Ok(BytesString { bytes: bytes })
+    }
+
+    /// Converts `Bytes` to a `BytesString` without checking that the bytes
+    /// are valid UTF-8.
+    ///
+    /// # Safety
+    ///
+    /// The bytes must be valid UTF-8, since `as_str` and the `Deref` to `str`
+    /// assume so.
+    pub unsafe fn from_utf8_unchecked(bytes: Bytes) -> BytesString {
+        BytesString { bytes: bytes }
+    }
+
+    /// Returns the string as a `&str`.
+    pub fn as_str(&self) -> &str {
+        unsafe { str::from_utf8_unchecked(&self.bytes) }
+    }
+
+    /// Returns the underlying `Bytes`.
+    pub fn as_bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    /// Converts the string into the underlying `Bytes`.
+    pub fn into_bytes(self) -> Bytes {
+        self.bytes
+    }
+}
+
+impl Deref for BytesString {
+    type Target = str;
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for BytesString {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Borrow<str> for BytesString {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Hash for BytesString {
+    // Hashes must agree with `str` for `Borrow<str>` to be correct.
+    fn hash<H>(&self, state: &mut H) where H: Hasher {
+        self.as_str().hash(state)
+    }
+}
+
+impl fmt::Debug for BytesString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for BytesString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl From<String> for BytesString {
+    fn from(s: String) -> BytesString {
+        BytesString { bytes: Bytes::from(s) }
+    }
+}
+
+impl <'a> From<&'a str> for BytesString {
+    fn from(s: &'a str) -> BytesString {
+        BytesString { bytes: Bytes::from(s) }
+    }
+}
+
+impl From<BytesString> for Bytes {
+    fn from(s: BytesString) -> Bytes {
+        s.bytes
+    }
+}
+
+impl PartialEq<str> for BytesString {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl <'a> PartialEq<&'a str> for BytesString {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<String> for BytesString {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other
+    }
+}
@@ -0,0 +1,127 @@
+//! A `tokio-io` codec for streams of length-delimited messages.
+
+use std::io::{
+    self,
+    Cursor,
+};
+use std::marker::PhantomData;
+
+use bytes::BytesMut;
+use tokio_io::codec::{
+    Decoder,
+    Encoder,
+};
+
+use encoding::{
+    decode_varint,
+    encoded_len_varint,
+};
+use DecodeErrorKind;
+use DecodeOptions;
+use Message;
+
+/// The default maximum length of a frame: 64MiB.
+const DEFAULT_MAX_FRAME_LENGTH: usize = 64 * 1024 * 1024;
+
+/// Encodes and decodes messages of type `M` framed with a varint length
+/// prefix, the same framing as `Message::encode_length_delimited`.
+///
+/// The decoder buffers partial frames until they are complete. Frames are
+/// decoded from the read buffer without copying, so `Bytes` and `BytesString`
+/// fields are sliced from the buffer. Frames longer than the maximum frame
+/// length, the message size limit of the codec's `DecodeOptions`, are rejected with an `InvalidData` error when decoding, and an
+/// `InvalidInput` error when encoding.
+#[derive(Debug)]
+pub struct MessageCodec<M> {
+    options: DecodeOptions,
+    _marker: PhantomData<fn(M) -> M>,
+}
+
+impl <M> MessageCodec<M> where M: Message {
+
+    /// Creates a new `MessageCodec`, with the default maximum frame length of
+    /// 64MiB.
+    pub fn new() -> MessageCodec<M> {
+        MessageCodec {
+            options: DecodeOptions::new().max_message_size(DEFAULT_MAX_FRAME_LENGTH),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sets the maximum length of a frame, not including the length prefix,
+    /// which is the message size limit of the codec's `DecodeOptions`.
+    pub fn with_max_frame_length(mut self, max_frame_length: usize) -> MessageCodec<M> {
+        self.options = self.options.max_message_size(max_frame_length);
+        self
+    }
+
+    /// Sets the limits which frames are decoded with. The options' message
+    /// size limit replaces the default maximum frame length of 64MiB, and
+    /// frames longer than it are rejected before they are buffered.
+    pub fn with_options(mut self, options: DecodeOptions) -> MessageCodec<M> {
+        self.options = options;
+        self
+    }
+
+    /// Returns the maximum length of a frame, not including the length prefix.
+    pub fn max_frame_length(&self) -> usize {
+        self.options.message_size_limit()
+    }
+}
+
+impl <M> Default for MessageCodec<M> where M: Message {
+    fn default() -> MessageCodec<M> {
+        MessageCodec::new()
+    }
+}
+
+impl <M> Decoder for MessageCodec<M> where M: Message {
+    type Item = M;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<M>> {
+        let (len, prefix_len) = {
+            let mut buf = Cursor::new(&src[..]);
+            match decode_varint(&mut buf) {
+                Ok(len) => (len, buf.position() as usize),
+                // The length prefix is incomplete.
+                Err(ref error) if *error.kind() == DecodeErrorKind::BufferUnderflow => return Ok(None),
+                Err(error) => return Err(error.into()),
+            }
+        };
+
+        if len > self.max_frame_length() as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("frame length {} exceeds the maximum frame length {}",
+                                              len, self.max_frame_length())));
+        }
+
+        let frame_len = prefix_len + len as usize;
+        if src.len() < frame_len {
+            src.reserve(frame_len - src.len());
+            return Ok(None);
+        }
+
+        src.split_to(prefix_len);
+        let frame = src.split_to(len as usize).freeze();
+        M::decode_bytes_with_options(frame, &self.options).map(Some).map_err(From::from)
+    }
+}
+
+impl <M> Encoder for MessageCodec<M> where M: Message {
+    type Item = M;
+    type Error = io::Error;
+
+    fn encode(&mut self, msg: M, dst: &mut BytesMut) -> io::Result<()> {
+        let len = msg.encoded_len();
+        if len > self.max_frame_length() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("frame length {} exceeds the maximum frame length {}",
+                                              len, self.max_frame_length())));
+        }
+
+        dst.reserve(len + encoded_len_varint(len as u64));
+        msg.encode_length_delimited(dst)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+//! Streams of length-delimited messages over `std::io` readers and writers.
+
+use std::io::{
+    self,
+    Read,
+    Write,
+};
+use std::marker::PhantomData;
+
+use DecodeError;
+use DecodeErrorKind;
+use DecodeOptions;
+use Message;
+
+/// The default maximum length of a message read by a `DelimitedReader`: 64MiB.
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Writes a stream of length-delimited messages to an `io::Write`.
+///
+/// Each message is written with a varint length prefix, the same framing as
+/// `Message::encode_length_delimited`.
+#[derive(Debug)]
+pub struct DelimitedWriter<W> {
+    inner: W,
+    buf: Vec<u8>,
+}
+
+impl <W> DelimitedWriter<W> where W: Write {
+
+    /// Creates a new `DelimitedWriter` writing to `inner`.
+    pub fn new(inner: W) -> DelimitedWriter<W> {
+        DelimitedWriter {
+            inner: inner,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Writes a length-delimited message to the underlying writer.
+    pub fn write<M>(&mut self, msg: &M) -> io::Result<()> where M: Message {
+        self.buf.clear();
+        msg.encode_length_delimited(&mut self.buf)?;
+        self.inner.write_all(&self.buf)
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consumes the `DelimitedWriter`, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Reads a stream of length-delimited messages of type `M` from an `io::Read`.
+///
+/// `DelimitedReader` is an iterator which yields a message per frame, and
+/// ends cleanly when the reader is exhausted between frames. A stream which
+/// ends in the middle of a frame yields an `UnexpectedEof` error, and a frame
+/// which is longer than the message size limit of the reader's
+/// `DecodeOptions`, or which fails to decode, yields an `InvalidData` error. The iterator ends after yielding an error.
+///
+/// The length prefix is read a byte at a time, so readers which are not
+/// already buffered should be wrapped in an `io::BufReader`.
+#[derive(Debug)]
+pub struct DelimitedReader<R, M> {
+    inner: R,
+    buf: Vec<u8>,
+    options: DecodeOptions,
+    done: bool,
+    _marker: PhantomData<fn() -> M>,
+}
+
+impl <R, M> DelimitedReader<R, M> where R: Read, M: Message {
+
+    /// Creates a new `DelimitedReader` reading from `inner`, with the
+    /// default maximum message size of 64MiB.
+    pub fn new(inner: R) -> DelimitedReader<R, M> {
+        DelimitedReader {
+            inner: inner,
+            buf: Vec::new(),
+            options: DecodeOptions::new().max_message_size(DEFAULT_MAX_MESSAGE_SIZE),
+            done: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sets the maximum length of a message, the message size limit of the
+    /// reader's `DecodeOptions`. Frames with a longer length prefix are
+    /// rejected without being read.
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> DelimitedReader<R, M> {
+        self.options = self.options.max_message_size(max_message_size);
+        self
+    }
+
+    /// Sets the limits which messages are decoded with. Frames longer than
+    /// the options' message size limit are rejected without being read, so
+    /// the options replace the default maximum message size of 64MiB.
+    pub fn with_options(mut self, options: DecodeOptions) -> DelimitedReader<R, M> {
+        self.options = options;
+        self
+    }
+
+    /// Reads the next message from the stream, or returns `None` if the
+    /// stream ended cleanly before the next frame.
+    pub fn read_message(&mut self) -> io::Result<Option<M>> {
+        let len = match self.read_length()? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        let max_message_size = self.options.message_size_limit();
+        if len > max_message_size as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("message length {} exceeds the maximum message size {}",
+                                              len, max_message_size)));
+        }
+
+        self.buf.clear();
+        self.buf.resize(len as usize, 0);
+        self.inner.read_exact(&mut self.buf)?;
+        M::decode_from_slice_with_options(&self.buf, &self.options).map(Some).map_err(From::from)
+    }
+
+    /// Reads a varint length prefix from the stream, or returns `None` if the
+    /// stream is exhausted before the first byte.
+    fn read_length(&mut self) -> io::Result<Option<u64>> {
+        let mut value = 0;
+        for count in 0..10 {
+            let mut byte = [0];
+            loop {
+                match self.inner.read(&mut byte) {
+                    Ok(0) if count == 0 => return Ok(None),
+                    Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                                       "stream ended within a message length prefix")),
+                    Ok(_) => break,
+                    Err(ref error) if error.kind() == io::ErrorKind::Interrupted => (),
+                    Err(error) => return Err(error),
+                }
+            }
+            value |= ((byte[0] & 0x7F) as u64) << (count * 7);
+            if byte[0] <= 0x7F {
+                return Ok(Some(value));
+            }
+        }
+        Err(DecodeError::new(DecodeErrorKind::InvalidVarint).into())
+    }
+
+    /// Returns a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Consumes the `DelimitedReader`, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl <R, M> Iterator for DelimitedReader<R, M> where R: Read, M: Message {
+    type Item = io::Result<M>;
+
+    fn next(&mut self) -> Option<io::Result<M>> {
+        if self.done {
+            return None;
+        }
+        match self.read_message() {
+            Ok(Some(msg)) => Some(Ok(msg)),
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            },
+        }
+    }
+}
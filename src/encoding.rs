@@ -1,44 +1,71 @@
 //! Utility functions and types for encoding and decoding Protobuf types.
 
-use std::cmp::min;
-use std::error;
-use std::io::{
-    Result,
-    Error,
-    ErrorKind,
-};
-use std::str;
-use std::u32;
-use std::usize;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::str;
+use core::u32;
+use core::usize;
 
 use bytes::{
     Buf,
     BufMut,
+    Bytes,
     LittleEndian,
     Take,
 };
 
+use BytesString;
+use DecodeError;
+use DecodeErrorKind;
+use ExtensionRegistry;
 use Message;
 
-/// Returns an invalid data IO error wrapping the provided cause.
-///
-/// This should be used primarily when decoding a Protobuf type fails.
-pub fn invalid_data<E>(error: E) -> Error where E: Into<Box<error::Error + Send + Sync>> {
-    Error::new(ErrorKind::InvalidData, error.into())
-}
+/// Encodes an integer value into LEB128 variable length format, and writes it to the buffer.
+/// The buffer must have enough remaining space (maximum 10 bytes).
+#[inline]
+pub fn encode_varint<B>(value: u64, buf: &mut B) where B: BufMut {
+    let len = {
+        // bytes_mut is unsafe because it may return an uninitialized slice.
+        // This use is safe because the slice is only written to, not read from.
+        let bytes = unsafe { buf.bytes_mut() };
+        if bytes.len() >= encoded_len_varint(value) {
+            Some(encode_varint_slice(value, bytes))
+        } else {
+            None
+        }
+    };
 
-/// Returns an invalid input IO error wrapping the provided cause.
-///
-/// This should be used primarily when encoding a Protobuf type fails due to
-/// insufficient output buffer space.
-pub fn invalid_input<E>(error: E) -> Error where E: Into<Box<error::Error + Send + Sync>> {
-    Error::new(ErrorKind::InvalidInput, error.into())
+    match len {
+        // advance_mut is unsafe because it could cause uninitialized memory to
+        // be advanced over. This use is safe since each byte which is advanced
+        // over has been written to by encode_varint_slice.
+        Some(len) => unsafe { buf.advance_mut(len) },
+        None => encode_varint_slow(value, buf),
+    }
 }
 
-/// Encodes an integer value into LEB128 variable length format, and writes it to the buffer.
-/// The buffer must have enough remaining space (maximum 10 bytes).
+/// Encodes an integer value into LEB128 variable length format, writing it to
+/// the start of the slice, and returns the number of bytes written. The slice
+/// must be at least as long as the encoded length of the value.
 #[inline]
-pub fn encode_varint<B>(mut value: u64, buf: &mut B) where B: BufMut {
+fn encode_varint_slice(mut value: u64, bytes: &mut [u8]) -> usize {
+    let mut i = 0;
+    while value >= 0x80 {
+        bytes[i] = ((value & 0x7F) | 0x80) as u8;
+        value >>= 7;
+        i += 1;
+    }
+    bytes[i] = value as u8;
+    i + 1
+}
+
+/// Encodes an integer value into LEB128 variable length format one chunk of
+/// the buffer at a time. This is the fallback used by `encode_varint` when the
+/// buffer's current chunk is too short to hold the encoded value, and is only
+/// public for benchmarking.
+#[doc(hidden)]
+pub fn encode_varint_slow<B>(mut value: u64, buf: &mut B) where B: BufMut {
     let mut i;
     'outer: loop {
         i = 0;
@@ -68,9 +95,94 @@ pub fn encode_varint<B>(mut value: u64, buf: &mut B) where B: BufMut {
 
 /// Decodes a LEB128-encoded variable length integer from the buffer.
 #[inline]
-pub fn decode_varint<B>(buf: &mut B) -> Result<u64> where B: Buf {
+pub fn decode_varint<B>(buf: &mut B) -> Result<u64, DecodeError> where B: Buf {
+    let decoded = {
+        let bytes = buf.bytes();
+        let len = bytes.len();
+        // The fast path may only be taken if the varint is guaranteed to end
+        // within the current chunk: either the chunk holds a maximum length
+        // varint, or its last byte terminates a varint.
+        if len >= 10 || (len > 0 && bytes[len - 1] < 0x80) {
+            Some(decode_varint_slice(bytes))
+        } else {
+            None
+        }
+    };
+
+    match decoded {
+        Some(Ok((value, advance))) => {
+            buf.advance(advance);
+            Ok(value)
+        },
+        Some(Err(error)) => Err(error),
+        None => decode_varint_slow(buf),
+    }
+}
+
+/// Decodes a LEB128-encoded variable length integer from the start of the
+/// slice, returning the value and its encoded length.
+///
+/// The slice must either be at least 10 bytes long, or end with a byte less
+/// than 0x80, so that decoding never reads past its end. The loop is fully
+/// unrolled, and accumulates the value in 32-bit parts.
+#[inline]
+fn decode_varint_slice(bytes: &[u8]) -> Result<(u64, usize), DecodeError> {
+    let mut b = bytes[0];
+    let mut part0 = u32::from(b);
+    if b < 0x80 { return Ok((u64::from(part0), 1)); }
+    part0 -= 0x80;
+    b = bytes[1];
+    part0 += u32::from(b) << 7;
+    if b < 0x80 { return Ok((u64::from(part0), 2)); }
+    part0 -= 0x80 << 7;
+    b = bytes[2];
+    part0 += u32::from(b) << 14;
+    if b < 0x80 { return Ok((u64::from(part0), 3)); }
+    part0 -= 0x80 << 14;
+    b = bytes[3];
+    part0 += u32::from(b) << 21;
+    if b < 0x80 { return Ok((u64::from(part0), 4)); }
+    part0 -= 0x80 << 21;
+    let value = u64::from(part0);
+
+    b = bytes[4];
+    let mut part1 = u32::from(b);
+    if b < 0x80 { return Ok((value + (u64::from(part1) << 28), 5)); }
+    part1 -= 0x80;
+    b = bytes[5];
+    part1 += u32::from(b) << 7;
+    if b < 0x80 { return Ok((value + (u64::from(part1) << 28), 6)); }
+    part1 -= 0x80 << 7;
+    b = bytes[6];
+    part1 += u32::from(b) << 14;
+    if b < 0x80 { return Ok((value + (u64::from(part1) << 28), 7)); }
+    part1 -= 0x80 << 14;
+    b = bytes[7];
+    part1 += u32::from(b) << 21;
+    if b < 0x80 { return Ok((value + (u64::from(part1) << 28), 8)); }
+    part1 -= 0x80 << 21;
+    let value = value + (u64::from(part1) << 28);
+
+    b = bytes[8];
+    let mut part2 = u32::from(b);
+    if b < 0x80 { return Ok((value + (u64::from(part2) << 56), 9)); }
+    part2 -= 0x80;
+    b = bytes[9];
+    part2 += u32::from(b) << 7;
+    // Bits beyond the 64th are discarded, as in decode_varint_slow.
+    if b < 0x80 { return Ok((value + (u64::from(part2) << 56), 10)); }
+
+    Err(DecodeError::new(DecodeErrorKind::InvalidVarint))
+}
+
+/// Decodes a LEB128-encoded variable length integer from the buffer one byte
+/// at a time. This is the fallback used by `decode_varint` when the varint may
+/// span multiple chunks of the buffer, and is only public for benchmarking.
+#[doc(hidden)]
+pub fn decode_varint_slow<B>(buf: &mut B) -> Result<u64, DecodeError> where B: Buf {
     let mut value = 0;
-    for count in 0..min(10, buf.remaining()) {
+    let limit = min(10, buf.remaining());
+    for count in 0..limit {
         let byte = buf.get_u8();
         value |= ((byte & 0x7F) as u64) << (count * 7);
         if byte <= 0x7F {
@@ -78,7 +190,11 @@ pub fn decode_varint<B>(buf: &mut B) -> Result<u64> where B: Buf {
         }
     }
 
-    Err(invalid_data("failed to decode varint"))
+    if limit < 10 {
+        Err(DecodeError::new(DecodeErrorKind::BufferUnderflow))
+    } else {
+        Err(DecodeError::new(DecodeErrorKind::InvalidVarint))
+    }
 }
 
 /// Returns the encoded length of the value in LEB128 variable length format.
@@ -97,13 +213,15 @@ pub fn encoded_len_varint(value: u64) -> usize {
     else { 10 }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum WireType {
     Varint = 0,
     SixtyFourBit = 1,
     LengthDelimited = 2,
-    ThirtyTwoBit = 5
+    StartGroup = 3,
+    EndGroup = 4,
+    ThirtyTwoBit = 5,
 }
 
 pub const MIN_TAG: u32 = 1;
@@ -112,14 +230,364 @@ pub const MAX_TAG: u32 = (1 << 29) - 1;
 impl WireType {
     // TODO: impl TryFrom<u8> when stable.
     #[inline]
-    pub fn try_from(val: u8) -> Result<WireType> {
+    pub fn try_from(val: u8) -> Result<WireType, DecodeError> {
         match val {
             0 => Ok(WireType::Varint),
             1 => Ok(WireType::SixtyFourBit),
             2 => Ok(WireType::LengthDelimited),
+            3 => Ok(WireType::StartGroup),
+            4 => Ok(WireType::EndGroup),
             5 => Ok(WireType::ThirtyTwoBit),
-            _ => Err(invalid_data(format!("invalid wire type value: {}", val))),
+            _ => Err(DecodeError::new(DecodeErrorKind::InvalidWireType(val))),
+        }
+    }
+}
+
+/// A buffer which reads from a byte slice.
+///
+/// Messages are decoded from slices, for instance by
+/// `Message::decode_from_slice`, through a `SliceBuf`.
+#[derive(Clone, Copy, Debug)]
+pub struct SliceBuf<'a> {
+    bytes: &'a [u8],
+}
+
+impl <'a> SliceBuf<'a> {
+    /// Creates a buffer which reads from the slice.
+    pub fn new(bytes: &'a [u8]) -> SliceBuf<'a> {
+        SliceBuf { bytes: bytes }
+    }
+}
+
+impl <'a> Buf for SliceBuf<'a> {
+    fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.bytes
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        self.bytes = &self.bytes[cnt..];
+    }
+}
+
+/// The default maximum depth of nested messages and groups which may be decoded.
+pub const RECURSION_LIMIT: u32 = 100;
+
+/// Limits on the resources used to decode a message from untrusted input,
+/// passed to `Message::decode_with_options`.
+///
+/// By default there are no size limits, and the recursion limit is
+/// `RECURSION_LIMIT`. Each limit which is exceeded fails decoding with a
+/// distinct `DecodeErrorKind`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeOptions {
+    max_message_size: usize,
+    max_length: usize,
+    max_repeated_len: usize,
+    recursion_limit: u32,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> DecodeOptions {
+        DecodeOptions {
+            max_message_size: usize::MAX,
+            max_length: usize::MAX,
+            max_repeated_len: usize::MAX,
+            recursion_limit: RECURSION_LIMIT,
+        }
+    }
+}
+
+impl DecodeOptions {
+
+    /// Creates options with the default limits.
+    pub fn new() -> DecodeOptions {
+        DecodeOptions::default()
+    }
+
+    /// Sets the maximum encoded length of the message, in bytes. Longer
+    /// messages fail with `DecodeErrorKind::MessageTooLarge`.
+    pub fn max_message_size(mut self, max_message_size: usize) -> DecodeOptions {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Sets the maximum length of a string or bytes value, in bytes, including
+    /// those of unknown fields. Longer values fail with
+    /// `DecodeErrorKind::LengthLimitExceeded`.
+    pub fn max_length(mut self, max_length: usize) -> DecodeOptions {
+        self.max_length = max_length;
+        self
+    }
+
+    /// Sets the maximum number of elements of a repeated or map field. Fields
+    /// with more elements fail with `DecodeErrorKind::RepeatedLimitExceeded`.
+    pub fn max_repeated_len(mut self, max_repeated_len: usize) -> DecodeOptions {
+        self.max_repeated_len = max_repeated_len;
+        self
+    }
+
+    /// Sets the maximum depth of nested messages and groups. Deeper messages
+    /// fail with `DecodeErrorKind::RecursionLimitReached`.
+    pub fn recursion_limit(mut self, recursion_limit: u32) -> DecodeOptions {
+        self.recursion_limit = recursion_limit;
+        self
+    }
+
+    /// Returns the maximum encoded length of the message, in bytes.
+    pub fn message_size_limit(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// Returns an error if a message of encoded length `len` exceeds the
+    /// message size limit.
+    #[doc(hidden)]
+    #[inline]
+    pub fn check_message_size(&self, len: usize) -> Result<(), DecodeError> {
+        if len > self.max_message_size {
+            return Err(DecodeError::new(DecodeErrorKind::MessageTooLarge));
+        }
+        Ok(())
+    }
+}
+
+/// Additional information passed to every merge function.
+///
+/// The context tracks how many more levels of nested messages and groups may
+/// be decoded, so that a deeply nested (possibly malicious) input fails to
+/// decode instead of overflowing the stack. It also holds the length limits
+/// of `DecodeOptions`.
+///
+/// The context may also hold the `Bytes` buffer which is being decoded, in
+/// which case `Bytes` and `BytesString` fields are sliced from it instead of
+/// being copied, and an `ExtensionRegistry` which is used to check the
+/// extension fields of extendable messages.
+#[derive(Clone, Copy, Debug)]
+pub struct DecodeContext<'a> {
+    recurse_count: u32,
+    max_length: usize,
+    max_repeated_len: usize,
+    source: Option<&'a Bytes>,
+    registry: Option<&'a ExtensionRegistry>,
+}
+
+impl <'a> Default for DecodeContext<'a> {
+    fn default() -> DecodeContext<'a> {
+        DecodeContext::with_recursion_limit(RECURSION_LIMIT)
+    }
+}
+
+impl <'a> DecodeContext<'a> {
+
+    /// Creates a context which allows at most `limit` levels of nested
+    /// messages and groups to be decoded.
+    pub fn with_recursion_limit(limit: u32) -> DecodeContext<'a> {
+        DecodeContext::with_options(&DecodeOptions::default().recursion_limit(limit))
+    }
+
+    /// Creates a context which enforces the recursion and length limits of
+    /// `options`. The message size limit is checked by the caller, before
+    /// decoding starts.
+    pub fn with_options(options: &DecodeOptions) -> DecodeContext<'a> {
+        DecodeContext {
+            recurse_count: options.recursion_limit,
+            max_length: options.max_length,
+            max_repeated_len: options.max_repeated_len,
+            source: None,
+            registry: None,
+        }
+    }
+
+    /// Returns a copy of the context which slices `Bytes` and `BytesString`
+    /// field values out of `source`, when the buffer being decoded is backed
+    /// by it.
+    pub fn with_source<'b>(self, source: &'b Bytes) -> DecodeContext<'b> where 'a: 'b {
+        DecodeContext {
+            recurse_count: self.recurse_count,
+            max_length: self.max_length,
+            max_repeated_len: self.max_repeated_len,
+            source: Some(source),
+            registry: self.registry,
+        }
+    }
+
+    /// Returns a copy of the context which checks extension fields against
+    /// the extensions in `registry`.
+    pub fn with_extension_registry<'b>(self, registry: &'b ExtensionRegistry) -> DecodeContext<'b>
+    where 'a: 'b {
+        DecodeContext {
+            recurse_count: self.recurse_count,
+            max_length: self.max_length,
+            max_repeated_len: self.max_repeated_len,
+            source: self.source,
+            registry: Some(registry),
+        }
+    }
+
+    /// Returns a copy of the context without its source buffer and extension
+    /// registry, which may be stored to decode a value later.
+    #[doc(hidden)]
+    pub fn detach(&self) -> DecodeContext<'static> {
+        DecodeContext {
+            recurse_count: self.recurse_count,
+            max_length: self.max_length,
+            max_repeated_len: self.max_repeated_len,
+            source: None,
+            registry: None,
+        }
+    }
+
+    /// Returns the extension registry held by the context, if any.
+    #[doc(hidden)]
+    pub fn extension_registry(&self) -> Option<&'a ExtensionRegistry> {
+        self.registry
+    }
+
+    /// Returns a context for decoding a nested message or group, or an error
+    /// if the recursion limit has been reached.
+    #[doc(hidden)]
+    #[inline]
+    pub fn enter_recursion(&self) -> Result<DecodeContext<'a>, DecodeError> {
+        if self.recurse_count == 0 {
+            return Err(DecodeError::new(DecodeErrorKind::RecursionLimitReached));
+        }
+        Ok(DecodeContext {
+            recurse_count: self.recurse_count - 1,
+            ..*self
+        })
+    }
+
+    /// Returns an error if a string or bytes value of length `len` exceeds
+    /// the length limit.
+    #[doc(hidden)]
+    #[inline]
+    pub fn check_length(&self, len: u64) -> Result<(), DecodeError> {
+        if len > self.max_length as u64 {
+            return Err(DecodeError::new(DecodeErrorKind::LengthLimitExceeded));
+        }
+        Ok(())
+    }
+
+    /// Returns an error if another element may not be added to a repeated or
+    /// map field which already holds `len` elements.
+    #[doc(hidden)]
+    #[inline]
+    pub fn check_repeated_len(&self, len: usize) -> Result<(), DecodeError> {
+        if len >= self.max_repeated_len {
+            return Err(DecodeError::new(DecodeErrorKind::RepeatedLimitExceeded));
+        }
+        Ok(())
+    }
+
+    /// Returns the first `len` bytes of the buffer as a slice of the source,
+    /// or `None` if the bytes are not contiguous in the source buffer.
+    fn slice_source<B>(&self, buf: &B, len: usize) -> Option<Bytes> where B: Buf {
+        let source = match self.source {
+            Some(source) => source,
+            None => return None,
+        };
+        let bytes = buf.bytes();
+        if bytes.len() < len {
+            return None;
         }
+        let start = bytes.as_ptr() as usize;
+        let source_start = source.as_ptr() as usize;
+        if start < source_start || start + len > source_start + source.len() {
+            return None;
+        }
+        let offset = start - source_start;
+        Some(source.slice(offset, offset + len))
+    }
+}
+
+/// The encoded lengths of the nested messages in a message, in the order they
+/// are encoded.
+///
+/// Encoding a nested message requires its length up front, so encoding a
+/// message tree naively recomputes the length of each message once per level
+/// of nesting above it. Instead, `Message::encode` computes every length once
+/// with `Message::encoded_len_cached`, and `Message::encode_raw_cached` reads
+/// the lengths back while encoding.
+///
+/// The cache also records whether the encoding is deterministic, in which case
+/// map entries are encoded in key order rather than hash order, and holds the
+/// deterministic re-encodings of undecoded `Lazy` fields, so that each is
+/// decoded once per encoding.
+#[doc(hidden)]
+#[derive(Clone, Debug, Default)]
+pub struct SizeCache {
+    sizes: Vec<usize>,
+    position: usize,
+    deterministic: bool,
+    encodings: Vec<Bytes>,
+    encoding_position: usize,
+}
+
+impl SizeCache {
+
+    /// Creates an empty size cache.
+    pub fn new() -> SizeCache {
+        SizeCache::default()
+    }
+
+    /// Creates an empty size cache for a deterministic encoding.
+    pub fn deterministic() -> SizeCache {
+        SizeCache {
+            deterministic: true,
+            ..SizeCache::default()
+        }
+    }
+
+    /// Returns `true` if map entries must be encoded in key order.
+    #[inline]
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// Reserves a slot for the length of a nested message, which must be
+    /// filled with `set` once the length is known.
+    #[inline]
+    pub fn reserve(&mut self) -> usize {
+        self.sizes.push(0);
+        self.sizes.len() - 1
+    }
+
+    /// Sets the length of the nested message in a reserved slot.
+    #[inline]
+    pub fn set(&mut self, slot: usize, len: usize) {
+        self.sizes[slot] = len;
+    }
+
+    /// Returns the length of the next nested message to be encoded.
+    #[inline]
+    pub fn next_len(&mut self) -> usize {
+        let len = self.sizes[self.position];
+        self.position += 1;
+        len
+    }
+
+    /// Stores the encoding of a nested message, computed while measuring it,
+    /// to be read back with `next_encoding` while encoding.
+    #[inline]
+    pub fn push_encoding(&mut self, encoded: Bytes) {
+        self.encodings.push(encoded);
+    }
+
+    /// Returns the next stored encoding of a nested message.
+    #[inline]
+    pub fn next_encoding(&mut self) -> Bytes {
+        let encoded = self.encodings[self.encoding_position].clone();
+        self.encoding_position += 1;
+        encoded
+    }
+
+    /// Returns the number of cached lengths which have not been read yet.
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        self.sizes.len() - self.position
     }
 }
 
@@ -135,16 +603,16 @@ pub fn encode_key<B>(tag: u32, wire_type: WireType, buf: &mut B) where B: BufMut
 /// Decodes a Protobuf field key, which consists of a wire type designator and
 /// the field tag.
 #[inline]
-pub fn decode_key<B>(buf: &mut B) -> Result<(u32, WireType)> where B: Buf {
+pub fn decode_key<B>(buf: &mut B) -> Result<(u32, WireType), DecodeError> where B: Buf {
     let key = decode_varint(buf)?;
     if key > u32::MAX as u64 {
-        return Err(invalid_data("failed to decode field key: u32 overflow"));
+        return Err(DecodeError::new(DecodeErrorKind::InvalidKey));
     }
     let wire_type = WireType::try_from(key as u8 & 0x07)?;
     let tag = key as u32 >> 3;
 
     if tag < MIN_TAG {
-        return Err(invalid_data("invalid zero tag value"));
+        return Err(DecodeError::new(DecodeErrorKind::InvalidKey));
     }
 
     Ok((tag, wire_type))
@@ -160,39 +628,64 @@ pub fn key_len(tag: u32) -> usize {
 /// Checks that the expected wire type matches the actual wire type,
 /// or returns an error result.
 #[inline]
-pub fn check_wire_type(expected: WireType, actual: WireType) -> Result<()> {
+pub fn check_wire_type(expected: WireType, actual: WireType) -> Result<(), DecodeError> {
     if expected != actual {
-        return Err(invalid_data(format!("invalid wire type: {:?} (expected {:?})", actual, expected)));
+        return Err(DecodeError::new(DecodeErrorKind::UnexpectedWireType {
+            expected: expected,
+            actual: actual,
+        }));
     }
     Ok(())
 }
 
-pub fn skip_field<B>(wire_type: WireType, buf: &mut B) -> Result<()> where B: Buf {
+/// Skips over the value of a field with the given tag and wire type.
+///
+/// Groups are skipped by consuming nested fields until the matching end group
+/// key is found, so the field tag is required.
+pub fn skip_field<B>(wire_type: WireType,
+                     tag: u32,
+                     buf: &mut B,
+                     ctx: DecodeContext)
+                     -> Result<(), DecodeError> where B: Buf {
     match wire_type {
         WireType::Varint => {
-            decode_varint(buf).map_err(|error| {
-                Error::new(error.kind(), format!("failed to skip varint field: {}", error))
-            })?;
+            decode_varint(buf)?;
         },
         WireType::SixtyFourBit => {
             if buf.remaining() < 8 {
-                return Err(invalid_data("failed to skip 64-bit field: buffer underflow"));
+                return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
             }
             buf.advance(8);
         },
         WireType::ThirtyTwoBit => {
             if buf.remaining() < 4 {
-                return Err(invalid_data("failed to skip 32-bit field: buffer underflow"));
+                return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
             }
             buf.advance(4);
         },
         WireType::LengthDelimited => {
             let len = decode_varint(buf)?;
             if len > buf.remaining() as u64 {
-                return Err(invalid_data("failed to skip length delimited field: buffer underflow"));
+                return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
             }
             buf.advance(len as usize);
         },
+        WireType::StartGroup => {
+            let ctx = ctx.enter_recursion()?;
+            loop {
+                let (inner_tag, inner_wire_type) = decode_key(buf)?;
+                if inner_wire_type == WireType::EndGroup {
+                    if inner_tag != tag {
+                        return Err(DecodeError::new(DecodeErrorKind::UnexpectedEndGroup));
+                    }
+                    break;
+                }
+                skip_field(inner_wire_type, inner_tag, buf, ctx)?;
+            }
+        },
+        WireType::EndGroup => {
+            return Err(DecodeError::new(DecodeErrorKind::UnexpectedEndGroup));
+        },
     };
     Ok(())
 }
@@ -216,27 +709,30 @@ macro_rules! merge_repeated_numeric {
      $merge_repeated:ident) => (
         pub fn $merge_repeated<B>(wire_type: WireType,
                                   values: &mut Vec<$ty>,
-                                  buf: &mut Take<B>)
-                                  -> Result<()> where B: Buf {
+                                  buf: &mut Take<B>,
+                                  ctx: DecodeContext)
+                                  -> Result<(), DecodeError> where B: Buf {
             if wire_type == WireType::LengthDelimited {
                 let len = decode_varint(buf)?;
                 if len > buf.remaining() as u64 {
-                    return Err(invalid_data("buffer underflow"));
+                    return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
                 }
                 let len = len as usize;
                 let limit = buf.limit();
                 buf.set_limit(len);
 
                 while buf.has_remaining() {
-                let mut value = Default::default();
-                $merge($wire_type, &mut value, buf)?;
-                values.push(value);
+                    ctx.check_repeated_len(values.len())?;
+                    let mut value = Default::default();
+                    $merge($wire_type, &mut value, buf, ctx)?;
+                    values.push(value);
                 }
                 buf.set_limit(limit - len);
             } else {
                 check_wire_type($wire_type, wire_type)?;
+                ctx.check_repeated_len(values.len())?;
                 let mut value = Default::default();
-                $merge(wire_type, &mut value, buf)?;
+                $merge(wire_type, &mut value, buf, ctx)?;
                 values.push(value);
             }
             Ok(())
@@ -268,7 +764,11 @@ macro_rules! varint {
                 encode_varint($to_uint64, buf);
             }
 
-            pub fn merge<B>(wire_type: WireType, value: &mut $ty, buf: &mut B) -> Result<()> where B: Buf {
+            pub fn merge<B>(wire_type: WireType,
+                            value: &mut $ty,
+                            buf: &mut B,
+                            _ctx: DecodeContext)
+                            -> Result<(), DecodeError> where B: Buf {
                 check_wire_type(WireType::Varint, wire_type)?;
                 let $from_uint64_value = decode_varint(buf)?;
                 *value = $from_uint64;
@@ -385,10 +885,14 @@ macro_rules! fixed_width {
                 buf.$put::<LittleEndian>(*value);
             }
 
-            pub fn merge<B>(wire_type: WireType, value: &mut $ty, buf: &mut B) -> Result<()> where B: Buf {
+            pub fn merge<B>(wire_type: WireType,
+                            value: &mut $ty,
+                            buf: &mut B,
+                            _ctx: DecodeContext)
+                            -> Result<(), DecodeError> where B: Buf {
                 check_wire_type($wire_type, wire_type)?;
                 if buf.remaining() < $width {
-                    return Err(invalid_data("buffer underflow"));
+                    return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
                 }
                 *value = buf.$get::<LittleEndian>();
                 Ok(())
@@ -464,27 +968,44 @@ fixed_width!(u64, 8, WireType::SixtyFourBit, fixed64, put_u64, get_u64);
 fixed_width!(i32, 4, WireType::ThirtyTwoBit, sfixed32, put_i32, get_i32);
 fixed_width!(i64, 8, WireType::SixtyFourBit, sfixed64, put_i64, get_i64);
 
-/// Macro which emits encoding functions for a length-delimited type.
+/// Macro which emits encoding functions for a length-delimited type. Values
+/// are encoded from any `AsRef<$target>` type, and merged into any type
+/// implementing the adapter trait. The tests use the test type.
 macro_rules! length_delimited {
-    ($ty:ty) => (
+    ($target:ty, $adapter:ident, $test_ty:ty) => (
 
-        encode_repeated!($ty);
+         pub fn encode_repeated<A, B>(tag: u32, values: &Vec<A>, buf: &mut B)
+         where A: AsRef<$target>,
+               B: BufMut {
+             for value in values {
+                 encode(tag, value, buf);
+             }
+         }
 
-         pub fn merge_repeated<B>(wire_type: WireType, values: &mut Vec<$ty>, buf: &mut Take<B>) -> Result<()> where B: Buf {
+         pub fn merge_repeated<A, B>(wire_type: WireType,
+                                     values: &mut Vec<A>,
+                                     buf: &mut Take<B>,
+                                     ctx: DecodeContext)
+                                     -> Result<(), DecodeError>
+         where A: $adapter,
+               B: Buf {
                 check_wire_type(WireType::LengthDelimited, wire_type)?;
+                ctx.check_repeated_len(values.len())?;
                 let mut value = Default::default();
-                merge(wire_type, &mut value, buf)?;
+                merge(wire_type, &mut value, buf, ctx)?;
                 values.push(value);
                 Ok(())
          }
 
-         pub fn encoded_len(tag: u32, value: &$ty) -> usize {
-             key_len(tag) + encoded_len_varint(value.len() as u64) + value.len()
+         pub fn encoded_len<A>(tag: u32, value: &A) -> usize where A: AsRef<$target> {
+             let len = value.as_ref().len();
+             key_len(tag) + encoded_len_varint(len as u64) + len
          }
 
-         pub fn encoded_len_repeated(tag: u32, values: &Vec<$ty>) -> usize {
+         pub fn encoded_len_repeated<A>(tag: u32, values: &Vec<A>) -> usize where A: AsRef<$target> {
              key_len(tag) * values.len() + values.iter().map(|value| {
-                 encoded_len_varint(value.len() as u64) + value.len()
+                 let len = value.as_ref().len();
+                 encoded_len_varint(len as u64) + len
              }).sum::<usize>()
          }
 
@@ -499,11 +1020,11 @@ macro_rules! length_delimited {
             };
 
              quickcheck! {
-                 fn check(value: $ty, tag: u32) -> TestResult {
+                 fn check(value: $test_ty, tag: u32) -> TestResult {
                      super::test::check_type(value, tag, WireType::LengthDelimited,
                                              encode, merge, encoded_len)
                  }
-                 fn check_repeated(value: Vec<$ty>, tag: u32) -> TestResult {
+                 fn check_repeated(value: Vec<$test_ty>, tag: u32) -> TestResult {
                      super::test::check_collection_type(value, tag, WireType::LengthDelimited,
                                                         encode_repeated, merge_repeated,
                                                         encoded_len_repeated)
@@ -513,66 +1034,149 @@ macro_rules! length_delimited {
     )
 }
 
-pub mod string {
-    use super::*;
+/// A type which can represent a Protobuf `string` field: `String` or
+/// `BytesString`.
+pub trait StringAdapter: AsRef<str> + Default + Sized {
+    /// Replaces the contents of the string with the first `len` bytes of
+    /// the buffer, which must be valid UTF-8.
+    #[doc(hidden)]
+    fn replace_with<B>(&mut self, buf: &mut B, len: usize, ctx: DecodeContext) -> Result<(), DecodeError>
+    where B: Buf;
+}
 
-    pub fn encode<B>(tag: u32,
-                     value: &String,
-                     buf: &mut B) where B: BufMut {
-        encode_key(tag, WireType::LengthDelimited, buf);
-        encode_varint(value.len() as u64, buf);
-        buf.put_slice(value.as_bytes());
-    }
-    pub fn merge<B>(wire_type: WireType,
-                    value: &mut String,
-                    buf: &mut Take<B>) -> Result<()> where B: Buf {
+impl StringAdapter for String {
+    fn replace_with<B>(&mut self, buf: &mut B, len: usize, ctx: DecodeContext) -> Result<(), DecodeError>
+    where B: Buf {
         unsafe {
             // String::as_mut_vec is unsafe because it doesn't check that the bytes
             // inserted into it the resulting vec are valid UTF-8. We check
             // explicitly in order to ensure this is safe.
-            super::bytes::merge(wire_type, value.as_mut_vec(), buf)?;
-            str::from_utf8(value.as_bytes()).map_err(|_| {
-                invalid_data("failed to decode string: data is not UTF-8 encoded")
-            })?;
+            self.as_mut_vec().replace_with(buf, len, ctx);
+            if str::from_utf8(self.as_bytes()).is_err() {
+                // Don't leave invalid UTF-8 behind in the string.
+                self.clear();
+                return Err(DecodeError::new(DecodeErrorKind::InvalidUtf8));
+            }
         }
         Ok(())
     }
+}
 
-    length_delimited!(String);
+impl StringAdapter for BytesString {
+    fn replace_with<B>(&mut self, buf: &mut B, len: usize, ctx: DecodeContext) -> Result<(), DecodeError>
+    where B: Buf {
+        let mut bytes = Bytes::new();
+        bytes.replace_with(buf, len, ctx);
+        match BytesString::from_utf8(bytes) {
+            Ok(string) => *self = string,
+            Err(..) => {
+                *self = BytesString::new();
+                return Err(DecodeError::new(DecodeErrorKind::InvalidUtf8));
+            },
+        }
+        Ok(())
+    }
 }
 
-pub mod bytes {
+pub mod string {
     use super::*;
 
-    pub fn encode<B>(tag: u32, value: &Vec<u8>, buf: &mut B) where B: BufMut {
+    pub fn encode<A, B>(tag: u32, value: &A, buf: &mut B)
+    where A: AsRef<str>,
+          B: BufMut {
+        let value = value.as_ref();
         encode_key(tag, WireType::LengthDelimited, buf);
         encode_varint(value.len() as u64, buf);
-        buf.put_slice(value);
+        buf.put_slice(value.as_bytes());
     }
 
-    pub fn merge<B>(wire_type: WireType, value: &mut Vec<u8>, buf: &mut Take<B>) -> Result<()> where B: Buf {
+    pub fn merge<A, B>(wire_type: WireType,
+                       value: &mut A,
+                       buf: &mut Take<B>,
+                       ctx: DecodeContext)
+                       -> Result<(), DecodeError>
+    where A: StringAdapter,
+          B: Buf {
         check_wire_type(WireType::LengthDelimited, wire_type)?;
         let len = decode_varint(buf)?;
+        ctx.check_length(len)?;
         if (buf.remaining() as u64) < len {
-            return Err(invalid_data("buffer underflow"));
+            return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
         }
-        let limit = buf.limit();
-        buf.set_limit(len as usize);
-        value.clear();
-        value.reserve_exact(len as usize);
-        while buf.has_remaining() {
-            let len = {
+        value.replace_with(buf, len as usize, ctx)
+    }
+
+    length_delimited!(str, StringAdapter, String);
+}
+
+/// A type which can represent a Protobuf `bytes` field: `Vec<u8>` or `Bytes`.
+pub trait BytesAdapter: AsRef<[u8]> + Default + Sized {
+    /// Replaces the contents of the value with the first `len` bytes of the
+    /// buffer. The buffer must contain at least `len` bytes.
+    #[doc(hidden)]
+    fn replace_with<B>(&mut self, buf: &mut B, len: usize, ctx: DecodeContext) where B: Buf;
+}
+
+impl BytesAdapter for Vec<u8> {
+    fn replace_with<B>(&mut self, buf: &mut B, mut len: usize, _ctx: DecodeContext) where B: Buf {
+        self.clear();
+        self.reserve_exact(len);
+        while len > 0 {
+            let n = {
                 let bytes = buf.bytes();
-                value.extend_from_slice(bytes);
-                bytes.len()
+                let n = min(bytes.len(), len);
+                self.extend_from_slice(&bytes[..n]);
+                n
             };
+            buf.advance(n);
+            len -= n;
+        }
+    }
+}
+
+impl BytesAdapter for Bytes {
+    fn replace_with<B>(&mut self, buf: &mut B, len: usize, ctx: DecodeContext) where B: Buf {
+        if let Some(bytes) = ctx.slice_source(buf, len) {
             buf.advance(len);
+            *self = bytes;
+            return;
         }
-        buf.set_limit(limit - len as usize);
+        let mut vec = Vec::new();
+        vec.replace_with(buf, len, ctx);
+        *self = Bytes::from(vec);
+    }
+}
+
+pub mod bytes {
+    use super::*;
+
+    pub fn encode<A, B>(tag: u32, value: &A, buf: &mut B)
+    where A: AsRef<[u8]>,
+          B: BufMut {
+        let value = value.as_ref();
+        encode_key(tag, WireType::LengthDelimited, buf);
+        encode_varint(value.len() as u64, buf);
+        buf.put_slice(value);
+    }
+
+    pub fn merge<A, B>(wire_type: WireType,
+                       value: &mut A,
+                       buf: &mut Take<B>,
+                       ctx: DecodeContext)
+                       -> Result<(), DecodeError>
+    where A: BytesAdapter,
+          B: Buf {
+        check_wire_type(WireType::LengthDelimited, wire_type)?;
+        let len = decode_varint(buf)?;
+        ctx.check_length(len)?;
+        if (buf.remaining() as u64) < len {
+            return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
+        }
+        value.replace_with(buf, len as usize, ctx);
         Ok(())
     }
 
-    length_delimited!(Vec<u8>);
+    length_delimited!([u8], BytesAdapter, Vec<u8>);
 }
 
 pub mod message {
@@ -586,19 +1190,24 @@ pub mod message {
         msg.encode_raw(buf);
     }
 
-    pub fn merge<M, B>(wire_type: WireType, msg: &mut M, buf: &mut Take<B>) -> Result<()>
+    pub fn merge<M, B>(wire_type: WireType,
+                       msg: &mut M,
+                       buf: &mut Take<B>,
+                       ctx: DecodeContext)
+                       -> Result<(), DecodeError>
     where M: Message,
         B: Buf {
         check_wire_type(WireType::LengthDelimited, wire_type)?;
+        let ctx = ctx.enter_recursion()?;
         let len = decode_varint(buf)?;
         if len > buf.remaining() as u64 {
-            return Err(invalid_data("buffer underflow"));
+            return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
         }
 
         let len = len as usize;
         let limit = buf.limit();
         buf.set_limit(len);
-        msg.merge(buf)?;
+        msg.merge_with_context(buf, ctx)?;
         buf.set_limit(limit - len);
         Ok(())
     }
@@ -611,19 +1220,24 @@ pub mod message {
         }
     }
 
-    pub fn merge_repeated<M, B>(wire_type: WireType, messages: &mut Vec<M>, buf: &mut Take<B>) -> Result<()>
+    pub fn merge_repeated<M, B>(wire_type: WireType,
+                                messages: &mut Vec<M>,
+                                buf: &mut Take<B>,
+                                ctx: DecodeContext)
+                                -> Result<(), DecodeError>
     where M: Message,
         B: Buf {
         check_wire_type(WireType::LengthDelimited, wire_type)?;
+        ctx.check_repeated_len(messages.len())?;
         let mut msg = M::default();
-        merge(WireType::LengthDelimited, &mut msg, buf)?;
+        merge(WireType::LengthDelimited, &mut msg, buf, ctx)?;
         messages.push(msg);
         Ok(())
     }
 
     pub fn encoded_len<M>(tag: u32, msg: &M) -> usize where M: Message {
         let len = msg.encoded_len();
-        key_len(tag) + encoded_len_varint(len as u64) + msg.encoded_len()
+        key_len(tag) + encoded_len_varint(len as u64) + len
     }
 
     pub fn encoded_len_repeated<M>(tag: u32, messages: &[M]) -> usize where M: Message {
@@ -633,164 +1247,813 @@ pub mod message {
                       .map(|len| len + encoded_len_varint(len as u64))
                       .sum::<usize>()
     }
+
+    pub fn encode_cached<M, B>(tag: u32, msg: &M, buf: &mut B, cache: &mut SizeCache)
+    where M: Message,
+          B: BufMut {
+        encode_key(tag, WireType::LengthDelimited, buf);
+        encode_varint(cache.next_len() as u64, buf);
+        msg.encode_raw_cached(buf, cache);
+    }
+
+    pub fn encode_repeated_cached<M, B>(tag: u32, messages: &[M], buf: &mut B, cache: &mut SizeCache)
+    where M: Message,
+          B: BufMut {
+        for msg in messages {
+            encode_cached(tag, msg, buf, cache);
+        }
+    }
+
+    pub fn encoded_len_cached<M>(tag: u32, msg: &M, cache: &mut SizeCache) -> usize where M: Message {
+        let slot = cache.reserve();
+        let len = msg.encoded_len_cached(cache);
+        cache.set(slot, len);
+        key_len(tag) + encoded_len_varint(len as u64) + len
+    }
+
+    pub fn encoded_len_repeated_cached<M>(tag: u32, messages: &[M], cache: &mut SizeCache) -> usize
+    where M: Message {
+        messages.iter().map(|msg| encoded_len_cached(tag, msg, cache)).sum::<usize>()
+    }
 }
 
-pub mod map {
-    use std::collections::HashMap;
-    use std::hash::Hash;
+pub mod group {
+    use super::*;
+
+    pub fn encode<M, B>(tag: u32, msg: &M, buf: &mut B)
+    where M: Message,
+          B: BufMut {
+        encode_key(tag, WireType::StartGroup, buf);
+        msg.encode_raw(buf);
+        encode_key(tag, WireType::EndGroup, buf);
+    }
+
+    pub fn merge<M, B>(tag: u32,
+                       wire_type: WireType,
+                       msg: &mut M,
+                       buf: &mut Take<B>,
+                       ctx: DecodeContext)
+                       -> Result<(), DecodeError>
+    where M: Message,
+          B: Buf {
+        check_wire_type(WireType::StartGroup, wire_type)?;
+        let ctx = ctx.enter_recursion()?;
+        loop {
+            if !buf.has_remaining() {
+                return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
+            }
+            let (field_tag, field_wire_type) = decode_key(buf)?;
+            if field_wire_type == WireType::EndGroup {
+                if field_tag != tag {
+                    return Err(DecodeError::new(DecodeErrorKind::UnexpectedEndGroup));
+                }
+                return Ok(());
+            }
+            msg.merge_field(field_tag, field_wire_type, buf, ctx)?;
+        }
+    }
+
+    pub fn encode_repeated<M, B>(tag: u32, messages: &[M], buf: &mut B)
+    where M: Message,
+          B: BufMut {
+        for msg in messages {
+            encode(tag, msg, buf);
+        }
+    }
+
+    pub fn merge_repeated<M, B>(tag: u32,
+                                wire_type: WireType,
+                                messages: &mut Vec<M>,
+                                buf: &mut Take<B>,
+                                ctx: DecodeContext)
+                                -> Result<(), DecodeError>
+    where M: Message,
+          B: Buf {
+        check_wire_type(WireType::StartGroup, wire_type)?;
+        ctx.check_repeated_len(messages.len())?;
+        let mut msg = M::default();
+        merge(tag, WireType::StartGroup, &mut msg, buf, ctx)?;
+        messages.push(msg);
+        Ok(())
+    }
+
+    pub fn encoded_len<M>(tag: u32, msg: &M) -> usize where M: Message {
+        2 * key_len(tag) + msg.encoded_len()
+    }
+
+    pub fn encoded_len_repeated<M>(tag: u32, messages: &[M]) -> usize where M: Message {
+        2 * key_len(tag) * messages.len()
+            + messages.iter().map(Message::encoded_len).sum::<usize>()
+    }
+
+    pub fn encode_cached<M, B>(tag: u32, msg: &M, buf: &mut B, cache: &mut SizeCache)
+    where M: Message,
+          B: BufMut {
+        encode_key(tag, WireType::StartGroup, buf);
+        msg.encode_raw_cached(buf, cache);
+        encode_key(tag, WireType::EndGroup, buf);
+    }
+
+    pub fn encode_repeated_cached<M, B>(tag: u32, messages: &[M], buf: &mut B, cache: &mut SizeCache)
+    where M: Message,
+          B: BufMut {
+        for msg in messages {
+            encode_cached(tag, msg, buf, cache);
+        }
+    }
+
+    pub fn encoded_len_cached<M>(tag: u32, msg: &M, cache: &mut SizeCache) -> usize where M: Message {
+        2 * key_len(tag) + msg.encoded_len_cached(cache)
+    }
+
+    pub fn encoded_len_repeated_cached<M>(tag: u32, messages: &[M], cache: &mut SizeCache) -> usize
+    where M: Message {
+        messages.iter().map(|msg| encoded_len_cached(tag, msg, cache)).sum::<usize>()
+    }
+}
+
+/// Encoding functions for the fields of `BorrowedMessage` types, which may
+/// borrow string and bytes values from the buffer being decoded.
+pub mod borrowed {
+    use core::str;
 
     use ::encoding::*;
 
-    /// Generic protobuf map encode function.
-    pub fn encode<K, V, B, KE, KL, VE, VL>(key_encode: KE,
-                                           key_encoded_len: KL,
-                                           val_encode: VE,
-                                           val_encoded_len: VL,
-                                           tag: u32,
-                                           values: &HashMap<K, V>,
-                                           buf: &mut B)
-    where K: Default + Eq + Hash,
-        V: Default + PartialEq,
-        B: BufMut,
-        KE: Fn(u32, &K, &mut B),
-        KL: Fn(u32, &K) -> usize,
-        VE: Fn(u32, &V, &mut B),
-        VL: Fn(u32, &V) -> usize {
-        encode_with_default(key_encode, key_encoded_len, val_encode, val_encoded_len, &V::default(),
-                            tag, values, buf)
-    }
-
-    /// Generic protobuf map merge function.
-    pub fn merge<K, V, B, KM, VM>(key_merge: KM,
-                                  val_merge: VM,
-                                  values: &mut HashMap<K, V>,
-                                  buf: &mut Take<B>)
-                                  -> Result<()>
-    where K: Default + Eq + Hash,
-        V: Default,
-        B: Buf,
-        KM: Fn(WireType, &mut K, &mut Take<B>) -> Result<()>,
-        VM: Fn(WireType, &mut V, &mut Take<B>) -> Result<()> {
-        merge_with_default(key_merge, val_merge, V::default(),
-                            values, buf)
-    }
-
-    /// Generic protobuf map encode function.
-    pub fn encoded_len<K, V, KL, VL>(key_encoded_len: KL,
-                                     val_encoded_len: VL,
-                                     tag: u32,
-                                     values: &HashMap<K, V>)
-                                     -> usize
-    where K: Default + Eq + Hash,
-        V: Default + PartialEq,
-        KL: Fn(u32, &K) -> usize,
-        VL: Fn(u32, &V) -> usize {
-        encoded_len_with_default(key_encoded_len, val_encoded_len, &V::default(),
-                                    tag, values)
-    }
-
-    /// Generic protobuf map encode function with an overriden value default.
+    /// A buffer which can lend slices of its contents for the lifetime `'a`.
+    pub trait BorrowBuf<'a>: Buf {
+        /// Returns the next `len` bytes of the buffer, and advances past them.
+        /// The buffer must contain at least `len` bytes.
+        fn take_slice(&mut self, len: usize) -> &'a [u8];
+    }
+
+    impl <'a> BorrowBuf<'a> for SliceBuf<'a> {
+        fn take_slice(&mut self, len: usize) -> &'a [u8] {
+            let (bytes, rest) = self.bytes.split_at(len);
+            self.bytes = rest;
+            bytes
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl <'a> BorrowBuf<'a> for ::std::io::Cursor<&'a [u8]> {
+        fn take_slice(&mut self, len: usize) -> &'a [u8] {
+            let pos = self.position() as usize;
+            let bytes: &'a [u8] = *self.get_ref();
+            self.set_position((pos + len) as u64);
+            &bytes[pos..pos + len]
+        }
+    }
+
+    impl <'a, B> BorrowBuf<'a> for Take<B> where B: BorrowBuf<'a> {
+        fn take_slice(&mut self, len: usize) -> &'a [u8] {
+            let limit = self.limit();
+            assert!(len <= limit);
+            let bytes = self.get_mut().take_slice(len);
+            self.set_limit(limit - len);
+            bytes
+        }
+    }
+
+    /// A type which can represent a Protobuf `string` field in a borrowed
+    /// message: `&'a str`, or any owned `StringAdapter` type.
+    pub trait StrAdapter<'a>: AsRef<str> + Default + Sized {
+        #[doc(hidden)]
+        fn replace_with<B>(&mut self, buf: &mut B, len: usize, ctx: DecodeContext) -> Result<(), DecodeError>
+        where B: BorrowBuf<'a>;
+    }
+
+    impl <'a> StrAdapter<'a> for &'a str {
+        fn replace_with<B>(&mut self, buf: &mut B, len: usize, _ctx: DecodeContext) -> Result<(), DecodeError>
+        where B: BorrowBuf<'a> {
+            match str::from_utf8(buf.take_slice(len)) {
+                Ok(value) => *self = value,
+                Err(..) => {
+                    *self = "";
+                    return Err(DecodeError::new(DecodeErrorKind::InvalidUtf8));
+                },
+            }
+            Ok(())
+        }
+    }
+
+    impl <'a, S> StrAdapter<'a> for S where S: StringAdapter {
+        fn replace_with<B>(&mut self, buf: &mut B, len: usize, ctx: DecodeContext) -> Result<(), DecodeError>
+        where B: BorrowBuf<'a> {
+            StringAdapter::replace_with(self, buf, len, ctx)
+        }
+    }
+
+    /// A type which can represent a Protobuf `bytes` field in a borrowed
+    /// message: `&'a [u8]`, or any owned `BytesAdapter` type.
+    pub trait SliceAdapter<'a>: AsRef<[u8]> + Default + Sized {
+        #[doc(hidden)]
+        fn replace_with<B>(&mut self, buf: &mut B, len: usize, ctx: DecodeContext) where B: BorrowBuf<'a>;
+    }
+
+    impl <'a> SliceAdapter<'a> for &'a [u8] {
+        fn replace_with<B>(&mut self, buf: &mut B, len: usize, _ctx: DecodeContext) where B: BorrowBuf<'a> {
+            *self = buf.take_slice(len);
+        }
+    }
+
+    impl <'a, A> SliceAdapter<'a> for A where A: BytesAdapter {
+        fn replace_with<B>(&mut self, buf: &mut B, len: usize, ctx: DecodeContext) where B: BorrowBuf<'a> {
+            BytesAdapter::replace_with(self, buf, len, ctx)
+        }
+    }
+
+    /// A type which can be embedded as a message or group field in a borrowed
+    /// message: any `Message`, or a `BorrowedMessage` with the same lifetime.
     ///
-    /// This is necessary because enumeration values can have a default value other
-    /// than 0 in proto2.
-    pub fn encode_with_default<K, V, B, KE, KL, VE, VL>(key_encode: KE,
-                                                        key_encoded_len: KL,
-                                                        val_encode: VE,
-                                                        val_encoded_len: VL,
-                                                        val_default: &V,
-                                                        tag: u32,
-                                                        values: &HashMap<K, V>,
-                                                        buf: &mut B)
-    where K: Default + Eq + Hash,
-        V: PartialEq,
-        B: BufMut,
-        KE: Fn(u32, &K, &mut B),
-        KL: Fn(u32, &K) -> usize,
-        VE: Fn(u32, &V, &mut B),
-        VL: Fn(u32, &V) -> usize {
-        for (key, val) in values.iter() {
-            let skip_key = key == &K::default();
-            let skip_val = val == val_default;
-
-            let len = (if skip_key { 0 } else { key_encoded_len(1, key) }) +
-                      (if skip_val { 0 } else { val_encoded_len(2, val) });
+    /// Implemented by `#[derive(Message)]` for messages with a lifetime.
+    #[doc(hidden)]
+    pub trait MessageAdapter<'a>: Default {
+        fn encode_raw<B>(&self, buf: &mut B) where B: BufMut;
+        fn merge_field<B>(&mut self,
+                          tag: u32,
+                          wire_type: WireType,
+                          buf: &mut Take<B>,
+                          ctx: DecodeContext)
+                          -> Result<(), DecodeError> where B: BorrowBuf<'a>;
+        fn encoded_len(&self) -> usize;
+    }
+
+    impl <'a, M> MessageAdapter<'a> for M where M: Message {
+        fn encode_raw<B>(&self, buf: &mut B) where B: BufMut {
+            Message::encode_raw(self, buf)
+        }
+        fn merge_field<B>(&mut self,
+                          tag: u32,
+                          wire_type: WireType,
+                          buf: &mut Take<B>,
+                          ctx: DecodeContext)
+                          -> Result<(), DecodeError> where B: BorrowBuf<'a> {
+            Message::merge_field(self, tag, wire_type, buf, ctx)
+        }
+        fn encoded_len(&self) -> usize {
+            Message::encoded_len(self)
+        }
+    }
 
+    pub mod string {
+        use super::*;
+
+        pub fn merge<'a, A, B>(wire_type: WireType,
+                               value: &mut A,
+                               buf: &mut Take<B>,
+                               ctx: DecodeContext)
+                               -> Result<(), DecodeError>
+        where A: StrAdapter<'a>,
+              B: BorrowBuf<'a> {
+            check_wire_type(WireType::LengthDelimited, wire_type)?;
+            let len = decode_varint(buf)?;
+            ctx.check_length(len)?;
+            if (buf.remaining() as u64) < len {
+                return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
+            }
+            value.replace_with(buf, len as usize, ctx)
+        }
+
+        pub fn merge_repeated<'a, A, B>(wire_type: WireType,
+                                        values: &mut Vec<A>,
+                                        buf: &mut Take<B>,
+                                        ctx: DecodeContext)
+                                        -> Result<(), DecodeError>
+        where A: StrAdapter<'a>,
+              B: BorrowBuf<'a> {
+            ctx.check_repeated_len(values.len())?;
+            let mut value = A::default();
+            merge(wire_type, &mut value, buf, ctx)?;
+            values.push(value);
+            Ok(())
+        }
+    }
+
+    pub mod bytes {
+        use super::*;
+
+        pub fn merge<'a, A, B>(wire_type: WireType,
+                               value: &mut A,
+                               buf: &mut Take<B>,
+                               ctx: DecodeContext)
+                               -> Result<(), DecodeError>
+        where A: SliceAdapter<'a>,
+              B: BorrowBuf<'a> {
+            check_wire_type(WireType::LengthDelimited, wire_type)?;
+            let len = decode_varint(buf)?;
+            ctx.check_length(len)?;
+            if (buf.remaining() as u64) < len {
+                return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
+            }
+            value.replace_with(buf, len as usize, ctx);
+            Ok(())
+        }
+
+        pub fn merge_repeated<'a, A, B>(wire_type: WireType,
+                                        values: &mut Vec<A>,
+                                        buf: &mut Take<B>,
+                                        ctx: DecodeContext)
+                                        -> Result<(), DecodeError>
+        where A: SliceAdapter<'a>,
+              B: BorrowBuf<'a> {
+            ctx.check_repeated_len(values.len())?;
+            let mut value = A::default();
+            merge(wire_type, &mut value, buf, ctx)?;
+            values.push(value);
+            Ok(())
+        }
+    }
+
+    pub mod message {
+        use super::*;
+
+        pub fn encode<'a, M, B>(tag: u32, msg: &M, buf: &mut B)
+        where M: MessageAdapter<'a>,
+              B: BufMut {
             encode_key(tag, WireType::LengthDelimited, buf);
-            encode_varint(len as u64, buf);
-            if !skip_key {
-                key_encode(1, key, buf);
+            encode_varint(msg.encoded_len() as u64, buf);
+            msg.encode_raw(buf);
+        }
+
+        pub fn merge<'a, M, B>(wire_type: WireType,
+                               msg: &mut M,
+                               buf: &mut Take<B>,
+                               ctx: DecodeContext)
+                               -> Result<(), DecodeError>
+        where M: MessageAdapter<'a>,
+              B: BorrowBuf<'a> {
+            check_wire_type(WireType::LengthDelimited, wire_type)?;
+            let ctx = ctx.enter_recursion()?;
+            let len = decode_varint(buf)?;
+            if len > buf.remaining() as u64 {
+                return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
             }
-            if !skip_val {
-                val_encode(2, val, buf);
+
+            let len = len as usize;
+            let limit = buf.limit();
+            buf.set_limit(len);
+            while buf.has_remaining() {
+                let (tag, wire_type) = decode_key(buf)?;
+                msg.merge_field(tag, wire_type, buf, ctx)?;
             }
+            buf.set_limit(limit - len);
+            Ok(())
+        }
+
+        pub fn encode_repeated<'a, M, B>(tag: u32, messages: &[M], buf: &mut B)
+        where M: MessageAdapter<'a>,
+              B: BufMut {
+            for msg in messages {
+                encode(tag, msg, buf);
+            }
+        }
+
+        pub fn merge_repeated<'a, M, B>(wire_type: WireType,
+                                        messages: &mut Vec<M>,
+                                        buf: &mut Take<B>,
+                                        ctx: DecodeContext)
+                                        -> Result<(), DecodeError>
+        where M: MessageAdapter<'a>,
+              B: BorrowBuf<'a> {
+            ctx.check_repeated_len(messages.len())?;
+            let mut msg = M::default();
+            merge(wire_type, &mut msg, buf, ctx)?;
+            messages.push(msg);
+            Ok(())
+        }
+
+        pub fn encoded_len<'a, M>(tag: u32, msg: &M) -> usize where M: MessageAdapter<'a> {
+            let len = msg.encoded_len();
+            key_len(tag) + encoded_len_varint(len as u64) + len
+        }
+
+        pub fn encoded_len_repeated<'a, M>(tag: u32, messages: &[M]) -> usize where M: MessageAdapter<'a> {
+            key_len(tag) * messages.len()
+                + messages.iter()
+                          .map(MessageAdapter::encoded_len)
+                          .map(|len| len + encoded_len_varint(len as u64))
+                          .sum::<usize>()
         }
     }
 
-    /// Generic protobuf map merge function with an overriden value default.
-    ///
-    /// This is necessary because enumeration values can have a default value other
-    /// than 0 in proto2.
-    pub fn merge_with_default<K, V, B, KM, VM>(key_merge: KM,
-                                               val_merge: VM,
-                                               val_default: V,
-                                               values: &mut HashMap<K, V>,
-                                               buf: &mut Take<B>)
-                                               -> Result<()>
-    where K: Default + Eq + Hash,
-        B: Buf,
-        KM: Fn(WireType, &mut K, &mut Take<B>) -> Result<()>,
-        VM: Fn(WireType, &mut V, &mut Take<B>) -> Result<()> {
-        let len = decode_varint(buf)?;
-        if len > buf.remaining() as u64 {
-            return Err(invalid_data("buffer underflow"));
+    pub mod group {
+        use super::*;
+
+        pub fn encode<'a, M, B>(tag: u32, msg: &M, buf: &mut B)
+        where M: MessageAdapter<'a>,
+              B: BufMut {
+            encode_key(tag, WireType::StartGroup, buf);
+            msg.encode_raw(buf);
+            encode_key(tag, WireType::EndGroup, buf);
         }
-        let len = len as usize;
-        let limit = buf.limit();
-        buf.set_limit(len);
 
-        let mut key = Default::default();
-        let mut val = val_default;
+        pub fn merge<'a, M, B>(tag: u32,
+                               wire_type: WireType,
+                               msg: &mut M,
+                               buf: &mut Take<B>,
+                               ctx: DecodeContext)
+                               -> Result<(), DecodeError>
+        where M: MessageAdapter<'a>,
+              B: BorrowBuf<'a> {
+            check_wire_type(WireType::StartGroup, wire_type)?;
+            let ctx = ctx.enter_recursion()?;
+            loop {
+                if !buf.has_remaining() {
+                    return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
+                }
+                let (field_tag, field_wire_type) = decode_key(buf)?;
+                if field_wire_type == WireType::EndGroup {
+                    if field_tag != tag {
+                        return Err(DecodeError::new(DecodeErrorKind::UnexpectedEndGroup));
+                    }
+                    return Ok(());
+                }
+                msg.merge_field(field_tag, field_wire_type, buf, ctx)?;
+            }
+        }
 
-        while buf.has_remaining() {
-            let (tag, wire_type) = decode_key(buf)?;
-            match tag {
-                1 => key_merge(wire_type, &mut key, buf)?,
-                2 => val_merge(wire_type, &mut val, buf)?,
-                _ => (),
+        pub fn encode_repeated<'a, M, B>(tag: u32, messages: &[M], buf: &mut B)
+        where M: MessageAdapter<'a>,
+              B: BufMut {
+            for msg in messages {
+                encode(tag, msg, buf);
             }
         }
 
-        values.insert(key, val);
-        buf.set_limit(limit - len);
-        Ok(())
+        pub fn merge_repeated<'a, M, B>(tag: u32,
+                                        wire_type: WireType,
+                                        messages: &mut Vec<M>,
+                                        buf: &mut Take<B>,
+                                        ctx: DecodeContext)
+                                        -> Result<(), DecodeError>
+        where M: MessageAdapter<'a>,
+              B: BorrowBuf<'a> {
+            ctx.check_repeated_len(messages.len())?;
+            let mut msg = M::default();
+            merge(tag, wire_type, &mut msg, buf, ctx)?;
+            messages.push(msg);
+            Ok(())
+        }
+
+        pub fn encoded_len<'a, M>(tag: u32, msg: &M) -> usize where M: MessageAdapter<'a> {
+            2 * key_len(tag) + msg.encoded_len()
+        }
+
+        pub fn encoded_len_repeated<'a, M>(tag: u32, messages: &[M]) -> usize where M: MessageAdapter<'a> {
+            2 * key_len(tag) * messages.len()
+                + messages.iter().map(MessageAdapter::encoded_len).sum::<usize>()
+        }
     }
+}
 
-    /// Generic protobuf map encode function with an overriden value default.
-    ///
-    /// This is necessary because enumeration values can have a default value other
-    /// than 0 in proto2.
-    pub fn encoded_len_with_default<K, V, KL, VL>(key_encoded_len: KL,
-                                                  val_encoded_len: VL,
+/// Generates the map field encoding functions for a map type, which is either
+/// `HashMap` or `BTreeMap`.
+macro_rules! map {
+    ($map_ty:ident) => {
+        use core::hash::Hash;
+
+        use ::encoding::*;
+
+        /// Generic protobuf map encode function.
+        pub fn encode<K, V, B, KE, KL, VE, VL>(key_encode: KE,
+                                               key_encoded_len: KL,
+                                               val_encode: VE,
+                                               val_encoded_len: VL,
+                                               tag: u32,
+                                               values: &$map_ty<K, V>,
+                                               buf: &mut B)
+        where K: Default + Eq + Hash + Ord,
+            V: Default + PartialEq,
+            B: BufMut,
+            KE: Fn(u32, &K, &mut B),
+            KL: Fn(u32, &K) -> usize,
+            VE: Fn(u32, &V, &mut B),
+            VL: Fn(u32, &V) -> usize {
+            encode_with_default(key_encode, key_encoded_len, val_encode, val_encoded_len, &V::default(),
+                                tag, values, buf)
+        }
+
+        /// Generic protobuf map merge function.
+        pub fn merge<K, V, B, KM, VM>(key_merge: KM,
+                                      val_merge: VM,
+                                      values: &mut $map_ty<K, V>,
+                                      buf: &mut Take<B>,
+                                      ctx: DecodeContext)
+                                      -> Result<(), DecodeError>
+        where K: Default + Eq + Hash + Ord,
+            V: Default,
+            B: Buf,
+            KM: Fn(WireType, &mut K, &mut Take<B>, DecodeContext) -> Result<(), DecodeError>,
+            VM: Fn(WireType, &mut V, &mut Take<B>, DecodeContext) -> Result<(), DecodeError> {
+            merge_with_default(key_merge, val_merge, V::default(),
+                                values, buf, ctx)
+        }
+
+        /// Generic protobuf map encode function.
+        pub fn encoded_len<K, V, KL, VL>(key_encoded_len: KL,
+                                         val_encoded_len: VL,
+                                         tag: u32,
+                                         values: &$map_ty<K, V>)
+                                         -> usize
+        where K: Default + Eq + Hash + Ord,
+            V: Default + PartialEq,
+            KL: Fn(u32, &K) -> usize,
+            VL: Fn(u32, &V) -> usize {
+            encoded_len_with_default(key_encoded_len, val_encoded_len, &V::default(),
+                                        tag, values)
+        }
+
+        /// Generic protobuf map encode function with an overriden value default.
+        ///
+        /// This is necessary because enumeration values can have a default value other
+        /// than 0 in proto2.
+        pub fn encode_with_default<K, V, B, KE, KL, VE, VL>(key_encode: KE,
+                                                            key_encoded_len: KL,
+                                                            val_encode: VE,
+                                                            val_encoded_len: VL,
+                                                            val_default: &V,
+                                                            tag: u32,
+                                                            values: &$map_ty<K, V>,
+                                                            buf: &mut B)
+        where K: Default + Eq + Hash + Ord,
+            V: PartialEq,
+            B: BufMut,
+            KE: Fn(u32, &K, &mut B),
+            KL: Fn(u32, &K) -> usize,
+            VE: Fn(u32, &V, &mut B),
+            VL: Fn(u32, &V) -> usize {
+            for (key, val) in values.iter() {
+                let skip_key = key == &K::default();
+                let skip_val = val == val_default;
+
+                let len = (if skip_key { 0 } else { key_encoded_len(1, key) }) +
+                          (if skip_val { 0 } else { val_encoded_len(2, val) });
+
+                encode_key(tag, WireType::LengthDelimited, buf);
+                encode_varint(len as u64, buf);
+                if !skip_key {
+                    key_encode(1, key, buf);
+                }
+                if !skip_val {
+                    val_encode(2, val, buf);
+                }
+            }
+        }
+
+        /// Generic protobuf map merge function with an overriden value default.
+        ///
+        /// This is necessary because enumeration values can have a default value other
+        /// than 0 in proto2.
+        pub fn merge_with_default<K, V, B, KM, VM>(key_merge: KM,
+                                                   val_merge: VM,
+                                                   val_default: V,
+                                                   values: &mut $map_ty<K, V>,
+                                                   buf: &mut Take<B>,
+                                                   ctx: DecodeContext)
+                                                   -> Result<(), DecodeError>
+        where K: Default + Eq + Hash + Ord,
+            B: Buf,
+            KM: Fn(WireType, &mut K, &mut Take<B>, DecodeContext) -> Result<(), DecodeError>,
+            VM: Fn(WireType, &mut V, &mut Take<B>, DecodeContext) -> Result<(), DecodeError> {
+            let len = decode_varint(buf)?;
+            if len > buf.remaining() as u64 {
+                return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
+            }
+            let len = len as usize;
+            let limit = buf.limit();
+            buf.set_limit(len);
+
+            let mut key = Default::default();
+            let mut val = val_default;
+
+            while buf.has_remaining() {
+                let (tag, wire_type) = decode_key(buf)?;
+                match tag {
+                    1 => key_merge(wire_type, &mut key, buf, ctx)?,
+                    2 => val_merge(wire_type, &mut val, buf, ctx)?,
+                    _ => skip_field(wire_type, tag, buf, ctx)?,
+                }
+            }
+
+            if !values.contains_key(&key) {
+                ctx.check_repeated_len(values.len())?;
+            }
+            values.insert(key, val);
+            buf.set_limit(limit - len);
+            Ok(())
+        }
+
+        /// Generic protobuf map encode function with an overriden value default.
+        ///
+        /// This is necessary because enumeration values can have a default value other
+        /// than 0 in proto2.
+        pub fn encoded_len_with_default<K, V, KL, VL>(key_encoded_len: KL,
+                                                      val_encoded_len: VL,
+                                                      val_default: &V,
+                                                      tag: u32,
+                                                      values: &$map_ty<K, V>)
+                                                      -> usize
+        where K: Default + Eq + Hash + Ord,
+            V: PartialEq,
+            KL: Fn(u32, &K) -> usize,
+            VL: Fn(u32, &V) -> usize {
+            key_len(tag) * values.len() + values.iter().map(|(key, val)| {
+                let len = (if key == &K::default() { 0 } else { key_encoded_len(1, key) })
+                        + (if val == val_default { 0 } else { val_encoded_len(2, val) });
+                encoded_len_varint(len as u64) + len
+            }).sum::<usize>()
+        }
+
+        /// Generic protobuf map encode function, with the lengths of the entries
+        /// read from the size cache. Entries are encoded in key order if the
+        /// encoding is deterministic.
+        pub fn encode_cached<K, V, B, KE, VE>(key_encode: KE,
+                                              val_encode: VE,
+                                              tag: u32,
+                                              values: &$map_ty<K, V>,
+                                              buf: &mut B,
+                                              cache: &mut SizeCache)
+        where K: Default + Eq + Hash + Ord,
+            V: Default + PartialEq,
+            B: BufMut,
+            KE: Fn(u32, &K, &mut B),
+            VE: Fn(u32, &V, &mut B) {
+            encode_with_default_cached(key_encode, val_encode, &V::default(), tag, values, buf, cache)
+        }
+
+        /// Generic protobuf map encode function with an overriden value default,
+        /// with the lengths of the entries read from the size cache.
+        pub fn encode_with_default_cached<K, V, B, KE, VE>(key_encode: KE,
+                                                           val_encode: VE,
+                                                           val_default: &V,
+                                                           tag: u32,
+                                                           values: &$map_ty<K, V>,
+                                                           buf: &mut B,
+                                                           cache: &mut SizeCache)
+        where K: Default + Eq + Hash + Ord,
+            V: PartialEq,
+            B: BufMut,
+            KE: Fn(u32, &K, &mut B),
+            VE: Fn(u32, &V, &mut B) {
+            encode_entries_cached(key_encode, |tag, val, buf, _| val_encode(tag, val, buf), val_default,
+                                  tag, values, buf, cache)
+        }
+
+        /// Protobuf map encode function for message values, with the lengths of
+        /// the entries and values read from the size cache.
+        pub fn encode_message_cached<K, V, B, KE>(key_encode: KE,
+                                                  tag: u32,
+                                                  values: &$map_ty<K, V>,
+                                                  buf: &mut B,
+                                                  cache: &mut SizeCache)
+        where K: Default + Eq + Hash + Ord,
+            V: Message,
+            B: BufMut,
+            KE: Fn(u32, &K, &mut B) {
+            encode_entries_cached(key_encode, message::encode_cached, &V::default(), tag, values, buf, cache)
+        }
+
+        /// Generic protobuf map encoded length function, recording the lengths of
+        /// the entries in the size cache.
+        pub fn encoded_len_cached<K, V, KL, VL>(key_encoded_len: KL,
+                                               val_encoded_len: VL,
+                                               tag: u32,
+                                               values: &$map_ty<K, V>,
+                                               cache: &mut SizeCache)
+                                               -> usize
+        where K: Default + Eq + Hash + Ord,
+            V: Default + PartialEq,
+            KL: Fn(u32, &K) -> usize,
+            VL: Fn(u32, &V) -> usize {
+            encoded_len_with_default_cached(key_encoded_len, val_encoded_len, &V::default(), tag, values, cache)
+        }
+
+        /// Generic protobuf map encoded length function with an overriden value
+        /// default, recording the lengths of the entries in the size cache.
+        pub fn encoded_len_with_default_cached<K, V, KL, VL>(key_encoded_len: KL,
+                                                             val_encoded_len: VL,
+                                                             val_default: &V,
+                                                             tag: u32,
+                                                             values: &$map_ty<K, V>,
+                                                             cache: &mut SizeCache)
+                                                             -> usize
+        where K: Default + Eq + Hash + Ord,
+            V: PartialEq,
+            KL: Fn(u32, &K) -> usize,
+            VL: Fn(u32, &V) -> usize {
+            encoded_len_entries_cached(key_encoded_len, |tag, val, _| val_encoded_len(tag, val), val_default,
+                                       tag, values, cache)
+        }
+
+        /// Protobuf map encoded length function for message values, recording the
+        /// lengths of the entries and values in the size cache.
+        pub fn encoded_len_message_cached<K, V, KL>(key_encoded_len: KL,
+                                                    tag: u32,
+                                                    values: &$map_ty<K, V>,
+                                                    cache: &mut SizeCache)
+                                                    -> usize
+        where K: Default + Eq + Hash + Ord,
+            V: Message,
+            KL: Fn(u32, &K) -> usize {
+            encoded_len_entries_cached(key_encoded_len, message::encoded_len_cached, &V::default(),
+                                       tag, values, cache)
+        }
+
+        /// Calls `f` with each entry of the map, in key order if `deterministic`
+        /// is set, and otherwise in the iteration order of the map.
+        fn for_each_entry<K, V, F>(values: &$map_ty<K, V>, deterministic: bool, mut f: F)
+        where K: Eq + Hash + Ord,
+              F: FnMut(&K, &V) {
+            if deterministic {
+                let mut entries = values.iter().collect::<Vec<_>>();
+                entries.sort_by(|a, b| a.0.cmp(b.0));
+                for (key, val) in entries {
+                    f(key, val);
+                }
+            } else {
+                for (key, val) in values {
+                    f(key, val);
+                }
+            }
+        }
+
+        fn encode_entries_cached<K, V, B, KE, VE>(key_encode: KE,
+                                                  val_encode: VE,
                                                   val_default: &V,
                                                   tag: u32,
-                                                  values: &HashMap<K, V>)
-                                                  -> usize
-    where K: Default + Eq + Hash,
-        V: PartialEq,
-        KL: Fn(u32, &K) -> usize,
-        VL: Fn(u32, &V) -> usize {
-        key_len(tag) * values.len() + values.iter().map(|(key, val)| {
-            let len = (if key == &K::default() { 0 } else { key_encoded_len(1, key) })
-                    + (if val == val_default { 0 } else { val_encoded_len(2, val) });
-            encoded_len_varint(len as u64) + len
-        }).sum::<usize>()
+                                                  values: &$map_ty<K, V>,
+                                                  buf: &mut B,
+                                                  cache: &mut SizeCache)
+        where K: Default + Eq + Hash + Ord,
+            V: PartialEq,
+            B: BufMut,
+            KE: Fn(u32, &K, &mut B),
+            VE: Fn(u32, &V, &mut B, &mut SizeCache) {
+            let key_default = K::default();
+            let deterministic = cache.is_deterministic();
+            for_each_entry(values, deterministic, |key, val| {
+                encode_key(tag, WireType::LengthDelimited, buf);
+                encode_varint(cache.next_len() as u64, buf);
+                if key != &key_default {
+                    key_encode(1, key, buf);
+                }
+                if val != val_default {
+                    val_encode(2, val, buf, cache);
+                }
+            });
+        }
+
+        fn encoded_len_entries_cached<K, V, KL, VL>(key_encoded_len: KL,
+                                                    val_encoded_len: VL,
+                                                    val_default: &V,
+                                                    tag: u32,
+                                                    values: &$map_ty<K, V>,
+                                                    cache: &mut SizeCache)
+                                                    -> usize
+        where K: Default + Eq + Hash + Ord,
+            V: PartialEq,
+            KL: Fn(u32, &K) -> usize,
+            VL: Fn(u32, &V, &mut SizeCache) -> usize {
+            let key_default = K::default();
+            let deterministic = cache.is_deterministic();
+            let mut encoded_len = key_len(tag) * values.len();
+            for_each_entry(values, deterministic, |key, val| {
+                // The entry length must be reserved before the value is measured,
+                // since encoding reads it first.
+                let slot = cache.reserve();
+                let len = (if key == &key_default { 0 } else { key_encoded_len(1, key) })
+                        + (if val == val_default { 0 } else { val_encoded_len(2, val, cache) });
+                cache.set(slot, len);
+                encoded_len += encoded_len_varint(len as u64) + len;
+            });
+            encoded_len
+        }
     }
 }
 
+/// Map field encoding functions for `HashMap` fields.
+#[cfg(feature = "std")]
+pub mod map {
+    use std::collections::HashMap;
+
+    map!(HashMap);
+}
+
+/// Map field encoding functions for `BTreeMap` fields.
+pub mod btree_map {
+    use alloc::collections::BTreeMap;
+
+    map!(BTreeMap);
+}
+
+/// Without `std`, `map` fields are `BTreeMap`s.
+#[cfg(not(feature = "std"))]
+pub use self::btree_map as map;
+
 #[cfg(test)]
 mod test {
-    use std::fmt::Debug;
-    use std::io::Cursor;
+    use alloc::string::ToString;
+    use core::fmt::Debug;
 
     use bytes::{Bytes, BytesMut, IntoBuf, Take};
     use quickcheck::TestResult;
@@ -801,7 +2064,7 @@ mod test {
                          tag: u32,
                          wire_type: WireType,
                          encode: fn(u32, &T, &mut BytesMut),
-                         merge: fn(WireType, &mut T, &mut Take<Cursor<Bytes>>) -> Result<()>,
+                         merge: fn(WireType, &mut T, &mut Take<<Bytes as IntoBuf>::Buf>, DecodeContext) -> Result<(), DecodeError>,
                          encoded_len: fn(u32, &T) -> usize)
                          -> TestResult
     where T: Debug + Default + PartialEq {
@@ -859,7 +2122,7 @@ mod test {
         }
 
         let mut roundtrip_value = T::default();
-        if let Err(error) = merge(wire_type, &mut roundtrip_value, &mut buf) {
+        if let Err(error) = merge(wire_type, &mut roundtrip_value, &mut buf, DecodeContext::default()) {
             return TestResult::error(error.to_string());
         };
 
@@ -884,7 +2147,7 @@ mod test {
                                              -> TestResult
     where T: Debug + Default + PartialEq,
           E: FnOnce(u32, &T, &mut BytesMut),
-          M: FnMut(WireType, &mut T, &mut Take<Cursor<Bytes>>) -> Result<()>,
+          M: FnMut(WireType, &mut T, &mut Take<<Bytes as IntoBuf>::Buf>, DecodeContext) -> Result<(), DecodeError>,
           L: FnOnce(u32, &T) -> usize {
 
         if tag > MAX_TAG || tag < MIN_TAG {
@@ -923,7 +2186,7 @@ mod test {
                             wire_type, decoded_wire_type));
             }
 
-            if let Err(error) = merge(wire_type, &mut roundtrip_value, &mut buf) {
+            if let Err(error) = merge(wire_type, &mut roundtrip_value, &mut buf, DecodeContext::default()) {
                 return TestResult::error(error.to_string());
             };
         }
@@ -944,8 +2207,29 @@ mod test {
 
             assert_eq!(buf, encoded);
 
+            let mut buf = Vec::new();
+            encode_varint_slow(value, &mut buf);
+            assert_eq!(buf, encoded);
+
             let roundtrip_value = decode_varint(&mut Bytes::from(encoded).into_buf()).expect("decoding failed");
             assert_eq!(value, roundtrip_value);
+
+            let roundtrip_value = decode_varint_slow(&mut Bytes::from(encoded).into_buf()).expect("decoding failed");
+            assert_eq!(value, roundtrip_value);
+
+            // Trailing bytes let decode_varint take the fast path even when
+            // the varint doesn't end the chunk.
+            let mut padded = encoded.to_vec();
+            padded.extend_from_slice(&[0xFF; 10]);
+            let mut buf = Bytes::from(padded).into_buf();
+            assert_eq!(value, decode_varint(&mut buf).expect("decoding failed"));
+            assert_eq!(10, buf.remaining());
+
+            // A varint split across chunks is decoded by the slow path.
+            let (head, tail) = encoded.split_at(encoded.len() / 2);
+            let mut buf = SliceBuf::new(head).chain(SliceBuf::new(tail));
+            assert_eq!(value, decode_varint(&mut buf).expect("decoding failed"));
+            assert!(!buf.has_remaining());
         }
 
         check(0, &[0b0000_0000]);
@@ -958,6 +2242,54 @@ mod test {
 
         check(16_383, &[0b1111_1111, 0b0111_1111]);
         check(16_384, &[0b1000_0000, 0b1000_0000, 0b0000_0001]);
+
+        check(1 << 35, &[0x80, 0x80, 0x80, 0x80, 0x80, 0x01]);
+        check((1 << 56) - 1, &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F]);
+        check(1 << 63, &[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01]);
+        check(u64::MAX, &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]);
+    }
+
+    #[test]
+    fn varint_errors() {
+        let error = decode_varint(&mut Bytes::from(&[0x80, 0x80][..]).into_buf()).unwrap_err();
+        assert_eq!(&DecodeErrorKind::BufferUnderflow, error.kind());
+
+        let error = decode_varint(&mut Bytes::from(&[0xFF; 11][..]).into_buf()).unwrap_err();
+        assert_eq!(&DecodeErrorKind::InvalidVarint, error.kind());
+
+        let error = decode_varint_slow(&mut Bytes::from(&[0xFF; 11][..]).into_buf()).unwrap_err();
+        assert_eq!(&DecodeErrorKind::InvalidVarint, error.kind());
+    }
+
+    #[test]
+    fn skip_group() {
+        let mut buf = Vec::new();
+        encode_key(1, WireType::StartGroup, &mut buf);
+        ::encoding::uint32::encode(2, &42, &mut buf);
+        encode_key(3, WireType::StartGroup, &mut buf);
+        ::encoding::string::encode(4, &"foo".to_string(), &mut buf);
+        encode_key(3, WireType::EndGroup, &mut buf);
+        encode_key(1, WireType::EndGroup, &mut buf);
+        ::encoding::uint32::encode(5, &7, &mut buf);
+
+        let mut buf = Bytes::from(buf).into_buf();
+        assert_eq!((1, WireType::StartGroup), decode_key(&mut buf).unwrap());
+        skip_field(WireType::StartGroup, 1, &mut buf, DecodeContext::default()).unwrap();
+        assert_eq!((5, WireType::Varint), decode_key(&mut buf).unwrap());
+        assert_eq!(7, decode_varint(&mut buf).unwrap());
+        assert!(!buf.has_remaining());
+    }
+
+    #[test]
+    fn skip_group_mismatched_end() {
+        let mut buf = Vec::new();
+        encode_key(1, WireType::StartGroup, &mut buf);
+        encode_key(2, WireType::EndGroup, &mut buf);
+
+        let mut buf = Bytes::from(buf).into_buf();
+        decode_key(&mut buf).unwrap();
+        let error = skip_field(WireType::StartGroup, 1, &mut buf, DecodeContext::default()).unwrap_err();
+        assert_eq!(&DecodeErrorKind::UnexpectedEndGroup, error.kind());
     }
 
     /// This big bowl o' macro soup generates a quickcheck encoding test for each
@@ -968,7 +2300,11 @@ mod test {
          vals: $vals:tt) => {
             $(
                 mod $key_proto {
-                    use std::collections::HashMap;
+                    // Without `std`, `map` encodes `BTreeMap`s.
+                    #[cfg(feature = "std")]
+                    use std::collections::HashMap as Map;
+                    #[cfg(not(feature = "std"))]
+                    use alloc::collections::BTreeMap as Map;
                     use quickcheck::TestResult;
 
                     use ::encoding::*;
@@ -983,7 +2319,7 @@ mod test {
                   [$(($val_ty:ty, $val_proto:ident)),*]) => {
             $(
                 quickcheck! {
-                    fn $val_proto(values: HashMap<$key_ty, $val_ty>, tag: u32) -> TestResult {
+                    fn $val_proto(values: Map<$key_ty, $val_ty>, tag: u32) -> TestResult {
                         check_collection_type(values, tag, WireType::LengthDelimited,
                                               |tag, values, buf| {
                                                   map::encode($key_proto::encode,
@@ -994,12 +2330,13 @@ mod test {
                                                               values,
                                                               buf)
                                               },
-                                              |wire_type, values, buf| {
+                                              |wire_type, values, buf, ctx| {
                                                   check_wire_type(WireType::LengthDelimited, wire_type)?;
                                                   map::merge($key_proto::merge,
                                                              $val_proto::merge,
                                                              values,
-                                                             buf)
+                                                             buf,
+                                                             ctx)
                                               },
                                               |tag, values| {
                                                   map::encoded_len($key_proto::encoded_len,
@@ -1044,4 +2381,39 @@ mod test {
                    (String, string),
                    (Vec<u8>, bytes)
                ]);
+
+    /// The `BTreeMap` encoding functions are generated from the same macro as
+    /// the `HashMap` functions, so a single key and value type is tested.
+    mod btree_map_string {
+        use alloc::collections::BTreeMap;
+        use quickcheck::TestResult;
+
+        use ::encoding::*;
+        use ::encoding::test::check_collection_type;
+
+        quickcheck! {
+            fn sint64(values: BTreeMap<String, i64>, tag: u32) -> TestResult {
+                check_collection_type(values, tag, WireType::LengthDelimited,
+                                      |tag, values, buf| {
+                                          btree_map::encode(string::encode,
+                                                            string::encoded_len,
+                                                            sint64::encode,
+                                                            sint64::encoded_len,
+                                                            tag,
+                                                            values,
+                                                            buf)
+                                      },
+                                      |wire_type, values, buf, ctx| {
+                                          check_wire_type(WireType::LengthDelimited, wire_type)?;
+                                          btree_map::merge(string::merge, sint64::merge, values, buf, ctx)
+                                      },
+                                      |tag, values| {
+                                          btree_map::encoded_len(string::encoded_len,
+                                                                 sint64::encoded_len,
+                                                                 tag,
+                                                                 values)
+                                      })
+            }
+        }
+    }
 }
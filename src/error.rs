@@ -0,0 +1,198 @@
+//! Protobuf encoding and decoding errors.
+
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error;
+#[cfg(feature = "std")]
+use std::io;
+
+use encoding::WireType;
+
+/// The cause of a `DecodeError`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeErrorKind {
+    /// The buffer ended before the value was completely decoded.
+    BufferUnderflow,
+    /// A varint was longer than 10 bytes.
+    InvalidVarint,
+    /// A field key contained an invalid wire type value.
+    InvalidWireType(u8),
+    /// A field key contained a zero tag, or a tag which overflows a `u32`.
+    InvalidKey,
+    /// The wire type of a field did not match the wire type of its declared type.
+    UnexpectedWireType {
+        expected: WireType,
+        actual: WireType,
+    },
+    /// An end group key was encountered without a matching start group key.
+    UnexpectedEndGroup,
+    /// A string field contained invalid UTF-8 data.
+    InvalidUtf8,
+    /// Messages or groups were nested more deeply than the recursion limit allows.
+    RecursionLimitReached,
+    /// The encoded message was longer than `DecodeOptions::max_message_size`.
+    MessageTooLarge,
+    /// A string or bytes value was longer than `DecodeOptions::max_length`.
+    LengthLimitExceeded,
+    /// A repeated or map field had more elements than
+    /// `DecodeOptions::max_repeated_len`.
+    RepeatedLimitExceeded,
+}
+
+impl DecodeErrorKind {
+    fn description(&self) -> &'static str {
+        match *self {
+            DecodeErrorKind::BufferUnderflow => "buffer underflow",
+            DecodeErrorKind::InvalidVarint => "invalid varint",
+            DecodeErrorKind::InvalidWireType(..) => "invalid wire type value",
+            DecodeErrorKind::InvalidKey => "invalid field key",
+            DecodeErrorKind::UnexpectedWireType { .. } => "unexpected wire type",
+            DecodeErrorKind::UnexpectedEndGroup => "unexpected end group tag",
+            DecodeErrorKind::InvalidUtf8 => "invalid string value: data is not UTF-8 encoded",
+            DecodeErrorKind::RecursionLimitReached => "recursion limit reached",
+            DecodeErrorKind::MessageTooLarge => "message size limit exceeded",
+            DecodeErrorKind::LengthLimitExceeded => "string or bytes length limit exceeded",
+            DecodeErrorKind::RepeatedLimitExceeded => "repeated field length limit exceeded",
+        }
+    }
+}
+
+impl fmt::Display for DecodeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeErrorKind::InvalidWireType(value) => {
+                write!(f, "{}: {}", self.description(), value)
+            },
+            DecodeErrorKind::UnexpectedWireType { expected, actual } => {
+                write!(f, "{}: {:?} (expected {:?})", self.description(), actual, expected)
+            },
+            _ => f.write_str(self.description()),
+        }
+    }
+}
+
+/// A Protobuf message decoding error.
+///
+/// `DecodeError` indicates that the input buffer does not contain a valid
+/// Protobuf message. The error details should be considered 'best effort': in
+/// general it is not possible to exactly pinpoint why data is malformed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    kind: DecodeErrorKind,
+    /// A stack of `(message, field)` name pairs, which identify the specific
+    /// message type and field where decoding failed. The stack contains an
+    /// entry per level of nesting, innermost first.
+    stack: Vec<(&'static str, &'static str)>,
+}
+
+impl DecodeError {
+    /// Creates a new `DecodeError` with the provided cause.
+    pub fn new(kind: DecodeErrorKind) -> DecodeError {
+        DecodeError {
+            kind: kind,
+            stack: Vec::new(),
+        }
+    }
+
+    /// Returns the cause of the error.
+    pub fn kind(&self) -> &DecodeErrorKind {
+        &self.kind
+    }
+
+    /// Returns the `(message, field)` name pairs identifying where decoding
+    /// failed, innermost first.
+    pub fn stack(&self) -> &[(&'static str, &'static str)] {
+        &self.stack
+    }
+
+    /// Pushes a `(message, field)` name location pair on to the location stack.
+    ///
+    /// Meant to be used only by `Message` implementations.
+    #[doc(hidden)]
+    pub fn push(&mut self, message: &'static str, field: &'static str) {
+        self.stack.push((message, field));
+    }
+}
+
+impl From<DecodeErrorKind> for DecodeError {
+    fn from(kind: DecodeErrorKind) -> DecodeError {
+        DecodeError::new(kind)
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("failed to decode Protobuf message: ")?;
+        for &(message, field) in self.stack.iter().rev() {
+            write!(f, "{}.{}: ", message, field)?;
+        }
+        fmt::Display::fmt(&self.kind, f)
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for DecodeError {
+    fn description(&self) -> &str {
+        self.kind.description()
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<DecodeError> for io::Error {
+    fn from(error: DecodeError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+/// A Protobuf message encoding error.
+///
+/// `EncodeError` always indicates that a message failed to encode because the
+/// provided buffer had insufficient capacity. Message encoding is otherwise
+/// infallible.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EncodeError {
+    required: usize,
+    remaining: usize,
+}
+
+impl EncodeError {
+    /// Creates a new `EncodeError`.
+    pub fn new(required: usize, remaining: usize) -> EncodeError {
+        EncodeError {
+            required: required,
+            remaining: remaining,
+        }
+    }
+
+    /// Returns the required buffer capacity to encode the message.
+    pub fn required_capacity(&self) -> usize {
+        self.required
+    }
+
+    /// Returns the remaining length in the provided buffer at the time of encoding.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to encode Protobuf message; insufficient buffer capacity (required: {}, remaining: {})",
+               self.required, self.remaining)
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for EncodeError {
+    fn description(&self) -> &str {
+        "failed to encode Protobuf message: insufficient buffer capacity"
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<EncodeError> for io::Error {
+    fn from(error: EncodeError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, error)
+    }
+}
@@ -0,0 +1,542 @@
+//! Protobuf extensions.
+//!
+//! A proto2 message may declare ranges of field tags which are reserved for
+//! extensions, and other `.proto` files may then declare extension fields of
+//! the message in those ranges. Extendable messages hold the extension fields
+//! in an `ExtensionSet` member annotated with `#[prost(extension_set="..")]`,
+//! and implement `ExtendableMessage`, which gets and sets the fields through
+//! typed `Extension` descriptors.
+//!
+//! The types in this module which do not appear in the crate root are
+//! marker types which describe the Protobuf type of an extension field.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::any::TypeId;
+use core::fmt;
+use core::marker::PhantomData;
+
+use bytes::{
+    Buf,
+    BufMut,
+    Take,
+};
+
+use encoding::*;
+use DecodeError;
+use UnknownFieldSet;
+
+/// The Protobuf type of an extension field, which determines the Rust type of
+/// the field value and how the value is encoded.
+pub trait ExtensionType {
+    /// The Rust type of the extension field value.
+    type Value: Clone + fmt::Debug + Default + PartialEq;
+
+    /// Encodes the value with the given tag, writing it to the buffer.
+    fn encode<B>(tag: u32, value: &Self::Value, buf: &mut B) where B: BufMut;
+
+    /// Decodes a value from the buffer, and merges it into `value`.
+    fn merge<B>(tag: u32,
+                wire_type: WireType,
+                value: &mut Self::Value,
+                buf: &mut Take<B>,
+                ctx: DecodeContext)
+                -> Result<(), DecodeError> where B: Buf;
+
+    /// Decodes one or more values from the buffer, and appends them to
+    /// `values`.
+    fn merge_repeated<B>(tag: u32,
+                         wire_type: WireType,
+                         values: &mut Vec<Self::Value>,
+                         buf: &mut Take<B>,
+                         ctx: DecodeContext)
+                         -> Result<(), DecodeError> where B: Buf {
+        let mut value = Self::Value::default();
+        Self::merge(tag, wire_type, &mut value, buf, ctx)?;
+        values.push(value);
+        Ok(())
+    }
+
+    /// Returns the encoded length of the value with the given tag.
+    fn encoded_len(tag: u32, value: &Self::Value) -> usize;
+}
+
+/// An extension type which may be used as the element type of a packed
+/// repeated extension field.
+pub trait PackedExtensionType: ExtensionType {
+    /// Encodes the values in a single packed field with the given tag.
+    fn encode_packed<B>(tag: u32, values: &Vec<Self::Value>, buf: &mut B) where B: BufMut;
+
+    /// Returns the encoded length of the values in a single packed field.
+    fn encoded_len_packed(tag: u32, values: &Vec<Self::Value>) -> usize;
+}
+
+macro_rules! scalar {
+    ($(#[$attr:meta])* $name:ident, $ty:ty, $proto_ty:ident) => (
+        $(#[$attr])*
+        #[derive(Debug)]
+        pub enum $name {}
+
+        impl ExtensionType for $name {
+            type Value = $ty;
+
+            fn encode<B>(tag: u32, value: &$ty, buf: &mut B) where B: BufMut {
+                $proto_ty::encode(tag, value, buf)
+            }
+
+            fn merge<B>(_tag: u32,
+                        wire_type: WireType,
+                        value: &mut $ty,
+                        buf: &mut Take<B>,
+                        ctx: DecodeContext)
+                        -> Result<(), DecodeError> where B: Buf {
+                $proto_ty::merge(wire_type, value, buf, ctx)
+            }
+
+            fn merge_repeated<B>(_tag: u32,
+                                 wire_type: WireType,
+                                 values: &mut Vec<$ty>,
+                                 buf: &mut Take<B>,
+                                 ctx: DecodeContext)
+                                 -> Result<(), DecodeError> where B: Buf {
+                $proto_ty::merge_repeated(wire_type, values, buf, ctx)
+            }
+
+            fn encoded_len(tag: u32, value: &$ty) -> usize {
+                $proto_ty::encoded_len(tag, value)
+            }
+        }
+    );
+
+    ($(#[$attr:meta])* $name:ident, $ty:ty, $proto_ty:ident, packed) => (
+        scalar!($(#[$attr])* $name, $ty, $proto_ty);
+
+        impl PackedExtensionType for $name {
+            fn encode_packed<B>(tag: u32, values: &Vec<$ty>, buf: &mut B) where B: BufMut {
+                $proto_ty::encode_packed(tag, values, buf)
+            }
+
+            fn encoded_len_packed(tag: u32, values: &Vec<$ty>) -> usize {
+                $proto_ty::encoded_len_packed(tag, values)
+            }
+        }
+    );
+}
+
+scalar!(#[doc="The `double` extension type."] Double, f64, double, packed);
+scalar!(#[doc="The `float` extension type."] Float, f32, float, packed);
+scalar!(#[doc="The `int32` extension type."] Int32, i32, int32, packed);
+scalar!(#[doc="The `int64` extension type."] Int64, i64, int64, packed);
+scalar!(#[doc="The `uint32` extension type."] Uint32, u32, uint32, packed);
+scalar!(#[doc="The `uint64` extension type."] Uint64, u64, uint64, packed);
+scalar!(#[doc="The `sint32` extension type."] Sint32, i32, sint32, packed);
+scalar!(#[doc="The `sint64` extension type."] Sint64, i64, sint64, packed);
+scalar!(#[doc="The `fixed32` extension type."] Fixed32, u32, fixed32, packed);
+scalar!(#[doc="The `fixed64` extension type."] Fixed64, u64, fixed64, packed);
+scalar!(#[doc="The `sfixed32` extension type."] Sfixed32, i32, sfixed32, packed);
+scalar!(#[doc="The `sfixed64` extension type."] Sfixed64, i64, sfixed64, packed);
+scalar!(#[doc="The `bool` extension type."] Bool, bool, bool, packed);
+scalar!(#[doc="The `enum` extension type. Values are the raw enum discriminants."] Enumeration, i32, int32, packed);
+scalar!(#[doc="The `string` extension type."] String, ::alloc::string::String, string);
+scalar!(#[doc="The `bytes` extension type."] Bytes, Vec<u8>, bytes);
+
+/// The message extension type.
+#[derive(Debug)]
+pub struct Message<M>(PhantomData<fn() -> M>);
+
+impl <M> ExtensionType for Message<M> where M: ::Message + Clone + PartialEq {
+    type Value = M;
+
+    fn encode<B>(tag: u32, value: &M, buf: &mut B) where B: BufMut {
+        message::encode(tag, value, buf)
+    }
+
+    fn merge<B>(_tag: u32,
+                wire_type: WireType,
+                value: &mut M,
+                buf: &mut Take<B>,
+                ctx: DecodeContext)
+                -> Result<(), DecodeError> where B: Buf {
+        message::merge(wire_type, value, buf, ctx)
+    }
+
+    fn encoded_len(tag: u32, value: &M) -> usize {
+        message::encoded_len(tag, value)
+    }
+}
+
+/// The group extension type.
+#[derive(Debug)]
+pub struct Group<M>(PhantomData<fn() -> M>);
+
+impl <M> ExtensionType for Group<M> where M: ::Message + Clone + PartialEq {
+    type Value = M;
+
+    fn encode<B>(tag: u32, value: &M, buf: &mut B) where B: BufMut {
+        group::encode(tag, value, buf)
+    }
+
+    fn merge<B>(tag: u32,
+                wire_type: WireType,
+                value: &mut M,
+                buf: &mut Take<B>,
+                ctx: DecodeContext)
+                -> Result<(), DecodeError> where B: Buf {
+        group::merge(tag, wire_type, value, buf, ctx)
+    }
+
+    fn encoded_len(tag: u32, value: &M) -> usize {
+        group::encoded_len(tag, value)
+    }
+}
+
+/// A repeated extension type, with elements of type `T`.
+///
+/// Repeated numeric fields are decoded whether or not they were encoded
+/// packed, but are always encoded unpacked. Use `Packed` for repeated fields
+/// declared with `[packed = true]`.
+#[derive(Debug)]
+pub struct Repeated<T>(PhantomData<fn() -> T>);
+
+impl <T> ExtensionType for Repeated<T> where T: ExtensionType {
+    type Value = Vec<T::Value>;
+
+    fn encode<B>(tag: u32, values: &Vec<T::Value>, buf: &mut B) where B: BufMut {
+        for value in values {
+            T::encode(tag, value, buf);
+        }
+    }
+
+    fn merge<B>(tag: u32,
+                wire_type: WireType,
+                values: &mut Vec<T::Value>,
+                buf: &mut Take<B>,
+                ctx: DecodeContext)
+                -> Result<(), DecodeError> where B: Buf {
+        T::merge_repeated(tag, wire_type, values, buf, ctx)
+    }
+
+    fn encoded_len(tag: u32, values: &Vec<T::Value>) -> usize {
+        values.iter().map(|value| T::encoded_len(tag, value)).sum()
+    }
+}
+
+/// A packed repeated extension type, with elements of type `T`.
+#[derive(Debug)]
+pub struct Packed<T>(PhantomData<fn() -> T>);
+
+impl <T> ExtensionType for Packed<T> where T: PackedExtensionType {
+    type Value = Vec<T::Value>;
+
+    fn encode<B>(tag: u32, values: &Vec<T::Value>, buf: &mut B) where B: BufMut {
+        T::encode_packed(tag, values, buf)
+    }
+
+    fn merge<B>(tag: u32,
+                wire_type: WireType,
+                values: &mut Vec<T::Value>,
+                buf: &mut Take<B>,
+                ctx: DecodeContext)
+                -> Result<(), DecodeError> where B: Buf {
+        T::merge_repeated(tag, wire_type, values, buf, ctx)
+    }
+
+    fn encoded_len(tag: u32, values: &Vec<T::Value>) -> usize {
+        T::encoded_len_packed(tag, values)
+    }
+}
+
+/// A typed descriptor of an extension field of message type `M`, with
+/// extension type `T`.
+///
+/// Extensions are declared as constants, and are usually generated by
+/// `prost-build` from `extend` blocks in `.proto` files:
+///
+/// ```rust,ignore
+/// pub const FOO: ::prost::Extension<Bar, ::prost::extension::Int32> = ::prost::Extension {
+///     name: "package.foo",
+///     tag: 100,
+///     marker: ::std::marker::PhantomData,
+/// };
+/// ```
+pub struct Extension<M, T> {
+    /// The fully qualified name of the extension field.
+    pub name: &'static str,
+    /// The tag of the extension field.
+    pub tag: u32,
+    #[doc(hidden)]
+    pub marker: PhantomData<fn(M, T)>,
+}
+
+impl <M, T> Clone for Extension<M, T> {
+    fn clone(&self) -> Extension<M, T> {
+        *self
+    }
+}
+
+impl <M, T> Copy for Extension<M, T> {}
+
+impl <M, T> fmt::Debug for Extension<M, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Extension")
+         .field("name", &self.name)
+         .field("tag", &self.tag)
+         .finish()
+    }
+}
+
+/// The extension fields of an extendable message.
+///
+/// Fields are held in their encoded form, in the order they were decoded or
+/// set, and are decoded on access with `ExtensionSet::get`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExtensionSet {
+    fields: UnknownFieldSet,
+}
+
+impl ExtensionSet {
+
+    /// Creates an empty set of extension fields.
+    pub fn new() -> ExtensionSet {
+        ExtensionSet::default()
+    }
+
+    /// Returns `true` if the set contains no fields.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Returns the encoded extension fields in the set.
+    pub fn fields(&self) -> &UnknownFieldSet {
+        &self.fields
+    }
+
+    /// Returns `true` if the set contains a value for the extension.
+    pub fn has<M, T>(&self, extension: &Extension<M, T>) -> bool {
+        self.fields.iter().any(|&(tag, _)| tag == extension.tag)
+    }
+
+    /// Decodes the value of the extension, or returns `None` if the set
+    /// contains no value for it.
+    ///
+    /// Decoded values are not cached: each call decodes the value from the
+    /// encoded fields, including every nested message of a message
+    /// extension. Callers which read an extension repeatedly should keep the
+    /// returned value rather than calling `get` again.
+    pub fn get<M, T>(&self, extension: &Extension<M, T>) -> Result<Option<T::Value>, DecodeError>
+    where T: ExtensionType {
+        if !self.has(extension) {
+            return Ok(None);
+        }
+        let mut value = T::Value::default();
+        merge_value::<T>(extension.tag, &mut value, &self.fields, DecodeContext::default())?;
+        Ok(Some(value))
+    }
+
+    /// Sets the value of the extension, replacing any existing value.
+    pub fn set<M, T>(&mut self, extension: &Extension<M, T>, value: &T::Value) where T: ExtensionType {
+        self.clear(extension);
+        let mut buf = Vec::with_capacity(T::encoded_len(extension.tag, value));
+        T::encode(extension.tag, value, &mut buf);
+
+        let len = buf.len();
+        let mut buf = SliceBuf::new(&buf).take(len);
+        while buf.has_remaining() {
+            let (tag, wire_type) = decode_key(&mut buf).expect("invalid extension encoding");
+            self.fields.merge_field(tag, wire_type, &mut buf, DecodeContext::default())
+                       .expect("invalid extension encoding");
+        }
+    }
+
+    /// Removes the value of the extension from the set.
+    pub fn clear<M, T>(&mut self, extension: &Extension<M, T>) {
+        let tag = extension.tag;
+        self.fields.retain(|field_tag, _| field_tag != tag);
+    }
+
+    /// Decodes an extension field of message type `M` from the buffer, and
+    /// appends it to the set. If the context holds an extension registry
+    /// which contains the extension, the field is checked to be a valid
+    /// encoding of the extension type.
+    #[doc(hidden)]
+    pub fn merge_field<M, B>(&mut self,
+                             tag: u32,
+                             wire_type: WireType,
+                             buf: &mut B,
+                             ctx: DecodeContext)
+                             -> Result<(), DecodeError>
+    where M: 'static,
+          B: Buf {
+        let start = self.fields.len();
+        self.fields.merge_field(tag, wire_type, buf, ctx)?;
+        let entry = ctx.extension_registry().and_then(|registry| registry.entry(TypeId::of::<M>(), tag));
+        if let Some(entry) = entry {
+            if let Err(error) = (entry.validate)(tag, &self.fields, start, ctx) {
+                self.fields.truncate(start);
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Encodes the fields in the set, writing them to the buffer.
+    #[doc(hidden)]
+    pub fn encode_raw<B>(&self, buf: &mut B) where B: BufMut {
+        self.fields.encode_raw(buf)
+    }
+
+    /// Returns the encoded length of the fields in the set.
+    pub fn encoded_len(&self) -> usize {
+        self.fields.encoded_len()
+    }
+}
+
+/// Decodes the fields in the field set with the given tag, and merges them into
+/// `value`.
+fn merge_value<T>(tag: u32,
+                  value: &mut T::Value,
+                  fields: &UnknownFieldSet,
+                  ctx: DecodeContext)
+                  -> Result<(), DecodeError> where T: ExtensionType {
+    let mut matching = UnknownFieldSet::new();
+    for &(field_tag, ref field) in fields.iter() {
+        if field_tag == tag {
+            matching.push(tag, field.clone());
+        }
+    }
+    let mut buf = Vec::with_capacity(matching.encoded_len());
+    matching.encode_raw(&mut buf);
+
+    let len = buf.len();
+    let mut buf = SliceBuf::new(&buf).take(len);
+    while buf.has_remaining() {
+        let (tag, wire_type) = decode_key(&mut buf)?;
+        T::merge(tag, wire_type, value, &mut buf, ctx)?;
+    }
+    Ok(())
+}
+
+/// Checks that the field at index `start` of the field set is a valid encoding
+/// of the extension type `T`.
+fn validate<T>(tag: u32, fields: &UnknownFieldSet, start: usize, ctx: DecodeContext) -> Result<(), DecodeError>
+where T: ExtensionType {
+    let mut field = UnknownFieldSet::new();
+    for &(field_tag, ref value) in fields.iter().skip(start) {
+        field.push(field_tag, value.clone());
+    }
+    let mut value = T::Value::default();
+    merge_value::<T>(tag, &mut value, &field, ctx)
+}
+
+/// A registered extension.
+#[derive(Clone, Copy)]
+struct RegistryEntry {
+    name: &'static str,
+    validate: fn(u32, &UnknownFieldSet, usize, DecodeContext) -> Result<(), DecodeError>,
+}
+
+/// A set of extensions, which is used to check extension fields while
+/// decoding, and to look up extensions by tag or by name.
+///
+/// Extendable messages decode every field in their extension ranges into
+/// their `ExtensionSet`, whether or not the extension is known. When the
+/// `DecodeContext` holds a registry (see
+/// `DecodeContext::with_extension_registry`), extension fields which are in
+/// the registry must also be valid encodings of their extension type, so that
+/// malformed extensions are rejected at decode time instead of on access.
+#[derive(Clone, Default)]
+pub struct ExtensionRegistry {
+    entries: BTreeMap<(TypeId, u32), RegistryEntry>,
+}
+
+impl ExtensionRegistry {
+
+    /// Creates an empty extension registry.
+    pub fn new() -> ExtensionRegistry {
+        ExtensionRegistry::default()
+    }
+
+    /// Adds an extension to the registry, replacing any extension of the same
+    /// message type with the same tag.
+    pub fn register<M, T>(&mut self, extension: &Extension<M, T>) where M: 'static, T: ExtensionType {
+        self.entries.insert((TypeId::of::<M>(), extension.tag), RegistryEntry {
+            name: extension.name,
+            validate: validate::<T>,
+        });
+    }
+
+    /// Returns the fully qualified name of the extension of message type `M`
+    /// with the given tag, or `None` if the registry does not contain it.
+    pub fn find<M>(&self, tag: u32) -> Option<&'static str> where M: 'static {
+        self.entry(TypeId::of::<M>(), tag).map(|entry| entry.name)
+    }
+
+    /// Returns the tag of the extension of message type `M` with the given
+    /// fully qualified name, or `None` if the registry does not contain it.
+    pub fn find_by_name<M>(&self, name: &str) -> Option<u32> where M: 'static {
+        let type_id = TypeId::of::<M>();
+        self.entries.iter()
+                    .find(|&(&(id, _), entry)| id == type_id && entry.name == name)
+                    .map(|(&(_, tag), _)| tag)
+    }
+
+    /// Returns the number of extensions in the registry.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the registry contains no extensions.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn entry(&self, type_id: TypeId, tag: u32) -> Option<&RegistryEntry> {
+        self.entries.get(&(type_id, tag))
+    }
+}
+
+impl fmt::Debug for ExtensionRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+         .entries(self.entries.iter().map(|(&(_, tag), entry)| (tag, entry.name)))
+         .finish()
+    }
+}
+
+/// A message with extension ranges, which holds its extension fields in an
+/// `ExtensionSet`.
+///
+/// `ExtendableMessage` is implemented by `#[derive(Message)]` for messages
+/// with an `#[prost(extension_set="..")]` member.
+pub trait ExtendableMessage: ::Message + Sized + 'static {
+
+    /// Returns the extension fields of the message.
+    fn extension_set(&self) -> &ExtensionSet;
+
+    /// Returns a mutable reference to the extension fields of the message.
+    fn extension_set_mut(&mut self) -> &mut ExtensionSet;
+
+    /// Returns `true` if the message contains a value for the extension.
+    fn has_extension<T>(&self, extension: &Extension<Self, T>) -> bool {
+        self.extension_set().has(extension)
+    }
+
+    /// Decodes the value of the extension, or returns `None` if the message
+    /// contains no value for it. The value is decoded on every call, see
+    /// `ExtensionSet::get`.
+    fn get_extension<T>(&self, extension: &Extension<Self, T>) -> Result<Option<T::Value>, DecodeError>
+    where T: ExtensionType {
+        self.extension_set().get(extension)
+    }
+
+    /// Sets the value of the extension, replacing any existing value.
+    fn set_extension<T>(&mut self, extension: &Extension<Self, T>, value: T::Value)
+    where T: ExtensionType {
+        self.extension_set_mut().set(extension, &value)
+    }
+
+    /// Removes the value of the extension from the message.
+    fn clear_extension<T>(&mut self, extension: &Extension<Self, T>) {
+        self.extension_set_mut().clear(extension)
+    }
+}
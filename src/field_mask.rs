@@ -0,0 +1,190 @@
+//! Field mask support.
+//!
+//! A `google.protobuf.FieldMask` selects a subset of the fields of a message
+//! by their paths, for instance `a.b.c`, where each path segment is the
+//! Protobuf name of a field, and every segment but the last names a singular
+//! message field. Messages which derive `Message` implement
+//! `MaskableMessage`, which merges and prunes messages by a `FieldMaskTree`
+//! built from the paths of a mask without using reflection.
+
+use alloc::boxed::Box;
+use alloc::collections::btree_map::{
+    self,
+    BTreeMap,
+};
+use alloc::string::{
+    String,
+    ToString,
+};
+use alloc::vec::Vec;
+use core::u32;
+
+use bytes::Buf;
+
+use encoding::*;
+use DecodeError;
+use Message;
+
+/// The paths of a field mask, as a tree of field names.
+///
+/// A node without children selects the entire field, so adding the path
+/// `a` to a tree containing `a.b` replaces it, and adding `a.b` to a tree
+/// containing `a` has no effect.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FieldMaskTree {
+    children: BTreeMap<String, FieldMaskTree>,
+}
+
+impl FieldMaskTree {
+
+    /// Creates a tree from field mask paths.
+    pub fn new<I, S>(paths: I) -> FieldMaskTree where I: IntoIterator<Item=S>, S: AsRef<str> {
+        let mut tree = FieldMaskTree::default();
+        for path in paths {
+            tree.add_path(path.as_ref());
+        }
+        tree
+    }
+
+    /// Adds a field mask path to the tree. Empty paths are ignored.
+    pub fn add_path(&mut self, path: &str) {
+        if !path.is_empty() {
+            self.add(&path.split('.').collect::<Vec<_>>());
+        }
+    }
+
+    fn add(&mut self, path: &[&str]) {
+        let (name, rest) = match path.split_first() {
+            Some(split) => split,
+            None => return,
+        };
+        if rest.is_empty() {
+            self.children.insert(name.to_string(), FieldMaskTree::default());
+            return;
+        }
+        match self.children.entry(name.to_string()) {
+            btree_map::Entry::Occupied(entry) => {
+                // A leaf already selects the entire field.
+                if !entry.get().is_leaf() {
+                    entry.into_mut().add(rest);
+                }
+            },
+            btree_map::Entry::Vacant(entry) => entry.insert(FieldMaskTree::default()).add(rest),
+        }
+    }
+
+    /// Returns the subtree of the field with the given name, or `None` if the
+    /// field is not selected.
+    pub fn get(&self, name: &str) -> Option<&FieldMaskTree> {
+        self.children.get(name)
+    }
+
+    /// Returns `true` if the node has no children. A leaf node selects the
+    /// entire field, and an empty tree selects no fields.
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Returns the paths of the tree, in canonical (sorted and deduplicated)
+    /// order.
+    pub fn paths(&self) -> Vec<String> {
+        let mut paths = Vec::new();
+        for (name, child) in &self.children {
+            if child.is_leaf() {
+                paths.push(name.clone());
+            } else {
+                paths.extend(child.paths().into_iter().map(|path| format!("{}.{}", name, path)));
+            }
+        }
+        paths
+    }
+}
+
+/// A message whose fields can be merged and pruned by field mask.
+///
+/// `MaskableMessage` is implemented by `#[derive(Message)]`. Field masks
+/// name fields by their Protobuf names, which default to the names of the
+/// Rust fields, and may be set with the `name` attribute, for instance
+/// `#[prost(int32, tag="1", name="FieldName")]`.
+pub trait MaskableMessage: Message {
+
+    /// Returns `true` if the path, split into field names, names a field of
+    /// the message.
+    fn is_valid_path(path: &[&str]) -> bool;
+
+    /// Merges the fields selected by the mask from `source` into `self`.
+    ///
+    /// Selected fields are merged with Protobuf merge semantics: scalar
+    /// fields are copied, repeated and map fields are appended, and message
+    /// fields are merged recursively. Proto3 scalar fields are copied even if
+    /// they hold the default value, while optional scalar fields are only
+    /// copied if they are set. Paths which do not name a field are ignored.
+    ///
+    /// An error is returned if a message field fails to merge, which happens
+    /// if it is a `Lazy` field whose bytes can not be decoded. The fields
+    /// merged before the error are left merged.
+    fn merge_masked(&mut self, source: &Self, mask: &FieldMaskTree) -> Result<(), DecodeError>;
+
+    /// Clears the fields which are not selected by the mask, along with any
+    /// unknown and extension fields.
+    fn prune_masked(&mut self, mask: &FieldMaskTree);
+}
+
+impl <M> MaskableMessage for Box<M> where M: MaskableMessage {
+    #[inline]
+    fn is_valid_path(path: &[&str]) -> bool {
+        M::is_valid_path(path)
+    }
+    #[inline]
+    fn merge_masked(&mut self, source: &Box<M>, mask: &FieldMaskTree) -> Result<(), DecodeError> {
+        (**self).merge_masked(source, mask)
+    }
+    #[inline]
+    fn prune_masked(&mut self, mask: &FieldMaskTree) {
+        (**self).prune_masked(mask)
+    }
+}
+
+/// Returns `true` if the path names a field of the message type returned by
+/// `field`. Used by `#[derive(Message)]` to validate nested paths, since the
+/// derive does not know the types of message fields.
+#[doc(hidden)]
+pub fn is_valid_message_path<T, M, F>(_field: F, path: &[&str]) -> bool
+where F: Fn(&T) -> Option<&M>, M: MaskableMessage {
+    M::is_valid_path(path)
+}
+
+/// Merges `source` into `destination` with Protobuf merge semantics, by
+/// encoding `source` and merging the encoding into `destination`. Used by
+/// `#[derive(Message)]` to merge message fields selected by a field mask.
+#[doc(hidden)]
+pub fn merge_message<M>(destination: &mut M, source: &M) -> Result<(), DecodeError> where M: Message {
+    let encoded = source.encode_to_vec();
+    let len = encoded.len();
+    // The source is already in memory, so its nesting does not need to be
+    // limited.
+    let ctx = DecodeContext::with_recursion_limit(u32::MAX);
+    destination.merge_with_context(&mut Buf::take(SliceBuf::new(&encoded), len), ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_mask_tree() {
+        let tree = FieldMaskTree::new(&["a.b.c", "a.b.d", "e", "a.f", "e.g", ""]);
+        assert_eq!(vec!["a.b.c", "a.b.d", "a.f", "e"], tree.paths());
+        assert!(tree.get("e").unwrap().is_leaf());
+        assert!(!tree.get("a").unwrap().is_leaf());
+        assert_eq!(None, tree.get("b"));
+
+        let mut tree = tree;
+        tree.add_path("a.b");
+        assert_eq!(vec!["a.b", "a.f", "e"], tree.paths());
+        tree.add_path("a");
+        assert_eq!(vec!["a", "e"], tree.paths());
+
+        assert!(FieldMaskTree::new(Vec::<String>::new()).paths().is_empty());
+    }
+}
@@ -0,0 +1,283 @@
+use core::fmt;
+use core::mem;
+
+use bytes::{
+    Buf,
+    BufMut,
+    Bytes,
+    BytesMut,
+    Take,
+};
+
+use encoding::{
+    BytesAdapter,
+    DecodeContext,
+    SizeCache,
+    SliceBuf,
+    WireType,
+};
+use DecodeError;
+use FieldMaskTree;
+use MaskableMessage;
+use Message;
+
+/// A message field which is decoded on first access.
+///
+/// `Lazy<M>` may be used in place of `M` as the type of a message field. When
+/// the containing message is decoded, the field's encoded bytes are stored
+/// without being decoded. The message is decoded when it is first accessed
+/// with `get` or `get_mut`, and if it has not been modified through
+/// `get_mut`, re-encoding the field writes the original bytes untouched.
+/// Deterministic encodings are the exception: they decode and re-encode the
+/// message, so that its map entries are written in key order.
+///
+/// `get` takes `&mut self` because it caches the decoded message in the field.
+/// Caching it behind `&self` would need a lock, since `Message` types are
+/// `Sync`, and a lock is not available without `std`. To read a field through
+/// a shared reference, use `with_msg`, which decodes a temporary copy of the
+/// message if it has not been decoded yet.
+///
+/// Deferred decoding uses the recursion and length limits of the context the
+/// field was decoded with, but not its extension registry.
+#[derive(Clone)]
+pub struct Lazy<M> {
+    state: State<M>,
+    ctx: DecodeContext<'static>,
+}
+
+#[derive(Clone)]
+enum State<M> {
+    /// The message has not been decoded.
+    Encoded(Bytes),
+    /// The message has been decoded, but not modified.
+    Decoded(Bytes, M),
+    /// The message has been modified, so the encoded bytes are stale.
+    Modified(M),
+}
+
+impl <M> Lazy<M> where M: Message {
+
+    /// Creates a `Lazy` holding a decoded message.
+    pub fn new(msg: M) -> Lazy<M> {
+        Lazy {
+            state: State::Modified(msg),
+            ctx: DecodeContext::default(),
+        }
+    }
+
+    /// Returns `true` if the message has been decoded.
+    pub fn is_decoded(&self) -> bool {
+        match self.state {
+            State::Encoded(..) => false,
+            State::Decoded(..) | State::Modified(..) => true,
+        }
+    }
+
+    /// Returns the encoded message, or `None` if it has been modified since
+    /// it was decoded.
+    pub fn encoded(&self) -> Option<&Bytes> {
+        match self.state {
+            State::Encoded(ref encoded) | State::Decoded(ref encoded, _) => Some(encoded),
+            State::Modified(..) => None,
+        }
+    }
+
+    /// Returns the message, decoding it if it has not yet been decoded.
+    pub fn get(&mut self) -> Result<&M, DecodeError> {
+        if let State::Encoded(ref encoded) = self.state {
+            let msg = self.decode_encoded(encoded)?;
+            self.state = State::Decoded(encoded.clone(), msg);
+        }
+        match self.state {
+            State::Decoded(_, ref msg) | State::Modified(ref msg) => Ok(msg),
+            State::Encoded(..) => unreachable!(),
+        }
+    }
+
+    /// Returns the message mutably, decoding it if it has not yet been
+    /// decoded. The field is re-encoded from the message from then on.
+    pub fn get_mut(&mut self) -> Result<&mut M, DecodeError> {
+        self.state = match mem::replace(&mut self.state, State::Encoded(Bytes::new())) {
+            State::Encoded(encoded) => match self.decode_encoded(&encoded) {
+                Ok(msg) => State::Modified(msg),
+                Err(error) => {
+                    self.state = State::Encoded(encoded);
+                    return Err(error);
+                },
+            },
+            State::Decoded(_, msg) | State::Modified(msg) => State::Modified(msg),
+        };
+        match self.state {
+            State::Modified(ref mut msg) => Ok(msg),
+            State::Encoded(..) | State::Decoded(..) => unreachable!(),
+        }
+    }
+
+    /// Replaces the message.
+    pub fn set(&mut self, msg: M) {
+        self.state = State::Modified(msg);
+    }
+
+    /// Returns the message, decoding it if it has not yet been decoded.
+    pub fn into_inner(self) -> Result<M, DecodeError> {
+        match self.state {
+            State::Encoded(ref encoded) => self.decode_encoded(encoded),
+            State::Decoded(_, msg) | State::Modified(msg) => Ok(msg),
+        }
+    }
+
+    /// Applies `f` to the message, decoding a temporary copy of it if it has
+    /// not yet been decoded.
+    pub fn with_msg<F, R>(&self, f: F) -> Result<R, DecodeError> where F: FnOnce(&M) -> R {
+        match self.state {
+            State::Encoded(ref encoded) => self.decode_encoded(encoded).map(|msg| f(&msg)),
+            State::Decoded(_, ref msg) | State::Modified(ref msg) => Ok(f(msg)),
+        }
+    }
+
+    fn decode_encoded(&self, encoded: &Bytes) -> Result<M, DecodeError> {
+        let ctx = self.ctx.with_source(encoded);
+        M::decode_with_context(&mut Buf::take(SliceBuf::new(encoded), encoded.len()), ctx)
+    }
+}
+
+impl <M> Default for Lazy<M> {
+    fn default() -> Lazy<M> {
+        // The empty encoding decodes to the default message.
+        Lazy {
+            state: State::Encoded(Bytes::new()),
+            ctx: DecodeContext::default(),
+        }
+    }
+}
+
+impl <M> From<M> for Lazy<M> where M: Message {
+    fn from(msg: M) -> Lazy<M> {
+        Lazy::new(msg)
+    }
+}
+
+impl <M> PartialEq for Lazy<M> where M: Message {
+    fn eq(&self, other: &Lazy<M>) -> bool {
+        if let (Some(encoded), Some(other_encoded)) = (self.encoded(), other.encoded()) {
+            if encoded == other_encoded {
+                return true;
+            }
+        }
+        self.with_msg(|msg| other.with_msg(|other_msg| msg == other_msg))
+            .and_then(|result| result)
+            .unwrap_or(false)
+    }
+}
+
+impl <M> fmt::Debug for Lazy<M> where M: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.state {
+            State::Encoded(ref encoded) => {
+                f.debug_struct("Lazy").field("encoded_len", &encoded.len()).finish()
+            },
+            State::Decoded(_, ref msg) | State::Modified(ref msg) => {
+                f.debug_tuple("Lazy").field(msg).finish()
+            },
+        }
+    }
+}
+
+impl <M> Message for Lazy<M> where M: Message {
+    fn encode_raw<B>(&self, buf: &mut B) where B: BufMut {
+        match self.state {
+            State::Encoded(ref encoded) | State::Decoded(ref encoded, _) => buf.put_slice(encoded),
+            State::Modified(ref msg) => msg.encode_raw(buf),
+        }
+    }
+
+    /// Deterministic encodings re-encode the message, since the stored bytes
+    /// may not be in deterministic order. An undecoded message is decoded
+    /// once, by `encoded_len_cached`, which stores its re-encoding in the
+    /// cache.
+    fn encode_raw_cached<B>(&self, buf: &mut B, cache: &mut SizeCache) where B: BufMut {
+        match self.state {
+            State::Encoded(_) if cache.is_deterministic() => buf.put_slice(&cache.next_encoding()),
+            State::Decoded(_, ref msg) if cache.is_deterministic() => msg.encode_raw_cached(buf, cache),
+            State::Encoded(ref encoded) | State::Decoded(ref encoded, _) => buf.put_slice(encoded),
+            State::Modified(ref msg) => msg.encode_raw_cached(buf, cache),
+        }
+    }
+
+    /// Stores the encoded message if it has not been decoded, appending to
+    /// the previously stored bytes, since concatenated encodings decode to
+    /// the merged message. Otherwise the message is merged and modified.
+    fn merge_with_context<B>(&mut self, buf: &mut Take<B>, ctx: DecodeContext) -> Result<(), DecodeError>
+    where B: Buf {
+        if let State::Encoded(ref mut encoded) = self.state {
+            let len = buf.remaining();
+            let mut bytes = Bytes::new();
+            bytes.replace_with(buf, len, ctx);
+            if encoded.is_empty() {
+                *encoded = bytes;
+            } else {
+                let mut merged = BytesMut::with_capacity(encoded.len() + bytes.len());
+                merged.extend_from_slice(encoded);
+                merged.extend_from_slice(&bytes);
+                *encoded = merged.freeze();
+            }
+            self.ctx = ctx.detach();
+            return Ok(());
+        }
+        self.get_mut()?.merge_with_context(buf, ctx)
+    }
+
+    fn merge_field<B>(&mut self,
+                      tag: u32,
+                      wire_type: WireType,
+                      buf: &mut Take<B>,
+                      ctx: DecodeContext)
+                      -> Result<(), DecodeError> where B: Buf {
+        self.get_mut()?.merge_field(tag, wire_type, buf, ctx)
+    }
+
+    fn encoded_len(&self) -> usize {
+        match self.state {
+            State::Encoded(ref encoded) | State::Decoded(ref encoded, _) => encoded.len(),
+            State::Modified(ref msg) => msg.encoded_len(),
+        }
+    }
+
+    /// If the message fails to decode for a deterministic encoding, the
+    /// stored bytes are written instead.
+    fn encoded_len_cached(&self, cache: &mut SizeCache) -> usize {
+        match self.state {
+            State::Encoded(ref encoded) if cache.is_deterministic() => {
+                let encoded = match self.decode_encoded(encoded) {
+                    Ok(msg) => Bytes::from(msg.encode_to_vec_deterministic()),
+                    Err(_) => encoded.clone(),
+                };
+                let len = encoded.len();
+                cache.push_encoding(encoded);
+                len
+            },
+            State::Decoded(_, ref msg) if cache.is_deterministic() => msg.encoded_len_cached(cache),
+            State::Encoded(ref encoded) | State::Decoded(ref encoded, _) => encoded.len(),
+            State::Modified(ref msg) => msg.encoded_len_cached(cache),
+        }
+    }
+}
+
+/// Masked merges and prunes decode the message. If it fails to decode, a
+/// merge returns the error, and a prune leaves the field unchanged.
+impl <M> MaskableMessage for Lazy<M> where M: MaskableMessage {
+    fn is_valid_path(path: &[&str]) -> bool {
+        M::is_valid_path(path)
+    }
+
+    fn merge_masked(&mut self, source: &Lazy<M>, mask: &FieldMaskTree) -> Result<(), DecodeError> {
+        let msg = self.get_mut()?;
+        source.with_msg(|source| msg.merge_masked(source, mask))?
+    }
+
+    fn prune_masked(&mut self, mask: &FieldMaskTree) {
+        if let Ok(msg) = self.get_mut() {
+            msg.prune_masked(mask);
+        }
+    }
+}
@@ -1,14 +1,84 @@
 #![doc(html_root_url = "https://docs.rs/prost/0.1.1")]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+// The runtime is written against `core` and `alloc`, although the `bytes`
+// dependency still requires `std`. With `std`, the `alloc` module below stands
+// in for the `alloc` crate, which requires Rust 1.36. `alloc` is re-exported
+// for use by derived code.
+#[cfg(not(feature = "std"))]
+#[doc(hidden)]
+#[macro_use]
+pub extern crate alloc;
 extern crate byteorder;
 extern crate bytes;
+#[cfg(feature = "std")]
+extern crate core;
+#[cfg(feature = "tokio-io")]
+extern crate tokio_io;
 
 #[cfg(test)]
 #[macro_use]
 extern crate quickcheck;
 
+mod bytes_string;
+#[cfg(feature = "tokio-io")]
+mod codec;
+#[cfg(feature = "std")]
+mod delimited;
+mod error;
+mod lazy;
 mod message;
+mod name;
+mod unknown;
 
 pub mod encoding;
+pub mod extension;
+pub mod field_mask;
+
+/// The `std` re-exports of the `alloc` modules used by `prost` and derived
+/// code.
+#[cfg(feature = "std")]
+#[doc(hidden)]
+pub mod alloc {
+    pub use std::{
+        boxed,
+        collections,
+        string,
+        vec,
+    };
+}
 
-pub use message::Message;
+pub use bytes_string::BytesString;
+#[cfg(feature = "tokio-io")]
+pub use codec::MessageCodec;
+#[cfg(feature = "std")]
+pub use delimited::{
+    DelimitedReader,
+    DelimitedWriter,
+};
+pub use encoding::DecodeOptions;
+pub use error::{
+    DecodeError,
+    DecodeErrorKind,
+    EncodeError,
+};
+pub use extension::{
+    ExtendableMessage,
+    Extension,
+    ExtensionRegistry,
+    ExtensionSet,
+};
+pub use field_mask::{
+    FieldMaskTree,
+    MaskableMessage,
+};
+pub use lazy::Lazy;
+pub use message::{
+    BorrowedMessage,
+    Message,
+};
+pub use name::Name;
+pub use unknown::{
+    UnknownField,
+    UnknownFieldSet,
+};
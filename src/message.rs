@@ -1,43 +1,100 @@
-use std::fmt::Debug;
-use std::io::Result;
-use std::usize;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::usize;
 
 use bytes::{
     Buf,
     BufMut,
+    Bytes,
+    BytesMut,
     Take,
 };
 
 use encoding::*;
+use DecodeError;
+use DecodeErrorKind;
+use EncodeError;
 
 /// A Protocol Buffers message.
 pub trait Message: Debug + Default + PartialEq /*+ PartialOrd*/ + Send + Sync {
 
     /// Encodes the message, and writes it to the buffer. An error will be
     /// returned if the buffer does not have sufficient capacity.
-    fn encode<B>(&self, buf: &mut B) -> Result<()> where B: BufMut {
-        let len = self.encoded_len();
-        if len > buf.remaining_mut() {
-            return Err(invalid_input("failed to encode message: insufficient buffer capacity"));
+    fn encode<B>(&self, buf: &mut B) -> Result<(), EncodeError> where B: BufMut {
+        let mut cache = SizeCache::new();
+        let required = self.encoded_len_cached(&mut cache);
+        let remaining = buf.remaining_mut();
+        if required > remaining {
+            return Err(EncodeError::new(required, remaining));
         }
 
-        self.encode_raw(buf);
+        self.encode_raw_cached(buf, &mut cache);
         Ok(())
     }
 
     /// Encodes the message, and writes it with a length-delimiter prefix to
     /// the buffer. An error will be returned if the buffer does not have
     /// sufficient capacity.
-    fn encode_length_delimited<B>(&self, buf: &mut B) -> Result<()> where B: BufMut {
-        let len = self.encoded_len();
-        if len + encoded_len_varint(len as u64) > buf.remaining_mut() {
-            return Err(invalid_input("failed to encode message: insufficient buffer capacity"));
+    fn encode_length_delimited<B>(&self, buf: &mut B) -> Result<(), EncodeError> where B: BufMut {
+        let mut cache = SizeCache::new();
+        let len = self.encoded_len_cached(&mut cache);
+        let required = len + encoded_len_varint(len as u64);
+        let remaining = buf.remaining_mut();
+        if required > remaining {
+            return Err(EncodeError::new(required, remaining));
         }
         encode_varint(len as u64, buf);
-        self.encode_raw(buf);
+        self.encode_raw_cached(buf, &mut cache);
+        Ok(())
+    }
+
+    /// Encodes the message to a newly allocated `Vec<u8>`.
+    fn encode_to_vec(&self) -> Vec<u8> {
+        let mut cache = SizeCache::new();
+        let mut buf = Vec::with_capacity(self.encoded_len_cached(&mut cache));
+        self.encode_raw_cached(&mut buf, &mut cache);
+        buf
+    }
+
+    /// Encodes the message deterministically, and writes it to the buffer. An
+    /// error will be returned if the buffer does not have sufficient capacity.
+    ///
+    /// Map entries are encoded in key order instead of hash order, including
+    /// in nested messages, so equal messages always encode to the same bytes.
+    /// `Lazy` fields are decoded and re-encoded, unless they fail to decode,
+    /// in which case their stored bytes are written. Unknown and extension
+    /// fields are written as they were decoded.
+    fn encode_deterministic<B>(&self, buf: &mut B) -> Result<(), EncodeError> where B: BufMut {
+        let mut cache = SizeCache::deterministic();
+        let required = self.encoded_len_cached(&mut cache);
+        let remaining = buf.remaining_mut();
+        if required > remaining {
+            return Err(EncodeError::new(required, remaining));
+        }
+
+        self.encode_raw_cached(buf, &mut cache);
         Ok(())
     }
 
+    /// Encodes the message deterministically to a newly allocated `Vec<u8>`.
+    ///
+    /// See `Message::encode_deterministic`.
+    fn encode_to_vec_deterministic(&self) -> Vec<u8> {
+        let mut cache = SizeCache::deterministic();
+        let mut buf = Vec::with_capacity(self.encoded_len_cached(&mut cache));
+        self.encode_raw_cached(&mut buf, &mut cache);
+        buf
+    }
+
+    /// Encodes the message to a newly allocated `Bytes` buffer.
+    fn encode_to_bytes(&self) -> Bytes {
+        let mut cache = SizeCache::new();
+        let mut buf = BytesMut::with_capacity(self.encoded_len_cached(&mut cache));
+        self.encode_raw_cached(&mut buf, &mut cache);
+        buf.freeze()
+    }
+
     /// Encodes the message, writing it to the buffer.
     ///
     /// This method will panic if the buffer has insufficient capacity.
@@ -46,36 +103,159 @@ pub trait Message: Debug + Default + PartialEq /*+ PartialOrd*/ + Send + Sync {
     #[doc(hidden)]
     fn encode_raw<B>(&self, buf: &mut B) where B: BufMut;
 
+    /// Encodes the message, writing it to the buffer, with the lengths of
+    /// nested messages read from a cache filled by `encoded_len_cached`.
+    #[doc(hidden)]
+    fn encode_raw_cached<B>(&self, buf: &mut B, _cache: &mut SizeCache) where B: BufMut {
+        self.encode_raw(buf)
+    }
+
     /// Decodes an instance of the message from the buffer.
     /// The entire buffer will be consumed.
-    fn decode<B>(buf: &mut Take<B>) -> Result<Self> where B: Buf, Self: Default {
+    fn decode<B>(buf: &mut Take<B>) -> Result<Self, DecodeError> where B: Buf, Self: Default {
         let mut message = Self::default();
         Self::merge(&mut message, buf).map(|_| message)
     }
 
+    /// Decodes an instance of the message from the buffer, using the provided
+    /// context. The entire buffer will be consumed.
+    ///
+    /// Use this instead of `Message::decode` to decode with a recursion limit
+    /// other than the default.
+    fn decode_with_context<B>(buf: &mut Take<B>, ctx: DecodeContext) -> Result<Self, DecodeError>
+    where B: Buf, Self: Default {
+        let mut message = Self::default();
+        message.merge_with_context(buf, ctx)?;
+        Ok(message)
+    }
+
+    /// Decodes an instance of the message from the buffer, enforcing the
+    /// size, length and recursion limits of `options`. The entire buffer will
+    /// be consumed.
+    ///
+    /// Use this instead of `Message::decode` to decode untrusted input.
+    fn decode_with_options<B>(buf: &mut Take<B>, options: &DecodeOptions) -> Result<Self, DecodeError>
+    where B: Buf, Self: Default {
+        options.check_message_size(buf.remaining())?;
+        Self::decode_with_context(buf, DecodeContext::with_options(options))
+    }
+
+    /// Decodes an instance of the message from a `Bytes` buffer. The entire
+    /// buffer will be consumed.
+    ///
+    /// `Bytes` and `BytesString` fields are sliced from the buffer instead of
+    /// being copied.
+    fn decode_bytes(buf: Bytes) -> Result<Self, DecodeError> where Self: Default {
+        let len = buf.len();
+        let ctx = DecodeContext::default().with_source(&buf);
+        Self::decode_with_context(&mut Buf::take(SliceBuf::new(&buf), len), ctx)
+    }
+
+    /// Decodes an instance of the message from a `Bytes` buffer, enforcing
+    /// the limits of `options`. The entire buffer will be consumed.
+    ///
+    /// See `Message::decode_bytes` and `Message::decode_with_options`.
+    fn decode_bytes_with_options(buf: Bytes, options: &DecodeOptions) -> Result<Self, DecodeError>
+    where Self: Default {
+        options.check_message_size(buf.len())?;
+        let len = buf.len();
+        let ctx = DecodeContext::with_options(options).with_source(&buf);
+        Self::decode_with_context(&mut Buf::take(SliceBuf::new(&buf), len), ctx)
+    }
+
+    /// Decodes an instance of the message from the slice. The entire slice
+    /// will be consumed.
+    fn decode_from_slice(buf: &[u8]) -> Result<Self, DecodeError> where Self: Default {
+        Self::decode(&mut Buf::take(SliceBuf::new(buf), buf.len()))
+    }
+
+    /// Decodes an instance of the message from the slice, enforcing the
+    /// limits of `options`. The entire slice will be consumed.
+    fn decode_from_slice_with_options(buf: &[u8], options: &DecodeOptions) -> Result<Self, DecodeError>
+    where Self: Default {
+        Self::decode_with_options(&mut Buf::take(SliceBuf::new(buf), buf.len()), options)
+    }
+
+    /// Decodes a length-delimited instance of the message from the start of
+    /// the slice. Bytes following the message are ignored.
+    fn decode_length_delimited_from_slice(buf: &[u8]) -> Result<Self, DecodeError> where Self: Default {
+        Self::decode_length_delimited(&mut SliceBuf::new(buf))
+    }
+
     /// Decodes a length-delimited instance of the message from the buffer.
-    fn decode_length_delimited<B>(buf: &mut B) -> Result<Self> where B: Buf, Self: Default {
+    fn decode_length_delimited<B>(buf: &mut B) -> Result<Self, DecodeError> where B: Buf, Self: Default {
         let mut message = Self::default();
         message.merge_length_delimited(buf)?;
         Ok(message)
     }
 
+    /// Decodes a length-delimited instance of the message from the buffer,
+    /// enforcing the limits of `options`. The message size limit is checked
+    /// against the length prefix before the message is decoded.
+    fn decode_length_delimited_with_options<B>(buf: &mut B, options: &DecodeOptions) -> Result<Self, DecodeError>
+    where B: Buf, Self: Default {
+        let len = decode_varint(buf)?;
+        if len > buf.remaining() as u64 {
+            return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
+        }
+        Self::decode_with_options(&mut buf.take(len as usize), options)
+    }
+
     /// Decodes an instance of the message from the buffer, and merges
     /// it into `self`. The entire buffer will be consumed.
-    fn merge<B>(&mut self, buf: &mut Take<B>) -> Result<()> where B: Buf;
+    fn merge<B>(&mut self, buf: &mut Take<B>) -> Result<(), DecodeError> where B: Buf {
+        self.merge_with_context(buf, DecodeContext::default())
+    }
+
+    /// Decodes an instance of the message from the slice, and merges it into
+    /// `self`. The entire slice will be consumed.
+    fn merge_from_slice(&mut self, buf: &[u8]) -> Result<(), DecodeError> {
+        self.merge(&mut Buf::take(SliceBuf::new(buf), buf.len()))
+    }
+
+    /// Decodes an instance of the message from the buffer using the provided
+    /// context, and merges it into `self`. The entire buffer will be consumed.
+    fn merge_with_context<B>(&mut self, buf: &mut Take<B>, ctx: DecodeContext) -> Result<(), DecodeError>
+    where B: Buf {
+        while buf.has_remaining() {
+            let (tag, wire_type) = decode_key(buf)?;
+            self.merge_field(tag, wire_type, buf, ctx)?;
+        }
+        Ok(())
+    }
+
+    /// Decodes a field from the buffer, and merges it into `self`.
+    ///
+    /// The field key has already been consumed from the buffer. Unknown
+    /// fields are skipped.
+    #[doc(hidden)]
+    fn merge_field<B>(&mut self,
+                      tag: u32,
+                      wire_type: WireType,
+                      buf: &mut Take<B>,
+                      ctx: DecodeContext)
+                      -> Result<(), DecodeError> where B: Buf;
 
     /// Decodes a length-delimited instance of the message from the
     /// buffer, and merges it into `self`.
-    fn merge_length_delimited<B>(&mut self, buf: &mut B) -> Result<()> where B: Buf {
+    fn merge_length_delimited<B>(&mut self, buf: &mut B) -> Result<(), DecodeError> where B: Buf {
         let len = decode_varint(buf)?;
         if len > buf.remaining() as u64 {
-            return Err(invalid_input("failed to merge message: buffer underflow"));
+            return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
         }
         self.merge(&mut buf.take(len as usize))
     }
 
     /// The encoded length of the message without a length delimiter.
     fn encoded_len(&self) -> usize;
+
+    /// The encoded length of the message without a length delimiter. The
+    /// lengths of nested messages are recorded in the cache, in the order
+    /// they are encoded.
+    #[doc(hidden)]
+    fn encoded_len_cached(&self, _cache: &mut SizeCache) -> usize {
+        self.encoded_len()
+    }
 }
 
 impl <M> Message for Box<M> where M: Message {
@@ -84,11 +264,160 @@ impl <M> Message for Box<M> where M: Message {
         (**self).encode_raw(buf)
     }
     #[inline]
-    fn merge<B>(&mut self, buf: &mut Take<B>) -> Result<()> where B: Buf {
-        (**self).merge(buf)
+    fn merge_with_context<B>(&mut self, buf: &mut Take<B>, ctx: DecodeContext) -> Result<(), DecodeError>
+    where B: Buf {
+        (**self).merge_with_context(buf, ctx)
+    }
+    #[inline]
+    fn merge_field<B>(&mut self,
+                      tag: u32,
+                      wire_type: WireType,
+                      buf: &mut Take<B>,
+                      ctx: DecodeContext)
+                      -> Result<(), DecodeError> where B: Buf {
+        (**self).merge_field(tag, wire_type, buf, ctx)
+    }
+    #[inline]
+    fn encode_raw_cached<B>(&self, buf: &mut B, cache: &mut SizeCache) where B: BufMut {
+        (**self).encode_raw_cached(buf, cache)
     }
     #[inline]
     fn encoded_len(&self) -> usize {
         (**self).encoded_len()
     }
+    #[inline]
+    fn encoded_len_cached(&self, cache: &mut SizeCache) -> usize {
+        (**self).encoded_len_cached(cache)
+    }
+}
+
+/// A Protocol Buffers message which borrows string and bytes field values
+/// from the buffer it is decoded from.
+///
+/// `BorrowedMessage` is implemented by `#[derive(Message)]` for message types
+/// with a lifetime parameter, for instance with `&'a str` and `&'a [u8]`
+/// fields. Decoding such a message does not copy string or bytes values.
+///
+/// Borrowed messages may not have map or oneof fields, and deriving
+/// `Message` for one which does fails with an error naming the field:
+///
+/// ```compile_fail
+/// #[macro_use]
+/// extern crate prost_derive;
+/// extern crate prost;
+///
+/// #[derive(Clone, Debug, PartialEq, Message)]
+/// pub struct Foo<'a> {
+///     #[prost(string, tag="1")]
+///     pub name: &'a str,
+///     #[prost(map="string, string", tag="2")]
+///     pub attributes: ::std::collections::HashMap<String, String>,
+/// }
+/// # fn main() {}
+/// ```
+///
+/// ```compile_fail
+/// #[macro_use]
+/// extern crate prost_derive;
+/// extern crate prost;
+///
+/// #[derive(Clone, Debug, PartialEq, Oneof)]
+/// pub enum Value {
+///     #[prost(int32, tag="2")]
+///     Int(i32),
+/// }
+///
+/// #[derive(Clone, Debug, PartialEq, Message)]
+/// pub struct Foo<'a> {
+///     #[prost(string, tag="1")]
+///     pub name: &'a str,
+///     #[prost(oneof="Value", tags="2")]
+///     pub value: Option<Value>,
+/// }
+/// # fn main() {}
+/// ```
+pub trait BorrowedMessage<'a>: Debug + Default + PartialEq + Send + Sync {
+
+    /// Encodes the message, and writes it to the buffer. An error will be
+    /// returned if the buffer does not have sufficient capacity.
+    fn encode<B>(&self, buf: &mut B) -> Result<(), EncodeError> where B: BufMut {
+        let required = self.encoded_len();
+        let remaining = buf.remaining_mut();
+        if required > remaining {
+            return Err(EncodeError::new(required, remaining));
+        }
+
+        self.encode_raw(buf);
+        Ok(())
+    }
+
+    /// Encodes the message, and writes it with a length-delimiter prefix to
+    /// the buffer. An error will be returned if the buffer does not have
+    /// sufficient capacity.
+    fn encode_length_delimited<B>(&self, buf: &mut B) -> Result<(), EncodeError> where B: BufMut {
+        let len = self.encoded_len();
+        let required = len + encoded_len_varint(len as u64);
+        let remaining = buf.remaining_mut();
+        if required > remaining {
+            return Err(EncodeError::new(required, remaining));
+        }
+        encode_varint(len as u64, buf);
+        self.encode_raw(buf);
+        Ok(())
+    }
+
+    /// Encodes the message, writing it to the buffer.
+    ///
+    /// This method will panic if the buffer has insufficient capacity.
+    ///
+    /// Prefer using `BorrowedMessage::encode`.
+    #[doc(hidden)]
+    fn encode_raw<B>(&self, buf: &mut B) where B: BufMut;
+
+    /// Decodes an instance of the message from the slice. The entire slice
+    /// will be consumed.
+    fn decode(buf: &'a [u8]) -> Result<Self, DecodeError> {
+        Self::decode_with_context(buf, DecodeContext::default())
+    }
+
+    /// Decodes an instance of the message from the slice, using the provided
+    /// context. The entire slice will be consumed.
+    fn decode_with_context(buf: &'a [u8], ctx: DecodeContext) -> Result<Self, DecodeError> {
+        let mut message = Self::default();
+        message.merge_with_context(buf, ctx)?;
+        Ok(message)
+    }
+
+    /// Decodes an instance of the message from the slice, and merges it into
+    /// `self`. The entire slice will be consumed.
+    fn merge(&mut self, buf: &'a [u8]) -> Result<(), DecodeError> {
+        self.merge_with_context(buf, DecodeContext::default())
+    }
+
+    /// Decodes an instance of the message from the slice using the provided
+    /// context, and merges it into `self`. The entire slice will be consumed.
+    fn merge_with_context(&mut self, buf: &'a [u8], ctx: DecodeContext) -> Result<(), DecodeError> {
+        let len = buf.len();
+        let mut buf = Buf::take(SliceBuf::new(buf), len);
+        while buf.has_remaining() {
+            let (tag, wire_type) = decode_key(&mut buf)?;
+            self.merge_field(tag, wire_type, &mut buf, ctx)?;
+        }
+        Ok(())
+    }
+
+    /// Decodes a field from the buffer, and merges it into `self`.
+    ///
+    /// The field key has already been consumed from the buffer. Unknown
+    /// fields are skipped.
+    #[doc(hidden)]
+    fn merge_field<B>(&mut self,
+                      tag: u32,
+                      wire_type: WireType,
+                      buf: &mut Take<B>,
+                      ctx: DecodeContext)
+                      -> Result<(), DecodeError> where B: borrowed::BorrowBuf<'a>;
+
+    /// The encoded length of the message without a length delimiter.
+    fn encoded_len(&self) -> usize;
 }
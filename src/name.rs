@@ -0,0 +1,37 @@
+use alloc::string::{
+    String,
+    ToString,
+};
+
+use Message;
+
+/// A message type with a fully qualified Protobuf name.
+///
+/// `Name` is implemented by generated messages, and is used to build the type
+/// URLs identifying the contents of `google.protobuf.Any` values.
+pub trait Name: Message {
+
+    /// The name of the message type, including the names of any enclosing
+    /// message types, e.g. `Timestamp`, or `Outer.Inner`.
+    const NAME: &'static str;
+
+    /// The Protobuf package of the message type, e.g. `google.protobuf`, or
+    /// an empty string if the type is not in a package.
+    const PACKAGE: &'static str;
+
+    /// Returns the fully qualified name of the message type, e.g.
+    /// `google.protobuf.Timestamp`.
+    fn full_name() -> String {
+        if Self::PACKAGE.is_empty() {
+            Self::NAME.to_string()
+        } else {
+            format!("{}.{}", Self::PACKAGE, Self::NAME)
+        }
+    }
+
+    /// Returns the type URL of the message type, e.g.
+    /// `type.googleapis.com/google.protobuf.Timestamp`.
+    fn type_url() -> String {
+        format!("type.googleapis.com/{}", Self::full_name())
+    }
+}
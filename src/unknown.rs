@@ -0,0 +1,182 @@
+use alloc::vec::Vec;
+use core::slice;
+
+use bytes::{
+    Buf,
+    BufMut,
+    LittleEndian,
+};
+
+use encoding::*;
+use DecodeError;
+use DecodeErrorKind;
+
+/// The value of a field which was not recognized while decoding a message.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UnknownField {
+    Varint(u64),
+    SixtyFourBit(u64),
+    LengthDelimited(Vec<u8>),
+    Group(UnknownFieldSet),
+    ThirtyTwoBit(u32),
+}
+
+impl UnknownField {
+    /// Returns the wire type of the field.
+    pub fn wire_type(&self) -> WireType {
+        match *self {
+            UnknownField::Varint(..) => WireType::Varint,
+            UnknownField::SixtyFourBit(..) => WireType::SixtyFourBit,
+            UnknownField::LengthDelimited(..) => WireType::LengthDelimited,
+            UnknownField::Group(..) => WireType::StartGroup,
+            UnknownField::ThirtyTwoBit(..) => WireType::ThirtyTwoBit,
+        }
+    }
+}
+
+/// A set of fields which were not recognized while decoding a message, in the
+/// order they were decoded.
+///
+/// Messages which contain an `UnknownFieldSet` member annotated with
+/// `#[prost(unknown_fields)]` retain unknown fields when decoding, and write
+/// them back out when encoding.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UnknownFieldSet {
+    fields: Vec<(u32, UnknownField)>,
+}
+
+impl UnknownFieldSet {
+
+    /// Creates an empty set of unknown fields.
+    pub fn new() -> UnknownFieldSet {
+        UnknownFieldSet::default()
+    }
+
+    /// Returns `true` if the set contains no fields.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Returns the number of fields in the set.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Returns an iterator over the `(tag, value)` pairs in the set.
+    pub fn iter(&self) -> slice::Iter<(u32, UnknownField)> {
+        self.fields.iter()
+    }
+
+    /// Appends a field to the set.
+    pub fn push(&mut self, tag: u32, field: UnknownField) {
+        debug_assert!(tag >= MIN_TAG && tag <= MAX_TAG);
+        self.fields.push((tag, field));
+    }
+
+    /// Removes all fields from the set.
+    pub fn clear(&mut self) {
+        self.fields.clear();
+    }
+
+    /// Retains only the fields for which the predicate returns `true`.
+    pub fn retain<F>(&mut self, mut f: F) where F: FnMut(u32, &UnknownField) -> bool {
+        self.fields.retain(|&(tag, ref field)| f(tag, field));
+    }
+
+    /// Shortens the set, keeping the first `len` fields.
+    pub fn truncate(&mut self, len: usize) {
+        self.fields.truncate(len);
+    }
+
+    /// Decodes an unknown field from the buffer, and appends it to the set.
+    /// The field key has already been consumed from the buffer.
+    #[doc(hidden)]
+    pub fn merge_field<B>(&mut self,
+                          tag: u32,
+                          wire_type: WireType,
+                          buf: &mut B,
+                          ctx: DecodeContext)
+                          -> Result<(), DecodeError> where B: Buf {
+        let field = match wire_type {
+            WireType::Varint => UnknownField::Varint(decode_varint(buf)?),
+            WireType::SixtyFourBit => {
+                if buf.remaining() < 8 {
+                    return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
+                }
+                UnknownField::SixtyFourBit(buf.get_u64::<LittleEndian>())
+            },
+            WireType::ThirtyTwoBit => {
+                if buf.remaining() < 4 {
+                    return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
+                }
+                UnknownField::ThirtyTwoBit(buf.get_u32::<LittleEndian>())
+            },
+            WireType::LengthDelimited => {
+                let len = decode_varint(buf)?;
+                ctx.check_length(len)?;
+                if len > buf.remaining() as u64 {
+                    return Err(DecodeError::new(DecodeErrorKind::BufferUnderflow));
+                }
+                let mut value = vec![0; len as usize];
+                buf.copy_to_slice(&mut value);
+                UnknownField::LengthDelimited(value)
+            },
+            WireType::StartGroup => {
+                let ctx = ctx.enter_recursion()?;
+                let mut group = UnknownFieldSet::new();
+                loop {
+                    let (inner_tag, inner_wire_type) = decode_key(buf)?;
+                    if inner_wire_type == WireType::EndGroup {
+                        if inner_tag != tag {
+                            return Err(DecodeError::new(DecodeErrorKind::UnexpectedEndGroup));
+                        }
+                        break;
+                    }
+                    group.merge_field(inner_tag, inner_wire_type, buf, ctx)?;
+                }
+                UnknownField::Group(group)
+            },
+            WireType::EndGroup => {
+                return Err(DecodeError::new(DecodeErrorKind::UnexpectedEndGroup));
+            },
+        };
+        self.fields.push((tag, field));
+        Ok(())
+    }
+
+    /// Encodes the fields in the set, writing them to the buffer.
+    #[doc(hidden)]
+    pub fn encode_raw<B>(&self, buf: &mut B) where B: BufMut {
+        for &(tag, ref field) in &self.fields {
+            encode_key(tag, field.wire_type(), buf);
+            match *field {
+                UnknownField::Varint(value) => encode_varint(value, buf),
+                UnknownField::SixtyFourBit(value) => buf.put_u64::<LittleEndian>(value),
+                UnknownField::ThirtyTwoBit(value) => buf.put_u32::<LittleEndian>(value),
+                UnknownField::LengthDelimited(ref value) => {
+                    encode_varint(value.len() as u64, buf);
+                    buf.put_slice(value);
+                },
+                UnknownField::Group(ref group) => {
+                    group.encode_raw(buf);
+                    encode_key(tag, WireType::EndGroup, buf);
+                },
+            }
+        }
+    }
+
+    /// Returns the encoded length of the fields in the set.
+    pub fn encoded_len(&self) -> usize {
+        self.fields.iter().map(|&(tag, ref field)| {
+            key_len(tag) + match *field {
+                UnknownField::Varint(value) => encoded_len_varint(value),
+                UnknownField::SixtyFourBit(..) => 8,
+                UnknownField::ThirtyTwoBit(..) => 4,
+                UnknownField::LengthDelimited(ref value) => {
+                    encoded_len_varint(value.len() as u64) + value.len()
+                },
+                UnknownField::Group(ref group) => group.encoded_len() + key_len(tag),
+            }
+        }).sum()
+    }
+}
@@ -1,7 +1,13 @@
 extern crate prost_build;
 
 fn main() {
-    prost_build::compile_protos(&["src/test_messages_proto3.proto"],
-                                &["src"],
-                                None).unwrap();
+    let mut config = prost_build::Config::new();
+    config.json(true);
+    config.btree_map(&[".protobuf_test_messages.proto3.TestAllTypes.map_string_string",
+                       ".protobuf_test_messages.proto3.TestAllTypes.map_string_nested_enum"]);
+    config.lazy(&[".protobuf_test_messages.proto3.TestAllTypes.optional_foreign_message"]);
+    prost_build::compile_protos_with_config(&config,
+                                            &["src/test_messages_proto3.proto"],
+                                            &["src"],
+                                            None).unwrap();
 }
@@ -1,6 +1,7 @@
 extern crate bytes;
-extern crate ieee754;
 extern crate prost;
+extern crate prost_reflect;
+extern crate prost_types;
 #[macro_use] extern crate prost_derive;
 
 pub mod protobuf_test_messages {
@@ -10,23 +11,24 @@ pub mod protobuf_test_messages {
     }
 }
 
-pub mod google {
-    pub mod protobuf {
-        include!(concat!(env!("OUT_DIR"), "/protobuf.rs"));
-    }
-}
-
-use std::collections::HashMap;
 use std::io::{
     Cursor,
     Error,
     ErrorKind,
-    Result,
 };
 
 use bytes::Buf;
-use ieee754::Ieee754;
-use prost::Message;
+use prost::{
+    DecodeError,
+    Message,
+};
+
+use prost_reflect::diff;
+use prost_reflect::dynamic::{
+    DynamicMessage,
+    MessageDescriptor,
+};
+use prost_reflect::reflect;
 
 use protobuf_test_messages::proto3;
 
@@ -35,7 +37,7 @@ pub enum RoundtripResult {
     Ok(Vec<u8>),
     /// The data could not be decoded. This could indicate a bug in prost,
     /// or it could indicate that the input was bogus.
-    DecodeError(Error),
+    DecodeError(DecodeError),
     /// Re-encoding or validating the data failed.  This indicates a bug in `prost` (or in the
     /// test).
     Error(Error),
@@ -52,7 +54,7 @@ impl RoundtripResult {
     }
 
     /// Unwrap the roundtrip result. Panics if the result was a validation or re-encoding error.
-    pub fn unwrap_error(self) -> Result<Vec<u8>> {
+    pub fn unwrap_error(self) -> Result<Vec<u8>, DecodeError> {
         match self {
             RoundtripResult::Ok(buf) => Ok(buf),
             RoundtripResult::DecodeError(error) => Err(error),
@@ -77,13 +79,13 @@ pub fn test_all_types_proto3_roundtrip(data: &[u8]) -> RoundtripResult {
 
     let mut buf = Vec::new();
     if let Err(error) = all_types.encode(&mut buf) {
-        return RoundtripResult::Error(error);
+        return RoundtripResult::Error(error.into());
     }
     assert_eq!(encoded_len, buf.len());
 
     let roundtrip = match proto3::TestAllTypes::decode(&mut Buf::take(Cursor::new(&buf), encoded_len)) {
         Ok(roundtrip) => roundtrip,
-        Err(error) => return RoundtripResult::Error(error),
+        Err(error) => return RoundtripResult::Error(error.into()),
     };
 
     /*
@@ -93,172 +95,39 @@ pub fn test_all_types_proto3_roundtrip(data: &[u8]) -> RoundtripResult {
     eprintln!("a: {:?}\nb: {:?}", all_types, roundtrip);
     */
 
-    if !all_types_proto3_eq(all_types, roundtrip) {
+    // Floats are compared bitwise, so that NaNs roundtrip.
+    let differences = diff::diff(&all_types, &roundtrip);
+    if !differences.is_empty() {
+        let differences = differences.iter().map(ToString::to_string).collect::<Vec<_>>();
         return RoundtripResult::Error(Error::new(ErrorKind::Other,
-                                                 "roundtrip value does not equal original"));
-    }
-
-    RoundtripResult::Ok(buf)
-}
-
-/// Test that a pair of `TestAllTypes` messages are bit-for-bit equivalent.
-fn all_types_proto3_eq(mut a: proto3::TestAllTypes,
-                       mut b: proto3::TestAllTypes) -> bool {
-    use proto3::test_all_types::OneofField::*;
-
-
-    // First, check that all floating point fields are bit-for-bit equivalent.
-    fn float_eq(a: f32, b: f32) -> bool {
-        a == b || a.bits() == b.bits()
-    }
-    fn double_eq(a: f64, b: f64) -> bool {
-        a == b || a.bits() == b.bits()
-    }
-
-    // optional_[float,double]
-    if !float_eq(a.optional_float, b.optional_float) { return false; }
-    a.optional_float = 0.0;
-    b.optional_float = 0.0;
-    if !double_eq(a.optional_double, b.optional_double) { return false; }
-    a.optional_double = 0.0;
-    b.optional_double = 0.0;
-
-    // repeated_[float,double]
-    if a.repeated_float.len() != b.repeated_float.len() ||
-       a.repeated_float.iter().zip(b.repeated_float.iter()).any(|(&a, &b)| !float_eq(a, b)) {
-        return false;
-    }
-    a.repeated_float.clear();
-    b.repeated_float.clear();
-    if a.repeated_double.len() != b.repeated_double.len() ||
-       a.repeated_double.iter().zip(b.repeated_double.iter()).any(|(&a, &b)| !double_eq(a, b)) {
-        return false;
-    }
-    a.repeated_double.clear();
-    b.repeated_double.clear();
-
-    // map_int32_[float,double]
-    let mut a_entries = a.map_int32_float.into_iter().collect::<Vec<_>>();
-    let mut b_entries = b.map_int32_float.into_iter().collect::<Vec<_>>();
-    a_entries.sort_by_key(|&(k, _)| k);
-    b_entries.sort_by_key(|&(k, _)| k);
-    if a_entries.len() != b_entries.len() ||
-        a_entries.iter().zip(b_entries.iter()).any(|(&(ak, av), &(bk, bv))| ak != bk || !float_eq(av, bv)) {
-        return false;
-    }
-    a.map_int32_float = HashMap::new();
-    b.map_int32_float = HashMap::new();
-    let mut a_entries = a.map_int32_double.into_iter().collect::<Vec<_>>();
-    let mut b_entries = b.map_int32_double.into_iter().collect::<Vec<_>>();
-    a_entries.sort_by_key(|&(k, _)| k);
-    b_entries.sort_by_key(|&(k, _)| k);
-    if a_entries.len() != b_entries.len() ||
-        a_entries.iter().zip(b_entries.iter()).any(|(&(ak, av), &(bk, bv))| ak != bk || !double_eq(av, bv)) {
-        return false;
-    }
-    a.map_int32_double = HashMap::new();
-    b.map_int32_double = HashMap::new();
-
-    // optional_[float,double]_wrapper
-    if match (a.optional_float_wrapper.take(), b.optional_float_wrapper.take()) {
-        (Some(a), Some(b)) => !float_eq(a.value, b.value),
-        (None, None) => false,
-        _ => true,
-    } { return false; }
-    if match (a.optional_double_wrapper.take(), b.optional_double_wrapper.take()) {
-        (Some(a), Some(b)) => !double_eq(a.value, b.value),
-        (None, None) => false,
-        _ => true,
-    } { return false; }
-
-    // repeated_[float,double]_wrapper
-    if a.repeated_float_wrapper.len() != b.repeated_float_wrapper.len() ||
-       a.repeated_float_wrapper.iter().zip(b.repeated_float_wrapper.iter()).any(|(a, b)| !float_eq(a.value, b.value)) {
-        return false;
-    }
-    a.repeated_float_wrapper.clear();
-    b.repeated_float_wrapper.clear();
-    if a.repeated_double_wrapper.len() != b.repeated_double_wrapper.len() ||
-       a.repeated_double_wrapper.iter().zip(b.repeated_double_wrapper.iter()).any(|(a, b)| !double_eq(a.value, b.value)) {
-        return false;
+                                                 format!("roundtrip value does not equal original: {}",
+                                                         differences.join(", "))));
     }
-    a.repeated_double_wrapper.clear();
-    b.repeated_double_wrapper.clear();
 
-    // oneof_[float,double]
-    if let (&Some(OneofFloat(av)), &Some(OneofFloat(bv))) = (&a.oneof_field, &b.oneof_field) {
-        if !float_eq(av, bv) {
-            return false;
-        }
-        a.oneof_field = None;
-        b.oneof_field = None;
-    }
-    if let (&Some(OneofDouble(av)), &Some(OneofDouble(bv))) = (&a.oneof_field, &b.oneof_field) {
-        if !double_eq(av, bv) {
-            return false;
-        }
-        a.oneof_field = None;
-        b.oneof_field = None;
-    }
-
-    // Next, compare all (co)recursive nested fields.
-
-    fn optional_all_types_proto3_eq(a: Option<Box<proto3::TestAllTypes>>,
-                                    b: Option<Box<proto3::TestAllTypes>>) -> bool {
-        match (a, b) {
-            (Some(a), Some(b)) => all_types_proto3_eq(*a, *b),
-            (None, None) => true,
-            _ => false,
-        }
-    }
-
-    // recursive_message
-    if !optional_all_types_proto3_eq(a.recursive_message.take(), b.recursive_message.take()) {
-        return false;
-    }
-
-    // optional_nested_message
-    if !optional_all_types_proto3_eq(a.optional_nested_message.take().and_then(|mut a| a.corecursive.take()),
-                                     b.optional_nested_message.take().and_then(|mut b| b.corecursive.take())) {
-        return false;
-    }
-
-    // repeated_nested_message
-    if a.repeated_nested_message.len() != b.repeated_nested_message.len() ||
-       a.repeated_nested_message.iter_mut().map(|msg| msg.corecursive.take())
-        .zip(b.repeated_nested_message.iter_mut().map(|msg| msg.corecursive.take()))
-        .any(|(a, b)| !optional_all_types_proto3_eq(a, b)) {
-        return false;
-    }
-
-    // map_string_nested_message
-    let mut a_entries = a.map_string_nested_message.iter_mut().map(|(k, ref mut v)| (k.clone(), v.corecursive.take())).collect::<Vec<_>>();
-    let mut b_entries = b.map_string_nested_message.iter_mut().map(|(k, ref mut v)| (k.clone(), v.corecursive.take())).collect::<Vec<_>>();
-    a_entries.sort_by_key(|&(ref k, _)| k.clone());
-    b_entries.sort_by_key(|&(ref k, _)| k.clone());
-    if a_entries.len() != b_entries.len() ||
-       a_entries.into_iter().zip(b_entries.into_iter()).any(|((ak, av), (bk, bv))| ak != bk || !optional_all_types_proto3_eq(av, bv)) {
-        return false;
-    }
-
-    // oneof_nested_message
-    fn get_nested_message(oneof: &mut Option<proto3::test_all_types::OneofField>) -> Option<Box<proto3::TestAllTypes>> {
-        match *oneof {
-            Some(OneofNestedMessage(ref mut msg)) => msg.corecursive.take(),
-            _ => None,
-        }
-    }
-    if !optional_all_types_proto3_eq(get_nested_message(&mut a.oneof_field),
-                                     get_nested_message(&mut b.oneof_field)) {
-        return false;
+    // The message should also re-encode identically when decoded dynamically
+    // from its descriptor.
+    let pool = reflect::descriptor_pool(proto3::FILE_DESCRIPTOR_SET);
+    let descriptor = MessageDescriptor::new(pool, "protobuf_test_messages.proto3.TestAllTypes").unwrap();
+    let dynamic = match DynamicMessage::decode(descriptor, &buf) {
+        Ok(dynamic) => dynamic,
+        Err(error) => return RoundtripResult::Error(error.into()),
+    };
+    if dynamic.encoded_len() != buf.len() || dynamic.encode_to_vec() != buf {
+        return RoundtripResult::Error(Error::new(ErrorKind::Other,
+                                                 "dynamic roundtrip does not equal original"));
     }
 
-    (a == b)
+    RoundtripResult::Ok(buf)
 }
 
 #[cfg(test)]
 mod tests {
 
+    use std::collections::HashMap;
+
+    use prost_reflect::json::JsonMessage;
+    use prost_reflect::text_format;
+
     use super::*;
 
     #[test]
@@ -287,4 +156,557 @@ mod tests {
             test_all_types_proto3_roundtrip(msg).unwrap();
         }
     }
+
+    #[test]
+    fn test_dynamic_message() {
+        use prost_reflect::dynamic::{
+            SetFieldError,
+            Value,
+        };
+        use proto3::test_all_types::{
+            NestedEnum,
+            NestedMessage,
+            OneofField,
+        };
+
+        let mut all_types = proto3::TestAllTypes::default();
+        all_types.optional_int32 = -5;
+        all_types.optional_string = "foo".to_string();
+        all_types.optional_nested_enum = NestedEnum::Baz as i32;
+        all_types.optional_nested_message = Some(Box::new(NestedMessage {
+            a: 7,
+            corecursive: Some(Box::new(proto3::TestAllTypes {
+                optional_bool: true,
+                ..Default::default()
+            })),
+        }));
+        all_types.repeated_int64 = vec![1, -1, 300];
+        all_types.repeated_string = vec!["a".to_string(), String::new()];
+        all_types.map_int32_int32.insert(0, 4);
+        all_types.map_int32_int32.insert(3, 0);
+        all_types.map_string_nested_message.insert("x".to_string(), NestedMessage::default());
+        all_types.oneof_field = Some(OneofField::OneofUint32(0));
+        let buf = test_all_types_proto3_roundtrip(&all_types.encode_to_vec()).unwrap();
+
+        let pool = reflect::descriptor_pool(proto3::FILE_DESCRIPTOR_SET);
+        let descriptor = MessageDescriptor::new(pool.clone(), ".protobuf_test_messages.proto3.TestAllTypes").unwrap();
+        let mut dynamic = DynamicMessage::decode(descriptor.clone(), &buf).unwrap();
+
+        assert_eq!(Some(&Value::I32(-5)), dynamic.get("optional_int32"));
+        assert_eq!(Some(&Value::String("foo".to_string())), dynamic.get("optional_string"));
+        assert_eq!(Some(&Value::Enum(NestedEnum::Baz as i32)), dynamic.get("optional_nested_enum"));
+        assert_eq!(Some(&Value::List(vec![Value::I64(1), Value::I64(-1), Value::I64(300)])),
+                   dynamic.get("repeated_int64"));
+        assert_eq!(Some(&Value::U32(0)), dynamic.get("oneof_uint32"));
+        assert!(!dynamic.has("optional_bytes"));
+        match dynamic.get("optional_nested_message") {
+            Some(&Value::Message(ref nested)) => {
+                assert_eq!("protobuf_test_messages.proto3.TestAllTypes.NestedMessage",
+                           nested.descriptor().full_name());
+                assert_eq!(Some(&Value::I32(7)), nested.get("a"));
+            },
+            value => panic!("unexpected value: {:?}", value),
+        }
+        match dynamic.get("map_int32_int32") {
+            Some(&Value::Map(ref entries)) => {
+                assert_eq!(2, entries.len());
+                assert!(entries.contains(&(Value::I32(0), Value::I32(4))));
+                assert!(entries.contains(&(Value::I32(3), Value::I32(0))));
+            },
+            value => panic!("unexpected value: {:?}", value),
+        }
+
+        // Setting a oneof field clears the other fields of the oneof.
+        assert_eq!(Ok(None), dynamic.set("oneof_string", Value::String("bar".to_string())));
+        assert!(!dynamic.has("oneof_uint32"));
+        assert_eq!(Err(SetFieldError::InvalidValue("optional_int32".to_string())),
+                   dynamic.set("optional_int32", Value::I64(1)));
+        assert_eq!(Err(SetFieldError::NoSuchField("missing".to_string())),
+                   dynamic.set("missing", Value::I32(1)));
+        assert_eq!(Ok(Some(Value::I32(-5))), dynamic.set("optional_int32", Value::I32(0)));
+        assert_eq!(Some(Value::String("foo".to_string())), dynamic.clear("optional_string"));
+
+        all_types.optional_int32 = 0;
+        all_types.optional_string = String::new();
+        all_types.oneof_field = Some(OneofField::OneofString("bar".to_string()));
+        let decoded = proto3::TestAllTypes::decode_from_slice(&dynamic.encode_to_vec()).unwrap();
+        assert_eq!(all_types, decoded);
+
+        // Unknown fields are retained.
+        let unknown = [0xF8, 0xFF, 0xFF, 0xFF, 0x0F, 0x01];
+        let dynamic = DynamicMessage::decode(descriptor, &unknown).unwrap();
+        assert_eq!(0, dynamic.fields().count());
+        assert_eq!(&unknown[..], &dynamic.encode_to_vec()[..]);
+    }
+
+    #[test]
+    fn test_reflect_message() {
+        use prost_reflect::reflect::{
+            ReflectMessage,
+            Value,
+        };
+        use proto3::ForeignMessage;
+        use proto3::test_all_types::{
+            NestedMessage,
+            OneofField,
+        };
+
+        let mut all_types = proto3::TestAllTypes::default();
+        all_types.optional_int32 = -5;
+        all_types.optional_foreign_message = Some(prost::Lazy::new(ForeignMessage { c: 4 }));
+        all_types.optional_nested_message = Some(Box::new(NestedMessage { a: 7, corecursive: None }));
+        all_types.repeated_string = vec!["a".to_string(), "b".to_string()];
+        all_types.map_int32_int32.insert(1, 2);
+        all_types.oneof_field = Some(OneofField::OneofString("foo".to_string()));
+        all_types.optional_timestamp = Some(prost_types::Timestamp { seconds: 3, nanos: 0 });
+
+        assert_eq!("protobuf_test_messages.proto3.TestAllTypes", all_types.full_name());
+        assert_eq!("TestAllTypes", all_types.descriptor().name());
+
+        match all_types.field_by_name("optional_int32") {
+            Some(Value::I32(-5)) => (),
+            other => panic!("unexpected value: {:?}", other),
+        }
+        match all_types.field_by_name("optional_nested_message") {
+            Some(Value::Message(nested)) => {
+                assert_eq!("protobuf_test_messages.proto3.TestAllTypes.NestedMessage", nested.full_name());
+                match nested.field_by_name("a") {
+                    Some(Value::I32(7)) => (),
+                    other => panic!("unexpected value: {:?}", other),
+                }
+                assert!(nested.field_by_name("corecursive").is_none());
+            },
+            other => panic!("unexpected value: {:?}", other),
+        }
+        // Lazy fields are reflected in their encoded form.
+        match all_types.field_by_name("optional_foreign_message") {
+            Some(Value::EncodedMessage(ref encoded)) => assert_eq!(&ForeignMessage { c: 4 }.encode_to_vec(), encoded),
+            other => panic!("unexpected value: {:?}", other),
+        }
+        match all_types.field_by_name("repeated_string") {
+            Some(Value::List(ref values)) => match &values[..] {
+                &[Value::String("a"), Value::String("b")] => (),
+                other => panic!("unexpected values: {:?}", other),
+            },
+            other => panic!("unexpected value: {:?}", other),
+        }
+        match all_types.field_by_name("map_int32_int32") {
+            Some(Value::Map(ref entries)) => match &entries[..] {
+                &[(Value::I32(1), Value::I32(2))] => (),
+                other => panic!("unexpected entries: {:?}", other),
+            },
+            other => panic!("unexpected value: {:?}", other),
+        }
+        match all_types.field_by_name("oneof_string") {
+            Some(Value::String("foo")) => (),
+            other => panic!("unexpected value: {:?}", other),
+        }
+        assert!(all_types.field_by_name("oneof_uint32").is_none());
+        match all_types.field_by_name("optional_timestamp") {
+            Some(Value::Message(timestamp)) => {
+                assert_eq!("google.protobuf.Timestamp", timestamp.full_name());
+                match timestamp.field(1) {
+                    Some(Value::I64(3)) => (),
+                    other => panic!("unexpected value: {:?}", other),
+                }
+            },
+            other => panic!("unexpected value: {:?}", other),
+        }
+        assert!(all_types.field_by_name("missing").is_none());
+        let fields = all_types.fields();
+        assert!(fields.iter().any(|&(field, _)| field.name() == "oneof_string"));
+        assert!(!fields.iter().any(|&(field, _)| field.name() == "oneof_uint32"));
+    }
+
+    #[test]
+    fn test_text_format() {
+        use prost_types::{
+            Any,
+            Int32Value,
+        };
+        use proto3::ForeignMessage;
+        use proto3::test_all_types::{
+            NestedEnum,
+            NestedMessage,
+            OneofField,
+        };
+
+        let pool = reflect::descriptor_pool(proto3::FILE_DESCRIPTOR_SET);
+
+        let mut all_types = proto3::TestAllTypes::default();
+        all_types.optional_int32 = i32::min_value();
+        all_types.optional_uint64 = u64::max_value();
+        all_types.optional_sint64 = -12;
+        all_types.optional_float = -0.0;
+        all_types.optional_double = 1e300;
+        all_types.optional_bool = true;
+        all_types.optional_string = "tab\t \"quote\" caf\u{e9}".to_string();
+        all_types.optional_bytes = vec![0, b'\'', 0x7f, 0xff];
+        all_types.optional_nested_enum = NestedEnum::Neg as i32;
+        all_types.optional_foreign_enum = 42;
+        all_types.optional_nested_message = Some(Box::new(NestedMessage {
+            a: 1,
+            corecursive: Some(Box::new(proto3::TestAllTypes {
+                repeated_float: vec![::std::f32::INFINITY, ::std::f32::NAN, 0.1],
+                ..Default::default()
+            })),
+        }));
+        all_types.repeated_int32 = vec![-1, 0, 1];
+        all_types.repeated_nested_enum = vec![NestedEnum::Bar as i32, NestedEnum::Baz as i32];
+        all_types.repeated_foreign_message = vec![ForeignMessage { c: 3 }, ForeignMessage::default()];
+        all_types.map_bool_bool.insert(false, true);
+        all_types.map_string_bytes.insert("\n".to_string(), vec![1, 2]);
+        all_types.map_string_nested_enum.insert("a".to_string(), NestedEnum::Baz as i32);
+        all_types.map_string_foreign_message.insert("b".to_string(), ForeignMessage { c: -3 });
+        all_types.optional_int32_wrapper = Some(Int32Value { value: 0 });
+        all_types.optional_any = Some(Any {
+            type_url: "type.googleapis.com/protobuf_test_messages.proto3.ForeignMessage".to_string(),
+            value: ForeignMessage { c: 7 }.encode_to_vec(),
+        });
+        all_types.oneof_field = Some(OneofField::OneofBytes(b"oneof".to_vec()));
+
+        let text = text_format::print_message(&all_types, &pool).unwrap();
+        let parsed = text_format::parse_message::<proto3::TestAllTypes>(&pool, &text).unwrap();
+        let differences = diff::diff(&all_types, &parsed);
+        assert!(differences.is_empty(), "{}\n{:?}", text,
+                differences.iter().map(ToString::to_string).collect::<Vec<_>>());
+        assert!(text.contains("optional_nested_enum: NEG\n"), "{}", text);
+        assert!(text.contains("optional_foreign_enum: 42\n"), "{}", text);
+        assert!(text.contains("optional_bytes: \"\\000\\'\\177\\377\"\n"), "{}", text);
+        assert!(text.contains("optional_any {\n  [type.googleapis.com/protobuf_test_messages.proto3.ForeignMessage] {\n    c: 7\n  }\n}\n"),
+                "{}", text);
+
+        // Handwritten text, in the style of a test fixture.
+        let parsed = text_format::parse_message::<proto3::TestAllTypes>(&pool, r#"
+            optional_int32: -5
+            optional_string: "foo" 'bar'
+            optional_nested_enum: BAZ
+            optional_nested_message: { a: 7 }
+            repeated_int64: [1, -1, 0x10]
+            map_int32_int32 { key: 1 value: 2 }
+            map_int32_int32 < key: 3 >
+            map_string_nested_message {
+              key: "x"
+              value { corecursive { optional_bool: t } }
+            }
+            optional_any {
+              [type.googleapis.com/protobuf_test_messages.proto3.TestAllTypes] { optional_uint32: 3 }
+            }
+            oneof_uint32: 1;
+        "#).unwrap();
+
+        let mut expected = proto3::TestAllTypes::default();
+        expected.optional_int32 = -5;
+        expected.optional_string = "foobar".to_string();
+        expected.optional_nested_enum = NestedEnum::Baz as i32;
+        expected.optional_nested_message = Some(Box::new(NestedMessage { a: 7, corecursive: None }));
+        expected.repeated_int64 = vec![1, -1, 16];
+        expected.map_int32_int32.insert(1, 2);
+        expected.map_int32_int32.insert(3, 0);
+        expected.map_string_nested_message.insert("x".to_string(), NestedMessage {
+            a: 0,
+            corecursive: Some(Box::new(proto3::TestAllTypes {
+                optional_bool: true,
+                ..Default::default()
+            })),
+        });
+        expected.optional_any = Some(Any {
+            type_url: "type.googleapis.com/protobuf_test_messages.proto3.TestAllTypes".to_string(),
+            value: proto3::TestAllTypes { optional_uint32: 3, ..Default::default() }.encode_to_vec(),
+        });
+        expected.oneof_field = Some(OneofField::OneofUint32(1));
+        assert_eq!(expected, parsed);
+    }
+
+    #[test]
+    fn test_json() {
+        use prost_types::{
+            value,
+            Any,
+            Duration,
+            FieldMask,
+            Int64Value,
+            ListValue,
+            StringValue,
+            Struct,
+            Timestamp,
+            Value,
+        };
+        use proto3::ForeignMessage;
+        use proto3::test_all_types::{
+            NestedEnum,
+            NestedMessage,
+            OneofField,
+        };
+
+        let mut all_types = proto3::TestAllTypes::default();
+        all_types.optional_int32 = -7;
+        all_types.optional_int64 = i64::min_value();
+        all_types.optional_fixed64 = u64::max_value();
+        all_types.optional_float = ::std::f32::NEG_INFINITY;
+        all_types.optional_double = 0.5;
+        all_types.optional_string = "\"\u{1}\u{e9}".to_string();
+        all_types.optional_bytes = vec![0xfb, 0xff, 0x00];
+        all_types.optional_nested_enum = NestedEnum::Neg as i32;
+        all_types.optional_foreign_enum = 42;
+        all_types.optional_nested_message = Some(Box::new(NestedMessage { a: 3, corecursive: None }));
+        all_types.repeated_double = vec![::std::f64::NAN, 1.0];
+        all_types.map_int64_int64.insert(-2, 2);
+        all_types.map_bool_bool.insert(true, false);
+        all_types.map_string_foreign_message.insert("k".to_string(), ForeignMessage { c: 1 });
+        all_types.optional_foreign_message = Some(prost::Lazy::new(ForeignMessage { c: 2 }));
+        all_types.optional_int64_wrapper = Some(Int64Value { value: 0 });
+        all_types.repeated_string_wrapper = vec![StringValue { value: "w".to_string() }];
+        all_types.optional_duration = Some(Duration { seconds: -1, nanos: -500000000 });
+        all_types.optional_timestamp = Some(Timestamp { seconds: 951782400, nanos: 10000 });
+        all_types.optional_field_mask = Some(FieldMask { paths: vec!["foo_bar".to_string(), "baz".to_string()] });
+        let mut fields = HashMap::new();
+        fields.insert("n".to_string(), Value { kind: Some(value::Kind::NullValue(0)) });
+        fields.insert("l".to_string(), Value {
+            kind: Some(value::Kind::ListValue(ListValue {
+                values: vec![Value { kind: Some(value::Kind::BoolValue(true)) }],
+            })),
+        });
+        all_types.optional_struct = Some(Struct { fields: fields });
+        all_types.optional_value = Some(Value { kind: Some(value::Kind::NullValue(0)) });
+        all_types.optional_any = Some(Any {
+            type_url: "type.googleapis.com/google.protobuf.Duration".to_string(),
+            value: Duration { seconds: 1, nanos: 0 }.encode_to_vec(),
+        });
+        all_types.repeated_any = vec![Any {
+            type_url: "type.googleapis.com/protobuf_test_messages.proto3.ForeignMessage".to_string(),
+            value: ForeignMessage { c: 5 }.encode_to_vec(),
+        }];
+        all_types.oneof_field = Some(OneofField::OneofUint32(0));
+
+        let json = all_types.to_json().unwrap();
+        let parsed = proto3::TestAllTypes::from_json(&json).unwrap();
+        let differences = diff::diff(&all_types, &parsed);
+        assert!(differences.is_empty(), "{}\n{:?}", json,
+                differences.iter().map(ToString::to_string).collect::<Vec<_>>());
+        for member in &[r#""optionalInt32":-7"#,
+                        r#""optionalInt64":"-9223372036854775808""#,
+                        r#""optionalFixed64":"18446744073709551615""#,
+                        r#""optionalFloat":"-Infinity""#,
+                        r#""optionalString":"\"\u0001é""#,
+                        r#""optionalBytes":"+/8A""#,
+                        r#""optionalNestedEnum":"NEG""#,
+                        r#""optionalForeignEnum":42"#,
+                        r#""optionalForeignMessage":{"c":2}"#,
+                        r#""repeatedDouble":["NaN",1.0]"#,
+                        r#""mapInt64Int64":{"-2":"2"}"#,
+                        r#""mapBoolBool":{"true":false}"#,
+                        r#""optionalInt64Wrapper":"0""#,
+                        r#""repeatedStringWrapper":["w"]"#,
+                        r#""optionalDuration":"-1.500s""#,
+                        r#""optionalTimestamp":"2000-02-29T00:00:00.000010Z""#,
+                        r#""optionalFieldMask":"fooBar,baz""#,
+                        r#""optionalValue":null"#,
+                        r#""optionalAny":{"@type":"type.googleapis.com/google.protobuf.Duration","value":"1s"}"#,
+                        r#""repeatedAny":[{"@type":"type.googleapis.com/protobuf_test_messages.proto3.ForeignMessage","c":5}]"#,
+                        r#""oneofUint32":0"#] {
+            assert!(json.contains(member), "{} not found in {}", member, json);
+        }
+        assert!(!json.contains("optionalUint32"), "{}", json);
+
+        // Handwritten JSON, using the alternative forms accepted by the parser.
+        let parsed = proto3::TestAllTypes::from_json(r#"{
+            "optional_int32": "12",
+            "optionalUint64": 1e3,
+            "optionalBytes": "-_8",
+            "optionalNestedEnum": 2,
+            "optionalNestedMessage": null,
+            "repeatedInt32": [1, "-1"],
+            "optionalTimestamp": "1970-01-01T01:00:00.5+01:00",
+            "optionalDuration": "0.000000001s"
+        }"#).unwrap();
+        let mut expected = proto3::TestAllTypes::default();
+        expected.optional_int32 = 12;
+        expected.optional_uint64 = 1000;
+        expected.optional_bytes = vec![0xfb, 0xff];
+        expected.optional_nested_enum = NestedEnum::Baz as i32;
+        expected.repeated_int32 = vec![1, -1];
+        expected.optional_timestamp = Some(Timestamp { seconds: 0, nanos: 500000000 });
+        expected.optional_duration = Some(Duration { seconds: 0, nanos: 1 });
+        assert_eq!(expected, parsed);
+
+        for invalid in &[r#"{"optionalInt32": 1.5}"#,
+                         r#"{"optionalInt32": 2147483648}"#,
+                         r#"{"optionalUint32": -1}"#,
+                         r#"{"optionalInt32": 1, "optional_int32": 2}"#,
+                         r#"{"oneofUint32": 1, "oneofString": "a"}"#,
+                         r#"{"unknownField": 1}"#,
+                         r#"{"optionalNestedEnum": "MISSING"}"#,
+                         r#"{"optionalTimestamp": "2000-02-30T00:00:00Z"}"#,
+                         r#"{"optionalDuration": "1"}"#,
+                         r#"{"optionalBool": "true"}"#] {
+            assert!(proto3::TestAllTypes::from_json(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_any() {
+        use prost::Name;
+        use prost_types::{
+            Any,
+            TypeRegistry,
+            UnpackError,
+        };
+        use proto3::test_all_types::NestedMessage;
+
+        assert_eq!("TestAllTypes.NestedMessage", NestedMessage::NAME);
+        assert_eq!("protobuf_test_messages.proto3", NestedMessage::PACKAGE);
+        assert_eq!("type.googleapis.com/protobuf_test_messages.proto3.TestAllTypes.NestedMessage",
+                   NestedMessage::type_url());
+
+        let nested = NestedMessage {
+            a: 5,
+            corecursive: Some(Box::new(proto3::TestAllTypes { optional_int32: 1, ..Default::default() })),
+        };
+        let mut all_types = proto3::TestAllTypes::default();
+        all_types.optional_any = Some(Any::pack(&nested));
+        let all_types = proto3::TestAllTypes::decode_from_slice(&all_types.encode_to_vec()).unwrap();
+
+        let any = all_types.optional_any.unwrap();
+        assert_eq!(Ok(nested.clone()), any.unpack::<NestedMessage>());
+        match any.unpack::<proto3::TestAllTypes>() {
+            Err(UnpackError::TypeMismatch { expected, actual }) => {
+                assert_eq!("protobuf_test_messages.proto3.TestAllTypes", expected);
+                assert_eq!("protobuf_test_messages.proto3.TestAllTypes.NestedMessage", actual);
+            },
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        let mut registry = TypeRegistry::new();
+        registry.register::<proto3::TestAllTypes>();
+        registry.register::<NestedMessage>();
+        let message = registry.unpack(&any).unwrap();
+        assert_eq!(Some(&nested), message.downcast_ref::<NestedMessage>());
+    }
+
+    #[test]
+    fn test_field_mask() {
+        use std::collections::HashMap;
+
+        use prost_types::FieldMask;
+        use proto3::test_all_types::{
+            NestedMessage,
+            OneofField,
+        };
+
+        let mask = FieldMask::new(vec![
+            "optional_int32",
+            "FieldName8",
+            "optional_nested_message.corecursive.optional_string",
+            "repeated_int32",
+            "map_int32_int32",
+            "oneof_nested_message.a",
+        ]);
+        assert_eq!(Ok(()), mask.validate::<proto3::TestAllTypes>());
+        for &invalid in &["field_name8", "optional_int32.a", "repeated_nested_message.a",
+                          "optional_nested_message.b", "oneof_field", "oneof_uint32.a", ""] {
+            let mask = FieldMask::new(vec![invalid]);
+            assert_eq!(invalid, mask.validate::<proto3::TestAllTypes>().unwrap_err().path());
+        }
+
+        let mut map = HashMap::new();
+        map.insert(1, 2);
+        let source = proto3::TestAllTypes {
+            optional_int32: 1,
+            optional_int64: 2,
+            field_name8: 3,
+            optional_nested_message: Some(Box::new(NestedMessage {
+                a: 4,
+                corecursive: Some(Box::new(proto3::TestAllTypes {
+                    optional_string: "foo".to_string(),
+                    optional_bool: true,
+                    ..Default::default()
+                })),
+            })),
+            repeated_int32: vec![5, 6],
+            map_int32_int32: map.clone(),
+            oneof_field: Some(OneofField::OneofNestedMessage(NestedMessage { a: 7, corecursive: None })),
+            ..Default::default()
+        };
+
+        let expected = proto3::TestAllTypes {
+            optional_int32: 1,
+            field_name8: 3,
+            optional_nested_message: Some(Box::new(NestedMessage {
+                a: 0,
+                corecursive: Some(Box::new(proto3::TestAllTypes {
+                    optional_string: "foo".to_string(),
+                    ..Default::default()
+                })),
+            })),
+            repeated_int32: vec![5, 6],
+            map_int32_int32: map.clone(),
+            oneof_field: Some(OneofField::OneofNestedMessage(NestedMessage { a: 7, corecursive: None })),
+            ..Default::default()
+        };
+        assert_eq!(expected, mask.apply(&source).unwrap());
+
+        let mut pruned = source.clone();
+        mask.prune(&mut pruned);
+        assert_eq!(expected, pruned);
+
+        // Merging appends repeated fields, and merges into existing messages.
+        let mut destination = proto3::TestAllTypes {
+            optional_int64: 8,
+            optional_nested_message: Some(Box::new(NestedMessage { a: 9, corecursive: None })),
+            repeated_int32: vec![10],
+            oneof_field: Some(OneofField::OneofUint32(11)),
+            ..Default::default()
+        };
+        mask.merge(&source, &mut destination).unwrap();
+        assert_eq!(proto3::TestAllTypes {
+                       optional_int32: 1,
+                       optional_int64: 8,
+                       field_name8: 3,
+                       optional_nested_message: Some(Box::new(NestedMessage {
+                           a: 9,
+                           corecursive: Some(Box::new(proto3::TestAllTypes {
+                               optional_string: "foo".to_string(),
+                               ..Default::default()
+                           })),
+                       })),
+                       repeated_int32: vec![10, 5, 6],
+                       map_int32_int32: map,
+                       oneof_field: Some(OneofField::OneofNestedMessage(NestedMessage { a: 7, corecursive: None })),
+                       ..Default::default()
+                   },
+                   destination);
+
+        // Masked fields holding the default value clear the destination.
+        let mask = FieldMask::new(vec!["optional_int32", "optional_string", "optional_bool"]);
+        let mut destination = proto3::TestAllTypes {
+            optional_int32: 1,
+            optional_int64: 2,
+            optional_string: "foo".to_string(),
+            optional_bool: true,
+            ..Default::default()
+        };
+        mask.merge(&proto3::TestAllTypes::default(), &mut destination).unwrap();
+        assert_eq!(proto3::TestAllTypes { optional_int64: 2, ..Default::default() }, destination);
+    }
+    #[test]
+    fn test_btree_map() {
+        use std::collections::BTreeMap;
+
+        use proto3::test_all_types::NestedEnum;
+
+        let mut all_types = proto3::TestAllTypes::default();
+        for key in &["c", "a", "b"] {
+            all_types.map_string_string.insert(key.to_string(), key.to_uppercase());
+            all_types.insert_map_string_nested_enum(key.to_string(), NestedEnum::Baz);
+        }
+        let _: &BTreeMap<String, String> = &all_types.map_string_string;
+        assert_eq!(vec!["a", "b", "c"], all_types.map_string_string.keys().collect::<Vec<_>>());
+        assert_eq!(Some(NestedEnum::Baz), all_types.get_map_string_nested_enum("a"));
+
+        // `BTreeMap` fields are always encoded in key order.
+        let buf = all_types.encode_to_vec();
+        assert_eq!(all_types.encode_to_vec_deterministic(), buf);
+        assert_eq!(all_types, proto3::TestAllTypes::decode_from_slice(&buf).unwrap());
+    }
 }
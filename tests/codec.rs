@@ -0,0 +1,182 @@
+#![cfg(feature = "tokio-io")]
+
+#[macro_use]
+extern crate prost_derive;
+
+extern crate bytes;
+extern crate prost;
+extern crate tokio_io;
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{
+    self,
+    Read,
+    Write,
+};
+
+use bytes::BytesMut;
+use prost::MessageCodec;
+use tokio_io::codec::{
+    Decoder,
+    Encoder,
+};
+
+#[derive(Clone, Debug, PartialEq, Message)]
+pub struct Request {
+    #[prost(uint64, tag="1")]
+    pub id: u64,
+    #[prost(string, tag="2")]
+    pub payload: String,
+}
+
+/// One end of an in-memory duplex stream, which returns at most `chunk` bytes
+/// per read in order to exercise partial frames.
+struct Endpoint {
+    read: VecDeque<u8>,
+    write: VecDeque<u8>,
+    chunk: usize,
+}
+
+impl Endpoint {
+    /// Transfers the bytes written to `self` to the read side of `peer`.
+    fn send_to(&mut self, peer: &mut Endpoint) {
+        peer.read.extend(self.write.drain(..));
+    }
+}
+
+impl Read for Endpoint {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = cmp::min(cmp::min(buf.len(), self.chunk), self.read.len());
+        for (dst, src) in buf.iter_mut().zip(self.read.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for Endpoint {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write.extend(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn duplex(chunk: usize) -> (Endpoint, Endpoint) {
+    let endpoint = || Endpoint { read: VecDeque::new(), write: VecDeque::new(), chunk: chunk };
+    (endpoint(), endpoint())
+}
+
+/// Reads from the endpoint into the buffer until the codec yields a frame, or
+/// the endpoint is exhausted.
+fn read_frame(codec: &mut MessageCodec<Request>,
+              endpoint: &mut Endpoint,
+              buf: &mut BytesMut) -> io::Result<Option<Request>> {
+    let mut chunk = [0; 64];
+    loop {
+        if let Some(request) = codec.decode(buf)? {
+            return Ok(Some(request));
+        }
+        let n = endpoint.read(&mut chunk)?;
+        if n == 0 {
+            return codec.decode_eof(buf);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+#[test]
+fn check_codec_duplex() {
+    let requests = (0..4).map(|id| Request {
+        id: id,
+        payload: "x".repeat(id as usize * 50),
+    }).collect::<Vec<_>>();
+
+    let (mut client, mut server) = duplex(3);
+    let mut codec = MessageCodec::<Request>::new();
+
+    let mut buf = BytesMut::new();
+    for request in &requests {
+        codec.encode(request.clone(), &mut buf).unwrap();
+    }
+    client.write_all(&buf).unwrap();
+    client.send_to(&mut server);
+
+    let mut buf = BytesMut::new();
+    for request in &requests {
+        assert_eq!(Some(request), read_frame(&mut codec, &mut server, &mut buf).unwrap().as_ref());
+    }
+    assert_eq!(None, read_frame(&mut codec, &mut server, &mut buf).unwrap());
+
+    // Responses flow in the other direction over the same stream.
+    let mut buf = BytesMut::new();
+    codec.encode(requests[1].clone(), &mut buf).unwrap();
+    server.write_all(&buf).unwrap();
+    server.send_to(&mut client);
+    let mut buf = BytesMut::new();
+    assert_eq!(Some(requests[1].clone()), read_frame(&mut codec, &mut client, &mut buf).unwrap());
+}
+
+#[test]
+fn check_codec_partial_frame_at_eof() {
+    let (mut client, mut server) = duplex(3);
+    let mut codec = MessageCodec::<Request>::new();
+
+    let mut buf = BytesMut::new();
+    codec.encode(Request { id: 1, payload: "payload".to_string() }, &mut buf).unwrap();
+    let len = buf.len();
+    client.write_all(&buf[..len - 1]).unwrap();
+    client.send_to(&mut server);
+
+    let mut buf = BytesMut::new();
+    assert!(read_frame(&mut codec, &mut server, &mut buf).is_err());
+}
+
+#[test]
+fn check_codec_max_frame_length() {
+    let request = Request { id: 1, payload: "x".repeat(100) };
+    let mut codec = MessageCodec::<Request>::new().with_max_frame_length(64);
+
+    let mut buf = BytesMut::new();
+    let error = codec.encode(request.clone(), &mut buf).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+    assert!(buf.is_empty());
+
+    // The length prefix alone is enough to reject an oversized frame.
+    let mut buf = BytesMut::new();
+    MessageCodec::<Request>::new().encode(request, &mut buf).unwrap();
+    let mut prefix = BytesMut::from(&buf[..2]);
+    let error = codec.decode(&mut prefix).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, error.kind());
+}
+
+#[test]
+fn check_codec_decode_options() {
+    use prost::DecodeOptions;
+
+    let request = Request { id: 1, payload: "x".repeat(100) };
+    let mut buf = BytesMut::new();
+    MessageCodec::<Request>::new().encode(request.clone(), &mut buf).unwrap();
+
+    let mut codec = MessageCodec::<Request>::new().with_options(DecodeOptions::new().max_length(100));
+    assert_eq!(Some(request), codec.decode(&mut buf.clone()).unwrap());
+
+    let mut codec = MessageCodec::<Request>::new().with_options(DecodeOptions::new().max_length(99));
+    let error = codec.decode(&mut buf.clone()).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, error.kind());
+
+    // The message size limit rejects a frame from its length prefix alone.
+    let mut codec = MessageCodec::<Request>::new().with_options(DecodeOptions::new().max_message_size(64));
+    let mut prefix = BytesMut::from(&buf[..2]);
+    let error = codec.decode(&mut prefix).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, error.kind());
+
+    // The maximum frame length is the message size limit.
+    assert_eq!(64, codec.max_frame_length());
+    let codec = MessageCodec::<Request>::new().with_options(DecodeOptions::new()).with_max_frame_length(32);
+    assert_eq!(32, codec.max_frame_length());
+    assert_eq!(64 * 1024 * 1024, MessageCodec::<Request>::new().max_frame_length());
+}
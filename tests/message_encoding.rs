@@ -1,3 +1,5 @@
+#![cfg(feature = "std")]
+
 #[macro_use]
 extern crate prost_derive;
 
@@ -316,3 +318,746 @@ pub enum BasicOneof {
     #[prost(string, tag="9")]
     String(String),
 }
+
+/// A proto2 message with group fields.
+#[derive(Clone, Debug, PartialEq, Message)]
+pub struct Groups {
+    #[prost(group, optional, tag="1")]
+    pub optional_group: Option<Basic>,
+
+    #[prost(group, repeated, tag="2")]
+    pub repeated_group: Vec<Basic>,
+
+    #[prost(int32, optional, tag="3")]
+    pub trailing: Option<i32>,
+}
+
+#[test]
+fn check_groups() {
+    let _ = env_logger::init();
+    check_message(Groups::default());
+
+    let mut basic = Basic::default();
+    basic.int32 = 42;
+    basic.string = "fourty two".to_string();
+    basic.oneof = Some(BasicOneof::Int(7));
+    check_message(Groups {
+        optional_group: Some(basic.clone()),
+        repeated_group: vec![Basic::default(), basic],
+        trailing: Some(-1),
+    });
+}
+
+/// A message containing only the trailing field of `Groups`.
+#[derive(Clone, Debug, PartialEq, Message)]
+pub struct GroupsTrailing {
+    #[prost(int32, optional, tag="3")]
+    pub trailing: Option<i32>,
+}
+
+#[test]
+fn check_skip_unknown_group() {
+    let mut basic = Basic::default();
+    basic.int32 = 42;
+    let groups = Groups {
+        optional_group: Some(basic.clone()),
+        repeated_group: vec![basic],
+        trailing: Some(99),
+    };
+    let mut buf = Vec::new();
+    groups.encode(&mut buf).unwrap();
+
+    // The groups are unknown to `GroupsTrailing`, so they must be skipped as a unit.
+    let len = buf.len();
+    let trailing = GroupsTrailing::decode(&mut Cursor::new(&buf).take(len)).unwrap();
+    assert_eq!(Some(99), trailing.trailing);
+}
+
+/// An older version of `Basic`, which retains the fields it does not know about.
+#[derive(Clone, Debug, PartialEq, Message)]
+pub struct BasicV1 {
+    #[prost(int32, tag="1")]
+    pub int32: i32,
+
+    #[prost(string, tag="3")]
+    pub string: String,
+
+    #[prost(unknown_fields)]
+    pub unknown_fields: prost::UnknownFieldSet,
+}
+
+#[test]
+fn check_unknown_fields_roundtrip() {
+    let _ = env_logger::init();
+
+    let mut basic = Basic::default();
+    basic.int32 = 42;
+    basic.bools = vec![true, false];
+    basic.string = "fourty two".to_string();
+    basic.optional_string = Some("optional".to_string());
+    basic.string_map.insert("foo".to_string(), "bar".to_string());
+    basic.oneof = Some(BasicOneof::Int(7));
+
+    let mut buf = Vec::new();
+    basic.encode(&mut buf).unwrap();
+    let len = buf.len();
+
+    let v1 = BasicV1::decode(&mut Cursor::new(&buf).take(len)).unwrap();
+    assert_eq!(42, v1.int32);
+    assert_eq!("fourty two", v1.string);
+    assert!(!v1.unknown_fields.is_empty());
+    check_message(v1.clone());
+
+    // Re-encoding the old version must preserve the fields it did not recognize.
+    let mut buf = Vec::new();
+    v1.encode(&mut buf).unwrap();
+    assert_eq!(v1.encoded_len(), buf.len());
+    let len = buf.len();
+    let roundtrip = Basic::decode(&mut Cursor::new(&buf).take(len)).unwrap();
+    assert_eq!(basic, roundtrip);
+}
+
+/// A message with extension ranges, and some extensions of it.
+#[derive(Clone, Debug, PartialEq, Message)]
+pub struct Extendable {
+    #[prost(int32, tag="1")]
+    pub int32: i32,
+
+    #[prost(extension_set="100..200, 1000..536870912")]
+    pub extensions: prost::ExtensionSet,
+}
+
+const EXT_INT32: prost::Extension<Extendable, prost::extension::Int32> = prost::Extension {
+    name: "ext_int32",
+    tag: 100,
+    marker: std::marker::PhantomData,
+};
+
+const EXT_STRINGS: prost::Extension<Extendable, prost::extension::Repeated<prost::extension::String>> =
+    prost::Extension {
+        name: "ext_strings",
+        tag: 101,
+        marker: std::marker::PhantomData,
+    };
+
+const EXT_PACKED: prost::Extension<Extendable, prost::extension::Packed<prost::extension::Sint64>> =
+    prost::Extension {
+        name: "ext_packed",
+        tag: 1000,
+        marker: std::marker::PhantomData,
+    };
+
+const EXT_MESSAGE: prost::Extension<Extendable, prost::extension::Message<Basic>> = prost::Extension {
+    name: "ext_message",
+    tag: 1001,
+    marker: std::marker::PhantomData,
+};
+
+#[test]
+fn check_extensions() {
+    use prost::{ExtendableMessage, ExtensionRegistry};
+    use prost::encoding::{self, DecodeContext};
+
+    let mut msg = Extendable::default();
+    msg.int32 = 7;
+    assert_eq!(None, msg.get_extension(&EXT_INT32).unwrap());
+
+    let mut basic = Basic::default();
+    basic.string = "basic".to_string();
+    msg.set_extension(&EXT_INT32, -3);
+    msg.set_extension(&EXT_STRINGS, vec!["foo".to_string(), "bar".to_string()]);
+    msg.set_extension(&EXT_PACKED, vec![1, -2, 3]);
+    msg.set_extension(&EXT_MESSAGE, basic.clone());
+    msg.set_extension(&EXT_INT32, 42);
+    check_message(msg.clone());
+
+    let roundtrip = Extendable::decode_from_slice(&msg.encode_to_vec()).unwrap();
+    assert_eq!(Some(42), roundtrip.get_extension(&EXT_INT32).unwrap());
+    assert_eq!(Some(vec!["foo".to_string(), "bar".to_string()]),
+               roundtrip.get_extension(&EXT_STRINGS).unwrap());
+    assert_eq!(Some(vec![1, -2, 3]), roundtrip.get_extension(&EXT_PACKED).unwrap());
+    assert_eq!(Some(basic), roundtrip.get_extension(&EXT_MESSAGE).unwrap());
+
+    msg.clear_extension(&EXT_MESSAGE);
+    assert!(!msg.has_extension(&EXT_MESSAGE));
+    assert!(msg.has_extension(&EXT_STRINGS));
+
+    // A malformed extension is only detected on access, unless the extension
+    // is in the registry used to decode the message.
+    let mut buf = Vec::new();
+    encoding::bytes::encode(100, &vec![1, 2, 3], &mut buf);
+    let msg = Extendable::decode_from_slice(&buf).unwrap();
+    assert!(msg.has_extension(&EXT_INT32));
+    assert!(msg.get_extension(&EXT_INT32).is_err());
+
+    let mut registry = ExtensionRegistry::new();
+    registry.register(&EXT_INT32);
+    registry.register(&EXT_MESSAGE);
+    assert_eq!(Some("ext_int32"), registry.find::<Extendable>(100));
+    assert_eq!(Some(1001), registry.find_by_name::<Extendable>("ext_message"));
+    assert_eq!(None, registry.find::<Basic>(100));
+
+    let ctx = DecodeContext::default().with_extension_registry(&registry);
+    let mut slice = Buf::take(Cursor::new(&buf[..]), buf.len());
+    assert!(Extendable::decode_with_context(&mut slice, ctx).is_err());
+}
+
+#[test]
+fn check_decode_error_stack() {
+    use prost::DecodeErrorKind;
+    use prost::encoding::{self, WireType};
+
+    let mut buf = Vec::new();
+    encoding::encode_key(1, WireType::StartGroup, &mut buf);
+    encoding::bytes::encode(3, &vec![0xFF, 0xFE], &mut buf);
+    encoding::encode_key(1, WireType::EndGroup, &mut buf);
+
+    let len = buf.len();
+    let error = Groups::decode(&mut Cursor::new(&buf).take(len)).unwrap_err();
+    assert_eq!(&DecodeErrorKind::InvalidUtf8, error.kind());
+    assert_eq!(&[("Basic", "string"), ("Groups", "optional_group")], error.stack());
+    assert_eq!("failed to decode Protobuf message: Groups.optional_group: Basic.string: \
+                invalid string value: data is not UTF-8 encoded",
+               error.to_string());
+}
+
+#[test]
+fn check_slice_entry_points() {
+    let mut basic = Basic::default();
+    basic.int32 = 42;
+    basic.string = "fourty two".to_string();
+
+    let buf = basic.encode_to_vec();
+    assert_eq!(basic.encoded_len(), buf.len());
+    assert_eq!(&buf[..], &basic.encode_to_bytes()[..]);
+    assert_eq!(basic, Basic::decode_from_slice(&buf).unwrap());
+
+    let mut merged = Basic::default();
+    merged.bools = vec![true];
+    merged.merge_from_slice(&buf).unwrap();
+    assert_eq!(42, merged.int32);
+    assert_eq!(vec![true], merged.bools);
+
+    // Bytes following a length-delimited message are ignored.
+    let mut buf = Vec::new();
+    basic.encode_length_delimited(&mut buf).unwrap();
+    buf.extend_from_slice(&[0xFF, 0xFF]);
+    assert_eq!(basic, Basic::decode_length_delimited_from_slice(&buf).unwrap());
+}
+
+/// A self-referential message.
+#[derive(Clone, Debug, PartialEq, Message)]
+pub struct Recursive {
+    #[prost(message, optional, tag="1")]
+    pub child: Option<Box<Recursive>>,
+
+    #[prost(int32, tag="2")]
+    pub depth: i32,
+}
+
+fn nested(depth: i32) -> Recursive {
+    let mut msg = Recursive { child: None, depth: depth };
+    for depth in (0..depth).rev() {
+        msg = Recursive { child: Some(Box::new(msg)), depth: depth };
+    }
+    msg
+}
+
+#[test]
+fn check_size_cache() {
+    use prost::encoding::SizeCache;
+
+    let msg = nested(50);
+    let mut uncached = Vec::new();
+    msg.encode_raw(&mut uncached);
+    assert_eq!(uncached, msg.encode_to_vec());
+
+    // Nested messages within maps and groups are encoded in the same order as
+    // their lengths are cached.
+    let mut basic = Basic::default();
+    basic.string = "basic".to_string();
+    basic.oneof = Some(BasicOneof::Int(1));
+    let mut compound = Compound::default();
+    compound.optional_message = Some(basic.clone());
+    compound.repeated_message = vec![Basic::default(), basic.clone()];
+    compound.message_map.insert(1, basic.clone());
+    let mut uncached = Vec::new();
+    compound.encode_raw(&mut uncached);
+    assert_eq!(uncached, compound.encode_to_vec());
+    check_message(compound);
+
+    let groups = Groups {
+        optional_group: Some(basic.clone()),
+        repeated_group: vec![basic.clone(), Basic::default()],
+        trailing: Some(1),
+    };
+    let mut uncached = Vec::new();
+    groups.encode_raw(&mut uncached);
+    assert_eq!(uncached, groups.encode_to_vec());
+
+    // Every nested message in a tree nested through map values and oneofs has
+    // its length measured once: each map entry caches the lengths of the entry
+    // and its value, and each oneof caches the length of its message. Encoding
+    // reads all of them back.
+    let tree = tree(4);
+    let mut cache = SizeCache::new();
+    assert_eq!(tree.encoded_len(), tree.encoded_len_cached(&mut cache));
+    assert_eq!(30 * 2 + 31, cache.remaining());
+    let mut cached = Vec::new();
+    tree.encode_raw_cached(&mut cached, &mut cache);
+    assert_eq!(0, cache.remaining());
+    let mut uncached = Vec::new();
+    tree.encode_raw(&mut uncached);
+    assert_eq!(uncached, cached);
+    check_message(tree);
+}
+
+/// A message tree nested through map values and oneof variants.
+#[derive(Clone, Debug, PartialEq, Message)]
+pub struct Tree {
+    #[prost(map="int32, message", tag="1")]
+    pub children: ::std::collections::HashMap<i32, Tree>,
+
+    #[prost(oneof="TreeLeaf", tags="2, 3")]
+    pub leaf: Option<TreeLeaf>,
+}
+
+#[derive(Clone, Debug, PartialEq, Oneof)]
+pub enum TreeLeaf {
+    #[prost(message, tag="2")]
+    Message(Recursive),
+    #[prost(int32, tag="3")]
+    Int(i32),
+}
+
+/// Builds a complete binary tree of the given depth, with a message in each node.
+fn tree(depth: i32) -> Tree {
+    let mut tree = Tree::default();
+    tree.leaf = Some(TreeLeaf::Message(Recursive { depth: depth, child: None }));
+    if depth > 0 {
+        tree.children.insert(0, self::tree(depth - 1));
+        tree.children.insert(1, self::tree(depth - 1));
+    }
+    tree
+}
+
+#[test]
+fn check_deterministic_encoding() {
+    let mut basic = Basic::default();
+    basic.string_map.insert("b".to_string(), "2".to_string());
+    basic.string_map.insert("a".to_string(), "1".to_string());
+    basic.enumeration_map.insert(2, BasicEnumeration::TWO as i32);
+    basic.enumeration_map.insert(1, BasicEnumeration::ONE as i32);
+    assert_eq!(vec![0x32, 0x04, 0x08, 0x01, 0x10, 0x01,
+                    0x32, 0x04, 0x08, 0x02, 0x10, 0x02,
+                    0x3a, 0x06, 0x0a, 0x01, b'a', 0x12, 0x01, b'1',
+                    0x3a, 0x06, 0x0a, 0x01, b'b', 0x12, 0x01, b'2'],
+               basic.encode_to_vec_deterministic());
+
+    // Each `HashMap` is seeded differently, so maps with the same entries
+    // usually iterate in different orders, including within nested messages.
+    let compound = |keys: &[i32]| {
+        let mut compound = Compound::default();
+        for &key in keys {
+            let mut basic = Basic::default();
+            basic.oneof = Some(BasicOneof::Int(key));
+            for &key in keys {
+                basic.string_map.insert(key.to_string(), key.to_string());
+                basic.enumeration_map.insert(key, BasicEnumeration::THREE as i32);
+            }
+            compound.message_map.insert(key, basic);
+        }
+        compound.optional_message = compound.message_map.get(&0).cloned();
+        compound.repeated_message = vec![compound.message_map[&1].clone()];
+        compound
+    };
+    let keys = (-50..50).collect::<Vec<_>>();
+    let a = compound(&keys);
+    let b = compound(&keys.iter().rev().cloned().collect::<Vec<_>>());
+    let encoded = a.encode_to_vec_deterministic();
+    assert_eq!(a.encoded_len(), encoded.len());
+
+    let mut buf = Vec::new();
+    b.clone().encode_deterministic(&mut buf).unwrap();
+    assert!(encoded == buf);
+    assert_eq!(a, Compound::decode_from_slice(&encoded).unwrap());
+    assert!(encoded == Compound::decode_from_slice(&a.encode_to_vec()).unwrap().encode_to_vec_deterministic());
+}
+
+#[test]
+fn check_recursion_limit() {
+    use prost::DecodeErrorKind;
+    use prost::encoding::{DecodeContext, RECURSION_LIMIT};
+
+    let msg = nested(10);
+    let mut buf = Vec::new();
+    msg.encode(&mut buf).unwrap();
+    let len = buf.len();
+
+    let roundtrip = Recursive::decode_with_context(&mut Cursor::new(&buf).take(len),
+                                                   DecodeContext::with_recursion_limit(10)).unwrap();
+    assert_eq!(msg, roundtrip);
+
+    let error = Recursive::decode_with_context(&mut Cursor::new(&buf).take(len),
+                                               DecodeContext::with_recursion_limit(9)).unwrap_err();
+    assert_eq!(&DecodeErrorKind::RecursionLimitReached, error.kind());
+    assert_eq!(10, error.stack().len());
+
+    // Nesting beyond the default limit fails with a plain `decode`.
+    let msg = nested(RECURSION_LIMIT as i32 + 1);
+    let mut buf = Vec::new();
+    msg.encode(&mut buf).unwrap();
+    let len = buf.len();
+    let error = Recursive::decode(&mut Cursor::new(&buf).take(len)).unwrap_err();
+    assert_eq!(&DecodeErrorKind::RecursionLimitReached, error.kind());
+}
+
+#[test]
+fn check_decode_options() {
+    use prost::{DecodeErrorKind, DecodeOptions};
+
+    let decode = |buf: &[u8], options: DecodeOptions| {
+        Basic::decode_with_options(&mut Cursor::new(buf).take(buf.len()), &options)
+    };
+
+    let mut msg = Basic::default();
+    msg.bools = vec![true; 4];
+    msg.string = "four".to_string();
+    msg.string_map.insert("a".to_string(), "b".to_string());
+    msg.string_map.insert("c".to_string(), "d".to_string());
+    let buf = msg.encode_to_vec();
+
+    let options = DecodeOptions::new().max_message_size(buf.len())
+                                      .max_length(4)
+                                      .max_repeated_len(4);
+    assert_eq!(msg, decode(&buf, options).unwrap());
+
+    let error = decode(&buf, options.max_message_size(buf.len() - 1)).unwrap_err();
+    assert_eq!(&DecodeErrorKind::MessageTooLarge, error.kind());
+
+    let error = decode(&buf, options.max_length(3)).unwrap_err();
+    assert_eq!(&DecodeErrorKind::LengthLimitExceeded, error.kind());
+    assert_eq!(&[("Basic", "string")], error.stack());
+
+    let error = decode(&buf, options.max_repeated_len(3)).unwrap_err();
+    assert_eq!(&DecodeErrorKind::RepeatedLimitExceeded, error.kind());
+    assert_eq!(&[("Basic", "bools")], error.stack());
+
+    // The other decode methods enforce the same limits.
+    assert_eq!(msg, Basic::decode_from_slice_with_options(&buf, &options).unwrap());
+    let error = Basic::decode_from_slice_with_options(&buf, &options.max_length(3)).unwrap_err();
+    assert_eq!(&DecodeErrorKind::LengthLimitExceeded, error.kind());
+    let bytes = bytes::Bytes::from(buf.clone());
+    assert_eq!(msg, Basic::decode_bytes_with_options(bytes.clone(), &options).unwrap());
+    let error = Basic::decode_bytes_with_options(bytes, &options.max_repeated_len(3)).unwrap_err();
+    assert_eq!(&DecodeErrorKind::RepeatedLimitExceeded, error.kind());
+    let mut delimited = Vec::new();
+    msg.encode_length_delimited(&mut delimited).unwrap();
+    assert_eq!(msg, Basic::decode_length_delimited_with_options(&mut Cursor::new(&delimited), &options).unwrap());
+    let error = Basic::decode_length_delimited_with_options(&mut Cursor::new(&delimited),
+                                                            &options.max_message_size(buf.len() - 1)).unwrap_err();
+    assert_eq!(&DecodeErrorKind::MessageTooLarge, error.kind());
+
+    // Map entries count towards the repeated limit, but entries which replace
+    // an existing key do not.
+    let mut map_msg = Basic::default();
+    map_msg.string_map.insert("a".to_string(), "b".to_string());
+    map_msg.string_map.insert("c".to_string(), "d".to_string());
+    let error = decode(&map_msg.encode_to_vec(), DecodeOptions::new().max_repeated_len(1)).unwrap_err();
+    assert_eq!(&DecodeErrorKind::RepeatedLimitExceeded, error.kind());
+    assert_eq!(&[("Basic", "string_map")], error.stack());
+
+    map_msg.string_map.remove("c");
+    let mut buf = map_msg.encode_to_vec();
+    buf.extend(map_msg.encode_to_vec());
+    assert_eq!(map_msg, decode(&buf, DecodeOptions::new().max_repeated_len(1)).unwrap());
+}
+
+/// A message with `Bytes`-backed string and bytes fields.
+#[derive(Clone, Debug, PartialEq, Message)]
+pub struct BytesFields {
+    #[prost(bytes, tag="1")]
+    pub bytes: bytes::Bytes,
+
+    #[prost(string, tag="2")]
+    pub string: prost::BytesString,
+
+    #[prost(bytes, repeated, tag="3")]
+    pub repeated_bytes: Vec<bytes::Bytes>,
+
+    #[prost(string, optional, tag="4", default="foo")]
+    pub optional_string: Option<prost::BytesString>,
+
+    #[prost(bytes, required, tag="5", default="b\"bar\"")]
+    pub required_bytes: bytes::Bytes,
+
+    #[prost(map="string, bytes", tag="6")]
+    pub map: ::std::collections::HashMap<String, bytes::Bytes>,
+}
+
+#[test]
+fn check_bytes_fields() {
+    let _ = env_logger::init();
+
+    let mut msg = BytesFields::default();
+    assert_eq!("foo", msg.optional_string());
+    assert_eq!(&b"bar"[..], &msg.required_bytes[..]);
+
+    msg.bytes = bytes::Bytes::from(vec![0xAB; 64]);
+    msg.string = prost::BytesString::from("a string which is too long to be stored inline");
+    msg.repeated_bytes = vec![bytes::Bytes::from(&b"abc"[..]), bytes::Bytes::new()];
+    msg.optional_string = Some(prost::BytesString::from("baz"));
+    msg.map.insert("key".to_string(), bytes::Bytes::from(&b"value"[..]));
+    check_message(msg.clone());
+
+    let mut buf = Vec::new();
+    msg.encode(&mut buf).unwrap();
+    let buf = bytes::Bytes::from(buf);
+    let roundtrip = BytesFields::decode_bytes(buf.clone()).unwrap();
+    assert_eq!(msg, roundtrip);
+
+    // The fields are slices of the input buffer.
+    let start = buf.as_ptr() as usize;
+    let end = start + buf.len();
+    let bytes_ptr = roundtrip.bytes.as_ptr() as usize;
+    let string_ptr = roundtrip.string.as_ptr() as usize;
+    assert!(bytes_ptr >= start && bytes_ptr < end);
+    assert!(string_ptr >= start && string_ptr < end);
+}
+
+#[test]
+fn check_bytes_string_invalid_utf8() {
+    use prost::DecodeErrorKind;
+    use prost::encoding;
+
+    let mut buf = Vec::new();
+    encoding::bytes::encode(2, &vec![0xFF; 40], &mut buf);
+    let error = BytesFields::decode_bytes(bytes::Bytes::from(buf)).unwrap_err();
+    assert_eq!(&DecodeErrorKind::InvalidUtf8, error.kind());
+    assert_eq!(&[("BytesFields", "string")], error.stack());
+}
+
+/// A message with lazily decoded message fields.
+#[derive(Clone, Debug, PartialEq, Message)]
+pub struct LazyFields {
+    #[prost(message, optional, tag="1")]
+    pub basic: Option<prost::Lazy<Basic>>,
+
+    #[prost(message, repeated, tag="2")]
+    pub repeated_basic: Vec<prost::Lazy<Basic>>,
+
+    #[prost(int32, tag="3")]
+    pub int32: i32,
+}
+
+#[test]
+fn check_lazy_fields() {
+    use prost::{DecodeErrorKind, FieldMaskTree, Lazy, MaskableMessage};
+
+    let _ = env_logger::init();
+
+    let mut basic = Basic::default();
+    basic.string = "basic".to_string();
+    for key in 0..10 {
+        basic.string_map.insert(key.to_string(), key.to_string());
+    }
+    let mut msg = LazyFields::default();
+    msg.basic = Some(Lazy::new(basic.clone()));
+    msg.repeated_basic = vec![Lazy::new(basic.clone()), Lazy::default()];
+    msg.int32 = 42;
+    check_message(msg.clone());
+
+    let buf = msg.encode_to_vec();
+    let mut roundtrip = LazyFields::decode_from_slice(&buf).unwrap();
+    assert_eq!(msg, roundtrip);
+    assert_eq!(buf, roundtrip.encode_to_vec());
+
+    // Shared references read a temporary copy, leaving the field undecoded.
+    let shared = &roundtrip;
+    assert_eq!(Ok(5), shared.basic.as_ref().unwrap().with_msg(|basic| basic.string.len()));
+    assert!(!shared.basic.as_ref().unwrap().is_decoded());
+    {
+        let lazy = roundtrip.basic.as_mut().unwrap();
+        assert!(!lazy.is_decoded());
+        assert_eq!(&basic, lazy.get().unwrap());
+        assert!(lazy.is_decoded());
+    }
+
+    // Decoded but unmodified fields are re-encoded from the original bytes,
+    // so the map entries keep their order.
+    assert_eq!(buf, roundtrip.encode_to_vec());
+
+    roundtrip.basic.as_mut().unwrap().get_mut().unwrap().int32 = 7;
+    assert!(roundtrip.basic.as_ref().unwrap().encoded().is_none());
+    let mut expected = basic.clone();
+    expected.int32 = 7;
+    let decoded = LazyFields::decode_from_slice(&roundtrip.encode_to_vec()).unwrap();
+    assert_eq!(expected, decoded.basic.unwrap().into_inner().unwrap());
+
+    // Deterministic encodings re-encode lazy fields, even if not decoded.
+    let decoded = LazyFields::decode_from_slice(&msg.encode_to_vec()).unwrap();
+    assert!(!decoded.basic.as_ref().unwrap().is_decoded());
+    assert_eq!(msg.encode_to_vec_deterministic(), decoded.encode_to_vec_deterministic());
+
+    // Occurrences of a lazy field are merged when it is decoded.
+    let mut other = Basic::default();
+    other.int32 = 3;
+    let mut buf = msg.encode_to_vec();
+    buf.extend(LazyFields { basic: Some(Lazy::new(other)), ..LazyFields::default() }.encode_to_vec());
+    let mut expected = basic.clone();
+    expected.int32 = 3;
+    let decoded = LazyFields::decode_from_slice(&buf).unwrap();
+    assert_eq!(expected, decoded.basic.unwrap().into_inner().unwrap());
+
+    // Errors in a lazy field are reported when it is accessed.
+    let mut buf = Vec::new();
+    prost::encoding::bytes::encode(1, &vec![0x08], &mut buf);
+    let mut decoded = LazyFields::decode_from_slice(&buf).unwrap();
+    let error = decoded.basic.as_mut().unwrap().get().unwrap_err();
+    assert_eq!(&DecodeErrorKind::BufferUnderflow, error.kind());
+    assert_eq!(buf, decoded.encode_to_vec_deterministic());
+
+    // Masked merges return the error of lazy fields which fail to decode.
+    let mut destination = msg.clone();
+    let error = destination.merge_masked(&decoded, &FieldMaskTree::new(&["basic", "int32"])).unwrap_err();
+    assert_eq!(&DecodeErrorKind::BufferUnderflow, error.kind());
+}
+
+/// A message which borrows its string and bytes fields from the decoded buffer.
+#[derive(Clone, Debug, PartialEq, Message)]
+pub struct Borrowed<'a> {
+    #[prost(string, tag="1")]
+    pub name: &'a str,
+
+    #[prost(bytes, tag="2")]
+    pub data: &'a [u8],
+
+    #[prost(message, repeated, tag="3")]
+    pub items: Vec<BorrowedItem<'a>>,
+
+    #[prost(message, optional, tag="4")]
+    pub basic: Option<Basic>,
+
+    #[prost(string, optional, tag="5", default="foo")]
+    pub optional_name: Option<&'a str>,
+
+    #[prost(group, optional, tag="6")]
+    pub group: Option<BorrowedItem<'a>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Message)]
+pub struct BorrowedItem<'a> {
+    #[prost(string, repeated, tag="1")]
+    pub names: Vec<&'a str>,
+
+    #[prost(bytes, required, tag="2", default="b\"bar\"")]
+    pub data: &'a [u8],
+
+    #[prost(string, tag="3")]
+    pub owned: String,
+}
+
+#[test]
+fn check_borrowed() {
+    use prost::BorrowedMessage;
+
+    let _ = env_logger::init();
+
+    let mut msg = Borrowed::default();
+    assert_eq!("foo", msg.optional_name());
+
+    msg.name = "name";
+    msg.data = b"data";
+    msg.items.push(BorrowedItem {
+        names: vec!["a", "b"],
+        data: b"",
+        owned: "owned".to_string(),
+    });
+    msg.items.push(BorrowedItem::default());
+    msg.basic = Some(Basic { string: "basic".to_string(), ..Basic::default() });
+    msg.optional_name = Some("bar");
+    msg.group = Some(BorrowedItem { names: vec!["c"], ..BorrowedItem::default() });
+
+    let mut buf = Vec::new();
+    BorrowedMessage::encode(&msg, &mut buf).unwrap();
+    assert_eq!(BorrowedMessage::encoded_len(&msg), buf.len());
+
+    let roundtrip = Borrowed::decode(&buf).unwrap();
+    assert_eq!(msg, roundtrip);
+
+    // The fields are slices of the input buffer.
+    let range = buf.as_ptr() as usize..buf.as_ptr() as usize + buf.len();
+    let contains = |ptr: *const u8| ptr as usize >= range.start && (ptr as usize) < range.end;
+    assert!(contains(roundtrip.name.as_ptr()));
+    assert!(contains(roundtrip.data.as_ptr()));
+    assert!(contains(roundtrip.items[0].names[1].as_ptr()));
+    assert!(contains(roundtrip.group.as_ref().unwrap().names[0].as_ptr()));
+}
+
+#[test]
+fn check_borrowed_invalid_utf8() {
+    use prost::{BorrowedMessage, DecodeErrorKind};
+    use prost::encoding;
+
+    let mut item = Vec::new();
+    encoding::bytes::encode(1, &vec![0xFF], &mut item);
+    let mut buf = Vec::new();
+    encoding::bytes::encode(3, &item, &mut buf);
+
+    let error = Borrowed::decode(&buf).unwrap_err();
+    assert_eq!(&DecodeErrorKind::InvalidUtf8, error.kind());
+    assert_eq!(&[("BorrowedItem", "names"), ("Borrowed", "items")], error.stack());
+}
+
+#[test]
+fn check_delimited_stream() {
+    use std::io;
+    use prost::{DecodeOptions, DelimitedReader, DelimitedWriter};
+
+    let messages = (0..3).map(|i| {
+        let mut basic = Basic::default();
+        basic.int32 = i;
+        basic.string = "x".repeat(i as usize * 100);
+        basic
+    }).collect::<Vec<_>>();
+
+    let mut writer = DelimitedWriter::new(Vec::new());
+    for msg in &messages {
+        writer.write(msg).unwrap();
+    }
+    let buf = writer.into_inner();
+
+    let decoded = DelimitedReader::<_, Basic>::new(&buf[..]).collect::<io::Result<Vec<_>>>().unwrap();
+    assert_eq!(messages, decoded);
+
+    // A stream which ends within a frame is an error, not a clean end.
+    let mut reader = DelimitedReader::<_, Basic>::new(&buf[..buf.len() - 1]);
+    assert!(reader.next().unwrap().is_ok());
+    assert!(reader.next().unwrap().is_ok());
+    assert_eq!(io::ErrorKind::UnexpectedEof, reader.next().unwrap().unwrap_err().kind());
+    assert!(reader.next().is_none());
+
+    // Frames longer than the maximum message size are rejected.
+    let mut reader = DelimitedReader::<_, Basic>::new(&buf[..]).with_max_message_size(150);
+    assert!(reader.next().unwrap().is_ok());
+    assert!(reader.next().unwrap().is_ok());
+    assert_eq!(io::ErrorKind::InvalidData, reader.next().unwrap().unwrap_err().kind());
+
+    // Frames are decoded with the reader's options.
+    let mut reader = DelimitedReader::<_, Basic>::new(&buf[..]).with_options(DecodeOptions::new().max_length(150));
+    assert!(reader.next().unwrap().is_ok());
+    assert!(reader.next().unwrap().is_ok());
+    assert_eq!(io::ErrorKind::InvalidData, reader.next().unwrap().unwrap_err().kind());
+    let mut reader = DelimitedReader::<_, Basic>::new(&buf[..]).with_options(DecodeOptions::new().max_message_size(150));
+    assert!(reader.next().unwrap().is_ok());
+    assert!(reader.next().unwrap().is_ok());
+    assert_eq!(io::ErrorKind::InvalidData, reader.next().unwrap().unwrap_err().kind());
+
+    // The maximum message size is the options' message size limit.
+    let mut reader = DelimitedReader::<_, Basic>::new(&buf[..])
+        .with_options(DecodeOptions::new().max_length(1000))
+        .with_max_message_size(150);
+    assert!(reader.next().unwrap().is_ok());
+    assert!(reader.next().unwrap().is_ok());
+    assert_eq!(io::ErrorKind::InvalidData, reader.next().unwrap().unwrap_err().kind());
+}